@@ -2,15 +2,86 @@
 
 use crate::table::{record_batch_to_rows, schema_to_field_desc};
 use async_trait::async_trait;
-use convergence::engine::{Engine, Portal};
+use convergence::engine::{Engine, Parameters, Portal, PreparedStatement};
 use convergence::protocol::{ErrorResponse, FieldDescription, SqlState};
 use convergence::protocol_ext::DataRowBatch;
-use convergence::sqlparser::ast::{Expr, GroupByExpr, Query, Select, SelectItem, SetExpr, Statement, Value};
+use convergence::sqlparser::ast::{
+	Expr, GroupByExpr, Query, Select, SelectItem, SetExpr, Statement, TableFactor, Value, Visit, Visitor,
+};
+use datafusion::arrow::error::ArrowError;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::common::SchemaError;
 use datafusion::error::DataFusionError;
+use datafusion::physical_plan::SendableRecordBatchStream;
 use datafusion::prelude::*;
+use futures::StreamExt;
+use std::ops::ControlFlow;
+use std::sync::{Mutex, PoisonError};
 
 fn df_err_to_sql(err: DataFusionError) -> ErrorResponse {
-	ErrorResponse::error(SqlState::DataException, err.to_string())
+	let sql_state = match err.find_root() {
+		DataFusionError::SQL(..) => SqlState::SyntaxError,
+		DataFusionError::NotImplemented(_) => SqlState::FeatureNotSupported,
+		DataFusionError::SchemaError(SchemaError::FieldNotFound { .. }, _) => SqlState::UndefinedColumn,
+		DataFusionError::SchemaError(SchemaError::AmbiguousReference { .. }, _) => SqlState::AmbiguousColumn,
+		DataFusionError::SchemaError(
+			SchemaError::DuplicateQualifiedField { .. } | SchemaError::DuplicateUnqualifiedField { .. },
+			_,
+		) => SqlState::DuplicateColumn,
+		DataFusionError::ArrowError(ArrowError::DivideByZero, _) => SqlState::DivisionByZero,
+		DataFusionError::ArrowError(ArrowError::ArithmeticOverflow(_), _) => SqlState::NumericValueOutOfRange,
+		DataFusionError::ArrowError(ArrowError::CastError(_) | ArrowError::ParseError(_), _) => {
+			SqlState::InvalidTextRepresentation
+		}
+		DataFusionError::ResourcesExhausted(_) => SqlState::InsufficientResources,
+		DataFusionError::Internal(_) => SqlState::InternalError,
+		_ => SqlState::DataException,
+	};
+
+	ErrorResponse::error(sql_state, err.to_string())
+}
+
+// DataFusion reports missing tables as plan errors, so they're found by looking up each table the statement reads,
+// other than those defined by common table expressions
+struct MissingTable<'a> {
+	ctx: &'a SessionContext,
+	ctes: Vec<String>,
+}
+
+impl Visitor for MissingTable<'_> {
+	type Break = String;
+
+	fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<String> {
+		if let Some(with) = &query.with {
+			self.ctes
+				.extend(with.cte_tables.iter().map(|cte| cte.alias.name.value.clone()));
+		}
+		ControlFlow::Continue(())
+	}
+
+	fn pre_visit_table_factor(&mut self, table_factor: &TableFactor) -> ControlFlow<String> {
+		// tables with arguments are table functions, which are looked up separately
+		let name = match table_factor {
+			TableFactor::Table { name, args: None, .. } => name,
+			_ => return ControlFlow::Continue(()),
+		};
+
+		let is_cte = match name.0.as_slice() {
+			[ident] => self.ctes.iter().any(|cte| cte.eq_ignore_ascii_case(&ident.value)),
+			_ => false,
+		};
+		match self.ctx.table_exist(name.to_string().as_str()) {
+			Ok(false) if !is_cte => ControlFlow::Break(name.to_string()),
+			_ => ControlFlow::Continue(()),
+		}
+	}
+}
+
+fn missing_table(ctx: &SessionContext, statement: &Statement) -> Option<String> {
+	match statement.visit(&mut MissingTable { ctx, ctes: vec![] }) {
+		ControlFlow::Break(name) => Some(name),
+		ControlFlow::Continue(()) => None,
+	}
 }
 
 // dummy query used as replacement for set variable statements etc
@@ -56,9 +127,26 @@ fn translate_statement(statement: &Statement) -> Statement {
 	}
 }
 
+/// A statement planned by DataFusion, which portals are created from without planning it again.
+pub struct DataFusionStatement {
+	df: DataFrame,
+	fields: Vec<FieldDescription>,
+}
+
+impl PreparedStatement for DataFusionStatement {
+	fn fields(&self) -> &[FieldDescription] {
+		&self.fields
+	}
+}
+
 /// A portal built using a logical DataFusion query plan.
 pub struct DataFusionPortal {
 	df: DataFrame,
+	// the running query, once rows are fetched incrementally. The mutex only makes the stream Sync,
+	// as it's always accessed through a mutable reference.
+	stream: Option<Mutex<SendableRecordBatchStream>>,
+	// the rows of the last record batch which haven't been fetched yet
+	pending: Option<RecordBatch>,
 }
 
 #[async_trait]
@@ -69,6 +157,35 @@ impl Portal for DataFusionPortal {
 		}
 		Ok(())
 	}
+
+	async fn fetch_rows(&mut self, batch: &mut DataRowBatch, max_rows: usize) -> Result<bool, ErrorResponse> {
+		if self.stream.is_none() {
+			let stream = self.df.clone().execute_stream().await.map_err(df_err_to_sql)?;
+			self.stream = Some(Mutex::new(stream));
+		}
+
+		let mut remaining = max_rows;
+		while remaining > 0 {
+			let arrow_batch = match self.pending.take() {
+				Some(arrow_batch) => arrow_batch,
+				None => {
+					let stream = self.stream.as_mut().expect("stream has been started").get_mut();
+					match stream.unwrap_or_else(PoisonError::into_inner).next().await {
+						Some(arrow_batch) => arrow_batch.map_err(df_err_to_sql)?,
+						None => return Ok(false),
+					}
+				}
+			};
+
+			let num_rows = arrow_batch.num_rows().min(remaining);
+			record_batch_to_rows(&arrow_batch.slice(0, num_rows), batch)?;
+			if num_rows < arrow_batch.num_rows() {
+				self.pending = Some(arrow_batch.slice(num_rows, arrow_batch.num_rows() - num_rows));
+			}
+			remaining -= num_rows;
+		}
+		Ok(true)
+	}
 }
 
 /// An engine instance using DataFusion for catalogue management and queries.
@@ -85,25 +202,42 @@ impl DataFusionEngine {
 
 #[async_trait]
 impl Engine for DataFusionEngine {
+	type PreparedStatementType = DataFusionStatement;
 	type PortalType = DataFusionPortal;
 
-	async fn prepare(&mut self, statement: &Statement) -> Result<Vec<FieldDescription>, ErrorResponse> {
-		let plan = self
+	async fn prepare(&mut self, statement: &Statement) -> Result<Self::PreparedStatementType, ErrorResponse> {
+		let df = self
 			.ctx
 			.sql(&translate_statement(statement).to_string())
 			.await
-			.map_err(df_err_to_sql)?;
+			.map_err(|err| match (err.find_root(), missing_table(&self.ctx, statement)) {
+				(DataFusionError::Plan(_), Some(table)) => ErrorResponse::error(
+					SqlState::UndefinedTable,
+					format!("relation \"{}\" does not exist", table),
+				),
+				_ => df_err_to_sql(err),
+			})?;
+		let fields = schema_to_field_desc(&df.schema().clone().into())?;
 
-		schema_to_field_desc(&plan.schema().clone().into())
+		Ok(DataFusionStatement { df, fields })
 	}
 
-	async fn create_portal(&mut self, statement: &Statement) -> Result<Self::PortalType, ErrorResponse> {
-		let df = self
-			.ctx
-			.sql(&translate_statement(statement).to_string())
-			.await
-			.map_err(df_err_to_sql)?;
+	async fn create_portal(
+		&mut self,
+		statement: &Self::PreparedStatementType,
+		parameters: &Parameters,
+	) -> Result<Self::PortalType, ErrorResponse> {
+		if !parameters.values.is_empty() {
+			return Err(ErrorResponse::error(
+				SqlState::FeatureNotSupported,
+				"query parameters are not supported",
+			));
+		}
 
-		Ok(DataFusionPortal { df })
+		Ok(DataFusionPortal {
+			df: statement.df.clone(),
+			stream: None,
+			pending: None,
+		})
 	}
 }
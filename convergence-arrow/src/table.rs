@@ -28,12 +28,14 @@ macro_rules! array_val {
 /// Writes the contents of an Arrow [RecordBatch] into a Postgres [DataRowBatch].
 pub fn record_batch_to_rows(arrow_batch: &RecordBatch, pg_batch: &mut DataRowBatch) -> Result<(), ErrorResponse> {
 	for row_idx in 0..arrow_batch.num_rows() {
-		let mut row = pg_batch.create_row();
-		for col_idx in 0..arrow_batch.num_columns() {
-			let col = arrow_batch.column(col_idx);
-			if col.is_null(row_idx) {
-				row.write_null();
-			} else {
+		pg_batch.write_row(|row| {
+			for col_idx in 0..arrow_batch.num_columns() {
+				let col = arrow_batch.column(col_idx);
+				if col.is_null(row_idx) {
+					row.write_null()?;
+					continue;
+				}
+
 				match col.data_type() {
 					DataType::Boolean => row.write_bool(array_val!(BooleanArray, col, row_idx)),
 					DataType::Int8 => row.write_int2(array_val!(Int8Array, col, row_idx) as i16),
@@ -81,9 +83,11 @@ pub fn record_batch_to_rows(arrow_batch: &RecordBatch, pg_batch: &mut DataRowBat
 							format!("arrow to pg conversion not implemented for {}", other),
 						))
 					}
-				};
+				}?;
 			}
-		}
+
+			Ok(())
+		})?;
 	}
 
 	Ok(())
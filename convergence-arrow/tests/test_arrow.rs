@@ -1,6 +1,7 @@
 use async_trait::async_trait;
+use bytes::BytesMut;
 use chrono::{NaiveDate, NaiveDateTime};
-use convergence::engine::{Engine, Portal};
+use convergence::engine::{Engine, Parameters, Portal};
 use convergence::protocol::{ErrorResponse, FieldDescription};
 use convergence::protocol_ext::DataRowBatch;
 use convergence::server::{self, BindOptions};
@@ -9,7 +10,13 @@ use convergence_arrow::table::{record_batch_to_rows, schema_to_field_desc};
 use datafusion::arrow::array::{ArrayRef, Date32Array, Float32Array, Int32Array, StringArray, TimestampSecondArray};
 use datafusion::arrow::datatypes::{DataType, Field, Schema, TimeUnit};
 use datafusion::arrow::record_batch::RecordBatch;
+use fallible_iterator::FallibleIterator;
+use postgres_protocol::message::backend::Message;
+use postgres_protocol::message::frontend;
+use postgres_protocol::IsNull;
 use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
 use tokio_postgres::{connect, NoTls};
 
 struct ArrowPortal {
@@ -52,26 +59,35 @@ impl ArrowEngine {
 
 #[async_trait]
 impl Engine for ArrowEngine {
+	type PreparedStatementType = Vec<FieldDescription>;
 	type PortalType = ArrowPortal;
 
 	async fn prepare(&mut self, _: &Statement) -> Result<Vec<FieldDescription>, ErrorResponse> {
-		schema_to_field_desc(&*self.batch.schema())
+		schema_to_field_desc(&self.batch.schema())
 	}
 
-	async fn create_portal(&mut self, _: &Statement) -> Result<Self::PortalType, ErrorResponse> {
+	async fn create_portal(
+		&mut self,
+		_: &Self::PreparedStatementType,
+		_: &Parameters,
+	) -> Result<Self::PortalType, ErrorResponse> {
 		Ok(ArrowPortal {
 			batch: self.batch.clone(),
 		})
 	}
 }
 
-async fn setup() -> tokio_postgres::Client {
-	let port = server::run_background(
+async fn start_server() -> u16 {
+	server::run_background(
 		BindOptions::new().with_port(0),
 		Arc::new(|| Box::pin(async { ArrowEngine::new() })),
 	)
 	.await
-	.unwrap();
+	.unwrap()
+}
+
+async fn setup() -> tokio_postgres::Client {
+	let port = start_server().await;
 
 	let (client, conn) = connect(&format!("postgres://localhost:{}/test", port), NoTls)
 		.await
@@ -134,3 +150,112 @@ async fn basic_data_types() {
 		)
 	);
 }
+
+async fn read_message(stream: &mut TcpStream, buf: &mut BytesMut) -> Message {
+	loop {
+		if let Some(message) = Message::parse(buf).unwrap() {
+			return message;
+		}
+
+		assert_ne!(stream.read_buf(buf).await.unwrap(), 0, "connection closed");
+	}
+}
+
+// connects without a driver, so tests can send messages that tokio-postgres never does
+async fn raw_connect() -> (TcpStream, BytesMut) {
+	let mut stream = TcpStream::connect(("localhost", start_server().await)).await.unwrap();
+
+	let mut buf = BytesMut::new();
+	frontend::startup_message(vec![("user", "test")], &mut buf).unwrap();
+	stream.write_all(&buf).await.unwrap();
+
+	let mut read_buf = BytesMut::new();
+	while !matches!(
+		read_message(&mut stream, &mut read_buf).await,
+		Message::ReadyForQuery(_)
+	) {}
+
+	(stream, read_buf)
+}
+
+async fn send_extended_query(stream: &mut TcpStream, result_formats: Vec<i16>) {
+	let mut buf = BytesMut::new();
+	frontend::parse("", "select 1", None, &mut buf).unwrap();
+	frontend::bind(
+		"",
+		"",
+		None,
+		std::iter::empty::<()>(),
+		|_, _| Ok(IsNull::No),
+		result_formats,
+		&mut buf,
+	)
+	.map_err(|_| "failed to encode bind")
+	.unwrap();
+	frontend::describe(b'P', "", &mut buf).unwrap();
+	frontend::execute("", 0, &mut buf).unwrap();
+	frontend::sync(&mut buf);
+	stream.write_all(&buf).await.unwrap();
+}
+
+#[tokio::test]
+async fn per_column_formats() {
+	let (mut stream, mut buf) = raw_connect().await;
+	send_extended_query(&mut stream, vec![1, 0, 1, 0, 0]).await;
+
+	assert!(matches!(
+		read_message(&mut stream, &mut buf).await,
+		Message::ParseComplete
+	));
+	assert!(matches!(
+		read_message(&mut stream, &mut buf).await,
+		Message::BindComplete
+	));
+
+	let formats: Vec<i16> = match read_message(&mut stream, &mut buf).await {
+		Message::RowDescription(body) => body.fields().map(|f| Ok(f.format())).collect().unwrap(),
+		other => panic!("expected row description, got {:?}", std::mem::discriminant(&other)),
+	};
+	assert_eq!(formats, vec![1, 0, 1, 0, 0]);
+
+	let values: Vec<Vec<u8>> = match read_message(&mut stream, &mut buf).await {
+		Message::DataRow(body) => body
+			.ranges()
+			.map(|r| Ok(body.buffer()[r.unwrap()].to_vec()))
+			.collect()
+			.unwrap(),
+		other => panic!("expected data row, got {:?}", std::mem::discriminant(&other)),
+	};
+	assert_eq!(
+		values,
+		vec![
+			1i32.to_be_bytes().to_vec(),
+			b"1.5".to_vec(),
+			b"a".to_vec(),
+			b"2020-01-01 00:00:00".to_vec(),
+			b"1970-01-01".to_vec(),
+		]
+	);
+}
+
+#[tokio::test]
+async fn per_column_formats_count_mismatch() {
+	let (mut stream, mut buf) = raw_connect().await;
+	send_extended_query(&mut stream, vec![1, 0]).await;
+
+	assert!(matches!(
+		read_message(&mut stream, &mut buf).await,
+		Message::ParseComplete
+	));
+	match read_message(&mut stream, &mut buf).await {
+		Message::ErrorResponse(body) => {
+			let code = body
+				.fields()
+				.find(|f| Ok(f.type_() == b'C'))
+				.unwrap()
+				.map(|f| f.value_bytes().to_vec());
+			assert_eq!(code.as_deref(), Some(&b"08P01"[..]));
+		}
+		other => panic!("expected error, got {:?}", std::mem::discriminant(&other)),
+	}
+}
@@ -1,8 +1,9 @@
+use convergence::protocol::SqlState;
 use convergence::server::{self, BindOptions};
 use convergence_arrow::datafusion::DataFusionEngine;
 use datafusion::prelude::*;
 use std::sync::Arc;
-use tokio_postgres::{connect, NoTls};
+use tokio_postgres::{connect, NoTls, SimpleQueryMessage};
 
 async fn new_engine() -> DataFusionEngine {
 	let ctx = SessionContext::new();
@@ -65,3 +66,55 @@ async fn grouped_counts() {
 	assert_eq!(get_row(2), ("c", 25));
 	assert_eq!(get_row(3), ("d", 25));
 }
+
+#[tokio::test]
+async fn undefined_table_error() {
+	let client = setup().await;
+
+	let err = client
+		.query("select * from missing_table", &[])
+		.await
+		.expect_err("expected error in query");
+
+	assert_eq!(err.code().unwrap().code(), SqlState::UndefinedTable.code());
+	assert_eq!(
+		err.as_db_error().unwrap().message(),
+		"relation \"missing_table\" does not exist"
+	);
+
+	// tables defined by common table expressions aren't reported as missing
+	let err = client
+		.query("with t as (select 1 as a) select b from t", &[])
+		.await
+		.expect_err("expected error in query");
+	assert_eq!(err.code().unwrap().code(), SqlState::UndefinedColumn.code());
+
+	let err = client
+		.query(
+			"with t as (select 1 as a) select * from t join missing_table on true",
+			&[],
+		)
+		.await
+		.expect_err("expected error in query");
+	assert_eq!(err.code().unwrap().code(), SqlState::UndefinedTable.code());
+}
+
+#[tokio::test]
+async fn cursor_fetches_in_chunks() {
+	let client = setup().await;
+	client
+		.batch_execute("DECLARE c CURSOR FOR select bucket from test_100_4buckets")
+		.await
+		.unwrap();
+
+	let num_rows = |messages: Vec<SimpleQueryMessage>| {
+		messages
+			.iter()
+			.filter(|message| matches!(message, SimpleQueryMessage::Row(_)))
+			.count()
+	};
+	assert_eq!(num_rows(client.simple_query("FETCH 30 FROM c").await.unwrap()), 30);
+	assert_eq!(num_rows(client.simple_query("FETCH 30 FROM c").await.unwrap()), 30);
+	assert_eq!(num_rows(client.simple_query("FETCH ALL FROM c").await.unwrap()), 40);
+	assert_eq!(num_rows(client.simple_query("FETCH 1 FROM c").await.unwrap()), 0);
+}
@@ -0,0 +1,155 @@
+//! Derive macros for the `convergence` crate.
+
+#![warn(missing_docs)]
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Ident, LitStr};
+
+/// Derives `convergence::protocol_ext::PgRow` for a struct with named fields,
+/// writing each field as a column in declaration order.
+///
+/// Each field's type must implement `ToPgValue`; `Option<T>` fields are written as nulls when `None`.
+///
+/// Fields support the following attributes:
+/// - `#[pg(rename = "name")]` sets the column name, which defaults to the field name.
+/// - `#[pg(type = Varchar)]` sets the column's `DataTypeOid` variant, which defaults to the field type's own.
+///
+/// ```ignore
+/// #[derive(PgRow)]
+/// struct User {
+///     id: i64,
+///     #[pg(rename = "user_name", type = Varchar)]
+///     name: String,
+///     email: Option<String>,
+/// }
+/// ```
+#[proc_macro_derive(PgRow, attributes(pg))]
+pub fn derive_pg_row(input: TokenStream) -> TokenStream {
+	let input = parse_macro_input!(input as DeriveInput);
+	expand_pg_row(input).unwrap_or_else(|err| err.to_compile_error()).into()
+}
+
+struct FieldAttrs {
+	rename: Option<LitStr>,
+	data_type: Option<Ident>,
+}
+
+fn parse_field_attrs(field: &syn::Field) -> syn::Result<FieldAttrs> {
+	let mut attrs = FieldAttrs {
+		rename: None,
+		data_type: None,
+	};
+
+	for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("pg")) {
+		attr.parse_nested_meta(|meta| {
+			if meta.path.is_ident("rename") {
+				attrs.rename = Some(meta.value()?.parse()?);
+				Ok(())
+			} else if meta.path.is_ident("type") {
+				// accept both `type = Varchar` and `type = "Varchar"`
+				let value = meta.value()?;
+				attrs.data_type = Some(if value.peek(LitStr) {
+					value.parse::<LitStr>()?.parse()?
+				} else {
+					value.parse()?
+				});
+				Ok(())
+			} else {
+				Err(meta.error("unsupported pg attribute, expected `rename` or `type`"))
+			}
+		})?;
+	}
+
+	Ok(attrs)
+}
+
+fn expand_pg_row(input: DeriveInput) -> syn::Result<TokenStream2> {
+	let fields = match &input.data {
+		Data::Struct(data) => match &data.fields {
+			Fields::Named(fields) => &fields.named,
+			_ => {
+				return Err(Error::new(
+					input.span(),
+					"PgRow can only be derived for structs with named fields",
+				))
+			}
+		},
+		_ => return Err(Error::new(input.span(), "PgRow can only be derived for structs")),
+	};
+
+	let mut descriptions = Vec::new();
+	let mut writes = Vec::new();
+
+	for field in fields {
+		let attrs = parse_field_attrs(field)?;
+		let ident = field.ident.as_ref().expect("named fields have identifiers");
+		let ty = &field.ty;
+		let name = match attrs.rename {
+			Some(rename) => rename.value(),
+			None => ident.to_string().trim_start_matches("r#").to_owned(),
+		};
+
+		match attrs.data_type {
+			Some(data_type) => {
+				descriptions.push(quote_spanned! {data_type.span()=>
+					::convergence::protocol::FieldDescription {
+						name: #name.to_owned(),
+						data_type: ::convergence::protocol::DataTypeOid::#data_type,
+					}
+				});
+				writes.push(quote_spanned! {ty.span()=>
+					::convergence::protocol_ext::ToPgValue::write_pg_value_as(
+						&self.#ident,
+						::convergence::protocol::DataTypeOid::#data_type,
+						row,
+					)?;
+				});
+			}
+			None => {
+				descriptions.push(quote_spanned! {ty.span()=>
+					::convergence::protocol::FieldDescription {
+						name: #name.to_owned(),
+						data_type: <#ty as ::convergence::protocol_ext::ToPgValue>::data_type(),
+					}
+				});
+				writes.push(quote_spanned! {ty.span()=>
+					::convergence::protocol_ext::ToPgValue::write_pg_value(&self.#ident, row)?;
+				});
+			}
+		}
+	}
+
+	// generic field types need to be writable, which is implied for everything else
+	let mut generics = input.generics.clone();
+	if generics.type_params().next().is_some() {
+		let where_clause = generics.make_where_clause();
+		for field in fields {
+			let ty = &field.ty;
+			where_clause
+				.predicates
+				.push(syn::parse_quote!(#ty: ::convergence::protocol_ext::ToPgValue));
+		}
+	}
+
+	let name = &input.ident;
+	let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+	Ok(quote! {
+		impl #impl_generics ::convergence::protocol_ext::PgRow for #name #ty_generics #where_clause {
+			fn field_descriptions() -> ::std::vec::Vec<::convergence::protocol::FieldDescription> {
+				::std::vec![#(#descriptions),*]
+			}
+
+			fn write_row(
+				&self,
+				row: &mut ::convergence::protocol_ext::DataRowWriter,
+			) -> ::std::result::Result<(), ::convergence::protocol::ErrorResponse> {
+				#(#writes)*
+				::std::result::Result::Ok(())
+			}
+		}
+	})
+}
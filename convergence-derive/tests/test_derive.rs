@@ -0,0 +1,149 @@
+use bytes::{Buf, BytesMut};
+use convergence::protocol::{ConnectionCodec, DataTypeOid, FormatCode, SqlState};
+use convergence::protocol_ext::{DataRowBatch, PgRow};
+use convergence_derive::PgRow;
+use tokio_util::codec::Encoder;
+
+#[derive(PgRow)]
+struct User {
+	id: i64,
+	#[pg(rename = "user_name", type = Varchar)]
+	name: String,
+	email: Option<String>,
+	#[pg(type = "Jsonb")]
+	profile: String,
+	r#type: i16,
+}
+
+#[derive(PgRow)]
+struct Borrowed<'a, T> {
+	label: &'a str,
+	value: T,
+}
+
+fn users() -> Vec<User> {
+	vec![
+		User {
+			id: 1,
+			name: "ann".to_owned(),
+			email: Some("ann@example.com".to_owned()),
+			profile: "{}".to_owned(),
+			r#type: 2,
+		},
+		User {
+			id: 2,
+			name: "bob".to_owned(),
+			email: None,
+			profile: "[]".to_owned(),
+			r#type: 3,
+		},
+	]
+}
+
+// decodes the columns of each DataRow message, with None for nulls
+fn decode_rows(batch: DataRowBatch) -> Vec<Vec<Option<Vec<u8>>>> {
+	let mut buf = BytesMut::new();
+	ConnectionCodec::new().encode(batch, &mut buf).unwrap();
+
+	let mut rows = Vec::new();
+	while buf.has_remaining() {
+		assert_eq!(buf.get_u8(), b'D');
+		buf.get_i32();
+		let num_cols = buf.get_i16();
+		rows.push(
+			(0..num_cols)
+				.map(|_| match buf.get_i32() {
+					-1 => None,
+					len => Some(buf.split_to(len as usize).to_vec()),
+				})
+				.collect(),
+		);
+	}
+	rows
+}
+
+fn batch_for<T: PgRow>(format_code: FormatCode) -> DataRowBatch {
+	DataRowBatch::with_column_types(
+		format_code,
+		T::field_descriptions().iter().map(|f| f.data_type).collect(),
+	)
+}
+
+#[test]
+fn field_descriptions() {
+	let fields: Vec<_> = User::field_descriptions()
+		.into_iter()
+		.map(|f| (f.name, f.data_type))
+		.collect();
+
+	assert_eq!(
+		fields,
+		vec![
+			("id".to_owned(), DataTypeOid::Int8),
+			("user_name".to_owned(), DataTypeOid::Varchar),
+			("email".to_owned(), DataTypeOid::Text),
+			("profile".to_owned(), DataTypeOid::Jsonb),
+			("type".to_owned(), DataTypeOid::Int2),
+		]
+	);
+
+	let fields: Vec<_> = Borrowed::<'_, f64>::field_descriptions()
+		.into_iter()
+		.map(|f| f.data_type)
+		.collect();
+	assert_eq!(fields, vec![DataTypeOid::Text, DataTypeOid::Float8]);
+}
+
+#[test]
+fn write_text_rows() {
+	let mut batch = batch_for::<User>(FormatCode::Text);
+	for user in users() {
+		batch.write_row(|row| user.write_row(row)).unwrap();
+	}
+
+	let text = |val: &str| Some(val.as_bytes().to_vec());
+	assert_eq!(
+		decode_rows(batch),
+		vec![
+			vec![text("1"), text("ann"), text("ann@example.com"), text("{}"), text("2")],
+			vec![text("2"), text("bob"), None, text("[]"), text("3")],
+		]
+	);
+}
+
+#[test]
+fn write_binary_rows() {
+	let mut batch = batch_for::<User>(FormatCode::Binary);
+	for user in users() {
+		batch.write_row(|row| user.write_row(row)).unwrap();
+	}
+
+	let rows = decode_rows(batch);
+	assert_eq!(rows[0][0], Some(1i64.to_be_bytes().to_vec()));
+	// jsonb is prefixed with its format version in binary
+	assert_eq!(rows[0][3], Some(b"\x01{}".to_vec()));
+	assert_eq!(rows[1][2], None);
+
+	let mut batch = batch_for::<Borrowed<'_, f64>>(FormatCode::Binary);
+	batch
+		.write_row(|row| {
+			Borrowed {
+				label: "pi",
+				value: 3.5,
+			}
+			.write_row(row)
+		})
+		.unwrap();
+	assert_eq!(
+		decode_rows(batch),
+		vec![vec![Some(b"pi".to_vec()), Some(3.5f64.to_be_bytes().to_vec())]]
+	);
+}
+
+#[test]
+fn mismatched_batch() {
+	// writing a row into a batch with different column types is rejected
+	let mut batch = DataRowBatch::with_column_types(FormatCode::Binary, vec![DataTypeOid::Int4; 5]);
+	let err = batch.write_row(|row| users()[0].write_row(row)).unwrap_err();
+	assert_eq!(err.sql_state, SqlState::DatatypeMismatch);
+}
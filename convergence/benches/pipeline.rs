@@ -0,0 +1,189 @@
+use async_trait::async_trait;
+use convergence::connection::Connection;
+use convergence::engine::{Engine, Parameters, Portal};
+use convergence::protocol::{DataTypeOid, ErrorResponse, FieldDescription};
+use convergence::protocol_ext::DataRowBatch;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use futures::future::try_join_all;
+use futures::ready;
+use sqlparser::ast::Statement;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+use tokio_postgres::{connect, Client, NoTls};
+
+struct ReturnSingleScalarPortal;
+
+#[async_trait]
+impl Portal for ReturnSingleScalarPortal {
+	async fn fetch(&mut self, batch: &mut DataRowBatch) -> Result<(), ErrorResponse> {
+		batch.write_row(|row| row.write_int4(1))
+	}
+}
+
+struct ReturnSingleScalarEngine;
+
+#[async_trait]
+impl Engine for ReturnSingleScalarEngine {
+	type PreparedStatementType = Vec<FieldDescription>;
+	type PortalType = ReturnSingleScalarPortal;
+
+	async fn prepare(&mut self, _: &Statement) -> Result<Vec<FieldDescription>, ErrorResponse> {
+		Ok(vec![FieldDescription {
+			name: "test".to_owned(),
+			data_type: DataTypeOid::Int4,
+		}])
+	}
+
+	async fn create_portal(
+		&mut self,
+		_: &Self::PreparedStatementType,
+		_: &Parameters,
+	) -> Result<Self::PortalType, ErrorResponse> {
+		Ok(ReturnSingleScalarPortal)
+	}
+}
+
+// Writes at most one backend message to the socket per write, as a server which flushed after every message would,
+// counting the writes. Every byte is still written in order, so clients see the same responses either way.
+struct FlushPerMessage<S> {
+	inner: S,
+	// the number of bytes of the current message which are yet to be written
+	remaining: usize,
+	writes: Arc<AtomicUsize>,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for FlushPerMessage<S> {
+	fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.inner).poll_read(cx, buf)
+	}
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for FlushPerMessage<S> {
+	fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		if self.remaining == 0 {
+			// each message is a tag byte followed by its length, which includes the length itself
+			self.remaining = match *buf {
+				[_, a, b, c, d, ..] => 1 + u32::from_be_bytes([a, b, c, d]) as usize,
+				_ => buf.len(),
+			};
+		}
+
+		let len = self.remaining.min(buf.len());
+		let written = ready!(Pin::new(&mut self.inner).poll_write(cx, &buf[..len]))?;
+		self.remaining -= written;
+		self.writes.fetch_add(1, Ordering::Relaxed);
+		Poll::Ready(Ok(written))
+	}
+
+	fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.inner).poll_flush(cx)
+	}
+
+	fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.inner).poll_shutdown(cx)
+	}
+}
+
+// counts the writes made to the socket without changing them
+struct CountWrites<S> {
+	inner: S,
+	writes: Arc<AtomicUsize>,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CountWrites<S> {
+	fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.inner).poll_read(cx, buf)
+	}
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CountWrites<S> {
+	fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+		let written = ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+		self.writes.fetch_add(1, Ordering::Relaxed);
+		Poll::Ready(Ok(written))
+	}
+
+	fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.inner).poll_flush(cx)
+	}
+
+	fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.inner).poll_shutdown(cx)
+	}
+}
+
+// serves a single client, returning it along with a count of the server's socket writes
+async fn setup(flush_per_message: bool) -> (Client, Arc<AtomicUsize>) {
+	let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+	let port = listener.local_addr().unwrap().port();
+	let writes = Arc::new(AtomicUsize::new(0));
+
+	let server_writes = writes.clone();
+	tokio::spawn(async move {
+		let (stream, _) = listener.accept().await.unwrap();
+		stream.set_nodelay(true).unwrap();
+		let mut conn = Connection::new(ReturnSingleScalarEngine);
+		let result = match flush_per_message {
+			true => {
+				conn.run(FlushPerMessage {
+					inner: stream,
+					remaining: 0,
+					writes: server_writes,
+				})
+				.await
+			}
+			false => {
+				conn.run(CountWrites {
+					inner: stream,
+					writes: server_writes,
+				})
+				.await
+			}
+		};
+		result.unwrap();
+	});
+
+	let (client, conn) = connect(&format!("postgres://localhost:{}/test", port), NoTls)
+		.await
+		.expect("failed to init client");
+	tokio::spawn(async move { conn.await.unwrap() });
+
+	(client, writes)
+}
+
+// Compares the server buffering its responses until the client sends `Sync` or `Flush` with a baseline which writes
+// each message as soon as it's produced, for queries which are pipelined so that the server has several responses
+// to write at once. The number of socket writes per query is printed for each server, as the difference in time
+// depends on the platform's per-write overhead.
+fn pipeline(c: &mut Criterion) {
+	let runtime = Runtime::new().unwrap();
+
+	let mut group = c.benchmark_group("pipeline");
+	for (name, flush_per_message) in [("buffered", false), ("flush_per_message", true)] {
+		let (client, writes) = runtime.block_on(setup(flush_per_message));
+		let statement = runtime.block_on(client.prepare("select 1")).unwrap();
+
+		for num_queries in [1, 10, 100] {
+			let query_all = || try_join_all((0..num_queries).map(|_| client.query_one(&statement, &[])));
+
+			let before = writes.load(Ordering::Relaxed);
+			runtime.block_on(query_all()).unwrap();
+			let per_query = (writes.load(Ordering::Relaxed) - before) as f64 / num_queries as f64;
+			println!("{}/{}: {:.1} socket writes per query", name, num_queries, per_query);
+
+			group.bench_with_input(BenchmarkId::new(name, num_queries), &num_queries, |b, _| {
+				b.to_async(&runtime).iter(|| async { query_all().await.unwrap() })
+			});
+		}
+	}
+	group.finish();
+}
+
+criterion_group!(benches, pipeline);
+criterion_main!(benches);
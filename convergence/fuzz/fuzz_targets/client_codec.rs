@@ -0,0 +1,12 @@
+#![no_main]
+
+use bytes::BytesMut;
+use convergence::protocol::ClientCodec;
+use libfuzzer_sys::fuzz_target;
+use tokio_util::codec::Decoder;
+
+fuzz_target!(|data: &[u8]| {
+	let mut buf = BytesMut::from(data);
+	let mut codec = ClientCodec::new().with_max_message_size(1 << 16);
+	while let Ok(Some(_)) = codec.decode(&mut buf) {}
+});
@@ -0,0 +1,24 @@
+#![no_main]
+
+use bytes::BytesMut;
+use convergence::protocol::{ClientCodec, ClientMessage, ConnectionCodec, Startup};
+use libfuzzer_sys::fuzz_target;
+use std::collections::HashMap;
+use tokio_util::codec::{Decoder, Encoder};
+
+fuzz_target!(|data: &[u8]| {
+	// inputs starting with an odd byte are preceded by a valid startup message,
+	// so that regular messages are reached without the fuzzer having to discover one
+	let mut buf = BytesMut::new();
+	if data.first().is_some_and(|b| b % 2 == 1) {
+		let startup = ClientMessage::Startup(Startup {
+			requested_protocol_version: (3, 0),
+			parameters: HashMap::new(),
+		});
+		ClientCodec::new().encode(startup, &mut buf).unwrap();
+	}
+	buf.extend_from_slice(data);
+
+	let mut codec = ConnectionCodec::new().with_max_message_size(1 << 16);
+	while let Ok(Some(_)) = codec.decode(&mut buf) {}
+});
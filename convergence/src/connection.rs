@@ -1,13 +1,14 @@
 //! Contains the [Connection] struct, which represents an individual Postgres session, and related types.
 
-use crate::engine::{Engine, Portal};
+use crate::engine::{Engine, Parameters, Portal, PreparedStatement as _, SessionEndReason, SessionInfo};
+use crate::plan_cache::{PlanCache, PlanCacheKey};
 use crate::protocol::*;
 use crate::protocol_ext::DataRowBatch;
+use crate::settings::SessionSettings;
 use futures::{SinkExt, StreamExt};
-use sqlparser::ast::Statement;
-use sqlparser::dialect::PostgreSqlDialect;
-use sqlparser::parser::Parser;
+use sqlparser::ast::{CloseCursor, DiscardObject, Expr, FetchDirection, Ident, Statement, Value};
 use std::collections::HashMap;
+use std::sync::Arc;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_util::codec::Framed;
 
@@ -31,17 +32,297 @@ pub enum ConnectionError {
 enum ConnectionState {
 	Startup,
 	Idle,
+	/// An error occurred while processing an extended query, so messages are discarded until the next `Sync`.
+	SkipUntilSync,
+}
+
+/// A statement parsed from a query string, classified by whether the connection handles it itself.
+enum ParsedStatement<E: Engine> {
+	/// A statement prepared by the engine, from either parsed SQL or query text accepted by [Engine::prepare_raw].
+	Engine(Arc<E::PreparedStatementType>),
+	/// A `SET` of one of the [SessionSettings].
+	SetSetting { name: String, value: String },
+	/// A command operating on a SQL-level cursor.
+	Cursor(CursorCommand),
+	/// `DISCARD ALL` or `RESET ALL`, which reset the session, see [Engine::reset].
+	Reset { discard_all: bool },
+}
+
+// derived impls would require the engine itself to be Clone
+impl<E: Engine> Clone for ParsedStatement<E> {
+	fn clone(&self) -> Self {
+		match self {
+			Self::Engine(statement) => Self::Engine(statement.clone()),
+			Self::SetSetting { name, value } => Self::SetSetting {
+				name: name.clone(),
+				value: value.clone(),
+			},
+			Self::Cursor(command) => Self::Cursor(command.clone()),
+			Self::Reset { discard_all } => Self::Reset {
+				discard_all: *discard_all,
+			},
+		}
+	}
 }
 
 #[derive(Debug, Clone)]
-struct PreparedStatement {
-	pub statement: Option<Statement>,
+enum CursorCommand {
+	/// `DECLARE name [BINARY] CURSOR [WITH HOLD] FOR query`.
+	Declare {
+		name: String,
+		query: Box<Statement>,
+		binary: bool,
+		hold: bool,
+	},
+	/// `FETCH` or `MOVE`, which returns or skips up to `max_rows` rows, or all remaining rows if `None`.
+	Fetch {
+		name: String,
+		max_rows: Option<usize>,
+		skip: bool,
+	},
+	/// `CLOSE name`, or `CLOSE ALL` if there's no name.
+	Close { name: Option<String> },
+}
+
+struct PreparedStatement<E: Engine> {
+	pub statement: Option<ParsedStatement<E>>,
 	pub fields: Vec<FieldDescription>,
+	pub parameter_types: Vec<DataTypeOid>,
+}
+
+// types given by the client with `Parse` take precedence over those inferred by the engine,
+// and any the client leaves unspecified are filled in from the engine's
+fn parameter_types(given: &[DataTypeOid], inferred: &[DataTypeOid]) -> Vec<DataTypeOid> {
+	(0..given.len().max(inferred.len()))
+		.map(|i| match given.get(i) {
+			Some(&data_type) if data_type != DataTypeOid::Unspecified => data_type,
+			_ => inferred.get(i).copied().unwrap_or(DataTypeOid::Unspecified),
+		})
+		.collect()
+}
+
+/// A cursor created by `DECLARE`, whose rows are returned in chunks by `FETCH`.
+///
+/// Rows are fetched from the engine's portal as they're needed, see [Portal::fetch_rows],
+/// so only portals which fetch every row at once have their whole result held in memory.
+struct Cursor<E: Engine> {
+	// the engine's portal, until all of its rows have been fetched
+	portal: Option<E::PortalType>,
+	row_desc: RowDescription,
+	// rows which have been fetched from the portal, but not yet returned
+	rows: DataRowBatch,
+}
+
+impl<E: Engine> Cursor<E> {
+	// fetches rows from the portal until at least `max_rows` are buffered or the portal has no rows left
+	async fn fill(&mut self, max_rows: usize) -> Result<(), ErrorResponse> {
+		while self.rows.num_rows() < max_rows {
+			let portal = match &mut self.portal {
+				Some(portal) => portal,
+				None => break,
+			};
+
+			let rows_before = self.rows.num_rows();
+			let more = portal.fetch_rows(&mut self.rows, max_rows - rows_before).await?;
+			// a portal claiming to have more rows without fetching any would otherwise be polled forever
+			if !more || self.rows.num_rows() == rows_before {
+				self.portal = None;
+			}
+		}
+		Ok(())
+	}
+
+	// fetches all of the portal's remaining rows
+	async fn materialize(&mut self) -> Result<(), ErrorResponse> {
+		self.fill(usize::MAX).await
+	}
+
+	async fn fetch(&mut self, max_rows: Option<usize>) -> Result<DataRowBatch, ErrorResponse> {
+		let max_rows = max_rows.unwrap_or(usize::MAX);
+		self.fill(max_rows).await?;
+		Ok(self.rows.take_rows(max_rows))
+	}
+}
+
+enum BoundPortal<E: Engine> {
+	/// An empty query string.
+	Empty,
+	/// A statement executed by the engine.
+	Engine {
+		portal: E::PortalType,
+		row_desc: RowDescription,
+	},
+	/// A `SET` of one of the [SessionSettings], which is handled by the connection itself.
+	SetSetting { name: String, value: String },
+	/// A cursor declared with `DECLARE`, which shares its namespace with protocol-level portals.
+	Cursor(Box<Cursor<E>>),
+	/// A cursor command, which is handled by the connection itself.
+	CursorCommand(CursorCommand),
+	/// `DISCARD ALL` or `RESET ALL`, which is handled by the connection before being passed on to the engine.
+	Reset { discard_all: bool },
 }
 
-struct BoundPortal<E: Engine> {
-	pub portal: E::PortalType,
-	pub row_desc: RowDescription,
+// Returns the name and value of a `SET` statement if it changes one of the session settings.
+fn session_setting(statement: &Statement) -> Option<(String, String)> {
+	match statement {
+		Statement::SetVariable { variable, value, .. } if variable.0.len() == 1 => {
+			let name = &variable.0[0].value;
+			if !SessionSettings::is_setting(name) {
+				return None;
+			}
+
+			let value = value
+				.iter()
+				.map(|expr| match expr {
+					Expr::Identifier(ident) => ident.value.clone(),
+					Expr::Value(Value::SingleQuotedString(value)) => value.clone(),
+					other => other.to_string(),
+				})
+				.collect::<Vec<_>>()
+				.join(", ");
+
+			Some((name.clone(), value))
+		}
+		_ => None,
+	}
+}
+
+// unquoted identifiers are case-insensitive, as in Postgres
+fn cursor_name(ident: &Ident) -> String {
+	match ident.quote_style {
+		Some(_) => ident.value.clone(),
+		None => ident.value.to_lowercase(),
+	}
+}
+
+// returns the number of rows a FETCH or MOVE moves forward by, or None for all remaining rows.
+// Only forward-only cursors are supported, matching Postgres' rules for NO SCROLL cursors.
+fn fetch_count(direction: &FetchDirection) -> Result<Option<usize>, ErrorResponse> {
+	let parse_count = |limit: &Value| match limit {
+		Value::Number(count, _) => count
+			.parse::<i64>()
+			.map_err(|_| ErrorResponse::error(SqlState::SyntaxError, format!("invalid row count: {}", count))),
+		other => Err(ErrorResponse::error(
+			SqlState::SyntaxError,
+			format!("invalid row count: {}", other),
+		)),
+	};
+
+	let count = match direction {
+		FetchDirection::All | FetchDirection::ForwardAll => return Ok(None),
+		FetchDirection::Next | FetchDirection::Forward { limit: None } => 1,
+		FetchDirection::Count { limit } | FetchDirection::Forward { limit: Some(limit) } => parse_count(limit)?,
+		_ => 0,
+	};
+
+	match count {
+		count if count > 0 => Ok(Some(count as usize)),
+		_ => Err(ErrorResponse::error(
+			SqlState::ObjectNotInPrerequisiteState,
+			"cursor can only scan forward",
+		)),
+	}
+}
+
+// Returns the cursor command for `DECLARE`, `FETCH` and `CLOSE` statements.
+fn cursor_command(statement: &Statement) -> Result<Option<CursorCommand>, ErrorResponse> {
+	let command = match statement {
+		Statement::Declare { stmts } => {
+			let (name, query, declare) = match stmts.as_slice() {
+				[declare] => match (declare.names.as_slice(), &declare.for_query) {
+					([name], Some(query)) => (name, query, declare),
+					_ => {
+						return Err(ErrorResponse::error(
+							SqlState::SyntaxError,
+							"expected DECLARE name CURSOR FOR query",
+						))
+					}
+				},
+				_ => {
+					return Err(ErrorResponse::error(
+						SqlState::SyntaxError,
+						"expected a single cursor declaration",
+					))
+				}
+			};
+
+			if declare.scroll == Some(true) {
+				return Err(ErrorResponse::error(
+					SqlState::FeatureNotSupported,
+					"scrollable cursors are not supported",
+				));
+			}
+
+			CursorCommand::Declare {
+				name: cursor_name(name),
+				query: Box::new(Statement::Query(query.clone())),
+				binary: declare.binary == Some(true),
+				hold: declare.hold == Some(true),
+			}
+		}
+		Statement::Fetch {
+			name,
+			direction,
+			into: None,
+		} => CursorCommand::Fetch {
+			name: cursor_name(name),
+			max_rows: fetch_count(direction)?,
+			skip: false,
+		},
+		Statement::Close { cursor } => CursorCommand::Close {
+			name: match cursor {
+				CloseCursor::All => None,
+				CloseCursor::Specific { name } => Some(cursor_name(name)),
+			},
+		},
+		_ => return Ok(None),
+	};
+
+	Ok(Some(command))
+}
+
+// sqlparser doesn't support RESET, so `RESET ALL` is recognised from the query text
+fn is_reset_all(text: &str) -> bool {
+	let words: Vec<_> = text.trim().trim_end_matches(';').split_whitespace().collect();
+	matches!(words.as_slice(), [reset, all] if reset.eq_ignore_ascii_case("reset") && all.eq_ignore_ascii_case("all"))
+}
+
+// returns the statements the connection handles itself, or None for those prepared by the engine
+fn classify_statement<E: Engine>(statement: &Statement) -> Result<Option<ParsedStatement<E>>, ErrorResponse> {
+	if let Some((name, value)) = session_setting(statement) {
+		return Ok(Some(ParsedStatement::SetSetting { name, value }));
+	}
+
+	if let Statement::Discard {
+		object_type: DiscardObject::ALL,
+	} = statement
+	{
+		return Ok(Some(ParsedStatement::Reset { discard_all: true }));
+	}
+
+	Ok(cursor_command(statement)?.map(ParsedStatement::Cursor))
+}
+
+// generates random cancellation keys, which are longer from protocol 3.2 onwards, matching the length used by Postgres
+fn generate_key_data(protocol_version: (i16, i16)) -> Result<BackendKeyData, ErrorResponse> {
+	let random_bytes = |len: usize| {
+		let mut bytes = vec![0; len];
+		getrandom::getrandom(&mut bytes).map(|_| bytes).map_err(|err| {
+			ErrorResponse::fatal(
+				SqlState::InternalError,
+				format!("failed to generate cancellation key: {}", err),
+			)
+		})
+	};
+
+	let process_id = random_bytes(4)?;
+	let key_len = if protocol_version >= (3, 2) { 32 } else { 4 };
+
+	Ok(BackendKeyData {
+		// process ids are always positive
+		process_id: i32::from_be_bytes([process_id[0], process_id[1], process_id[2], process_id[3]]) & i32::MAX,
+		secret_key: random_bytes(key_len)?,
+	})
 }
 
 /// Describes a connection using a specific engine.
@@ -49,8 +330,19 @@ struct BoundPortal<E: Engine> {
 pub struct Connection<E: Engine> {
 	engine: E,
 	state: ConnectionState,
-	statements: HashMap<String, PreparedStatement>,
-	portals: HashMap<String, Option<BoundPortal<E>>>,
+	settings: SessionSettings,
+	// the settings requested by the startup message, which `RESET ALL` returns to
+	startup_settings: SessionSettings,
+	// set once the engine has accepted the session
+	session: Option<SessionInfo>,
+	statements: HashMap<String, PreparedStatement<E>>,
+	// shared with the server's other connections, if the engine opted in
+	plan_cache: Option<Arc<PlanCache<E::PreparedStatementType>>>,
+	portals: HashMap<String, BoundPortal<E>>,
+	max_message_size: usize,
+	protocol_version: (i16, i16),
+	// whether the last message received was part of an extended query, rather than a simple `Query` or `FunctionCall`
+	extended_query: bool,
 }
 
 impl<E: Engine> Connection<E> {
@@ -58,47 +350,329 @@ impl<E: Engine> Connection<E> {
 	pub fn new(engine: E) -> Self {
 		Self {
 			state: ConnectionState::Startup,
+			settings: SessionSettings::default(),
+			startup_settings: SessionSettings::default(),
+			session: None,
 			statements: HashMap::new(),
+			plan_cache: engine.plan_cache(),
 			portals: HashMap::new(),
+			max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+			protocol_version: (3, 0),
+			extended_query: false,
 			engine,
 		}
 	}
 
-	fn prepared_statement(&self, name: &str) -> Result<&PreparedStatement, ConnectionError> {
+	/// Sets the largest message the client may send, see [ConnectionCodec::with_max_message_size].
+	pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+		self.max_message_size = max_message_size;
+		self
+	}
+
+	fn prepared_statement(&self, name: &str) -> Result<&PreparedStatement<E>, ConnectionError> {
 		Ok(self
 			.statements
 			.get(name)
 			.ok_or_else(|| ErrorResponse::error(SqlState::InvalidSQLStatementName, "missing statement"))?)
 	}
 
-	fn portal(&self, name: &str) -> Result<&Option<BoundPortal<E>>, ConnectionError> {
+	fn portal(&self, name: &str) -> Result<&BoundPortal<E>, ConnectionError> {
 		Ok(self
 			.portals
 			.get(name)
 			.ok_or_else(|| ErrorResponse::error(SqlState::InvalidCursorName, "missing portal"))?)
 	}
 
-	fn portal_mut(&mut self, name: &str) -> Result<&mut Option<BoundPortal<E>>, ConnectionError> {
+	fn portal_mut(&mut self, name: &str) -> Result<&mut BoundPortal<E>, ConnectionError> {
 		Ok(self
 			.portals
 			.get_mut(name)
 			.ok_or_else(|| ErrorResponse::error(SqlState::InvalidCursorName, "missing portal"))?)
 	}
 
-	fn parse_statement(&mut self, text: &str) -> Result<Option<Statement>, ErrorResponse> {
-		let statements = Parser::parse_sql(&PostgreSqlDialect {}, text)
-			.map_err(|err| ErrorResponse::error(SqlState::SyntaxError, err.to_string()))?;
+	fn cursor(&self, name: &str) -> Result<&Cursor<E>, ErrorResponse> {
+		match self.portals.get(name) {
+			Some(BoundPortal::Cursor(cursor)) => Ok(cursor),
+			_ => Err(ErrorResponse::error(
+				SqlState::InvalidCursorName,
+				format!("cursor \"{}\" does not exist", name),
+			)),
+		}
+	}
 
-		match statements.len() {
-			0 => Ok(None),
-			1 => Ok(Some(statements[0].clone())),
+	fn cursor_mut(&mut self, name: &str) -> Result<&mut Cursor<E>, ErrorResponse> {
+		match self.portals.get_mut(name) {
+			Some(BoundPortal::Cursor(cursor)) => Ok(cursor),
 			_ => Err(ErrorResponse::error(
-				SqlState::SyntaxError,
-				"expected zero or one statements",
+				SqlState::InvalidCursorName,
+				format!("cursor \"{}\" does not exist", name),
 			)),
 		}
 	}
 
+	// parses a query string and prepares it with the engine, unless the connection handles the statement itself.
+	// Statements prepared by the engine are shared through the plan cache, if there is one.
+	async fn parse_statement(
+		&mut self,
+		text: &str,
+		parameter_types: &[DataTypeOid],
+	) -> Result<Option<ParsedStatement<E>>, ErrorResponse> {
+		if is_reset_all(text) {
+			return Ok(Some(ParsedStatement::Reset { discard_all: false }));
+		}
+
+		let plan_cache = match &self.plan_cache {
+			Some(plan_cache) => plan_cache.clone(),
+			None => return self.parse_uncached(text).await,
+		};
+
+		let key = PlanCacheKey::new(text, parameter_types);
+		if let Some(statement) = plan_cache.get(&key) {
+			return Ok(Some(ParsedStatement::Engine(statement)));
+		}
+
+		let parsed = self.parse_uncached(text).await?;
+		if let Some(ParsedStatement::Engine(statement)) = &parsed {
+			plan_cache.insert(key, statement.clone());
+		}
+		Ok(parsed)
+	}
+
+	async fn parse_uncached(&mut self, text: &str) -> Result<Option<ParsedStatement<E>>, ErrorResponse> {
+		// sqlparser doesn't support MOVE, which takes the same arguments as FETCH but skips the rows
+		let trimmed = text.trim_start();
+		let is_move = trimmed
+			.get(..4)
+			.is_some_and(|keyword| keyword.eq_ignore_ascii_case("move"))
+			&& trimmed[4..].starts_with(char::is_whitespace);
+		let parsed = if is_move {
+			self.engine.parse(&format!("FETCH{}", &trimmed[4..]))
+		} else {
+			self.engine.parse(text)
+		};
+
+		// the engine gets a chance to handle queries that couldn't be parsed before the error is reported
+		let statements = match parsed {
+			Ok(statements) => statements,
+			Err(err) => {
+				return match self.engine.prepare_raw(text).await? {
+					Some(statement) => Ok(Some(ParsedStatement::Engine(Arc::new(statement)))),
+					None => Err(err),
+				}
+			}
+		};
+
+		let statement = match statements.as_slice() {
+			[] => return Ok(None),
+			[statement] => statement,
+			_ => {
+				return Err(ErrorResponse::error(
+					SqlState::SyntaxError,
+					"expected zero or one statements",
+				))
+			}
+		};
+
+		let mut parsed = match classify_statement(statement)? {
+			Some(parsed) => parsed,
+			None => ParsedStatement::Engine(Arc::new(self.engine.prepare(statement).await?)),
+		};
+		if let ParsedStatement::Cursor(CursorCommand::Fetch { skip, .. }) = &mut parsed {
+			*skip = is_move;
+		}
+		Ok(Some(parsed))
+	}
+
+	// fetches all of a portal's rows for a simple query, which are always in the text format
+	async fn run_simple_portal(
+		&mut self,
+		framed: &mut Framed<impl AsyncRead + AsyncWrite + Unpin, ConnectionCodec>,
+		statement: &E::PreparedStatementType,
+	) -> Result<(), ConnectionError> {
+		let mut portal = self.engine.create_portal(statement, &Parameters::default()).await?;
+		let row_desc = RowDescription::with_format(statement.fields().to_vec(), FormatCode::Text);
+
+		let mut batch_writer = DataRowBatch::from_row_desc(&row_desc).with_settings(self.settings);
+		portal.fetch(&mut batch_writer).await?;
+		let num_rows = batch_writer.num_rows();
+
+		framed.feed(row_desc).await?;
+		framed.feed(batch_writer).await?;
+
+		framed
+			.feed(CommandComplete {
+				command_tag: format!("SELECT {}", num_rows),
+			})
+			.await?;
+		Ok(())
+	}
+
+	// the row description is only sent for simple queries, as extended queries use Describe instead
+	async fn run_cursor_command(
+		&mut self,
+		framed: &mut Framed<impl AsyncRead + AsyncWrite + Unpin, ConnectionCodec>,
+		command: CursorCommand,
+		send_row_desc: bool,
+	) -> Result<(), ConnectionError> {
+		let command_tag = match command {
+			CursorCommand::Declare {
+				name,
+				query,
+				binary,
+				hold,
+			} => {
+				if self.portals.contains_key(&name) {
+					return Err(ErrorResponse::error(
+						SqlState::DuplicateCursor,
+						format!("cursor \"{}\" already exists", name),
+					)
+					.into());
+				}
+
+				let statement = self.engine.prepare(&query).await?;
+				let format_code = if binary { FormatCode::Binary } else { FormatCode::Text };
+				let row_desc = RowDescription::with_format(statement.fields().to_vec(), format_code);
+				let mut cursor = Cursor {
+					portal: Some(self.engine.create_portal(&statement, &Parameters::default()).await?),
+					rows: DataRowBatch::from_row_desc(&row_desc).with_settings(self.settings),
+					row_desc,
+				};
+
+				// there are no transaction blocks, so every cursor lasts until it's closed, but as in Postgres,
+				// WITH HOLD cursors fetch all of their rows up front rather than keeping the engine's portal open
+				if hold {
+					cursor.materialize().await?;
+				}
+
+				self.portals.insert(name, BoundPortal::Cursor(Box::new(cursor)));
+				"DECLARE CURSOR".to_owned()
+			}
+			CursorCommand::Fetch { name, max_rows, skip } => {
+				let cursor = self.cursor_mut(&name)?;
+				let rows = cursor.fetch(max_rows).await?;
+				let num_rows = rows.num_rows();
+
+				if skip {
+					format!("MOVE {}", num_rows)
+				} else {
+					if send_row_desc {
+						framed.feed(cursor.row_desc.clone()).await?;
+					}
+					framed.feed(rows).await?;
+					format!("FETCH {}", num_rows)
+				}
+			}
+			CursorCommand::Close { name: Some(name) } => {
+				self.cursor(&name)?;
+				self.portals.remove(&name);
+				"CLOSE CURSOR".to_owned()
+			}
+			CursorCommand::Close { name: None } => {
+				self.portals
+					.retain(|_, portal| !matches!(portal, BoundPortal::Cursor(_)));
+				"CLOSE CURSOR ALL".to_owned()
+			}
+		};
+
+		framed.feed(CommandComplete { command_tag }).await?;
+		Ok(())
+	}
+
+	async fn set_setting(
+		&mut self,
+		framed: &mut Framed<impl AsyncRead + AsyncWrite + Unpin, ConnectionCodec>,
+		name: &str,
+		value: &str,
+	) -> Result<(), ConnectionError> {
+		self.settings.set(name, value)?;
+		if let Some(status) = self.settings.parameter_status(name) {
+			framed.feed(status).await?;
+		}
+
+		framed
+			.feed(CommandComplete {
+				command_tag: "SET".to_owned(),
+			})
+			.await?;
+		Ok(())
+	}
+
+	// returns the session to the state it started in, as if a new client had connected
+	async fn reset_session(
+		&mut self,
+		framed: &mut Framed<impl AsyncRead + AsyncWrite + Unpin, ConnectionCodec>,
+		discard_all: bool,
+	) -> Result<(), ConnectionError> {
+		if discard_all {
+			self.statements.clear();
+			self.portals.clear();
+		}
+
+		let previous_statuses = self.settings.parameter_statuses();
+		self.settings = self.startup_settings;
+		for status in self.settings.parameter_statuses() {
+			if !previous_statuses.contains(&status) {
+				framed.feed(status).await?;
+			}
+		}
+
+		self.engine.reset().await?;
+
+		framed
+			.feed(CommandComplete {
+				command_tag: if discard_all { "DISCARD ALL" } else { "RESET" }.to_owned(),
+			})
+			.await?;
+		Ok(())
+	}
+
+	// follows ProcessStartupPacket: other major versions are rejected, while newer minor versions
+	// and unknown protocol options are negotiated down to what's supported
+	async fn negotiate_protocol_version(
+		&mut self,
+		framed: &mut Framed<impl AsyncRead + AsyncWrite + Unpin, ConnectionCodec>,
+		startup: &Startup,
+	) -> Result<(), ConnectionError> {
+		// versions are unsigned on the wire
+		let (major, minor) = (
+			startup.requested_protocol_version.0 as u16,
+			startup.requested_protocol_version.1 as u16,
+		);
+		let (latest_major, latest_minor) = LATEST_PROTOCOL_VERSION;
+
+		if major != latest_major as u16 {
+			return Err(ErrorResponse::fatal(
+				SqlState::FeatureNotSupported,
+				format!(
+					"unsupported frontend protocol {}.{}: server supports {}.0 to {}.{}",
+					major, minor, latest_major, latest_major, latest_minor
+				),
+			)
+			.into());
+		}
+
+		let mut unrecognized_options: Vec<_> = startup
+			.parameters
+			.keys()
+			.filter(|name| name.starts_with("_pq_."))
+			.cloned()
+			.collect();
+		unrecognized_options.sort();
+
+		let negotiated_minor = minor.min(latest_minor as u16);
+		if negotiated_minor < minor || !unrecognized_options.is_empty() {
+			framed
+				.feed(NegotiateProtocolVersion {
+					newest_minor_version: negotiated_minor as i32,
+					unrecognized_options,
+				})
+				.await?;
+		}
+
+		self.protocol_version = (latest_major, negotiated_minor as i16);
+		Ok(())
+	}
+
 	async fn step(
 		&mut self,
 		framed: &mut Framed<impl AsyncRead + AsyncWrite + Unpin, ConnectionCodec>,
@@ -106,8 +680,35 @@ impl<E: Engine> Connection<E> {
 		match self.state {
 			ConnectionState::Startup => {
 				match framed.next().await.ok_or(ConnectionError::ConnectionClosed)?? {
-					ClientMessage::Startup(_startup) => {
-						// do startup stuff
+					ClientMessage::Startup(startup) => {
+						self.negotiate_protocol_version(framed, &startup).await?;
+
+						if !startup.parameters.contains_key("user") {
+							return Err(ErrorResponse::fatal(
+								SqlState::InvalidAuthorizationSpecification,
+								"no PostgreSQL user name specified in startup packet",
+							)
+							.into());
+						}
+
+						for (name, value) in &startup.parameters {
+							if SessionSettings::is_setting(name) {
+								self.settings
+									.set(name, value)
+									.map_err(|err| ErrorResponse::fatal(err.sql_state, err.message))?;
+							}
+						}
+						self.startup_settings = self.settings;
+
+						let session = SessionInfo {
+							parameters: startup.parameters,
+							protocol_version: self.protocol_version,
+						};
+						self.engine
+							.on_session_start(&session)
+							.await
+							.map_err(|err| ErrorResponse::fatal(err.sql_state, err.message))?;
+						self.session = Some(session);
 					}
 					ClientMessage::SSLRequest => {
 						// we don't support SSL for now
@@ -115,6 +716,16 @@ impl<E: Engine> Connection<E> {
 						framed.send(SSLResponse(false)).await?;
 						return Ok(Some(ConnectionState::Startup));
 					}
+					ClientMessage::GSSENCRequest => {
+						// likewise for GSSAPI encryption
+						framed.send(GSSENCResponse(false)).await?;
+						return Ok(Some(ConnectionState::Startup));
+					}
+					ClientMessage::CancelRequest(_) => {
+						// queries can't be cancelled yet, and Postgres never replies to cancel requests,
+						// so the connection is just closed
+						return Ok(None);
+					}
 					_ => {
 						return Err(
 							ErrorResponse::fatal(SqlState::ProtocolViolation, "expected startup message").into(),
@@ -122,131 +733,213 @@ impl<E: Engine> Connection<E> {
 					}
 				}
 
-				framed.send(AuthenticationOk).await?;
+				framed.feed(AuthenticationOk).await?;
 
 				let param_statuses = &[
 					("server_version", "13"),
 					("server_encoding", "UTF8"),
 					("client_encoding", "UTF8"),
-					("DateStyle", "ISO"),
 					("TimeZone", "UTC"),
 					("integer_datetimes", "on"),
 				];
 
 				for &(param, status) in param_statuses {
-					framed.send(ParameterStatus::new(param, status)).await?;
+					framed.feed(ParameterStatus::new(param, status)).await?;
+				}
+
+				for status in self.settings.parameter_statuses() {
+					framed.feed(status).await?;
+				}
+
+				for status in self.engine.parameter_statuses() {
+					framed.feed(status).await?;
 				}
 
-				framed.send(ReadyForQuery).await?;
+				framed.feed(generate_key_data(self.protocol_version)?).await?;
+
+				framed.send(ReadyForQuery(TransactionStatus::Idle)).await?;
 				Ok(Some(ConnectionState::Idle))
 			}
 			ConnectionState::Idle => {
-				match framed.next().await.ok_or(ConnectionError::ConnectionClosed)?? {
+				let msg = framed.next().await.ok_or(ConnectionError::ConnectionClosed)??;
+				self.extended_query = !matches!(msg, ClientMessage::Query(_) | ClientMessage::FunctionCall(_));
+
+				match msg {
 					ClientMessage::Parse(parse) => {
-						let parsed_statement = self.parse_statement(&parse.query)?;
+						let parsed_statement = self.parse_statement(&parse.query, &parse.parameter_types).await?;
 
 						self.statements.insert(
 							parse.prepared_statement_name,
 							PreparedStatement {
 								fields: match &parsed_statement {
-									Some(statement) => self.engine.prepare(statement).await?,
-									None => vec![],
+									Some(ParsedStatement::Engine(statement)) => statement.fields().to_vec(),
+									Some(ParsedStatement::Cursor(CursorCommand::Fetch {
+										name, skip: false, ..
+									})) => self
+										.cursor(name)
+										.map(|cursor| cursor.row_desc.fields().to_vec())
+										.unwrap_or_default(),
+									_ => vec![],
+								},
+								parameter_types: match &parsed_statement {
+									Some(ParsedStatement::Engine(statement)) => {
+										parameter_types(&parse.parameter_types, statement.parameter_types())
+									}
+									_ => parse.parameter_types,
 								},
 								statement: parsed_statement,
 							},
 						);
-						framed.send(ParseComplete).await?;
+						framed.feed(ParseComplete).await?;
 					}
 					ClientMessage::Bind(bind) => {
-						let format_code = match bind.result_format {
-							BindFormat::All(format) => format,
-							BindFormat::PerColumn(_) => {
-								return Err(ErrorResponse::error(
-									SqlState::FeatureNotSupported,
-									"per-column format codes not supported",
-								)
-								.into());
-							}
-						};
-
-						let prepared = self.prepared_statement(&bind.prepared_statement_name)?.clone();
-						let portal = match prepared.statement {
-							Some(statement) => {
-								let portal = self.engine.create_portal(&statement).await?;
-								let row_desc = RowDescription {
-									fields: prepared.fields.clone(),
-									format_code,
+						let prepared = self.prepared_statement(&bind.prepared_statement_name)?;
+						let (statement, fields) = (prepared.statement.clone(), prepared.fields.clone());
+						let portal = match statement {
+							Some(ParsedStatement::Engine(statement)) => {
+								let parameters = Parameters {
+									format_codes: bind.parameter_formats()?,
+									values: bind.parameters,
 								};
+								// the statement was planned when it was parsed, so binding only needs the parameters
+								let portal = self.engine.create_portal(&statement, &parameters).await?;
+								let row_desc = RowDescription::with_bind_format(fields, &bind.result_format)?;
+
+								BoundPortal::Engine { portal, row_desc }
+							}
+							Some(ParsedStatement::SetSetting { name, value }) => {
+								BoundPortal::SetSetting { name, value }
+							}
+							Some(ParsedStatement::Reset { discard_all }) => BoundPortal::Reset { discard_all },
+							Some(ParsedStatement::Cursor(command)) => {
+								// a cursor's rows are encoded as they're fetched from the engine,
+								// so they can only be fetched in the formats the cursor was declared with
+								if let CursorCommand::Fetch { name, skip: false, .. } = &command {
+									let row_desc = &self.cursor(name)?.row_desc;
+									if bind.result_format.format_codes(row_desc.fields().len())?
+										!= row_desc.format_codes()
+									{
+										return Err(ErrorResponse::error(
+											SqlState::FeatureNotSupported,
+											"cursor rows must be fetched in the format the cursor was declared with",
+										)
+										.into());
+									}
+								}
 
-								Some(BoundPortal { portal, row_desc })
+								BoundPortal::CursorCommand(command)
 							}
-							None => None,
+							None => BoundPortal::Empty,
 						};
 
 						self.portals.insert(bind.portal, portal);
 
-						framed.send(BindComplete).await?;
+						framed.feed(BindComplete).await?;
 					}
 					ClientMessage::Describe(Describe::PreparedStatement(ref statement_name)) => {
-						let fields = self.prepared_statement(statement_name)?.fields.clone();
-						framed.send(ParameterDescription {}).await?;
+						let prepared = self.prepared_statement(statement_name)?;
+						let (fields, parameter_types) = (prepared.fields.clone(), prepared.parameter_types.clone());
+						framed.feed(ParameterDescription { parameter_types }).await?;
 						framed
-							.send(RowDescription {
-								fields,
-								format_code: FormatCode::Text,
-							})
+							.feed(RowDescription::with_format(fields, FormatCode::Text))
 							.await?;
 					}
 					ClientMessage::Describe(Describe::Portal(ref portal_name)) => match self.portal(portal_name)? {
-						Some(portal) => framed.send(portal.row_desc.clone()).await?,
-						None => framed.send(NoData).await?,
+						BoundPortal::Engine { row_desc, .. } => framed.feed(row_desc.clone()).await?,
+						BoundPortal::Cursor(cursor) => framed.feed(cursor.row_desc.clone()).await?,
+						BoundPortal::CursorCommand(CursorCommand::Fetch { name, skip: false, .. }) => {
+							framed.feed(self.cursor(name)?.row_desc.clone()).await?
+						}
+						BoundPortal::Empty
+						| BoundPortal::SetSetting { .. }
+						| BoundPortal::CursorCommand(_)
+						| BoundPortal::Reset { .. } => framed.feed(NoData).await?,
 					},
+					ClientMessage::Close(close) => {
+						// closing a statement or portal which doesn't exist isn't an error
+						match close {
+							Close::PreparedStatement(name) => self.statements.remove(&name).map(|_| ()),
+							Close::Portal(name) => self.portals.remove(&name).map(|_| ()),
+						};
+						framed.feed(CloseComplete).await?;
+					}
+					ClientMessage::Flush => {
+						// flushing doesn't depend on the message type, any encoder will do
+						SinkExt::<ServerMessage>::flush(framed).await?;
+					}
 					ClientMessage::Sync => {
-						framed.send(ReadyForQuery).await?;
+						framed.send(ReadyForQuery(TransactionStatus::Idle)).await?;
 					}
-					ClientMessage::Execute(exec) => match self.portal_mut(&exec.portal)? {
-						Some(bound) => {
-							let mut batch_writer = DataRowBatch::from_row_desc(&bound.row_desc);
-							bound.portal.fetch(&mut batch_writer).await?;
-							let num_rows = batch_writer.num_rows();
-
-							framed.send(batch_writer).await?;
-
-							framed
-								.send(CommandComplete {
-									command_tag: format!("SELECT {}", num_rows),
-								})
-								.await?;
-						}
-						None => {
-							framed.send(EmptyQueryResponse).await?;
+					ClientMessage::Execute(exec) => {
+						let settings = self.settings;
+						match self.portal_mut(&exec.portal)? {
+							BoundPortal::Engine { portal, row_desc } => {
+								let mut batch_writer = DataRowBatch::from_row_desc(row_desc).with_settings(settings);
+								portal.fetch(&mut batch_writer).await?;
+								let num_rows = batch_writer.num_rows();
+
+								framed.feed(batch_writer).await?;
+
+								framed
+									.feed(CommandComplete {
+										command_tag: format!("SELECT {}", num_rows),
+									})
+									.await?;
+							}
+							BoundPortal::SetSetting { name, value } => {
+								let (name, value) = (name.clone(), value.clone());
+								self.set_setting(framed, &name, &value).await?;
+							}
+							BoundPortal::Cursor(cursor) => {
+								// executing a cursor directly returns all of its remaining rows
+								let rows = cursor.fetch(None).await?;
+								let num_rows = rows.num_rows();
+
+								framed.feed(rows).await?;
+
+								framed
+									.feed(CommandComplete {
+										command_tag: format!("SELECT {}", num_rows),
+									})
+									.await?;
+							}
+							BoundPortal::CursorCommand(command) => {
+								let command = command.clone();
+								self.run_cursor_command(framed, command, false).await?;
+							}
+							BoundPortal::Reset { discard_all } => {
+								let discard_all = *discard_all;
+								self.reset_session(framed, discard_all).await?;
+							}
+							BoundPortal::Empty => {
+								framed.feed(EmptyQueryResponse).await?;
+							}
 						}
-					},
+					}
 					ClientMessage::Query(query) => {
-						if let Some(parsed) = self.parse_statement(&query)? {
-							let fields = self.engine.prepare(&parsed).await?;
-							let row_desc = RowDescription {
-								fields,
-								format_code: FormatCode::Text,
-							};
-							let mut portal = self.engine.create_portal(&parsed).await?;
-
-							let mut batch_writer = DataRowBatch::from_row_desc(&row_desc);
-							portal.fetch(&mut batch_writer).await?;
-							let num_rows = batch_writer.num_rows();
-
-							framed.send(row_desc).await?;
-							framed.send(batch_writer).await?;
-
-							framed
-								.send(CommandComplete {
-									command_tag: format!("SELECT {}", num_rows),
-								})
-								.await?;
-						} else {
-							framed.send(EmptyQueryResponse).await?;
+						match self.parse_statement(&query, &[]).await? {
+							Some(ParsedStatement::Engine(statement)) => {
+								self.run_simple_portal(framed, &statement).await?;
+							}
+							Some(ParsedStatement::SetSetting { name, value }) => {
+								self.set_setting(framed, &name, &value).await?;
+							}
+							Some(ParsedStatement::Cursor(command)) => {
+								self.run_cursor_command(framed, command, true).await?;
+							}
+							Some(ParsedStatement::Reset { discard_all }) => {
+								self.reset_session(framed, discard_all).await?;
+							}
+							None => {
+								framed.feed(EmptyQueryResponse).await?;
+							}
 						}
-						framed.send(ReadyForQuery).await?;
+						framed.send(ReadyForQuery(TransactionStatus::Idle)).await?;
+					}
+					ClientMessage::FunctionCall(call) => {
+						let result = self.engine.call_function(&call).await?;
+						framed.feed(FunctionCallResponse { result }).await?;
+						framed.send(ReadyForQuery(TransactionStatus::Idle)).await?;
 					}
 					ClientMessage::Terminate => return Ok(None),
 					_ => return Err(ErrorResponse::error(SqlState::ProtocolViolation, "unexpected message").into()),
@@ -254,13 +947,47 @@ impl<E: Engine> Connection<E> {
 
 				Ok(Some(ConnectionState::Idle))
 			}
+			ConnectionState::SkipUntilSync => match framed.next().await.ok_or(ConnectionError::ConnectionClosed)?? {
+				ClientMessage::Sync => {
+					framed.send(ReadyForQuery(TransactionStatus::Idle)).await?;
+					Ok(Some(ConnectionState::Idle))
+				}
+				ClientMessage::Terminate => Ok(None),
+				_ => Ok(Some(ConnectionState::SkipUntilSync)),
+			},
 		}
 	}
 
 	/// Given a stream (typically TCP), extract Postgres protocol messages and respond accordingly.
 	/// This function only returns when the connection is closed (either gracefully or due to an error).
+	/// Once the engine has accepted the session, it's notified when the session ends, see [Engine::on_session_end].
 	pub async fn run(&mut self, stream: impl AsyncRead + AsyncWrite + Unpin) -> Result<(), ConnectionError> {
-		let mut framed = Framed::new(stream, ConnectionCodec::new());
+		let result = self.run_session(stream).await;
+
+		if self.session.take().is_some() {
+			let reason = match &result {
+				Ok(()) => SessionEndReason::Terminated,
+				Err(ConnectionError::ConnectionClosed) | Err(ConnectionError::Protocol(ProtocolError::Io(_))) => {
+					SessionEndReason::Disconnected
+				}
+				Err(ConnectionError::ErrorResponse(err)) => SessionEndReason::Error(err.clone()),
+				Err(ConnectionError::Protocol(err)) => {
+					SessionEndReason::Error(ErrorResponse::fatal(SqlState::ProtocolViolation, err.to_string()))
+				}
+			};
+			self.engine.on_session_end(&reason).await;
+		}
+
+		result
+	}
+
+	async fn run_session(&mut self, stream: impl AsyncRead + AsyncWrite + Unpin) -> Result<(), ConnectionError> {
+		let codec = ConnectionCodec::new().with_max_message_size(self.max_message_size);
+		// responses are buffered with `feed` and only flushed when the client needs them, i.e. on `Sync`, `Flush`,
+		// the end of a simple query or an error, so that pipelined queries don't cost a write each. Framed also
+		// flushes on its own once the buffer passes its backpressure boundary, which bounds the memory used
+		// for large results.
+		let mut framed = Framed::new(stream, codec);
 		loop {
 			let new_state = match self.step(&mut framed).await {
 				Ok(Some(state)) => state,
@@ -268,12 +995,25 @@ impl<E: Engine> Connection<E> {
 				Err(ConnectionError::ErrorResponse(err_info)) => {
 					framed.send(err_info.clone()).await?;
 
-					if err_info.severity == Severity::Fatal {
+					if err_info.severity.is_fatal() {
 						return Err(err_info.into());
 					}
 
-					framed.send(ReadyForQuery).await?;
-					ConnectionState::Idle
+					// a failed simple query is complete, but the rest of a failed extended query is skipped,
+					// including any pipelined messages, so that the client sees a single ReadyForQuery
+					if self.extended_query {
+						ConnectionState::SkipUntilSync
+					} else {
+						framed.send(ReadyForQuery(TransactionStatus::Idle)).await?;
+						ConnectionState::Idle
+					}
+				}
+				// malformed messages can't be skipped reliably, so the connection is closed
+				Err(ConnectionError::Protocol(err)) if !matches!(err, ProtocolError::Io(_)) => {
+					framed
+						.send(ErrorResponse::fatal(SqlState::ProtocolViolation, err.to_string()))
+						.await?;
+					return Err(err.into());
 				}
 				Err(err) => {
 					framed
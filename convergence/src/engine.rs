@@ -1,9 +1,18 @@
 //! Contains core interface definitions for custom SQL engines.
 
-use crate::protocol::{ErrorResponse, FieldDescription};
+use crate::plan_cache::PlanCache;
+use crate::protocol::{
+	DataTypeOid, ErrorResponse, FieldDescription, FormatCode, FunctionCall, ParameterStatus, SqlState,
+};
 use crate::protocol_ext::DataRowBatch;
 use async_trait::async_trait;
-use sqlparser::ast::Statement;
+use bytes::Bytes;
+use sqlparser::ast::{CopySource, Query, SetExpr, Statement};
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::Parser;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
 
 /// A Postgres portal. Portals represent a prepared statement with all parameters specified.
 ///
@@ -13,6 +22,82 @@ use sqlparser::ast::Statement;
 pub trait Portal: Send + Sync {
 	/// Fetches the contents of the portal into a [DataRowBatch].
 	async fn fetch(&mut self, batch: &mut DataRowBatch) -> Result<(), ErrorResponse>;
+
+	/// Fetches up to `max_rows` more rows into a [DataRowBatch], returning false once the portal has no rows left.
+	/// Portals returning true must have fetched at least one row.
+	///
+	/// Cursors use this to fetch their rows in chunks. The default implementation fetches every row at once, which
+	/// cursors then hold in memory until they're returned, so portals which can produce rows incrementally should
+	/// override it.
+	async fn fetch_rows(&mut self, batch: &mut DataRowBatch, _max_rows: usize) -> Result<bool, ErrorResponse> {
+		self.fetch(batch).await?;
+		Ok(false)
+	}
+}
+
+/// A statement prepared by [Engine::prepare], such as a query plan, from which any number of portals can be created.
+pub trait PreparedStatement: Send + Sync {
+	/// Returns the field descriptions of the statement's result.
+	fn fields(&self) -> &[FieldDescription];
+
+	/// Returns the types of the statement's parameters, which are described to clients so that they know how many
+	/// parameters to send and how to encode them. Types given by the client with `Parse` take precedence,
+	/// so engines which can't infer parameter types can leave this empty.
+	fn parameter_types(&self) -> &[DataTypeOid] {
+		&[]
+	}
+}
+
+/// Engines which don't plan statements ahead of time can use the field descriptions as the prepared statement.
+impl PreparedStatement for Vec<FieldDescription> {
+	fn fields(&self) -> &[FieldDescription] {
+		self
+	}
+}
+
+/// The parameter values a portal is created with, as sent by the client with `Bind`.
+/// Simple queries and cursors don't have any parameters.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Parameters {
+	/// The value of each parameter, or `None` for nulls.
+	pub values: Vec<Option<Bytes>>,
+	/// The format of each value, with one code per value.
+	pub format_codes: Vec<FormatCode>,
+}
+
+/// Describes a client's session, as requested by its startup message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionInfo {
+	/// The startup parameters sent by the client, which always include `user`.
+	pub parameters: HashMap<String, String>,
+	/// The protocol version negotiated with the client.
+	pub protocol_version: (i16, i16),
+}
+
+impl SessionInfo {
+	/// Returns the name of the user the client connected as.
+	pub fn user(&self) -> &str {
+		self.parameters.get("user").map(String::as_str).unwrap_or_default()
+	}
+
+	/// Returns the name of the database the client connected to, which defaults to the user name as in Postgres.
+	pub fn database(&self) -> &str {
+		self.parameters
+			.get("database")
+			.map(String::as_str)
+			.unwrap_or(self.user())
+	}
+}
+
+/// Describes why a session ended, see [Engine::on_session_end].
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionEndReason {
+	/// The client ended the session with a `Terminate` message.
+	Terminated,
+	/// The client disconnected without ending the session.
+	Disconnected,
+	/// A fatal error ended the session, which was reported to the client if possible.
+	Error(ErrorResponse),
 }
 
 /// The engine trait is the core of the `convergence` crate, and is responsible for dispatching most SQL operations.
@@ -20,12 +105,623 @@ pub trait Portal: Send + Sync {
 /// Each connection is allocated an [Engine] instance, which it uses to prepare statements, create portals, etc.
 #[async_trait]
 pub trait Engine: Send + Sync + 'static {
+	/// The [PreparedStatement] implementation returned by [Engine::prepare].
+	type PreparedStatementType: PreparedStatement;
+
 	/// The [Portal] implementation used by [Engine::create_portal].
 	type PortalType: Portal;
 
-	/// Prepares a statement, returning a vector of field descriptions for the final statement result.
-	async fn prepare(&mut self, stmt: &Statement) -> Result<Vec<FieldDescription>, ErrorResponse>;
+	/// Parses a query string into statements, using sqlparser's Postgres dialect by default.
+	///
+	/// Engines can override this to use another dialect or parser. Engines which handle query text entirely by
+	/// themselves can reject every query here, so that all of them are passed to [Engine::prepare_raw] instead.
+	fn parse(&self, text: &str) -> Result<Vec<Statement>, ErrorResponse> {
+		Parser::parse_sql(&PostgreSqlDialect {}, text)
+			.map_err(|err| ErrorResponse::error(SqlState::SyntaxError, err.to_string()))
+	}
+
+	/// Prepares a statement, e.g. by planning it. Each statement is prepared once, even if it's executed many times.
+	async fn prepare(&mut self, stmt: &Statement) -> Result<Self::PreparedStatementType, ErrorResponse>;
+
+	/// Creates a new portal for a prepared statement by binding the given parameters to it.
+	async fn create_portal(
+		&mut self,
+		statement: &Self::PreparedStatementType,
+		parameters: &Parameters,
+	) -> Result<Self::PortalType, ErrorResponse>;
+
+	/// Prepares query text which [Engine::parse] rejected, such as vendor extensions or non-SQL commands.
+	///
+	/// Returning a prepared statement accepts the query, which is then run with [Engine::create_portal],
+	/// while returning `None` reports the original parse error to the client. By default, no queries are accepted.
+	async fn prepare_raw(&mut self, text: &str) -> Result<Option<Self::PreparedStatementType>, ErrorResponse> {
+		let _ = text;
+		Ok(None)
+	}
+
+	/// Returns a cache of prepared statements to share with other connections, or `None` to prepare every statement
+	/// on each connection, which is the default.
+	///
+	/// Engines opting in should return the same cache for every connection, and must only do so if their prepared
+	/// statements don't depend on per-session state. See [PlanCache] for details.
+	fn plan_cache(&self) -> Option<Arc<PlanCache<Self::PreparedStatementType>>> {
+		None
+	}
+
+	/// Returns any parameters to report to the client once its session has started, in addition to the connection's
+	/// own, such as `default_transaction_read_only`. By default, there are none.
+	fn parameter_statuses(&self) -> Vec<ParameterStatus> {
+		Vec::new()
+	}
+
+	/// Called once a client's startup message has been accepted, before any queries are run.
+	///
+	/// Returning an error rejects the session, which ends the connection with a fatal error.
+	async fn on_session_start(&mut self, info: &SessionInfo) -> Result<(), ErrorResponse> {
+		let _ = info;
+		Ok(())
+	}
+
+	/// Called when a session ends, whether gracefully or not, if [Engine::on_session_start] accepted it.
+	async fn on_session_end(&mut self, reason: &SessionEndReason) {
+		let _ = reason;
+	}
+
+	/// Called for `DISCARD ALL` and `RESET ALL`, which poolers send between clients, so that the engine can clear
+	/// any per-session state. The connection has already reset its own settings, and for `DISCARD ALL`,
+	/// dropped its prepared statements and portals.
+	async fn reset(&mut self) -> Result<(), ErrorResponse> {
+		Ok(())
+	}
+
+	/// Resolves the function with the OID given by a fast-path [FunctionCall] and invokes it,
+	/// returning its result in the requested format, or `None` for null.
+	///
+	/// By default, no functions exist.
+	async fn call_function(&mut self, call: &FunctionCall) -> Result<Option<Bytes>, ErrorResponse> {
+		Err(ErrorResponse::error(
+			SqlState::UndefinedFunction,
+			format!("function with OID {} does not exist", call.function_oid),
+		))
+	}
+}
+
+/// Wraps an [Engine] to add behaviour around it, similar in spirit to tower's `Layer`.
+///
+/// Layers compose as tuples, where `(A, B)` wraps the engine in `A` and then in `B`, so `B` sees each call first.
+pub trait Layer<E: Engine> {
+	/// The engine produced by wrapping an engine of type `E`.
+	type Engine: Engine;
+
+	/// Wraps the given engine.
+	fn layer(&self, inner: E) -> Self::Engine;
+}
+
+impl<E: Engine, A: Layer<E>, B: Layer<A::Engine>> Layer<E> for (A, B) {
+	type Engine = B::Engine;
+
+	fn layer(&self, inner: E) -> Self::Engine {
+		self.1.layer(self.0.layer(inner))
+	}
+}
+
+/// Adds [EngineExt::with_layer] to every [Engine].
+pub trait EngineExt: Engine + Sized {
+	/// Wraps this engine with the given layer.
+	fn with_layer<L: Layer<Self>>(self, layer: &L) -> L::Engine {
+		layer.layer(self)
+	}
+}
+
+impl<E: Engine> EngineExt for E {}
+
+/// A layer which logs each statement prepared, portal created and fetch made by the engine using the `log` crate.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoggingLayer;
+
+impl<E: Engine> Layer<E> for LoggingLayer {
+	type Engine = LoggingEngine<E>;
+
+	fn layer(&self, inner: E) -> Self::Engine {
+		LoggingEngine { inner }
+	}
+}
+
+/// The engine produced by [LoggingLayer].
+pub struct LoggingEngine<E> {
+	inner: E,
+}
+
+/// The portal used by [LoggingEngine], which logs the number of rows fetched and how long fetching took.
+pub struct LoggingPortal<P> {
+	inner: P,
+}
+
+#[async_trait]
+impl<P: Portal> Portal for LoggingPortal<P> {
+	async fn fetch(&mut self, batch: &mut DataRowBatch) -> Result<(), ErrorResponse> {
+		let (start, rows_before) = (Instant::now(), batch.num_rows());
+		let result = self.inner.fetch(batch).await;
+		match &result {
+			Ok(()) => log::info!(
+				"fetched {} rows in {:?}",
+				batch.num_rows() - rows_before,
+				start.elapsed()
+			),
+			Err(err) => log::warn!("failed to fetch rows: {}", err.message),
+		}
+		result
+	}
+
+	async fn fetch_rows(&mut self, batch: &mut DataRowBatch, max_rows: usize) -> Result<bool, ErrorResponse> {
+		let (start, rows_before) = (Instant::now(), batch.num_rows());
+		let result = self.inner.fetch_rows(batch, max_rows).await;
+		match &result {
+			Ok(_) => log::info!(
+				"fetched {} rows in {:?}",
+				batch.num_rows() - rows_before,
+				start.elapsed()
+			),
+			Err(err) => log::warn!("failed to fetch rows: {}", err.message),
+		}
+		result
+	}
+}
+
+#[async_trait]
+impl<E: Engine> Engine for LoggingEngine<E> {
+	type PreparedStatementType = E::PreparedStatementType;
+	type PortalType = LoggingPortal<E::PortalType>;
+
+	fn parse(&self, text: &str) -> Result<Vec<Statement>, ErrorResponse> {
+		self.inner.parse(text)
+	}
+
+	async fn prepare(&mut self, stmt: &Statement) -> Result<Self::PreparedStatementType, ErrorResponse> {
+		let start = Instant::now();
+		let result = self.inner.prepare(stmt).await;
+		match &result {
+			Ok(_) => log::info!("prepared statement in {:?}: {}", start.elapsed(), stmt),
+			Err(err) => log::warn!("failed to prepare statement: {}: {}", stmt, err.message),
+		}
+		result
+	}
+
+	async fn create_portal(
+		&mut self,
+		statement: &Self::PreparedStatementType,
+		parameters: &Parameters,
+	) -> Result<Self::PortalType, ErrorResponse> {
+		log::debug!("creating portal with {} parameters", parameters.values.len());
+		match self.inner.create_portal(statement, parameters).await {
+			Ok(inner) => Ok(LoggingPortal { inner }),
+			Err(err) => {
+				log::warn!("failed to create portal: {}", err.message);
+				Err(err)
+			}
+		}
+	}
+
+	async fn prepare_raw(&mut self, text: &str) -> Result<Option<Self::PreparedStatementType>, ErrorResponse> {
+		let result = self.inner.prepare_raw(text).await;
+		match &result {
+			Ok(Some(_)) => log::info!("prepared unparsed query: {}", text),
+			Ok(None) => (),
+			Err(err) => log::warn!("failed to prepare unparsed query: {}: {}", text, err.message),
+		}
+		result
+	}
+
+	fn plan_cache(&self) -> Option<Arc<PlanCache<Self::PreparedStatementType>>> {
+		self.inner.plan_cache()
+	}
+
+	fn parameter_statuses(&self) -> Vec<ParameterStatus> {
+		self.inner.parameter_statuses()
+	}
+
+	async fn on_session_start(&mut self, info: &SessionInfo) -> Result<(), ErrorResponse> {
+		log::info!(
+			"session started for user {} on database {}",
+			info.user(),
+			info.database()
+		);
+		self.inner.on_session_start(info).await
+	}
+
+	async fn on_session_end(&mut self, reason: &SessionEndReason) {
+		log::info!("session ended: {:?}", reason);
+		self.inner.on_session_end(reason).await
+	}
+
+	async fn reset(&mut self) -> Result<(), ErrorResponse> {
+		self.inner.reset().await
+	}
+
+	async fn call_function(&mut self, call: &FunctionCall) -> Result<Option<Bytes>, ErrorResponse> {
+		log::info!("calling function with OID {}", call.function_oid);
+		self.inner.call_function(call).await
+	}
+}
+
+fn is_read_only_query(query: &Query) -> bool {
+	fn is_read_only_set_expr(expr: &SetExpr) -> bool {
+		match expr {
+			SetExpr::Select(select) => select.into.is_none(),
+			SetExpr::Query(query) => is_read_only_query(query),
+			SetExpr::SetOperation { left, right, .. } => is_read_only_set_expr(left) && is_read_only_set_expr(right),
+			SetExpr::Values(_) | SetExpr::Table(_) => true,
+			SetExpr::Insert(_) | SetExpr::Update(_) => false,
+		}
+	}
+
+	// row locks and data-modifying CTEs are writes, as are `SELECT INTO`s, which create a table
+	query.locks.is_empty()
+		&& query
+			.with
+			.iter()
+			.flat_map(|with| &with.cte_tables)
+			.all(|cte| is_read_only_query(&cte.query))
+		&& is_read_only_set_expr(&query.body)
+}
+
+/// Returns true if the statement can run in a read-only transaction, i.e. it doesn't modify any data or schema.
+///
+/// Statements which aren't recognised are assumed to modify data.
+pub fn is_read_only(statement: &Statement) -> bool {
+	match statement {
+		Statement::Query(query) => is_read_only_query(query),
+		Statement::Declare { stmts } => stmts
+			.iter()
+			.all(|declare| declare.for_query.as_deref().is_none_or(is_read_only_query)),
+		Statement::Explain { analyze, statement, .. } => !analyze || is_read_only(statement),
+		Statement::Prepare { statement, .. } => is_read_only(statement),
+		Statement::Copy { source, to, .. } => {
+			*to && match source {
+				CopySource::Table { .. } => true,
+				CopySource::Query(query) => is_read_only_query(query),
+			}
+		}
+		Statement::Fetch { .. }
+		| Statement::Close { .. }
+		| Statement::Discard { .. }
+		| Statement::Deallocate { .. }
+		| Statement::ExplainTable { .. }
+		| Statement::ShowFunctions { .. }
+		| Statement::ShowVariable { .. }
+		| Statement::ShowStatus { .. }
+		| Statement::ShowVariables { .. }
+		| Statement::ShowCreate { .. }
+		| Statement::ShowColumns { .. }
+		| Statement::ShowTables { .. }
+		| Statement::ShowCollation { .. }
+		| Statement::SetVariable { .. }
+		| Statement::SetTimeZone { .. }
+		| Statement::SetNames { .. }
+		| Statement::SetNamesDefault {}
+		| Statement::SetRole { .. }
+		| Statement::SetTransaction { .. }
+		| Statement::StartTransaction { .. }
+		| Statement::Commit { .. }
+		| Statement::Rollback { .. }
+		| Statement::Savepoint { .. }
+		| Statement::ReleaseSavepoint { .. } => true,
+		_ => false,
+	}
+}
+
+// matches Postgres' error for writes in read-only transactions, e.g. "cannot execute INSERT in a read-only transaction"
+fn read_only_command_error(command: &str) -> ErrorResponse {
+	ErrorResponse::error(
+		SqlState::ReadOnlySqlTransaction,
+		format!("cannot execute {} in a read-only transaction", command),
+	)
+}
+
+// names the command which makes a query write, as Postgres does, e.g. `INSERT` for a data-modifying CTE
+fn query_command_name(query: &Query) -> String {
+	fn writing_command(expr: &SetExpr) -> Option<String> {
+		match expr {
+			SetExpr::Select(select) if select.into.is_some() => Some("SELECT INTO".to_owned()),
+			SetExpr::Query(query) if !is_read_only_query(query) => Some(query_command_name(query)),
+			SetExpr::SetOperation { left, right, .. } => writing_command(left).or_else(|| writing_command(right)),
+			SetExpr::Insert(statement) | SetExpr::Update(statement) => Some(command_name(statement)),
+			_ => None,
+		}
+	}
+
+	let writing_cte = query
+		.with
+		.iter()
+		.flat_map(|with| &with.cte_tables)
+		.find(|cte| !is_read_only_query(&cte.query));
+	if let Some(cte) = writing_cte {
+		return query_command_name(&cte.query);
+	}
+
+	writing_command(&query.body).unwrap_or_else(|| match query.locks.first() {
+		Some(lock) => format!("SELECT FOR {}", lock.lock_type),
+		None => "SELECT".to_owned(),
+	})
+}
+
+// names the command a statement runs, as Postgres does in its errors for writes in read-only transactions
+fn command_name(statement: &Statement) -> String {
+	let name = match statement {
+		Statement::Query(query) => return query_command_name(query),
+		Statement::Explain { statement, .. } | Statement::Prepare { statement, .. } => return command_name(statement),
+		Statement::Declare { stmts } => {
+			return stmts
+				.iter()
+				.filter_map(|declare| declare.for_query.as_deref())
+				.find(|query| !is_read_only_query(query))
+				.map_or_else(|| "DECLARE CURSOR".to_owned(), query_command_name)
+		}
+		Statement::Copy {
+			source: CopySource::Query(query),
+			..
+		} if !is_read_only_query(query) => return query_command_name(query),
+		Statement::Copy { to: false, .. } => "COPY FROM",
+		Statement::Drop { object_type, .. } => return format!("DROP {}", object_type),
+		Statement::Insert(_) => "INSERT",
+		Statement::Update { .. } => "UPDATE",
+		Statement::Delete(_) => "DELETE",
+		Statement::Merge { .. } => "MERGE",
+		Statement::Truncate { .. } => "TRUNCATE TABLE",
+		Statement::CreateTable { query: Some(_), .. } => "CREATE TABLE AS",
+		Statement::CreateTable { .. } => "CREATE TABLE",
+		Statement::CreateView { materialized: true, .. } => "CREATE MATERIALIZED VIEW",
+		Statement::CreateView { .. } => "CREATE VIEW",
+		Statement::CreateIndex { .. } => "CREATE INDEX",
+		Statement::CreateRole { .. } => "CREATE ROLE",
+		Statement::CreateSchema { .. } => "CREATE SCHEMA",
+		Statement::CreateDatabase { .. } => "CREATE DATABASE",
+		Statement::CreateFunction { .. } => "CREATE FUNCTION",
+		Statement::CreateProcedure { .. } => "CREATE PROCEDURE",
+		Statement::CreateSequence { .. } => "CREATE SEQUENCE",
+		Statement::CreateType { .. } => "CREATE TYPE",
+		Statement::CreateExtension { .. } => "CREATE EXTENSION",
+		Statement::AlterTable { .. } => "ALTER TABLE",
+		Statement::AlterIndex { .. } => "ALTER INDEX",
+		Statement::AlterView { .. } => "ALTER VIEW",
+		Statement::AlterRole { .. } => "ALTER ROLE",
+		Statement::DropFunction { .. } => "DROP FUNCTION",
+		// the remaining commands are single keywords, such as `GRANT`, `COMMENT` or `VACUUM`
+		_ => {
+			let text = statement.to_string();
+			return text.split_whitespace().next().unwrap_or_default().to_owned();
+		}
+	};
+	name.to_owned()
+}
+
+fn read_only_error(statement: &Statement) -> ErrorResponse {
+	read_only_command_error(&command_name(statement))
+}
+
+// unparsed queries can't be classified, so they're never allowed in read-only mode
+fn read_only_unparsed_error() -> ErrorResponse {
+	read_only_command_error("unparsed queries")
+}
+
+// nor can fast-path function calls, which may write data, e.g. `lo_write`
+fn read_only_function_call_error() -> ErrorResponse {
+	read_only_command_error("fast-path function calls")
+}
+
+/// A layer which rejects statements that modify data or schema, see [is_read_only].
+///
+/// Queries which the engine prepares with [Engine::prepare_raw] and fast-path function calls can't be checked,
+/// so they're rejected too. The engine's plan cache isn't used, as it may hold statements cached by engines
+/// without this layer. Clients are told that the session is read-only through the `default_transaction_read_only`
+/// and `transaction_read_only` parameters.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReadOnlyLayer;
+
+impl<E: Engine> Layer<E> for ReadOnlyLayer {
+	type Engine = ReadOnlyEngine<E>;
+
+	fn layer(&self, inner: E) -> Self::Engine {
+		ReadOnlyEngine { inner }
+	}
+}
+
+/// The engine produced by [ReadOnlyLayer].
+pub struct ReadOnlyEngine<E> {
+	inner: E,
+}
+
+#[async_trait]
+impl<E: Engine> Engine for ReadOnlyEngine<E> {
+	type PreparedStatementType = E::PreparedStatementType;
+	type PortalType = E::PortalType;
+
+	fn parse(&self, text: &str) -> Result<Vec<Statement>, ErrorResponse> {
+		self.inner.parse(text)
+	}
+
+	async fn prepare(&mut self, stmt: &Statement) -> Result<Self::PreparedStatementType, ErrorResponse> {
+		if !is_read_only(stmt) {
+			return Err(read_only_error(stmt));
+		}
+		self.inner.prepare(stmt).await
+	}
+
+	async fn create_portal(
+		&mut self,
+		statement: &Self::PreparedStatementType,
+		parameters: &Parameters,
+	) -> Result<Self::PortalType, ErrorResponse> {
+		self.inner.create_portal(statement, parameters).await
+	}
+
+	async fn prepare_raw(&mut self, text: &str) -> Result<Option<Self::PreparedStatementType>, ErrorResponse> {
+		match self.inner.prepare_raw(text).await? {
+			Some(_) => Err(read_only_unparsed_error()),
+			None => Ok(None),
+		}
+	}
+
+	// the inner engine's cache may be shared with connections which aren't read-only, and cache hits are returned
+	// without being prepared, so they'd never be checked
+	fn plan_cache(&self) -> Option<Arc<PlanCache<Self::PreparedStatementType>>> {
+		None
+	}
+
+	// reported as by Postgres when `default_transaction_read_only` is on, so that clients and poolers can tell
+	fn parameter_statuses(&self) -> Vec<ParameterStatus> {
+		let mut statuses = self.inner.parameter_statuses();
+		for param in ["default_transaction_read_only", "transaction_read_only"] {
+			statuses.push(ParameterStatus::new(param, "on"));
+		}
+		statuses
+	}
+
+	async fn on_session_start(&mut self, info: &SessionInfo) -> Result<(), ErrorResponse> {
+		self.inner.on_session_start(info).await
+	}
+
+	async fn on_session_end(&mut self, reason: &SessionEndReason) {
+		self.inner.on_session_end(reason).await
+	}
+
+	async fn reset(&mut self) -> Result<(), ErrorResponse> {
+		self.inner.reset().await
+	}
+
+	async fn call_function(&mut self, _: &FunctionCall) -> Result<Option<Bytes>, ErrorResponse> {
+		Err(read_only_function_call_error())
+	}
+}
+
+/// A layer which rewrites each statement before the engine prepares it, e.g. to inject a tenant filter.
+///
+/// Returning an error from the rewriter rejects the statement. Queries which the engine prepares with
+/// [Engine::prepare_raw] and fast-path function calls never reach the rewriter, so they're rejected too,
+/// unless [RewriteLayer::with_pass_through] opts in to forwarding them unchanged.
+pub struct RewriteLayer<F> {
+	rewrite: Arc<F>,
+	pass_through: bool,
+}
+
+impl<F> RewriteLayer<F>
+where
+	F: Fn(&mut Statement) -> Result<(), ErrorResponse> + Send + Sync + 'static,
+{
+	/// Creates a layer which rewrites statements using the given closure.
+	pub fn new(rewrite: F) -> Self {
+		Self {
+			rewrite: Arc::new(rewrite),
+			pass_through: false,
+		}
+	}
+
+	/// Forwards unparsed queries and fast-path function calls to the engine without rewriting them.
+	///
+	/// This bypasses the rewriter entirely, so it's only safe if the engine enforces the same rules itself.
+	pub fn with_pass_through(mut self) -> Self {
+		self.pass_through = true;
+		self
+	}
+}
+
+impl<F> Clone for RewriteLayer<F> {
+	fn clone(&self) -> Self {
+		Self {
+			rewrite: self.rewrite.clone(),
+			pass_through: self.pass_through,
+		}
+	}
+}
+
+impl<E, F> Layer<E> for RewriteLayer<F>
+where
+	E: Engine,
+	F: Fn(&mut Statement) -> Result<(), ErrorResponse> + Send + Sync + 'static,
+{
+	type Engine = RewriteEngine<E, F>;
+
+	fn layer(&self, inner: E) -> Self::Engine {
+		RewriteEngine {
+			inner,
+			rewrite: self.rewrite.clone(),
+			pass_through: self.pass_through,
+		}
+	}
+}
+
+// statements which bypass the rewriter would also bypass any rules it enforces
+fn not_rewritable_error(command: &str) -> ErrorResponse {
+	ErrorResponse::error(SqlState::FeatureNotSupported, format!("cannot rewrite {}", command))
+}
+
+/// The engine produced by [RewriteLayer].
+pub struct RewriteEngine<E, F> {
+	inner: E,
+	rewrite: Arc<F>,
+	pass_through: bool,
+}
+
+#[async_trait]
+impl<E, F> Engine for RewriteEngine<E, F>
+where
+	E: Engine,
+	F: Fn(&mut Statement) -> Result<(), ErrorResponse> + Send + Sync + 'static,
+{
+	type PreparedStatementType = E::PreparedStatementType;
+	type PortalType = E::PortalType;
+
+	fn parse(&self, text: &str) -> Result<Vec<Statement>, ErrorResponse> {
+		self.inner.parse(text)
+	}
+
+	async fn prepare(&mut self, stmt: &Statement) -> Result<Self::PreparedStatementType, ErrorResponse> {
+		let mut stmt = stmt.clone();
+		(self.rewrite)(&mut stmt)?;
+		self.inner.prepare(&stmt).await
+	}
+
+	async fn create_portal(
+		&mut self,
+		statement: &Self::PreparedStatementType,
+		parameters: &Parameters,
+	) -> Result<Self::PortalType, ErrorResponse> {
+		self.inner.create_portal(statement, parameters).await
+	}
+
+	async fn prepare_raw(&mut self, text: &str) -> Result<Option<Self::PreparedStatementType>, ErrorResponse> {
+		match self.inner.prepare_raw(text).await? {
+			Some(_) if !self.pass_through => Err(not_rewritable_error("unparsed queries")),
+			statement => Ok(statement),
+		}
+	}
+
+	// rewriters may depend on the connection, e.g. to filter by the session's tenant, so statements
+	// can't be shared with other connections
+	fn plan_cache(&self) -> Option<Arc<PlanCache<Self::PreparedStatementType>>> {
+		None
+	}
+
+	fn parameter_statuses(&self) -> Vec<ParameterStatus> {
+		self.inner.parameter_statuses()
+	}
+
+	async fn on_session_start(&mut self, info: &SessionInfo) -> Result<(), ErrorResponse> {
+		self.inner.on_session_start(info).await
+	}
+
+	async fn on_session_end(&mut self, reason: &SessionEndReason) {
+		self.inner.on_session_end(reason).await
+	}
+
+	async fn reset(&mut self) -> Result<(), ErrorResponse> {
+		self.inner.reset().await
+	}
 
-	/// Creates a new portal for the given statement.
-	async fn create_portal(&mut self, stmt: &Statement) -> Result<Self::PortalType, ErrorResponse>;
+	async fn call_function(&mut self, call: &FunctionCall) -> Result<Option<Bytes>, ErrorResponse> {
+		if !self.pass_through {
+			return Err(not_rewritable_error("fast-path function calls"));
+		}
+		self.inner.call_function(call).await
+	}
 }
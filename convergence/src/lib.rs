@@ -4,8 +4,15 @@
 
 pub mod connection;
 pub mod engine;
+pub mod plan_cache;
 pub mod protocol;
 pub mod protocol_ext;
+pub mod router;
 pub mod server;
+pub mod settings;
 
 pub use sqlparser;
+
+/// Derives [protocol_ext::PgRow] for structs, see the `convergence-derive` crate for details.
+#[cfg(feature = "derive")]
+pub use convergence_derive::PgRow;
@@ -0,0 +1,243 @@
+//! Contains [PlanCache], a server-wide cache of prepared statements which engines can opt in to.
+
+use crate::protocol::DataTypeOid;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Identifies a cached statement by its normalised query text and the parameter types given by the client.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PlanCacheKey {
+	query: String,
+	parameter_types: Vec<DataTypeOid>,
+}
+
+impl PlanCacheKey {
+	/// Creates a key for the given query text, which is normalised so that queries differing only in whitespace
+	/// outside of literals, identifiers and comments, or in trailing semicolons, share a key.
+	pub fn new(query: &str, parameter_types: &[DataTypeOid]) -> Self {
+		Self {
+			query: normalize_query(query),
+			parameter_types: parameter_types.to_vec(),
+		}
+	}
+
+	/// Returns the normalised query text.
+	pub fn query(&self) -> &str {
+		&self.query
+	}
+
+	/// Returns the parameter types given by the client, which are empty for simple queries.
+	pub fn parameter_types(&self) -> &[DataTypeOid] {
+		&self.parameter_types
+	}
+}
+
+#[derive(Clone, Copy)]
+enum Lexeme {
+	Code,
+	Quoted(char),
+	LineComment,
+	BlockComment(usize),
+}
+
+// collapses whitespace between tokens, leaving the contents of literals, quoted identifiers and comments untouched.
+// Dollar-quoted and backslash-escaped strings can't be delimited without a full lexer, so queries which may
+// contain them are left as they are.
+fn normalize_query(query: &str) -> String {
+	let query = query.trim().trim_end_matches(';').trim_end();
+	if query.contains('\\') {
+		return query.to_owned();
+	}
+
+	let mut normalized = String::with_capacity(query.len());
+	let mut lexeme = Lexeme::Code;
+	let mut pending_space = false;
+	let mut chars = query.chars().peekable();
+	while let Some(c) = chars.next() {
+		let next = chars.peek().copied();
+		// two-character delimiters are consumed together, so that e.g. `/*/` doesn't end the comment it starts
+		let mut delimiter = |normalized: &mut String| {
+			normalized.push(c);
+			normalized.extend(chars.next());
+		};
+
+		match lexeme {
+			Lexeme::Code if c.is_whitespace() => pending_space = true,
+			Lexeme::Code => {
+				if pending_space {
+					normalized.push(' ');
+					pending_space = false;
+				}
+
+				match (c, next) {
+					('\'', _) | ('"', _) => {
+						lexeme = Lexeme::Quoted(c);
+						normalized.push(c);
+					}
+					('-', Some('-')) => {
+						lexeme = Lexeme::LineComment;
+						delimiter(&mut normalized);
+					}
+					('/', Some('*')) => {
+						lexeme = Lexeme::BlockComment(1);
+						delimiter(&mut normalized);
+					}
+					// positional parameters are fine, but anything else may start a dollar-quoted string
+					('$', next) if !next.is_some_and(|next| next.is_ascii_digit()) => return query.to_owned(),
+					_ => normalized.push(c),
+				}
+			}
+			Lexeme::Quoted(quote) => {
+				if c == quote {
+					lexeme = Lexeme::Code;
+				}
+				normalized.push(c);
+			}
+			Lexeme::LineComment => {
+				if c == '\n' {
+					lexeme = Lexeme::Code;
+				}
+				normalized.push(c);
+			}
+			// block comments nest, as in Postgres
+			Lexeme::BlockComment(depth) => match (c, next) {
+				('*', Some('/')) => {
+					lexeme = match depth {
+						1 => Lexeme::Code,
+						depth => Lexeme::BlockComment(depth - 1),
+					};
+					delimiter(&mut normalized);
+				}
+				('/', Some('*')) => {
+					lexeme = Lexeme::BlockComment(depth + 1);
+					delimiter(&mut normalized);
+				}
+				_ => normalized.push(c),
+			},
+		}
+	}
+
+	normalized
+}
+
+struct Entry<S> {
+	statement: Arc<S>,
+	last_used: u64,
+}
+
+struct Entries<S> {
+	entries: HashMap<PlanCacheKey, Entry<S>>,
+	// the key of each entry by its last access, so that the least recently used entry is the first
+	recency: BTreeMap<u64, PlanCacheKey>,
+	// incremented on every access, so that the least recently used entry has the lowest timestamp
+	clock: u64,
+}
+
+impl<S> Entries<S> {
+	fn tick(&mut self) -> u64 {
+		self.clock += 1;
+		self.clock
+	}
+}
+
+/// A cache of prepared statements shared by every connection to a server, so that pooled clients which prepare the
+/// same queries on each connection only have them parsed and planned once.
+///
+/// Engines opt in by returning the same cache from [Engine::plan_cache](crate::engine::Engine::plan_cache) for every
+/// connection, which means their prepared statements must not depend on any per-session state. Once the cache is
+/// full, the least recently used statement is evicted. Engines should call [PlanCache::invalidate] or
+/// [PlanCache::clear] when a schema change makes cached statements stale.
+pub struct PlanCache<S> {
+	capacity: usize,
+	entries: Mutex<Entries<S>>,
+}
+
+impl<S> PlanCache<S> {
+	/// Creates an empty cache which holds up to `capacity` statements.
+	pub fn new(capacity: usize) -> Self {
+		Self {
+			capacity,
+			entries: Mutex::new(Entries {
+				entries: HashMap::new(),
+				recency: BTreeMap::new(),
+				clock: 0,
+			}),
+		}
+	}
+
+	// a panicking invalidation predicate poisons the lock, but it can't leave the entries in an inconsistent state
+	fn lock(&self) -> MutexGuard<'_, Entries<S>> {
+		self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+
+	/// Returns the statement cached for the given key, if any, marking it as recently used.
+	pub fn get(&self, key: &PlanCacheKey) -> Option<Arc<S>> {
+		let mut entries = self.lock();
+		let clock = entries.tick();
+		let Entries { entries, recency, .. } = &mut *entries;
+
+		entries.get_mut(key).map(|entry| {
+			recency.remove(&entry.last_used);
+			recency.insert(clock, key.clone());
+			entry.last_used = clock;
+			entry.statement.clone()
+		})
+	}
+
+	/// Caches a statement, evicting the least recently used statement if the cache is full.
+	pub fn insert(&self, key: PlanCacheKey, statement: Arc<S>) {
+		if self.capacity == 0 {
+			return;
+		}
+
+		let mut entries = self.lock();
+		let last_used = entries.tick();
+		let Entries { entries, recency, .. } = &mut *entries;
+
+		match entries.get(&key) {
+			Some(entry) => {
+				recency.remove(&entry.last_used);
+			}
+			None if entries.len() >= self.capacity => {
+				if let Some((_, oldest)) = recency.pop_first() {
+					entries.remove(&oldest);
+				}
+			}
+			None => (),
+		}
+
+		recency.insert(last_used, key.clone());
+		entries.insert(key, Entry { statement, last_used });
+	}
+
+	/// Removes every cached statement for which the predicate returns true,
+	/// e.g. those which reference a table that has been altered.
+	pub fn invalidate(&self, mut predicate: impl FnMut(&PlanCacheKey, &S) -> bool) {
+		let mut entries = self.lock();
+		let Entries { entries, recency, .. } = &mut *entries;
+		entries.retain(|key, entry| {
+			let invalid = predicate(key, &entry.statement);
+			if invalid {
+				recency.remove(&entry.last_used);
+			}
+			!invalid
+		});
+	}
+
+	/// Removes every cached statement.
+	pub fn clear(&self) {
+		let mut entries = self.lock();
+		entries.entries.clear();
+		entries.recency.clear();
+	}
+
+	/// Returns the number of cached statements.
+	pub fn len(&self) -> usize {
+		self.lock().entries.len()
+	}
+
+	/// Returns true if no statements are cached.
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+}
@@ -4,7 +4,7 @@
 // may want to build this automatically from Postgres docs if possible
 #![allow(missing_docs)]
 
-use bytes::{Buf, BufMut, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use std::convert::TryFrom;
 use std::fmt::Display;
 use std::mem::size_of;
@@ -12,13 +12,17 @@ use std::{collections::HashMap, convert::TryInto};
 use tokio_util::codec::{Decoder, Encoder};
 
 macro_rules! data_types {
-	($($name:ident = $oid:expr, $size: expr)*) => {
-		#[derive(Debug, Copy, Clone)]
+	($($name:ident = $oid:expr, $size: expr $(, $array_name:ident = $array_oid:expr)?)*) => {
+		#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 		/// Describes a Postgres data type.
 		pub enum DataTypeOid {
 			$(
 				#[allow(missing_docs)]
 				$name,
+				$(
+					#[allow(missing_docs)]
+					$array_name,
+				)?
 			)*
 			/// A type which is not known to this crate.
 			Unknown(u32),
@@ -26,13 +30,36 @@ macro_rules! data_types {
 
 		impl DataTypeOid {
 			/// Fetch the size in bytes for this data type.
-			/// Variably-sized types return -1.
+			/// Variably-sized types (including arrays and unknown types) return -1.
 			pub fn size_bytes(&self) -> i16 {
 				match self {
 					$(
 						Self::$name => $size,
+						$(
+							Self::$array_name => -1,
+						)?
 					)*
-					Self::Unknown(_) => unimplemented!(),
+					Self::Unknown(_) => -1,
+				}
+			}
+
+			/// Fetch the element type if this is an array type.
+			pub fn element_type(&self) -> Option<DataTypeOid> {
+				match self {
+					$($(
+						Self::$array_name => Some(Self::$name),
+					)?)*
+					_ => None,
+				}
+			}
+
+			/// Fetch the array type which has this type as its element type, if known.
+			pub fn array_type(&self) -> Option<DataTypeOid> {
+				match self {
+					$($(
+						Self::$name => Some(Self::$array_name),
+					)?)*
+					_ => None,
 				}
 			}
 		}
@@ -42,6 +69,9 @@ macro_rules! data_types {
 				match value {
 					$(
 						$oid => Self::$name,
+						$(
+							$array_oid => Self::$array_name,
+						)?
 					)*
 					other => Self::Unknown(other),
 				}
@@ -53,6 +83,9 @@ macro_rules! data_types {
 				match value {
 					$(
 						DataTypeOid::$name => $oid,
+						$(
+							DataTypeOid::$array_name => $array_oid,
+						)?
 					)*
 					DataTypeOid::Unknown(other) => other,
 				}
@@ -66,23 +99,42 @@ macro_rules! data_types {
 data_types! {
 	Unspecified = 0, 0
 
-	Bool = 16, 1
+	Bool = 16, 1, BoolArray = 1000
 
-	Int2 = 21, 2
-	Int4 = 23, 4
-	Int8 = 20, 8
+	Int2 = 21, 2, Int2Array = 1005
+	Int4 = 23, 4, Int4Array = 1007
+	Int8 = 20, 8, Int8Array = 1016
 
-	Float4 = 700, 4
-	Float8 = 701, 8
+	Float4 = 700, 4, Float4Array = 1021
+	Float8 = 701, 8, Float8Array = 1022
 
-	Date = 1082, 4
-	Timestamp = 1114, 8
+	Numeric = 1700, -1, NumericArray = 1231
 
-	Text = 25, -1
+	Oid = 26, 4, OidArray = 1028
+
+	Date = 1082, 4, DateArray = 1182
+	Time = 1083, 8, TimeArray = 1183
+	Timetz = 1266, 12, TimetzArray = 1270
+	Timestamp = 1114, 8, TimestampArray = 1115
+	Timestamptz = 1184, 8, TimestamptzArray = 1185
+	Interval = 1186, 16, IntervalArray = 1187
+
+	Text = 25, -1, TextArray = 1009
+	Varchar = 1043, -1, VarcharArray = 1015
+	Bpchar = 1042, -1, BpcharArray = 1014
+	Name = 19, 64, NameArray = 1003
+
+	Bytea = 17, -1, ByteaArray = 1001
+	Uuid = 2950, 16, UuidArray = 2951
+	Json = 114, -1, JsonArray = 199
+	Jsonb = 3802, -1, JsonbArray = 3807
+	Inet = 869, -1, InetArray = 1041
+
+	Record = 2249, -1, RecordArray = 2287
 }
 
 /// Describes how to format a given value or set of values.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum FormatCode {
 	/// Use the stable text representation.
 	Text = 0,
@@ -102,54 +154,139 @@ impl TryFrom<i16> for FormatCode {
 	}
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Startup {
 	pub requested_protocol_version: (i16, i16),
 	pub parameters: HashMap<String, String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Describe {
 	Portal(String),
 	PreparedStatement(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Close {
+	Portal(String),
+	PreparedStatement(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Parse {
 	pub prepared_statement_name: String,
 	pub query: String,
 	pub parameter_types: Vec<DataTypeOid>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum BindFormat {
 	All(FormatCode),
 	PerColumn(Vec<FormatCode>),
 }
 
-#[derive(Debug)]
+impl BindFormat {
+	/// Expands these format codes into one code for each of a result's columns.
+	pub fn format_codes(&self, num_cols: usize) -> Result<Vec<FormatCode>, ErrorResponse> {
+		self.expand(num_cols, |num_codes| {
+			format!(
+				"bind message has {} result formats but query has {} columns",
+				num_codes, num_cols
+			)
+		})
+	}
+
+	// expands these format codes into one code for each of `count` values,
+	// describing a mismatch given the number of codes sent
+	fn expand(&self, count: usize, mismatch: impl FnOnce(usize) -> String) -> Result<Vec<FormatCode>, ErrorResponse> {
+		match self {
+			Self::All(format_code) => Ok(vec![*format_code; count]),
+			Self::PerColumn(format_codes) if format_codes.len() == count => Ok(format_codes.clone()),
+			Self::PerColumn(format_codes) => Err(ErrorResponse::error(
+				SqlState::ProtocolViolation,
+				mismatch(format_codes.len()),
+			)),
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Bind {
 	pub portal: String,
 	pub prepared_statement_name: String,
+	pub parameter_format: BindFormat,
+	/// The value of each parameter, in the format given by `parameter_format`, or `None` for nulls.
+	pub parameters: Vec<Option<Bytes>>,
 	pub result_format: BindFormat,
 }
 
-#[derive(Debug)]
+impl Bind {
+	/// Expands the parameter format codes into one code for each parameter,
+	/// which can be decoded with [FromPgValue](crate::protocol_ext::FromPgValue).
+	pub fn parameter_formats(&self) -> Result<Vec<FormatCode>, ErrorResponse> {
+		let num_parameters = self.parameters.len();
+		self.parameter_format.expand(num_parameters, |num_codes| {
+			format!(
+				"bind message has {} parameter formats but {} parameters",
+				num_codes, num_parameters
+			)
+		})
+	}
+}
+
+/// Calls a function by its OID rather than through a query, using the legacy fast-path interface.
+/// Still used by some drivers, e.g. for large objects.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionCall {
+	pub function_oid: u32,
+	pub argument_format: BindFormat,
+	/// The value of each argument, in the format given by `argument_format`, or `None` for nulls.
+	pub arguments: Vec<Option<Bytes>>,
+	pub result_format: FormatCode,
+}
+
+impl FunctionCall {
+	/// Expands the argument format codes into one code for each argument,
+	/// which can be decoded with [FromPgValue](crate::protocol_ext::FromPgValue).
+	pub fn argument_formats(&self) -> Result<Vec<FormatCode>, ErrorResponse> {
+		let num_arguments = self.arguments.len();
+		self.argument_format.expand(num_arguments, |num_codes| {
+			format!(
+				"function call message has {} argument formats but {} arguments",
+				num_codes, num_arguments
+			)
+		})
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Execute {
 	pub portal: String,
 	pub max_rows: Option<i32>,
 }
 
-#[derive(Debug)]
+/// Asks the server to cancel the query running on another connection, identified by its [BackendKeyData].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CancelRequest {
+	pub process_id: i32,
+	pub secret_key: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum ClientMessage {
-	SSLRequest, // for SSL negotiation
+	SSLRequest,    // for SSL negotiation
+	GSSENCRequest, // for GSSAPI encryption negotiation
+	CancelRequest(CancelRequest),
 	Startup(Startup),
 	Parse(Parse),
 	Describe(Describe),
 	Bind(Bind),
+	Close(Close),
+	Flush,
 	Sync,
 	Execute(Execute),
 	Query(String),
+	FunctionCall(FunctionCall),
 	Terminate,
 }
 
@@ -159,55 +296,520 @@ pub trait BackendMessage: std::fmt::Debug {
 	fn encode(&self, dst: &mut BytesMut);
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum SqlState {
-	SuccessfulCompletion,
-	FeatureNotSupported,
-	InvalidCursorName,
-	ConnectionException,
-	InvalidSQLStatementName,
-	DataException,
-	ProtocolViolation,
-	SyntaxError,
-	InvalidDatetimeFormat,
+macro_rules! sql_states {
+	($($name:ident = $code:literal)*) => {
+		/// Describes a Postgres error code (SQLSTATE).
+		#[derive(Debug, Clone, PartialEq, Eq)]
+		pub enum SqlState {
+			$(
+				#[allow(missing_docs)]
+				$name,
+			)*
+			/// An error code which is not known to this crate.
+			Custom(String),
+		}
+
+		impl SqlState {
+			/// Fetch the five-character code for this state.
+			pub fn code(&self) -> &str {
+				match self {
+					$(
+						Self::$name => $code,
+					)*
+					Self::Custom(code) => code,
+				}
+			}
+
+			/// Looks up a state by its five-character code, falling back to [SqlState::Custom] for unknown codes.
+			pub fn from_code(code: &str) -> Self {
+				match code {
+					$(
+						$code => Self::$name,
+					)*
+					other => Self::Custom(other.to_owned()),
+				}
+			}
+		}
+	};
+}
+
+// For codes and classes see:
+// https://github.com/postgres/postgres/blob/master/src/backend/utils/errcodes.txt
+sql_states! {
+	// Class 00 - Successful Completion
+	SuccessfulCompletion = "00000"
+
+	// Class 01 - Warning
+	Warning = "01000"
+	WarningDynamicResultSetsReturned = "0100C"
+	WarningImplicitZeroBitPadding = "01008"
+	WarningNullValueEliminatedInSetFunction = "01003"
+	WarningPrivilegeNotGranted = "01007"
+	WarningPrivilegeNotRevoked = "01006"
+	WarningStringDataRightTruncation = "01004"
+	WarningDeprecatedFeature = "01P01"
+
+	// Class 02 - No Data
+	NoData = "02000"
+	NoAdditionalDynamicResultSetsReturned = "02001"
+
+	// Class 03 - SQL Statement Not Yet Complete
+	SqlStatementNotYetComplete = "03000"
+
+	// Class 08 - Connection Exception
+	ConnectionException = "08000"
+	ConnectionDoesNotExist = "08003"
+	ConnectionFailure = "08006"
+	SqlclientUnableToEstablishSqlconnection = "08001"
+	SqlserverRejectedEstablishmentOfSqlconnection = "08004"
+	TransactionResolutionUnknown = "08007"
+	ProtocolViolation = "08P01"
+
+	// Class 09 - Triggered Action Exception
+	TriggeredActionException = "09000"
+
+	// Class 0A - Feature Not Supported
+	FeatureNotSupported = "0A000"
+
+	// Class 0B - Invalid Transaction Initiation
+	InvalidTransactionInitiation = "0B000"
+
+	// Class 0F - Locator Exception
+	LocatorException = "0F000"
+	InvalidLocatorSpecification = "0F001"
+
+	// Class 0L - Invalid Grantor
+	InvalidGrantor = "0L000"
+	InvalidGrantOperation = "0LP01"
+
+	// Class 0P - Invalid Role Specification
+	InvalidRoleSpecification = "0P000"
+
+	// Class 0Z - Diagnostics Exception
+	DiagnosticsException = "0Z000"
+	StackedDiagnosticsAccessedWithoutActiveHandler = "0Z002"
+
+	// Class 20 - Case Not Found
+	CaseNotFound = "20000"
+
+	// Class 21 - Cardinality Violation
+	CardinalityViolation = "21000"
+
+	// Class 22 - Data Exception
+	DataException = "22000"
+	ArraySubscriptError = "2202E"
+	CharacterNotInRepertoire = "22021"
+	DatetimeFieldOverflow = "22008"
+	DivisionByZero = "22012"
+	ErrorInAssignment = "22005"
+	EscapeCharacterConflict = "2200B"
+	IndicatorOverflow = "22022"
+	IntervalFieldOverflow = "22015"
+	InvalidArgumentForLog = "2201E"
+	InvalidArgumentForNtile = "22014"
+	InvalidArgumentForNthValue = "22016"
+	InvalidArgumentForPowerFunction = "2201F"
+	InvalidArgumentForWidthBucketFunction = "2201G"
+	InvalidCharacterValueForCast = "22018"
+	InvalidDatetimeFormat = "22007"
+	InvalidEscapeCharacter = "22019"
+	InvalidEscapeOctet = "2200D"
+	InvalidEscapeSequence = "22025"
+	NonstandardUseOfEscapeCharacter = "22P06"
+	InvalidIndicatorParameterValue = "22010"
+	InvalidParameterValue = "22023"
+	InvalidPrecedingOrFollowingSize = "22013"
+	InvalidRegularExpression = "2201B"
+	InvalidRowCountInLimitClause = "2201W"
+	InvalidRowCountInResultOffsetClause = "2201X"
+	InvalidTablesampleArgument = "2202H"
+	InvalidTablesampleRepeat = "2202G"
+	InvalidTimeZoneDisplacementValue = "22009"
+	InvalidUseOfEscapeCharacter = "2200C"
+	MostSpecificTypeMismatch = "2200G"
+	NullValueNotAllowed = "22004"
+	NullValueNoIndicatorParameter = "22002"
+	NumericValueOutOfRange = "22003"
+	SequenceGeneratorLimitExceeded = "2200H"
+	StringDataLengthMismatch = "22026"
+	StringDataRightTruncation = "22001"
+	SubstringError = "22011"
+	TrimError = "22027"
+	UnterminatedCString = "22024"
+	ZeroLengthCharacterString = "2200F"
+	FloatingPointException = "22P01"
+	InvalidTextRepresentation = "22P02"
+	InvalidBinaryRepresentation = "22P03"
+	BadCopyFileFormat = "22P04"
+	UntranslatableCharacter = "22P05"
+	NotAnXmlDocument = "2200L"
+	InvalidXmlDocument = "2200M"
+	InvalidXmlContent = "2200N"
+	InvalidXmlComment = "2200S"
+	InvalidXmlProcessingInstruction = "2200T"
+	DuplicateJsonObjectKeyValue = "22030"
+	InvalidArgumentForSqlJsonDatetimeFunction = "22031"
+	InvalidJsonText = "22032"
+	InvalidSqlJsonSubscript = "22033"
+	MoreThanOneSqlJsonItem = "22034"
+	NoSqlJsonItem = "22035"
+	NonNumericSqlJsonItem = "22036"
+	NonUniqueKeysInAJsonObject = "22037"
+	SingletonSqlJsonItemRequired = "22038"
+	SqlJsonArrayNotFound = "22039"
+	SqlJsonMemberNotFound = "2203A"
+	SqlJsonNumberNotFound = "2203B"
+	SqlJsonObjectNotFound = "2203C"
+	TooManyJsonArrayElements = "2203D"
+	TooManyJsonObjectMembers = "2203E"
+	SqlJsonScalarRequired = "2203F"
+	SqlJsonItemCannotBeCastToTargetType = "2203G"
+
+	// Class 23 - Integrity Constraint Violation
+	IntegrityConstraintViolation = "23000"
+	RestrictViolation = "23001"
+	NotNullViolation = "23502"
+	ForeignKeyViolation = "23503"
+	UniqueViolation = "23505"
+	CheckViolation = "23514"
+	ExclusionViolation = "23P01"
+
+	// Class 24 - Invalid Cursor State
+	InvalidCursorState = "24000"
+
+	// Class 25 - Invalid Transaction State
+	InvalidTransactionState = "25000"
+	ActiveSqlTransaction = "25001"
+	BranchTransactionAlreadyActive = "25002"
+	HeldCursorRequiresSameIsolationLevel = "25008"
+	InappropriateAccessModeForBranchTransaction = "25003"
+	InappropriateIsolationLevelForBranchTransaction = "25004"
+	NoActiveSqlTransactionForBranchTransaction = "25005"
+	ReadOnlySqlTransaction = "25006"
+	SchemaAndDataStatementMixingNotSupported = "25007"
+	NoActiveSqlTransaction = "25P01"
+	InFailedSqlTransaction = "25P02"
+	IdleInTransactionSessionTimeout = "25P03"
+	TransactionTimeout = "25P04"
+
+	// Class 26 - Invalid SQL Statement Name
+	InvalidSQLStatementName = "26000"
+
+	// Class 27 - Triggered Data Change Violation
+	TriggeredDataChangeViolation = "27000"
+
+	// Class 28 - Invalid Authorization Specification
+	InvalidAuthorizationSpecification = "28000"
+	InvalidPassword = "28P01"
+
+	// Class 2B - Dependent Privilege Descriptors Still Exist
+	DependentPrivilegeDescriptorsStillExist = "2B000"
+	DependentObjectsStillExist = "2BP01"
+
+	// Class 2D - Invalid Transaction Termination
+	InvalidTransactionTermination = "2D000"
+
+	// Class 2F - SQL Routine Exception
+	SqlRoutineException = "2F000"
+	SreFunctionExecutedNoReturnStatement = "2F005"
+	SreModifyingSqlDataNotPermitted = "2F002"
+	SreProhibitedSqlStatementAttempted = "2F003"
+	SreReadingSqlDataNotPermitted = "2F004"
+
+	// Class 34 - Invalid Cursor Name
+	InvalidCursorName = "34000"
+
+	// Class 38 - External Routine Exception
+	ExternalRoutineException = "38000"
+	EreContainingSqlNotPermitted = "38001"
+	EreModifyingSqlDataNotPermitted = "38002"
+	EreProhibitedSqlStatementAttempted = "38003"
+	EreReadingSqlDataNotPermitted = "38004"
+
+	// Class 39 - External Routine Invocation Exception
+	ExternalRoutineInvocationException = "39000"
+	ErieInvalidSqlstateReturned = "39001"
+	ErieNullValueNotAllowed = "39004"
+	ErieTriggerProtocolViolated = "39P01"
+	ErieSrfProtocolViolated = "39P02"
+	ErieEventTriggerProtocolViolated = "39P03"
+
+	// Class 3B - Savepoint Exception
+	SavepointException = "3B000"
+	InvalidSavepointSpecification = "3B001"
+
+	// Class 3D - Invalid Catalog Name
+	InvalidCatalogName = "3D000"
+
+	// Class 3F - Invalid Schema Name
+	InvalidSchemaName = "3F000"
+
+	// Class 40 - Transaction Rollback
+	TransactionRollback = "40000"
+	TransactionIntegrityConstraintViolation = "40002"
+	SerializationFailure = "40001"
+	StatementCompletionUnknown = "40003"
+	DeadlockDetected = "40P01"
+
+	// Class 42 - Syntax Error or Access Rule Violation
+	SyntaxErrorOrAccessRuleViolation = "42000"
+	SyntaxError = "42601"
+	InsufficientPrivilege = "42501"
+	CannotCoerce = "42846"
+	GroupingError = "42803"
+	WindowingError = "42P20"
+	InvalidRecursion = "42P19"
+	InvalidForeignKey = "42830"
+	InvalidName = "42602"
+	NameTooLong = "42622"
+	ReservedName = "42939"
+	DatatypeMismatch = "42804"
+	IndeterminateDatatype = "42P18"
+	CollationMismatch = "42P21"
+	IndeterminateCollation = "42P22"
+	WrongObjectType = "42809"
+	GeneratedAlways = "428C9"
+	UndefinedColumn = "42703"
+	UndefinedFunction = "42883"
+	UndefinedTable = "42P01"
+	UndefinedParameter = "42P02"
+	UndefinedObject = "42704"
+	DuplicateColumn = "42701"
+	DuplicateCursor = "42P03"
+	DuplicateDatabase = "42P04"
+	DuplicateFunction = "42723"
+	DuplicatePstatement = "42P05"
+	DuplicateSchema = "42P06"
+	DuplicateTable = "42P07"
+	DuplicateAlias = "42712"
+	DuplicateObject = "42710"
+	AmbiguousColumn = "42702"
+	AmbiguousFunction = "42725"
+	AmbiguousParameter = "42P08"
+	AmbiguousAlias = "42P09"
+	InvalidColumnReference = "42P10"
+	InvalidColumnDefinition = "42611"
+	InvalidCursorDefinition = "42P11"
+	InvalidDatabaseDefinition = "42P12"
+	InvalidFunctionDefinition = "42P13"
+	InvalidPstatementDefinition = "42P14"
+	InvalidSchemaDefinition = "42P15"
+	InvalidTableDefinition = "42P16"
+	InvalidObjectDefinition = "42P17"
+
+	// Class 44 - WITH CHECK OPTION Violation
+	WithCheckOptionViolation = "44000"
+
+	// Class 53 - Insufficient Resources
+	InsufficientResources = "53000"
+	DiskFull = "53100"
+	OutOfMemory = "53200"
+	TooManyConnections = "53300"
+	ConfigurationLimitExceeded = "53400"
+
+	// Class 54 - Program Limit Exceeded
+	ProgramLimitExceeded = "54000"
+	StatementTooComplex = "54001"
+	TooManyColumns = "54011"
+	TooManyArguments = "54023"
+
+	// Class 55 - Object Not In Prerequisite State
+	ObjectNotInPrerequisiteState = "55000"
+	ObjectInUse = "55006"
+	CantChangeRuntimeParam = "55P02"
+	LockNotAvailable = "55P03"
+	UnsafeNewEnumValueUsage = "55P04"
+
+	// Class 57 - Operator Intervention
+	OperatorIntervention = "57000"
+	QueryCanceled = "57014"
+	AdminShutdown = "57P01"
+	CrashShutdown = "57P02"
+	CannotConnectNow = "57P03"
+	DatabaseDropped = "57P04"
+	IdleSessionTimeout = "57P05"
+
+	// Class 58 - System Error
+	SystemError = "58000"
+	IoError = "58030"
+	UndefinedFile = "58P01"
+	DuplicateFile = "58P02"
+
+	// Class 72 - Snapshot Failure
+	SnapshotTooOld = "72000"
+
+	// Class F0 - Configuration File Error
+	ConfigFileError = "F0000"
+	LockFileExists = "F0001"
+
+	// Class HV - Foreign Data Wrapper Error
+	FdwError = "HV000"
+	FdwColumnNameNotFound = "HV005"
+	FdwDynamicParameterValueNeeded = "HV002"
+	FdwFunctionSequenceError = "HV010"
+	FdwInconsistentDescriptorInformation = "HV021"
+	FdwInvalidAttributeValue = "HV024"
+	FdwInvalidColumnName = "HV007"
+	FdwInvalidColumnNumber = "HV008"
+	FdwInvalidDataType = "HV004"
+	FdwInvalidDataTypeDescriptors = "HV006"
+	FdwInvalidDescriptorFieldIdentifier = "HV091"
+	FdwInvalidHandle = "HV00B"
+	FdwInvalidOptionIndex = "HV00C"
+	FdwInvalidOptionName = "HV00D"
+	FdwInvalidStringLengthOrBufferLength = "HV090"
+	FdwInvalidStringFormat = "HV00A"
+	FdwInvalidUseOfNullPointer = "HV009"
+	FdwTooManyHandles = "HV014"
+	FdwOutOfMemory = "HV001"
+	FdwNoSchemas = "HV00P"
+	FdwOptionNameNotFound = "HV00J"
+	FdwReplyHandle = "HV00K"
+	FdwSchemaNotFound = "HV00Q"
+	FdwTableNotFound = "HV00R"
+	FdwUnableToCreateExecution = "HV00L"
+	FdwUnableToCreateReply = "HV00M"
+	FdwUnableToEstablishConnection = "HV00N"
+
+	// Class P0 - PL/pgSQL Error
+	PlpgsqlError = "P0000"
+	RaiseException = "P0001"
+	NoDataFound = "P0002"
+	TooManyRows = "P0003"
+	AssertFailure = "P0004"
+
+	// Class XX - Internal Error
+	InternalError = "XX000"
+	DataCorrupted = "XX001"
+	IndexCorrupted = "XX002"
 }
 
 impl SqlState {
-	pub fn code(&self) -> &str {
-		match self {
-			Self::SuccessfulCompletion => "00000",
-			Self::FeatureNotSupported => "0A000",
-			Self::InvalidCursorName => "34000",
-			Self::ConnectionException => "08000",
-			Self::InvalidSQLStatementName => "26000",
-			Self::DataException => "22000",
-			Self::ProtocolViolation => "08P01",
-			Self::SyntaxError => "42601",
-			Self::InvalidDatetimeFormat => "22007",
-		}
+	/// Fetch the two-character class for this state, e.g. `23` for integrity constraint violations.
+	pub fn class(&self) -> &str {
+		let code = self.code();
+		code.get(..2).unwrap_or(code)
+	}
+
+	/// Returns true for class `00`, successful completion.
+	pub fn is_successful_completion(&self) -> bool {
+		self.class() == "00"
+	}
+
+	/// Returns true for class `01`, warnings.
+	pub fn is_warning(&self) -> bool {
+		self.class() == "01"
+	}
+
+	/// Returns true for class `02`, no data, which Postgres also treats as a warning.
+	pub fn is_no_data(&self) -> bool {
+		self.class() == "02"
+	}
+
+	/// Returns true for class `08`, connection exceptions.
+	pub fn is_connection_exception(&self) -> bool {
+		self.class() == "08"
+	}
+
+	/// Returns true for class `0A`, unsupported features.
+	pub fn is_feature_not_supported(&self) -> bool {
+		self.class() == "0A"
+	}
+
+	/// Returns true for class `22`, data exceptions such as invalid input or out of range values.
+	pub fn is_data_exception(&self) -> bool {
+		self.class() == "22"
+	}
+
+	/// Returns true for class `23`, integrity constraint violations.
+	pub fn is_integrity_violation(&self) -> bool {
+		self.class() == "23"
+	}
+
+	/// Returns true for class `25`, invalid transaction states, e.g. writes in a read-only transaction.
+	pub fn is_invalid_transaction_state(&self) -> bool {
+		self.class() == "25"
+	}
+
+	/// Returns true for class `28`, invalid authorization specifications.
+	pub fn is_invalid_authorization(&self) -> bool {
+		self.class() == "28"
+	}
+
+	/// Returns true for class `40`, transaction rollbacks such as serialization failures.
+	pub fn is_transaction_rollback(&self) -> bool {
+		self.class() == "40"
+	}
+
+	/// Returns true for class `42`, syntax errors and access rule violations, which include undefined objects.
+	pub fn is_syntax_error_or_access_rule_violation(&self) -> bool {
+		self.class() == "42"
+	}
+
+	/// Returns true for class `53`, insufficient resources.
+	pub fn is_insufficient_resources(&self) -> bool {
+		self.class() == "53"
+	}
+
+	/// Returns true for class `54`, exceeded program limits.
+	pub fn is_program_limit_exceeded(&self) -> bool {
+		self.class() == "54"
+	}
+
+	/// Returns true for class `57`, operator intervention such as cancelled queries.
+	pub fn is_operator_intervention(&self) -> bool {
+		self.class() == "57"
+	}
+
+	/// Returns true for class `XX`, internal errors.
+	pub fn is_internal_error(&self) -> bool {
+		self.class() == "XX"
 	}
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Severity {
+	/// Aborts the current command, leaving the session usable.
 	Error,
+	/// Aborts the current session.
 	Fatal,
+	/// Aborts all sessions; convergence treats this the same as [Severity::Fatal].
+	Panic,
 }
 
 impl Severity {
 	pub fn code(&self) -> &str {
 		match self {
+			Self::Panic => "PANIC",
 			Self::Fatal => "FATAL",
 			Self::Error => "ERROR",
 		}
 	}
+
+	/// Looks up a severity by its non-localised name, as sent in error messages.
+	pub fn from_code(code: &str) -> Option<Self> {
+		match code {
+			"ERROR" => Some(Self::Error),
+			"FATAL" => Some(Self::Fatal),
+			"PANIC" => Some(Self::Panic),
+			_ => None,
+		}
+	}
+
+	/// Returns true if an error with this severity ends the connection after it's sent.
+	pub fn is_fatal(&self) -> bool {
+		matches!(self, Self::Fatal | Self::Panic)
+	}
 }
 
-#[derive(thiserror::Error, Debug, Clone)]
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
 pub struct ErrorResponse {
 	pub sql_state: SqlState,
 	pub severity: Severity,
 	pub message: String,
+	/// Any other fields, as pairs of field type and value, such as detail (`D`), hint (`H`) or position (`P`).
+	/// A localised severity (`S`) is sent in place of the non-localised one.
+	pub fields: Vec<(u8, String)>,
 }
 
 impl ErrorResponse {
@@ -216,6 +818,7 @@ impl ErrorResponse {
 			sql_state,
 			severity,
 			message: message.into(),
+			fields: Vec::new(),
 		}
 	}
 
@@ -224,7 +827,17 @@ impl ErrorResponse {
 	}
 
 	pub fn fatal(sql_state: SqlState, message: impl Into<String>) -> Self {
-		Self::new(sql_state, Severity::Error, message)
+		Self::new(sql_state, Severity::Fatal, message)
+	}
+
+	pub fn panic(sql_state: SqlState, message: impl Into<String>) -> Self {
+		Self::new(sql_state, Severity::Panic, message)
+	}
+
+	/// Adds a field to the error, e.g. `with_field(b'D', "...")` for a detail message.
+	pub fn with_field(mut self, field_type: u8, value: impl Into<String>) -> Self {
+		self.fields.push((field_type, value.into()));
+		self
 	}
 }
 
@@ -238,41 +851,97 @@ impl BackendMessage for ErrorResponse {
 	const TAG: u8 = b'E';
 
 	fn encode(&self, dst: &mut BytesMut) {
-		dst.put_u8(b'C');
-		dst.put_slice(self.sql_state.code().as_bytes());
-		dst.put_u8(0);
-		dst.put_u8(b'S');
-		dst.put_slice(self.severity.code().as_bytes());
-		dst.put_u8(0);
-		dst.put_u8(b'M');
-		dst.put_slice(self.message.as_bytes());
-		dst.put_u8(0);
+		encode_response_fields(dst, &self.sql_state, self.severity.code(), &self.message, &self.fields);
+	}
+}
 
-		dst.put_u8(0); // tag
+// writes the fields shared by error and notice responses, using the non-localised severity for the localised one
+// unless the remaining fields include it
+fn encode_response_fields(
+	dst: &mut BytesMut,
+	sql_state: &SqlState,
+	severity: &str,
+	message: &str,
+	fields: &[(u8, String)],
+) {
+	let localised_severity = fields
+		.iter()
+		.find(|(field_type, _)| *field_type == b'S')
+		.map_or(severity, |(_, value)| value.as_str());
+
+	let required = [
+		(b'C', sql_state.code()),
+		(b'S', localised_severity),
+		(b'V', severity),
+		(b'M', message),
+	];
+	let remaining = fields
+		.iter()
+		.filter(|(field_type, _)| *field_type != b'S')
+		.map(|(field_type, value)| (*field_type, value.as_str()));
+
+	for (field_type, value) in required.iter().copied().chain(remaining) {
+		dst.put_u8(field_type);
+		dst.put_slice(value.as_bytes());
+		dst.put_u8(0);
 	}
+
+	dst.put_u8(0); // tag
 }
 
-#[derive(Debug)]
-pub struct ParameterDescription {}
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParameterDescription {
+	pub parameter_types: Vec<DataTypeOid>,
+}
 
 impl BackendMessage for ParameterDescription {
 	const TAG: u8 = b't';
 
 	fn encode(&self, dst: &mut BytesMut) {
-		dst.put_i16(0);
+		dst.put_i16(self.parameter_types.len() as i16);
+		for &data_type in &self.parameter_types {
+			dst.put_u32(data_type.into());
+		}
 	}
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct FieldDescription {
 	pub name: String,
 	pub data_type: DataTypeOid,
 }
 
-#[derive(Debug, Clone)]
+/// Describes the columns of a result set.
+///
+/// Each field has exactly one format code, so the fields can only be set along with their formats.
+#[derive(Debug, Clone, PartialEq)]
 pub struct RowDescription {
-	pub fields: Vec<FieldDescription>,
-	pub format_code: FormatCode,
+	fields: Vec<FieldDescription>,
+	format_codes: Vec<FormatCode>,
+}
+
+impl RowDescription {
+	/// Creates a description using the same format for every field.
+	pub fn with_format(fields: Vec<FieldDescription>, format_code: FormatCode) -> Self {
+		let format_codes = vec![format_code; fields.len()];
+		Self { fields, format_codes }
+	}
+
+	/// Creates a description using the result formats requested by a `Bind` message,
+	/// which must provide a format for every field.
+	pub fn with_bind_format(fields: Vec<FieldDescription>, format: &BindFormat) -> Result<Self, ErrorResponse> {
+		let format_codes = format.format_codes(fields.len())?;
+		Ok(Self { fields, format_codes })
+	}
+
+	pub fn fields(&self) -> &[FieldDescription] {
+		&self.fields
+	}
+
+	/// Returns the format of each field, in the same order as [RowDescription::fields].
+	pub fn format_codes(&self) -> &[FormatCode] {
+		&self.format_codes
+	}
 }
 
 impl BackendMessage for RowDescription {
@@ -280,7 +949,7 @@ impl BackendMessage for RowDescription {
 
 	fn encode(&self, dst: &mut BytesMut) {
 		dst.put_i16(self.fields.len() as i16);
-		for field in &self.fields {
+		for (field, format_code) in self.fields.iter().zip(&self.format_codes) {
 			dst.put_slice(field.name.as_bytes());
 			dst.put_u8(0);
 			dst.put_i32(0); // table oid
@@ -288,11 +957,80 @@ impl BackendMessage for RowDescription {
 			dst.put_u32(field.data_type.into());
 			dst.put_i16(field.data_type.size_bytes());
 			dst.put_i32(-1); // data type modifier
-			dst.put_i16(self.format_code as i16);
+			dst.put_i16(*format_code as i16);
 		}
 	}
 }
 
+/// A single row of a result set, as received from a server.
+///
+/// Servers built on this crate write rows with [crate::protocol_ext::DataRowBatch] instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataRow {
+	/// The encoded value of each column, or `None` for nulls.
+	pub values: Vec<Option<Bytes>>,
+}
+
+impl BackendMessage for DataRow {
+	const TAG: u8 = b'D';
+
+	fn encode(&self, dst: &mut BytesMut) {
+		dst.put_i16(self.values.len() as i16);
+		for value in &self.values {
+			match value {
+				Some(value) => {
+					dst.put_i32(value.len() as i32);
+					dst.put_slice(value);
+				}
+				None => dst.put_i32(-1),
+			}
+		}
+	}
+}
+
+/// The newest protocol version supported by this crate.
+pub const LATEST_PROTOCOL_VERSION: (i16, i16) = (3, 2);
+
+/// Sent during startup to tell clients the newest minor protocol version and which protocol options are supported,
+/// if they requested a newer version or options which aren't known.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NegotiateProtocolVersion {
+	pub newest_minor_version: i32,
+	/// The `_pq_.` startup parameters which weren't recognised.
+	pub unrecognized_options: Vec<String>,
+}
+
+impl BackendMessage for NegotiateProtocolVersion {
+	const TAG: u8 = b'v';
+
+	fn encode(&self, dst: &mut BytesMut) {
+		dst.put_i32(self.newest_minor_version);
+		dst.put_i32(self.unrecognized_options.len() as i32);
+		for option in &self.unrecognized_options {
+			dst.put_slice(option.as_bytes());
+			dst.put_u8(0);
+		}
+	}
+}
+
+/// Identifies a connection, so that clients can cancel its queries from a separate connection.
+///
+/// The secret key is 4 bytes long before protocol 3.2, which allows keys of up to 256 bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackendKeyData {
+	pub process_id: i32,
+	pub secret_key: Vec<u8>,
+}
+
+impl BackendMessage for BackendKeyData {
+	const TAG: u8 = b'K';
+
+	fn encode(&self, dst: &mut BytesMut) {
+		dst.put_i32(self.process_id);
+		dst.put_slice(&self.secret_key);
+	}
+}
+
 #[derive(Debug)]
 pub struct AuthenticationOk;
 
@@ -304,14 +1042,145 @@ impl BackendMessage for AuthenticationOk {
 	}
 }
 
+/// Asks the client to authenticate, using the method given by the variant.
+///
+/// Authentication ends with [AuthenticationOk], which has its own message type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthenticationRequest {
+	KerberosV5,
+	CleartextPassword,
+	Md5Password {
+		salt: [u8; 4],
+	},
+	Gss,
+	GssContinue(Bytes),
+	Sspi,
+	/// Lists the SASL mechanisms supported by the server, in order of preference.
+	Sasl {
+		mechanisms: Vec<String>,
+	},
+	SaslContinue(Bytes),
+	SaslFinal(Bytes),
+}
+
+impl AuthenticationRequest {
+	fn code(&self) -> i32 {
+		match self {
+			Self::KerberosV5 => 2,
+			Self::CleartextPassword => 3,
+			Self::Md5Password { .. } => 5,
+			Self::Gss => 7,
+			Self::GssContinue(_) => 8,
+			Self::Sspi => 9,
+			Self::Sasl { .. } => 10,
+			Self::SaslContinue(_) => 11,
+			Self::SaslFinal(_) => 12,
+		}
+	}
+}
+
+impl BackendMessage for AuthenticationRequest {
+	const TAG: u8 = b'R';
+
+	fn encode(&self, dst: &mut BytesMut) {
+		dst.put_i32(self.code());
+		match self {
+			Self::KerberosV5 | Self::CleartextPassword | Self::Gss | Self::Sspi => (),
+			Self::Md5Password { salt } => dst.put_slice(salt),
+			Self::GssContinue(data) | Self::SaslContinue(data) | Self::SaslFinal(data) => dst.put_slice(data),
+			Self::Sasl { mechanisms } => {
+				for mechanism in mechanisms {
+					dst.put_slice(mechanism.as_bytes());
+					dst.put_u8(0);
+				}
+				dst.put_u8(0);
+			}
+		}
+	}
+}
+
+/// A warning or informational message, which doesn't affect the command being run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoticeResponse {
+	pub sql_state: SqlState,
+	/// The non-localised severity, such as `WARNING` or `NOTICE`.
+	pub severity: String,
+	pub message: String,
+	/// Any other fields, as for [ErrorResponse::fields].
+	pub fields: Vec<(u8, String)>,
+}
+
+impl BackendMessage for NoticeResponse {
+	const TAG: u8 = b'N';
+
+	fn encode(&self, dst: &mut BytesMut) {
+		encode_response_fields(dst, &self.sql_state, &self.severity, &self.message, &self.fields);
+	}
+}
+
+/// Delivers a notification sent with `NOTIFY` to a channel the session is listening on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotificationResponse {
+	/// The process ID of the session which sent the notification.
+	pub process_id: i32,
+	pub channel: String,
+	pub payload: String,
+}
+
+impl BackendMessage for NotificationResponse {
+	const TAG: u8 = b'A';
+
+	fn encode(&self, dst: &mut BytesMut) {
+		dst.put_i32(self.process_id);
+		dst.put_slice(self.channel.as_bytes());
+		dst.put_u8(0);
+		dst.put_slice(self.payload.as_bytes());
+		dst.put_u8(0);
+	}
+}
+
+/// Ends an `Execute` which reached its row limit before the portal was exhausted.
+#[derive(Debug)]
+pub struct PortalSuspended;
+
+impl BackendMessage for PortalSuspended {
+	const TAG: u8 = b's';
+
+	fn encode(&self, _dst: &mut BytesMut) {}
+}
+
+/// The session's transaction state, as reported by [ReadyForQuery].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TransactionStatus {
+	/// Not in a transaction block.
+	Idle = b'I' as isize,
+	/// In a transaction block.
+	InTransaction = b'T' as isize,
+	/// In a failed transaction block, where queries are rejected until the block ends.
+	Failed = b'E' as isize,
+}
+
+impl TryFrom<u8> for TransactionStatus {
+	type Error = ProtocolError;
+
+	fn try_from(value: u8) -> Result<Self, Self::Error> {
+		match value {
+			b'I' => Ok(TransactionStatus::Idle),
+			b'T' => Ok(TransactionStatus::InTransaction),
+			b'E' => Ok(TransactionStatus::Failed),
+			_ => Err(ProtocolError::ParserError),
+		}
+	}
+}
+
 #[derive(Debug)]
-pub struct ReadyForQuery;
+pub struct ReadyForQuery(pub TransactionStatus);
 
 impl BackendMessage for ReadyForQuery {
 	const TAG: u8 = b'Z';
 
 	fn encode(&self, dst: &mut BytesMut) {
-		dst.put_u8(b'I');
+		dst.put_u8(self.0 as u8);
 	}
 }
 
@@ -333,6 +1202,35 @@ impl BackendMessage for BindComplete {
 	fn encode(&self, _dst: &mut BytesMut) {}
 }
 
+#[derive(Debug)]
+pub struct CloseComplete;
+
+impl BackendMessage for CloseComplete {
+	const TAG: u8 = b'3';
+
+	fn encode(&self, _dst: &mut BytesMut) {}
+}
+
+/// The result of a [FunctionCall], in the requested format, or `None` for null.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionCallResponse {
+	pub result: Option<Bytes>,
+}
+
+impl BackendMessage for FunctionCallResponse {
+	const TAG: u8 = b'V';
+
+	fn encode(&self, dst: &mut BytesMut) {
+		match &self.result {
+			Some(result) => {
+				dst.put_i32(result.len() as i32);
+				dst.put_slice(result);
+			}
+			None => dst.put_i32(-1),
+		}
+	}
+}
+
 #[derive(Debug)]
 pub struct NoData;
 
@@ -351,7 +1249,7 @@ impl BackendMessage for EmptyQueryResponse {
 	fn encode(&self, _dst: &mut BytesMut) {}
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct CommandComplete {
 	pub command_tag: String,
 }
@@ -365,7 +1263,7 @@ impl BackendMessage for CommandComplete {
 	}
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ParameterStatus {
 	name: String,
 	value: String,
@@ -389,22 +1287,86 @@ impl ParameterStatus {
 			value: value.into(),
 		}
 	}
+
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	pub fn value(&self) -> &str {
+		&self.value
+	}
+}
+
+/// A message sent by the server, as decoded by [ClientCodec].
+///
+/// Messages with an unknown tag are kept as [ServerMessage::Other],
+/// so that proxies can relay them unchanged.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerMessage {
+	SSLResponse(bool),
+	GSSENCResponse(bool),
+	NegotiateProtocolVersion(NegotiateProtocolVersion),
+	AuthenticationOk,
+	AuthenticationRequest(AuthenticationRequest),
+	ParameterStatus(ParameterStatus),
+	BackendKeyData(BackendKeyData),
+	ReadyForQuery(TransactionStatus),
+	ErrorResponse(ErrorResponse),
+	NoticeResponse(NoticeResponse),
+	NotificationResponse(NotificationResponse),
+	ParameterDescription(ParameterDescription),
+	RowDescription(RowDescription),
+	DataRow(DataRow),
+	CommandComplete(CommandComplete),
+	EmptyQueryResponse,
+	ParseComplete,
+	BindComplete,
+	CloseComplete,
+	NoData,
+	FunctionCallResponse(FunctionCallResponse),
+	PortalSuspended,
+	Other { tag: u8, body: Bytes },
 }
 
-#[derive(Default, Debug)]
+/// The default limit on the size of a single message, matching Postgres' own limit of just under 1 GiB.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = (1 << 30) - 1;
+
+// startup packets are limited to 10000 bytes by Postgres, regardless of the message size limit
+const MAX_STARTUP_PACKET_SIZE: usize = 10000;
+
+// buffer space is only reserved in advance up to this size, so that a message claiming to be huge
+// can't allocate more than the data actually received
+const MAX_RESERVE_SIZE: usize = 1 << 20;
+
+#[derive(Debug)]
 pub struct ConnectionCodec {
 	// most state tracking is handled at a higher level
 	// however, the actual wire format uses a different header for startup vs normal messages
 	// so we need to be able to differentiate inside the decoder
 	startup_received: bool,
+	max_message_size: usize,
 }
 
 impl ConnectionCodec {
 	pub fn new() -> Self {
 		Self {
 			startup_received: false,
+			max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
 		}
 	}
+
+	/// Sets the largest message that will be accepted, including its length prefix.
+	/// Larger messages produce [ProtocolError::MessageTooLarge].
+	pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+		self.max_message_size = max_message_size;
+		self
+	}
+}
+
+impl Default for ConnectionCodec {
+	fn default() -> Self {
+		Self::new()
+	}
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -419,167 +1381,323 @@ pub enum ProtocolError {
 	InvalidMessageType(u8),
 	#[error("invalid format code: {0}")]
 	InvalidFormatCode(i16),
+	#[error("invalid message length: {0}")]
+	InvalidMessageLength(i32),
+	#[error("message of {0} bytes exceeds the maximum size")]
+	MessageTooLarge(usize),
+	#[error("message ended unexpectedly")]
+	UnexpectedEndOfMessage,
+	#[error("invalid startup packet layout: expected terminator as last byte")]
+	InvalidStartupPacketLayout,
 }
 
+// special request codes sent in place of a protocol version during startup
+const CANCEL_REQUEST_CODE: (i16, i16) = (1234, 5678);
+const SSL_REQUEST_CODE: (i16, i16) = (1234, 5679);
+const GSSENC_REQUEST_CODE: (i16, i16) = (1234, 5680);
+
+// cancellation keys may be up to 256 bytes from protocol 3.2 onwards
+const MAX_CANCEL_KEY_SIZE: usize = 256;
+
 // length prefix, two version components
 const STARTUP_HEADER_SIZE: usize = size_of::<i32>() + (size_of::<i16>() * 2);
 // message tag, length prefix
 const MESSAGE_HEADER_SIZE: usize = size_of::<u8>() + size_of::<i32>();
 
-impl Decoder for ConnectionCodec {
-	type Item = ClientMessage;
-	type Error = ProtocolError;
+fn read_u8(src: &mut impl Buf) -> Result<u8, ProtocolError> {
+	if src.remaining() < size_of::<u8>() {
+		return Err(ProtocolError::UnexpectedEndOfMessage);
+	}
+	Ok(src.get_u8())
+}
 
-	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-		if !self.startup_received {
-			if src.len() < STARTUP_HEADER_SIZE {
-				return Ok(None);
-			}
+fn read_i16(src: &mut impl Buf) -> Result<i16, ProtocolError> {
+	if src.remaining() < size_of::<i16>() {
+		return Err(ProtocolError::UnexpectedEndOfMessage);
+	}
+	Ok(src.get_i16())
+}
 
-			let mut header_buf = src.clone();
-			let message_len = header_buf.get_i32() as usize;
-			let protocol_version_major = header_buf.get_i16();
-			let protocol_version_minor = header_buf.get_i16();
+fn read_i32(src: &mut impl Buf) -> Result<i32, ProtocolError> {
+	if src.remaining() < size_of::<i32>() {
+		return Err(ProtocolError::UnexpectedEndOfMessage);
+	}
+	Ok(src.get_i32())
+}
 
-			if protocol_version_major == 1234i16 && protocol_version_minor == 5679i16 {
-				src.advance(STARTUP_HEADER_SIZE);
-				return Ok(Some(ClientMessage::SSLRequest));
-			}
+// reads the number of items in a list, which can't be negative
+fn read_count(src: &mut impl Buf) -> Result<usize, ProtocolError> {
+	match read_i16(src)? {
+		count if count < 0 => Err(ProtocolError::ParserError),
+		count => Ok(count as usize),
+	}
+}
 
-			if src.len() < message_len {
-				src.reserve(message_len - src.len());
-				return Ok(None);
+fn read_cstr(src: &mut Bytes) -> Result<String, ProtocolError> {
+	let next_null = src
+		.iter()
+		.position(|&b| b == 0)
+		.ok_or(ProtocolError::UnexpectedEndOfMessage)?;
+	let bytes = src.split_to(next_null);
+	src.advance(1);
+	Ok(String::from_utf8(bytes.to_vec())?)
+}
+
+// reads a length-prefixed value, where a length of -1 represents null
+fn read_nullable_bytes(src: &mut Bytes) -> Result<Option<Bytes>, ProtocolError> {
+	match read_i32(src)? {
+		-1 => Ok(None),
+		len if len < 0 => Err(ProtocolError::ParserError),
+		len if len as usize > src.len() => Err(ProtocolError::UnexpectedEndOfMessage),
+		len => Ok(Some(src.split_to(len as usize))),
+	}
+}
+
+// the whole message body must be consumed, as in Postgres' pq_getmsgend
+fn read_end(src: &Bytes) -> Result<(), ProtocolError> {
+	if !src.is_empty() {
+		return Err(ProtocolError::ParserError);
+	}
+	Ok(())
+}
+
+fn read_bind_format(src: &mut Bytes) -> Result<BindFormat, ProtocolError> {
+	Ok(match read_count(src)? {
+		0 => BindFormat::All(FormatCode::Text),
+		1 => BindFormat::All(read_i16(src)?.try_into()?),
+		n => {
+			let mut format_codes = Vec::new();
+			for _ in 0..n {
+				format_codes.push(read_i16(src)?.try_into()?);
 			}
+			BindFormat::PerColumn(format_codes)
+		}
+	})
+}
 
-			src.advance(STARTUP_HEADER_SIZE);
+// ensures the buffer has room for a message of the given total size, returning false if more data is needed
+fn message_available(src: &mut BytesMut, total_len: usize) -> bool {
+	if src.len() < total_len {
+		src.reserve((total_len - src.len()).min(MAX_RESERVE_SIZE));
+		return false;
+	}
+	true
+}
 
-			let mut parameters = HashMap::new();
+// splits a complete tagged message from the buffer, returning its tag and body
+fn split_message(src: &mut BytesMut, max_message_size: usize) -> Result<Option<(u8, Bytes)>, ProtocolError> {
+	if !message_available(src, MESSAGE_HEADER_SIZE) {
+		return Ok(None);
+	}
 
-			let mut param_str_start_pos = 0;
-			let mut current_key = None;
-			for (i, &blah) in src.iter().enumerate() {
-				if blah == 0 {
-					let string_value = String::from_utf8(src[param_str_start_pos..i].to_owned())?;
-					param_str_start_pos = i + 1;
+	let message_tag = src[0];
+	let message_len = i32::from_be_bytes([src[1], src[2], src[3], src[4]]);
+	// the length includes itself, but not the tag
+	if message_len < size_of::<i32>() as i32 {
+		return Err(ProtocolError::InvalidMessageLength(message_len));
+	}
+	if message_len as usize > max_message_size {
+		return Err(ProtocolError::MessageTooLarge(message_len as usize));
+	}
 
-					current_key = match current_key {
-						Some(key) => {
-							parameters.insert(key, string_value);
-							None
-						}
-						None => Some(string_value),
-					}
-				}
-			}
+	let total_len = message_len as usize + size_of::<u8>();
+	if !message_available(src, total_len) {
+		return Ok(None);
+	}
 
-			src.advance(message_len - STARTUP_HEADER_SIZE);
+	src.advance(MESSAGE_HEADER_SIZE);
+	Ok(Some((
+		message_tag,
+		src.split_to(total_len - MESSAGE_HEADER_SIZE).freeze(),
+	)))
+}
 
-			self.startup_received = true;
-			return Ok(Some(ClientMessage::Startup(Startup {
-				requested_protocol_version: (protocol_version_major, protocol_version_minor),
-				parameters,
-			})));
+// a list of key/value pairs, terminated by an empty key which must be the last byte
+fn read_startup_parameters(src: &mut Bytes) -> Result<HashMap<String, String>, ProtocolError> {
+	let mut parameters = HashMap::new();
+	loop {
+		let key = read_cstr(src)?;
+		if key.is_empty() {
+			read_end(src)?;
+			return Ok(parameters);
 		}
 
-		if src.len() < MESSAGE_HEADER_SIZE {
-			src.reserve(MESSAGE_HEADER_SIZE);
+		let value = read_cstr(src)?;
+		parameters.insert(key, value);
+	}
+}
+
+impl ConnectionCodec {
+	fn decode_startup(&mut self, src: &mut BytesMut) -> Result<Option<ClientMessage>, ProtocolError> {
+		if !message_available(src, size_of::<i32>()) {
 			return Ok(None);
 		}
 
-		let mut header_buf = src.clone();
-		let message_tag = header_buf.get_u8();
-		let message_len = header_buf.get_i32() as usize;
+		let message_len = i32::from_be_bytes([src[0], src[1], src[2], src[3]]);
+		if message_len < STARTUP_HEADER_SIZE as i32 {
+			return Err(ProtocolError::InvalidMessageLength(message_len));
+		}
+		if message_len as usize > MAX_STARTUP_PACKET_SIZE.min(self.max_message_size) {
+			return Err(ProtocolError::MessageTooLarge(message_len as usize));
+		}
 
-		if src.len() < message_len {
-			src.reserve(message_len - src.len());
+		if !message_available(src, message_len as usize) {
 			return Ok(None);
 		}
 
-		src.advance(MESSAGE_HEADER_SIZE);
+		let mut body = src.split_to(message_len as usize).freeze();
+		body.advance(size_of::<i32>());
+		let protocol_version = (read_i16(&mut body)?, read_i16(&mut body)?);
 
-		let read_cstr = |src: &mut BytesMut| -> Result<String, ProtocolError> {
-			let next_null = src.iter().position(|&b| b == 0).ok_or(ProtocolError::ParserError)?;
-			let bytes = src[..next_null].to_owned();
-			src.advance(bytes.len() + 1);
-			Ok(String::from_utf8(bytes)?)
-		};
-
-		let message = match message_tag {
-			b'P' => {
-				let prepared_statement_name = read_cstr(src)?;
-				let query = read_cstr(src)?;
-				let num_params = src.get_i16();
-				let _params: Vec<_> = (0..num_params).map(|_| src.get_u32()).collect();
-
-				ClientMessage::Parse(Parse {
-					prepared_statement_name,
-					query,
-					parameter_types: Vec::new(),
-				})
-			}
-			b'D' => {
-				let target_type = src.get_u8();
-				let name = read_cstr(src)?;
-
-				ClientMessage::Describe(match target_type {
-					b'P' => Describe::Portal(name),
-					b'S' => Describe::PreparedStatement(name),
-					_ => return Err(ProtocolError::ParserError),
-				})
-			}
-			b'S' => ClientMessage::Sync,
-			b'B' => {
-				let portal = read_cstr(src)?;
-				let prepared_statement_name = read_cstr(src)?;
-
-				let num_param_format_codes = src.get_i16();
-				for _ in 0..num_param_format_codes {
-					let _format_code = src.get_i16();
+		match protocol_version {
+			SSL_REQUEST_CODE => {
+				read_end(&body)?;
+				return Ok(Some(ClientMessage::SSLRequest));
+			}
+			GSSENC_REQUEST_CODE => {
+				read_end(&body)?;
+				return Ok(Some(ClientMessage::GSSENCRequest));
+			}
+			CANCEL_REQUEST_CODE => {
+				let process_id = read_i32(&mut body)?;
+				if body.len() < size_of::<i32>() || body.len() > MAX_CANCEL_KEY_SIZE {
+					return Err(ProtocolError::InvalidMessageLength(message_len));
 				}
 
-				let num_params = src.get_i16();
-				for _ in 0..num_params {
-					let param_len = src.get_i32() as usize;
-					let _bytes = &src[0..param_len];
-					src.advance(param_len);
-				}
+				return Ok(Some(ClientMessage::CancelRequest(CancelRequest {
+					process_id,
+					secret_key: body.to_vec(),
+				})));
+			}
+			_ => (),
+		}
 
-				let result_format = match src.get_i16() {
-					0 => BindFormat::All(FormatCode::Text),
-					1 => BindFormat::All(src.get_i16().try_into()?),
-					n => {
-						let mut result_format_codes = Vec::new();
-						for _ in 0..n {
-							result_format_codes.push(src.get_i16().try_into()?);
-						}
-						BindFormat::PerColumn(result_format_codes)
-					}
-				};
+		let parameters = read_startup_parameters(&mut body).map_err(|err| match err {
+			ProtocolError::Utf8(_) => err,
+			_ => ProtocolError::InvalidStartupPacketLayout,
+		})?;
 
-				ClientMessage::Bind(Bind {
-					portal,
-					prepared_statement_name,
-					result_format,
-				})
-			}
-			b'E' => {
-				let portal = read_cstr(src)?;
-				let max_rows = match src.get_i32() {
-					0 => None,
-					other => Some(other),
-				};
+		self.startup_received = true;
+		Ok(Some(ClientMessage::Startup(Startup {
+			requested_protocol_version: protocol_version,
+			parameters,
+		})))
+	}
+}
 
-				ClientMessage::Execute(Execute { portal, max_rows })
+fn read_client_message(message_tag: u8, src: &mut Bytes) -> Result<ClientMessage, ProtocolError> {
+	let message = match message_tag {
+		b'P' => {
+			let prepared_statement_name = read_cstr(src)?;
+			let query = read_cstr(src)?;
+			let num_params = read_count(src)?;
+			let mut parameter_types = Vec::new();
+			for _ in 0..num_params {
+				parameter_types.push((read_i32(src)? as u32).into());
+			}
+
+			ClientMessage::Parse(Parse {
+				prepared_statement_name,
+				query,
+				parameter_types,
+			})
+		}
+		b'D' => {
+			let target_type = read_u8(src)?;
+			let name = read_cstr(src)?;
+
+			ClientMessage::Describe(match target_type {
+				b'P' => Describe::Portal(name),
+				b'S' => Describe::PreparedStatement(name),
+				_ => return Err(ProtocolError::ParserError),
+			})
+		}
+		b'C' => {
+			let target_type = read_u8(src)?;
+			let name = read_cstr(src)?;
+
+			ClientMessage::Close(match target_type {
+				b'P' => Close::Portal(name),
+				b'S' => Close::PreparedStatement(name),
+				_ => return Err(ProtocolError::ParserError),
+			})
+		}
+		b'H' => ClientMessage::Flush,
+		b'S' => ClientMessage::Sync,
+		b'B' => {
+			let portal = read_cstr(src)?;
+			let prepared_statement_name = read_cstr(src)?;
+			let parameter_format = read_bind_format(src)?;
+
+			let num_params = read_count(src)?;
+			let mut parameters = Vec::new();
+			for _ in 0..num_params {
+				parameters.push(read_nullable_bytes(src)?);
 			}
-			b'Q' => {
-				let query = read_cstr(src)?;
-				ClientMessage::Query(query)
+
+			let result_format = read_bind_format(src)?;
+
+			ClientMessage::Bind(Bind {
+				portal,
+				prepared_statement_name,
+				parameter_format,
+				parameters,
+				result_format,
+			})
+		}
+		b'E' => {
+			let portal = read_cstr(src)?;
+			let max_rows = match read_i32(src)? {
+				0 => None,
+				other => Some(other),
+			};
+
+			ClientMessage::Execute(Execute { portal, max_rows })
+		}
+		b'Q' => {
+			let query = read_cstr(src)?;
+			ClientMessage::Query(query)
+		}
+		b'F' => {
+			let function_oid = read_i32(src)? as u32;
+			let argument_format = read_bind_format(src)?;
+
+			let num_args = read_count(src)?;
+			let mut arguments = Vec::new();
+			for _ in 0..num_args {
+				arguments.push(read_nullable_bytes(src)?);
 			}
-			b'X' => ClientMessage::Terminate,
-			other => return Err(ProtocolError::InvalidMessageType(other)),
-		};
 
-		Ok(Some(message))
+			let result_format = read_i16(src)?.try_into()?;
+
+			ClientMessage::FunctionCall(FunctionCall {
+				function_oid,
+				argument_format,
+				arguments,
+				result_format,
+			})
+		}
+		b'X' => ClientMessage::Terminate,
+		other => return Err(ProtocolError::InvalidMessageType(other)),
+	};
+
+	read_end(src)?;
+	Ok(message)
+}
+
+impl Decoder for ConnectionCodec {
+	type Item = ClientMessage;
+	type Error = ProtocolError;
+
+	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+		if !self.startup_received {
+			return self.decode_startup(src);
+		}
+
+		match split_message(src, self.max_message_size)? {
+			Some((message_tag, mut body)) => Ok(Some(read_client_message(message_tag, &mut body)?)),
+			None => Ok(None),
+		}
 	}
 }
 
@@ -597,6 +1715,7 @@ impl<T: BackendMessage> Encoder<T> for ConnectionCodec {
 	}
 }
 
+#[derive(Debug)]
 pub struct SSLResponse(pub bool);
 
 impl Encoder<SSLResponse> for ConnectionCodec {
@@ -607,3 +1726,468 @@ impl Encoder<SSLResponse> for ConnectionCodec {
 		Ok(())
 	}
 }
+
+/// The reply to a [ClientMessage::GSSENCRequest], indicating whether GSSAPI encryption will be used.
+#[derive(Debug)]
+pub struct GSSENCResponse(pub bool);
+
+impl Encoder<GSSENCResponse> for ConnectionCodec {
+	type Error = ProtocolError;
+
+	fn encode(&mut self, item: GSSENCResponse, dst: &mut BytesMut) -> Result<(), Self::Error> {
+		dst.put_u8(if item.0 { b'G' } else { b'N' });
+		Ok(())
+	}
+}
+
+impl Encoder<ServerMessage> for ConnectionCodec {
+	type Error = ProtocolError;
+
+	fn encode(&mut self, item: ServerMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
+		match item {
+			ServerMessage::SSLResponse(accepted) => self.encode(SSLResponse(accepted), dst),
+			ServerMessage::GSSENCResponse(accepted) => self.encode(GSSENCResponse(accepted), dst),
+			ServerMessage::NegotiateProtocolVersion(negotiate) => self.encode(negotiate, dst),
+			ServerMessage::AuthenticationOk => self.encode(AuthenticationOk, dst),
+			ServerMessage::AuthenticationRequest(request) => self.encode(request, dst),
+			ServerMessage::ParameterStatus(status) => self.encode(status, dst),
+			ServerMessage::BackendKeyData(key_data) => self.encode(key_data, dst),
+			ServerMessage::ReadyForQuery(status) => self.encode(ReadyForQuery(status), dst),
+			ServerMessage::ErrorResponse(err) => self.encode(err, dst),
+			ServerMessage::NoticeResponse(notice) => self.encode(notice, dst),
+			ServerMessage::NotificationResponse(notification) => self.encode(notification, dst),
+			ServerMessage::ParameterDescription(desc) => self.encode(desc, dst),
+			ServerMessage::RowDescription(desc) => self.encode(desc, dst),
+			ServerMessage::DataRow(row) => self.encode(row, dst),
+			ServerMessage::CommandComplete(complete) => self.encode(complete, dst),
+			ServerMessage::EmptyQueryResponse => self.encode(EmptyQueryResponse, dst),
+			ServerMessage::ParseComplete => self.encode(ParseComplete, dst),
+			ServerMessage::BindComplete => self.encode(BindComplete, dst),
+			ServerMessage::CloseComplete => self.encode(CloseComplete, dst),
+			ServerMessage::NoData => self.encode(NoData, dst),
+			ServerMessage::FunctionCallResponse(response) => self.encode(response, dst),
+			ServerMessage::PortalSuspended => self.encode(PortalSuspended, dst),
+			ServerMessage::Other { tag, body } => {
+				dst.put_u8(tag);
+				dst.put_i32((body.len() + 4) as i32);
+				dst.put_slice(&body);
+				Ok(())
+			}
+		}
+	}
+}
+
+/// The client side of the protocol: encodes [ClientMessage]s and decodes [ServerMessage]s.
+///
+/// Useful for building proxies and test clients on the same types as the server.
+#[derive(Debug)]
+pub struct ClientCodec {
+	// the server answers encryption requests with a single byte rather than a normal message
+	encryption_requested: Option<EncryptionRequest>,
+	max_message_size: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum EncryptionRequest {
+	Ssl,
+	Gssenc,
+}
+
+impl ClientCodec {
+	pub fn new() -> Self {
+		Self {
+			encryption_requested: None,
+			max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+		}
+	}
+
+	/// Sets the largest message that will be accepted, including its length prefix.
+	/// Larger messages produce [ProtocolError::MessageTooLarge].
+	pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+		self.max_message_size = max_message_size;
+		self
+	}
+}
+
+impl Default for ClientCodec {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+// reads the fields shared by error and notice responses, in the order they were sent
+fn read_response_fields(src: &mut Bytes) -> Result<Vec<(u8, String)>, ProtocolError> {
+	let mut fields = Vec::new();
+	loop {
+		let field_type = read_u8(src)?;
+		if field_type == 0 {
+			return Ok(fields);
+		}
+		fields.push((field_type, read_cstr(src)?));
+	}
+}
+
+fn take_response_field(fields: &mut Vec<(u8, String)>, field_type: u8) -> Option<String> {
+	let index = fields.iter().position(|(other, _)| *other == field_type)?;
+	Some(fields.remove(index).1)
+}
+
+fn read_error_response(src: &mut Bytes) -> Result<ErrorResponse, ProtocolError> {
+	let mut fields = read_response_fields(src)?;
+	let sql_state =
+		take_response_field(&mut fields, b'C').map_or(SqlState::InternalError, |code| SqlState::from_code(&code));
+	let message = take_response_field(&mut fields, b'M').unwrap_or_default();
+	let localised_severity = take_response_field(&mut fields, b'S');
+	// prefer the non-localised severity, which is only sent by newer servers
+	let severity = take_response_field(&mut fields, b'V')
+		.and_then(|code| Severity::from_code(&code))
+		.or_else(|| localised_severity.as_deref().and_then(Severity::from_code))
+		.unwrap_or(Severity::Error);
+
+	// a localised severity is only kept if it differs, so that it's relayed unchanged
+	if let Some(localised_severity) = localised_severity.filter(|value| value != severity.code()) {
+		fields.insert(0, (b'S', localised_severity));
+	}
+
+	Ok(ErrorResponse {
+		sql_state,
+		severity,
+		message,
+		fields,
+	})
+}
+
+fn read_notice_response(src: &mut Bytes) -> Result<NoticeResponse, ProtocolError> {
+	let mut fields = read_response_fields(src)?;
+	let sql_state =
+		take_response_field(&mut fields, b'C').map_or(SqlState::InternalError, |code| SqlState::from_code(&code));
+	let message = take_response_field(&mut fields, b'M').unwrap_or_default();
+	let localised_severity = take_response_field(&mut fields, b'S');
+	// as above, the localised severity is only a fallback for older servers
+	let severity = take_response_field(&mut fields, b'V')
+		.or_else(|| localised_severity.clone())
+		.unwrap_or_else(|| "NOTICE".to_owned());
+
+	if let Some(localised_severity) = localised_severity.filter(|value| *value != severity) {
+		fields.insert(0, (b'S', localised_severity));
+	}
+
+	Ok(NoticeResponse {
+		sql_state,
+		severity,
+		message,
+		fields,
+	})
+}
+
+fn read_authentication_request(src: &mut Bytes) -> Result<ServerMessage, ProtocolError> {
+	let request = match read_i32(src)? {
+		0 => return Ok(ServerMessage::AuthenticationOk),
+		2 => AuthenticationRequest::KerberosV5,
+		3 => AuthenticationRequest::CleartextPassword,
+		5 => {
+			let mut salt = [0; 4];
+			if src.len() < salt.len() {
+				return Err(ProtocolError::UnexpectedEndOfMessage);
+			}
+			src.copy_to_slice(&mut salt);
+			AuthenticationRequest::Md5Password { salt }
+		}
+		7 => AuthenticationRequest::Gss,
+		8 => AuthenticationRequest::GssContinue(std::mem::take(src)),
+		9 => AuthenticationRequest::Sspi,
+		10 => {
+			let mut mechanisms = Vec::new();
+			loop {
+				let mechanism = read_cstr(src)?;
+				if mechanism.is_empty() {
+					break;
+				}
+				mechanisms.push(mechanism);
+			}
+			AuthenticationRequest::Sasl { mechanisms }
+		}
+		11 => AuthenticationRequest::SaslContinue(std::mem::take(src)),
+		12 => AuthenticationRequest::SaslFinal(std::mem::take(src)),
+		_ => return Err(ProtocolError::ParserError),
+	};
+	Ok(ServerMessage::AuthenticationRequest(request))
+}
+
+fn read_row_description(src: &mut Bytes) -> Result<RowDescription, ProtocolError> {
+	let num_fields = read_count(src)?;
+	let mut fields = Vec::new();
+	let mut format_codes = Vec::new();
+
+	for _ in 0..num_fields {
+		let name = read_cstr(src)?;
+		let _table_oid = read_i32(src)?;
+		let _column_attr_number = read_i16(src)?;
+		let data_type = (read_i32(src)? as u32).into();
+		let _data_type_size = read_i16(src)?;
+		let _data_type_modifier = read_i32(src)?;
+		format_codes.push(read_i16(src)?.try_into()?);
+		fields.push(FieldDescription { name, data_type });
+	}
+
+	Ok(RowDescription { fields, format_codes })
+}
+
+fn read_data_row(src: &mut Bytes) -> Result<DataRow, ProtocolError> {
+	let num_values = read_count(src)?;
+	let mut values = Vec::new();
+
+	for _ in 0..num_values {
+		values.push(read_nullable_bytes(src)?);
+	}
+
+	Ok(DataRow { values })
+}
+
+fn read_server_message(message_tag: u8, src: &mut Bytes) -> Result<ServerMessage, ProtocolError> {
+	let message = match message_tag {
+		b'R' => read_authentication_request(src)?,
+		b'v' => {
+			let newest_minor_version = read_i32(src)?;
+			let num_options = read_i32(src)?;
+			if num_options < 0 {
+				return Err(ProtocolError::ParserError);
+			}
+
+			let mut unrecognized_options = Vec::new();
+			for _ in 0..num_options {
+				unrecognized_options.push(read_cstr(src)?);
+			}
+
+			ServerMessage::NegotiateProtocolVersion(NegotiateProtocolVersion {
+				newest_minor_version,
+				unrecognized_options,
+			})
+		}
+		b'S' => {
+			let name = read_cstr(src)?;
+			let value = read_cstr(src)?;
+			ServerMessage::ParameterStatus(ParameterStatus::new(name, value))
+		}
+		b'K' => {
+			let process_id = read_i32(src)?;
+			ServerMessage::BackendKeyData(BackendKeyData {
+				process_id,
+				secret_key: std::mem::take(src).to_vec(),
+			})
+		}
+		b'Z' => ServerMessage::ReadyForQuery(read_u8(src)?.try_into()?),
+		b'E' => ServerMessage::ErrorResponse(read_error_response(src)?),
+		b'N' => ServerMessage::NoticeResponse(read_notice_response(src)?),
+		b'A' => ServerMessage::NotificationResponse(NotificationResponse {
+			process_id: read_i32(src)?,
+			channel: read_cstr(src)?,
+			payload: read_cstr(src)?,
+		}),
+		b't' => {
+			let num_params = read_count(src)?;
+			let mut parameter_types = Vec::new();
+			for _ in 0..num_params {
+				parameter_types.push((read_i32(src)? as u32).into());
+			}
+			ServerMessage::ParameterDescription(ParameterDescription { parameter_types })
+		}
+		b'T' => ServerMessage::RowDescription(read_row_description(src)?),
+		b'D' => ServerMessage::DataRow(read_data_row(src)?),
+		b'C' => ServerMessage::CommandComplete(CommandComplete {
+			command_tag: read_cstr(src)?,
+		}),
+		b'I' => ServerMessage::EmptyQueryResponse,
+		b'1' => ServerMessage::ParseComplete,
+		b'2' => ServerMessage::BindComplete,
+		b'3' => ServerMessage::CloseComplete,
+		b'n' => ServerMessage::NoData,
+		b's' => ServerMessage::PortalSuspended,
+		b'V' => ServerMessage::FunctionCallResponse(FunctionCallResponse {
+			result: read_nullable_bytes(src)?,
+		}),
+		_ => {
+			return Ok(ServerMessage::Other {
+				tag: message_tag,
+				body: std::mem::take(src),
+			})
+		}
+	};
+
+	read_end(src)?;
+	Ok(message)
+}
+
+impl Decoder for ClientCodec {
+	type Item = ServerMessage;
+	type Error = ProtocolError;
+
+	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+		if let Some(request) = self.encryption_requested {
+			if src.is_empty() {
+				return Ok(None);
+			}
+
+			self.encryption_requested = None;
+			return match (request, src.get_u8()) {
+				(EncryptionRequest::Ssl, b'S') => Ok(Some(ServerMessage::SSLResponse(true))),
+				(EncryptionRequest::Ssl, b'N') => Ok(Some(ServerMessage::SSLResponse(false))),
+				(EncryptionRequest::Gssenc, b'G') => Ok(Some(ServerMessage::GSSENCResponse(true))),
+				(EncryptionRequest::Gssenc, b'N') => Ok(Some(ServerMessage::GSSENCResponse(false))),
+				(_, other) => Err(ProtocolError::InvalidMessageType(other)),
+			};
+		}
+
+		let (message_tag, mut body) = match split_message(src, self.max_message_size)? {
+			Some(message) => message,
+			None => return Ok(None),
+		};
+
+		Ok(Some(read_server_message(message_tag, &mut body)?))
+	}
+}
+
+fn put_cstr(dst: &mut BytesMut, value: &str) {
+	dst.put_slice(value.as_bytes());
+	dst.put_u8(0);
+}
+
+fn put_bind_format(dst: &mut BytesMut, format: &BindFormat) {
+	match format {
+		BindFormat::All(FormatCode::Text) => dst.put_i16(0),
+		BindFormat::All(format_code) => {
+			dst.put_i16(1);
+			dst.put_i16(*format_code as i16);
+		}
+		BindFormat::PerColumn(format_codes) => {
+			dst.put_i16(format_codes.len() as i16);
+			for format_code in format_codes {
+				dst.put_i16(*format_code as i16);
+			}
+		}
+	}
+}
+
+impl Encoder<ClientMessage> for ClientCodec {
+	type Error = ProtocolError;
+
+	fn encode(&mut self, item: ClientMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
+		// startup-phase messages have no tag, everything else has a tag before the length
+		let tag = match &item {
+			ClientMessage::SSLRequest
+			| ClientMessage::GSSENCRequest
+			| ClientMessage::CancelRequest(_)
+			| ClientMessage::Startup(_) => None,
+			ClientMessage::Parse(_) => Some(b'P'),
+			ClientMessage::Describe(_) => Some(b'D'),
+			ClientMessage::Bind(_) => Some(b'B'),
+			ClientMessage::Close(_) => Some(b'C'),
+			ClientMessage::Flush => Some(b'H'),
+			ClientMessage::Sync => Some(b'S'),
+			ClientMessage::Execute(_) => Some(b'E'),
+			ClientMessage::Query(_) => Some(b'Q'),
+			ClientMessage::FunctionCall(_) => Some(b'F'),
+			ClientMessage::Terminate => Some(b'X'),
+		};
+		if let Some(tag) = tag {
+			dst.put_u8(tag);
+		}
+
+		// the length is filled in once the body has been written
+		let len_pos = dst.len();
+		dst.put_i32(0);
+
+		match item {
+			ClientMessage::SSLRequest => {
+				self.encryption_requested = Some(EncryptionRequest::Ssl);
+				dst.put_i16(SSL_REQUEST_CODE.0);
+				dst.put_i16(SSL_REQUEST_CODE.1);
+			}
+			ClientMessage::GSSENCRequest => {
+				self.encryption_requested = Some(EncryptionRequest::Gssenc);
+				dst.put_i16(GSSENC_REQUEST_CODE.0);
+				dst.put_i16(GSSENC_REQUEST_CODE.1);
+			}
+			ClientMessage::CancelRequest(cancel) => {
+				dst.put_i16(CANCEL_REQUEST_CODE.0);
+				dst.put_i16(CANCEL_REQUEST_CODE.1);
+				dst.put_i32(cancel.process_id);
+				dst.put_slice(&cancel.secret_key);
+			}
+			ClientMessage::Startup(startup) => {
+				dst.put_i16(startup.requested_protocol_version.0);
+				dst.put_i16(startup.requested_protocol_version.1);
+				for (name, value) in &startup.parameters {
+					put_cstr(dst, name);
+					put_cstr(dst, value);
+				}
+				dst.put_u8(0);
+			}
+			ClientMessage::Parse(parse) => {
+				put_cstr(dst, &parse.prepared_statement_name);
+				put_cstr(dst, &parse.query);
+				dst.put_i16(parse.parameter_types.len() as i16);
+				for data_type in parse.parameter_types {
+					dst.put_u32(data_type.into());
+				}
+			}
+			ClientMessage::Describe(describe) => {
+				let (target_type, name) = match &describe {
+					Describe::Portal(name) => (b'P', name),
+					Describe::PreparedStatement(name) => (b'S', name),
+				};
+				dst.put_u8(target_type);
+				put_cstr(dst, name);
+			}
+			ClientMessage::Close(close) => {
+				let (target_type, name) = match &close {
+					Close::Portal(name) => (b'P', name),
+					Close::PreparedStatement(name) => (b'S', name),
+				};
+				dst.put_u8(target_type);
+				put_cstr(dst, name);
+			}
+			ClientMessage::Bind(bind) => {
+				put_cstr(dst, &bind.portal);
+				put_cstr(dst, &bind.prepared_statement_name);
+				put_bind_format(dst, &bind.parameter_format);
+				dst.put_i16(bind.parameters.len() as i16);
+				for param in &bind.parameters {
+					match param {
+						Some(value) => {
+							dst.put_i32(value.len() as i32);
+							dst.put_slice(value);
+						}
+						None => dst.put_i32(-1),
+					}
+				}
+				put_bind_format(dst, &bind.result_format);
+			}
+			ClientMessage::Execute(exec) => {
+				put_cstr(dst, &exec.portal);
+				dst.put_i32(exec.max_rows.unwrap_or(0));
+			}
+			ClientMessage::Query(query) => put_cstr(dst, &query),
+			ClientMessage::FunctionCall(call) => {
+				dst.put_u32(call.function_oid);
+				put_bind_format(dst, &call.argument_format);
+				dst.put_i16(call.arguments.len() as i16);
+				for arg in &call.arguments {
+					match arg {
+						Some(value) => {
+							dst.put_i32(value.len() as i32);
+							dst.put_slice(value);
+						}
+						None => dst.put_i32(-1),
+					}
+				}
+				dst.put_i16(call.result_format as i16);
+			}
+			ClientMessage::Flush | ClientMessage::Sync | ClientMessage::Terminate => (),
+		}
+
+		let len = (dst.len() - len_pos) as i32;
+		dst[len_pos..len_pos + size_of::<i32>()].copy_from_slice(&len.to_be_bytes());
+		Ok(())
+	}
+}
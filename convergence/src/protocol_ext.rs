@@ -1,65 +1,1011 @@
 //! Contains extensions that make working with the Postgres protocol simpler or more efficient.
 
-use crate::protocol::{ConnectionCodec, FormatCode, ProtocolError, RowDescription};
-use bytes::{BufMut, BytesMut};
-use chrono::{NaiveDate, NaiveDateTime};
+use crate::protocol::{
+	ConnectionCodec, DataTypeOid, ErrorResponse, FieldDescription, FormatCode, ProtocolError, RowDescription, SqlState,
+};
+use crate::settings::{DateOrder, DateStyle, IntervalStyle, SessionSettings};
+use bytes::{Buf, BufMut, BytesMut};
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Timelike, Utc};
+use std::convert::{TryFrom, TryInto};
+use std::fmt::{self, Display};
+use std::net::IpAddr;
 use tokio_util::codec::Encoder;
+use uuid::Uuid;
+
+/// A decimal value, as used by Postgres' `numeric` type.
+///
+/// Finite values are stored as an unscaled integer and a number of decimal places,
+/// so `Numeric::new(12345, 2)` represents `123.45`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Numeric {
+	/// A finite decimal value.
+	Value {
+		/// The value without its decimal point.
+		unscaled: i128,
+		/// The number of digits after the decimal point.
+		scale: u16,
+	},
+	/// Not a number.
+	NaN,
+	/// Positive infinity.
+	Infinity,
+	/// Negative infinity.
+	NegativeInfinity,
+}
+
+impl Numeric {
+	/// Creates a finite value from an unscaled integer and a number of decimal places.
+	pub fn new(unscaled: i128, scale: u16) -> Self {
+		Self::Value { unscaled, scale }
+	}
+
+	// splits the absolute value into its integer and fractional decimal digits
+	fn decimal_digits(unscaled: i128, scale: u16) -> (String, String) {
+		let digits = unscaled.unsigned_abs().to_string();
+		let scale = scale as usize;
+		if digits.len() > scale {
+			let (int_part, frac_part) = digits.split_at(digits.len() - scale);
+			(int_part.to_owned(), frac_part.to_owned())
+		} else {
+			("0".to_owned(), format!("{:0>width$}", digits, width = scale))
+		}
+	}
+
+	fn encode_binary(&self, dst: &mut BytesMut) {
+		const NUMERIC_POS: u16 = 0x0000;
+		const NUMERIC_NEG: u16 = 0x4000;
+		const NUMERIC_NAN: u16 = 0xC000;
+		const NUMERIC_PINF: u16 = 0xD000;
+		const NUMERIC_NINF: u16 = 0xF000;
+
+		let (unscaled, scale) = match *self {
+			Self::Value { unscaled, scale } => (unscaled, scale),
+			special => {
+				dst.put_i16(0); // ndigits
+				dst.put_i16(0); // weight
+				dst.put_u16(match special {
+					Self::NaN => NUMERIC_NAN,
+					Self::Infinity => NUMERIC_PINF,
+					_ => NUMERIC_NINF,
+				});
+				dst.put_u16(0); // dscale
+				return;
+			}
+		};
+
+		// postgres stores numerics as base-10000 digits, with the weight giving the power of the first digit
+		let (int_part, frac_part) = Self::decimal_digits(unscaled, scale);
+		let int_padding = (4 - int_part.len() % 4) % 4;
+		let int_part = format!("{}{}", "0".repeat(int_padding), int_part);
+		let frac_padding = (4 - frac_part.len() % 4) % 4;
+		let frac_part = format!("{}{}", frac_part, "0".repeat(frac_padding));
+
+		let to_groups = |s: &str| -> Vec<i16> {
+			s.as_bytes()
+				.chunks(4)
+				.map(|c| c.iter().fold(0, |acc, &d| acc * 10 + (d - b'0') as i16))
+				.collect()
+		};
+		let mut groups = to_groups(&int_part);
+		let mut weight = groups.len() as i16 - 1;
+		groups.extend(to_groups(&frac_part));
+
+		let leading_zeros = groups.iter().take_while(|&&g| g == 0).count();
+		groups.drain(..leading_zeros);
+		weight -= leading_zeros as i16;
+		while groups.last() == Some(&0) {
+			groups.pop();
+		}
+		if groups.is_empty() {
+			weight = 0;
+		}
+
+		dst.put_i16(groups.len() as i16);
+		dst.put_i16(weight);
+		dst.put_u16(if unscaled < 0 { NUMERIC_NEG } else { NUMERIC_POS });
+		dst.put_u16(scale);
+		for group in groups {
+			dst.put_i16(group);
+		}
+	}
+}
+
+impl Display for Numeric {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match *self {
+			Self::Value { unscaled, scale } => {
+				let (int_part, frac_part) = Self::decimal_digits(unscaled, scale);
+				if unscaled < 0 {
+					write!(f, "-")?;
+				}
+				write!(f, "{}", int_part)?;
+				if scale > 0 {
+					write!(f, ".{}", frac_part)?;
+				}
+				Ok(())
+			}
+			Self::NaN => write!(f, "NaN"),
+			Self::Infinity => write!(f, "Infinity"),
+			Self::NegativeInfinity => write!(f, "-Infinity"),
+		}
+	}
+}
+
+/// A time interval, as used by Postgres' `interval` type.
+///
+/// Months and days are kept separate from the time component, since their length in microseconds varies.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Interval {
+	/// Number of months.
+	pub months: i32,
+	/// Number of days.
+	pub days: i32,
+	/// Number of microseconds.
+	pub microseconds: i64,
+}
+
+impl Interval {
+	/// Creates an interval from its components.
+	pub fn new(months: i32, days: i32, microseconds: i64) -> Self {
+		Self {
+			months,
+			days,
+			microseconds,
+		}
+	}
+}
+
+// formats the given seconds and microseconds as Postgres does, omitting the fractional part when zero
+fn write_seconds(out: &mut String, secs: u64, micros: u64, fill_zeros: bool) {
+	if fill_zeros {
+		out.push_str(&format!("{:02}", secs));
+	} else {
+		out.push_str(&secs.to_string());
+	}
+
+	if micros != 0 {
+		let frac = format!("{:06}", micros);
+		out.push('.');
+		out.push_str(frac.trim_end_matches('0'));
+	}
+}
+
+// the fields of an interval as split by Postgres' interval2itm, each with the sign of its source field
+struct IntervalFields {
+	year: i64,
+	mon: i64,
+	mday: i64,
+	hour: i64,
+	min: i64,
+	sec: i64,
+	fsec: i64,
+}
+
+impl IntervalFields {
+	fn new(interval: &Interval) -> Self {
+		let time = interval.microseconds;
+		Self {
+			year: (interval.months / 12) as i64,
+			mon: (interval.months % 12) as i64,
+			mday: interval.days as i64,
+			hour: time / 3_600_000_000,
+			min: (time / 60_000_000) % 60,
+			sec: (time / 1_000_000) % 60,
+			fsec: time % 1_000_000,
+		}
+	}
+
+	fn has_time(&self) -> bool {
+		self.hour != 0 || self.min != 0 || self.sec != 0 || self.fsec != 0
+	}
+
+	fn write_time(&self, out: &mut String, pad_hours: bool) {
+		let hour = self.hour.unsigned_abs();
+		if pad_hours {
+			out.push_str(&format!("{:02}:{:02}:", hour, self.min.unsigned_abs()));
+		} else {
+			out.push_str(&format!("{}:{:02}:", hour, self.min.unsigned_abs()));
+		}
+		write_seconds(out, self.sec.unsigned_abs(), self.fsec.unsigned_abs(), true);
+	}
+}
+
+impl Interval {
+	/// Formats the interval as Postgres does for the given `IntervalStyle`.
+	pub fn format_with_style(&self, style: IntervalStyle) -> String {
+		let fields = IntervalFields::new(self);
+		let mut out = String::new();
+
+		match style {
+			IntervalStyle::Postgres => {
+				let mut is_zero = true;
+				let mut is_before = false;
+
+				for (value, unit) in [(fields.year, "year"), (fields.mon, "mon"), (fields.mday, "day")] {
+					if value == 0 {
+						continue;
+					}
+					if !is_zero {
+						out.push(' ');
+					}
+					if is_before && value > 0 {
+						out.push('+');
+					}
+					out.push_str(&format!("{} {}{}", value, unit, if value != 1 { "s" } else { "" }));
+
+					is_before = value < 0;
+					is_zero = false;
+				}
+
+				if is_zero || fields.has_time() {
+					if !is_zero {
+						out.push(' ');
+					}
+					if fields.hour < 0 || fields.min < 0 || fields.sec < 0 || fields.fsec < 0 {
+						out.push('-');
+					} else if is_before {
+						out.push('+');
+					}
+					fields.write_time(&mut out, true);
+				}
+			}
+			IntervalStyle::PostgresVerbose => {
+				let mut is_zero = true;
+				let mut is_before = false;
+				out.push('@');
+
+				for (value, unit) in [
+					(fields.year, "year"),
+					(fields.mon, "mon"),
+					(fields.mday, "day"),
+					(fields.hour, "hour"),
+					(fields.min, "min"),
+				] {
+					if value == 0 {
+						continue;
+					}
+
+					// the first non-zero field decides whether the interval is "ago", later fields are relative to it
+					let value = if is_zero {
+						is_before = value < 0;
+						value.abs()
+					} else if is_before {
+						-value
+					} else {
+						value
+					};
+					out.push_str(&format!(" {} {}{}", value, unit, if value != 1 { "s" } else { "" }));
+					is_zero = false;
+				}
+
+				if fields.sec != 0 || fields.fsec != 0 {
+					out.push(' ');
+					if fields.sec < 0 || (fields.sec == 0 && fields.fsec < 0) {
+						if is_zero {
+							is_before = true;
+						} else if !is_before {
+							out.push('-');
+						}
+					} else if is_before {
+						out.push('-');
+					}
+					write_seconds(&mut out, fields.sec.unsigned_abs(), fields.fsec.unsigned_abs(), false);
+					let plural = fields.sec.abs() != 1 || fields.fsec != 0;
+					out.push_str(if plural { " secs" } else { " sec" });
+					is_zero = false;
+				}
+
+				if is_zero {
+					out.push_str(" 0");
+				}
+				if is_before {
+					out.push_str(" ago");
+				}
+			}
+			IntervalStyle::SqlStandard => {
+				let IntervalFields {
+					year,
+					mon,
+					mday,
+					hour,
+					min,
+					sec,
+					fsec,
+				} = fields;
+				let has_negative = year < 0 || mon < 0 || mday < 0 || hour < 0 || min < 0 || sec < 0 || fsec < 0;
+				let has_positive = year > 0 || mon > 0 || mday > 0 || hour > 0 || min > 0 || sec > 0 || fsec > 0;
+				let has_year_month = year != 0 || mon != 0;
+				let has_day_time = mday != 0 || fields.has_time();
+				let is_standard = !(has_negative && has_positive || has_year_month && has_day_time);
+
+				if !has_negative && !has_positive {
+					out.push('0');
+				} else if !is_standard {
+					// mixed signs can't be expressed in the standard format, so every part gets an explicit sign
+					let sign = |negative: bool| if negative { '-' } else { '+' };
+					out.push_str(&format!(
+						"{}{}-{} {}{} {}",
+						sign(year < 0 || mon < 0),
+						year.abs(),
+						mon.abs(),
+						sign(mday < 0),
+						mday.abs(),
+						sign(hour < 0 || min < 0 || sec < 0 || fsec < 0),
+					));
+					fields.write_time(&mut out, false);
+				} else {
+					// the standard format has a single sign for the whole interval
+					if has_negative {
+						out.push('-');
+					}
+					if has_year_month {
+						out.push_str(&format!("{}-{}", year.abs(), mon.abs()));
+					} else {
+						if mday != 0 {
+							out.push_str(&format!("{} ", mday.abs()));
+						}
+						fields.write_time(&mut out, false);
+					}
+				}
+			}
+			IntervalStyle::Iso8601 => {
+				if fields.year == 0 && fields.mon == 0 && fields.mday == 0 && !fields.has_time() {
+					return "PT0S".to_owned();
+				}
+
+				out.push('P');
+				let add_part = |out: &mut String, value: i64, unit: char| {
+					if value != 0 {
+						out.push_str(&format!("{}{}", value, unit));
+					}
+				};
+				add_part(&mut out, fields.year, 'Y');
+				add_part(&mut out, fields.mon, 'M');
+				add_part(&mut out, fields.mday, 'D');
+				if fields.has_time() {
+					out.push('T');
+				}
+				add_part(&mut out, fields.hour, 'H');
+				add_part(&mut out, fields.min, 'M');
+				if fields.sec != 0 || fields.fsec != 0 {
+					if fields.sec < 0 || fields.fsec < 0 {
+						out.push('-');
+					}
+					write_seconds(&mut out, fields.sec.unsigned_abs(), fields.fsec.unsigned_abs(), false);
+					out.push('S');
+				}
+			}
+		}
+
+		out
+	}
+}
+
+impl Display for Interval {
+	// matches the output of the default "postgres" IntervalStyle
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.format_with_style(IntervalStyle::Postgres))
+	}
+}
+
+/// A date or timestamp value, which may also be one of Postgres' special infinite values.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MaybeInfinite<T> {
+	/// A finite value.
+	Value(T),
+	/// Later than all other values, written as `infinity`.
+	Infinity,
+	/// Earlier than all other values, written as `-infinity`.
+	NegativeInfinity,
+}
+
+impl<T> From<T> for MaybeInfinite<T> {
+	fn from(val: T) -> Self {
+		Self::Value(val)
+	}
+}
+
+impl<T> MaybeInfinite<T> {
+	// the text representation of the infinite values, shared by dates and timestamps
+	fn special_text(&self) -> Option<&'static str> {
+		match self {
+			Self::Value(_) => None,
+			Self::Infinity => Some("infinity"),
+			Self::NegativeInfinity => Some("-infinity"),
+		}
+	}
+}
+
+// Formats a float as Postgres' float4out/float8out do. `precision` is the type's number of reliable decimal
+// digits (FLT_DIG or DBL_DIG). When extra_float_digits is positive, the shortest representation that
+// round-trips is used, otherwise the value is rounded to `precision + extra_float_digits` digits like printf's %g.
+fn format_float<T: fmt::LowerExp + Into<f64> + Copy>(val: T, precision: i32, extra_float_digits: i32) -> String {
+	let wide: f64 = val.into();
+	if wide.is_nan() {
+		return "NaN".to_owned();
+	}
+	if wide.is_infinite() {
+		return if wide > 0.0 { "Infinity" } else { "-Infinity" }.to_owned();
+	}
+
+	let (exponential, max_fixed_exponent) = if extra_float_digits > 0 {
+		(format!("{:e}", val), precision)
+	} else {
+		let digits = (precision + extra_float_digits).max(1);
+		(format!("{:.*e}", (digits - 1) as usize, val), digits)
+	};
+
+	let (mantissa, exponent) = exponential.split_once('e').expect("exponential float format");
+	let exponent: i32 = exponent.parse().expect("exponential float exponent");
+	let (negative, mantissa) = match mantissa.strip_prefix('-') {
+		Some(mantissa) => (true, mantissa),
+		None => (false, mantissa),
+	};
+	let digits = mantissa.replace('.', "");
+	let digits = match digits.trim_end_matches('0') {
+		"" => "0",
+		digits => digits,
+	};
+
+	let mut out = String::new();
+	if negative {
+		out.push('-');
+	}
+
+	if exponent < -4 || exponent >= max_fixed_exponent {
+		out.push_str(&digits[..1]);
+		if digits.len() > 1 {
+			out.push('.');
+			out.push_str(&digits[1..]);
+		}
+		let sign = if exponent < 0 { '-' } else { '+' };
+		out.push_str(&format!("e{}{:02}", sign, exponent.abs()));
+	} else if exponent < 0 {
+		out.push_str("0.");
+		out.push_str(&"0".repeat((-exponent - 1) as usize));
+		out.push_str(digits);
+	} else {
+		let int_len = exponent as usize + 1;
+		if digits.len() <= int_len {
+			out.push_str(digits);
+			out.push_str(&"0".repeat(int_len - digits.len()));
+		} else {
+			out.push_str(&digits[..int_len]);
+			out.push('.');
+			out.push_str(&digits[int_len..]);
+		}
+	}
+
+	out
+}
+
+// postgres has no year zero, so years before 1 AD are written as BC
+fn pg_year(date: &NaiveDate) -> (i32, bool) {
+	match date.year() {
+		year if year > 0 => (year, false),
+		year => (1 - year, true),
+	}
+}
+
+// formats the date fields shared by the date and timestamp formats, excluding the BC suffix
+fn write_date_fields(out: &mut String, date: &NaiveDate, settings: &SessionSettings) {
+	let (year, _) = pg_year(date);
+	let (month, day) = (date.month(), date.day());
+	let day_first = settings.date_order == DateOrder::Dmy;
+
+	out.push_str(&match settings.date_style {
+		DateStyle::Iso => format!("{:04}-{:02}-{:02}", year, month, day),
+		DateStyle::Sql if day_first => format!("{:02}/{:02}/{:04}", day, month, year),
+		DateStyle::Sql => format!("{:02}/{:02}/{:04}", month, day, year),
+		DateStyle::German => format!("{:02}.{:02}.{:04}", day, month, year),
+		DateStyle::Postgres if day_first => format!("{:02}-{:02}-{:04}", day, month, year),
+		DateStyle::Postgres => format!("{:02}-{:02}-{:04}", month, day, year),
+	});
+}
+
+fn format_date(val: &MaybeInfinite<NaiveDate>, settings: &SessionSettings) -> String {
+	let date = match val {
+		MaybeInfinite::Value(date) => date,
+		special => return special.special_text().unwrap_or_default().to_owned(),
+	};
+
+	let mut out = String::new();
+	write_date_fields(&mut out, date, settings);
+	if pg_year(date).1 {
+		out.push_str(" BC");
+	}
+	out
+}
+
+fn write_time_fields(out: &mut String, time: &NaiveTime) {
+	out.push_str(&format!("{:02}:{:02}:", time.hour(), time.minute()));
+	write_seconds(out, time.second() as u64, (time.nanosecond() / 1000) as u64, true);
+}
+
+// Formats a timestamp as Postgres' EncodeDateTime does. Values with a time zone are always in UTC,
+// which is written as an offset in the ISO style and as an abbreviation otherwise.
+fn format_timestamp(val: &MaybeInfinite<NaiveDateTime>, with_time_zone: bool, settings: &SessionSettings) -> String {
+	let timestamp = match val {
+		MaybeInfinite::Value(timestamp) => timestamp,
+		special => return special.special_text().unwrap_or_default().to_owned(),
+	};
+	let date = timestamp.date();
+	let mut out = String::new();
+
+	match settings.date_style {
+		DateStyle::Postgres => {
+			out.push_str(&date.format("%a ").to_string());
+			if settings.date_order == DateOrder::Dmy {
+				out.push_str(&date.format("%d %b ").to_string());
+			} else {
+				out.push_str(&date.format("%b %d ").to_string());
+			}
+			write_time_fields(&mut out, &timestamp.time());
+			out.push_str(&format!(" {:04}", pg_year(&date).0));
+		}
+		_ => {
+			write_date_fields(&mut out, &date, settings);
+			out.push(' ');
+			write_time_fields(&mut out, &timestamp.time());
+		}
+	}
+
+	if with_time_zone {
+		out.push_str(match settings.date_style {
+			DateStyle::Iso => "+00",
+			_ => " UTC",
+		});
+	}
+	if pg_year(&date).1 {
+		out.push_str(" BC");
+	}
+	out
+}
 
 /// Supports batched rows for e.g. returning portal result sets.
 ///
-/// NB: this struct only performs limited validation of column consistency across rows.
+/// Rows are validated as they're written: each value must match the type of its column (when known),
+/// and each row must contain exactly the expected number of columns.
+///
+/// Rows are encoded directly into the batch's buffer as complete `DataRow` messages, so sending a batch
+/// doesn't copy them again. A batch can be cleared and reused, keeping its allocated capacity, and reports
+/// its encoded size so that callers can send rows once a threshold is reached.
 pub struct DataRowBatch {
-	format_code: FormatCode,
-	num_cols: usize,
+	format_codes: Vec<FormatCode>,
+	column_types: Vec<DataTypeOid>,
+	settings: SessionSettings,
 	num_rows: usize,
 	data: BytesMut,
-	row: BytesMut,
 }
 
 impl DataRowBatch {
 	/// Creates a new row batch using the given format code, requiring a certain number of columns per row.
+	///
+	/// Column types aren't known, so values of any type are accepted.
 	pub fn new(format_code: FormatCode, num_cols: usize) -> Self {
+		Self::with_column_types(format_code, vec![DataTypeOid::Unspecified; num_cols])
+	}
+
+	/// Creates a new row batch using the given format code, requiring values of the given types in each row.
+	pub fn with_column_types(format_code: FormatCode, column_types: Vec<DataTypeOid>) -> Self {
+		Self::with_column_formats(column_types.iter().map(|_| format_code).collect(), column_types)
+	}
+
+	/// Creates a new row batch using a separate format code for each column,
+	/// requiring values of the given types in each row.
+	///
+	/// Columns without a corresponding format code use the text format.
+	pub fn with_column_formats(format_codes: Vec<FormatCode>, column_types: Vec<DataTypeOid>) -> Self {
 		Self {
-			format_code,
-			num_cols,
+			format_codes,
+			column_types,
+			settings: SessionSettings::default(),
 			num_rows: 0,
 			data: BytesMut::new(),
-			row: BytesMut::new(),
 		}
 	}
 
 	/// Creates a [DataRowBatch] from the given [RowDescription].
 	pub fn from_row_desc(desc: &RowDescription) -> Self {
-		Self::new(desc.format_code, desc.fields.len())
+		Self::with_column_formats(
+			desc.format_codes().to_vec(),
+			desc.fields().iter().map(|f| f.data_type).collect(),
+		)
+	}
+
+	/// Uses the given session settings for values written in the text format, rather than Postgres' defaults.
+	pub fn with_settings(mut self, settings: SessionSettings) -> Self {
+		self.settings = settings;
+		self
+	}
+
+	/// Pre-allocates space for at least the given number of encoded bytes.
+	pub fn with_capacity(mut self, capacity: usize) -> Self {
+		self.reserve(capacity);
+		self
 	}
 
-	/// Starts writing a new row.
+	/// Reserves space for at least the given number of additional encoded bytes.
+	pub fn reserve(&mut self, additional: usize) {
+		self.data.reserve(additional);
+	}
+
+	/// Removes all rows from the batch, keeping its allocated capacity for reuse.
+	pub fn clear(&mut self) {
+		self.data.clear();
+		self.num_rows = 0;
+	}
+
+	/// Writes a new row, using the provided [DataRowWriter] to encode each column's value in turn.
 	///
-	/// Returns a [DataRowWriter] that is responsible for the actual value encoding.
-	pub fn create_row(&mut self) -> DataRowWriter {
-		self.num_rows += 1;
-		DataRowWriter::new(self)
+	/// If the closure returns an error, or writes the wrong number of columns, the partial row is discarded
+	/// and the batch is left as it was before the call.
+	pub fn write_row(
+		&mut self,
+		f: impl FnOnce(&mut DataRowWriter) -> Result<(), ErrorResponse>,
+	) -> Result<(), ErrorResponse> {
+		let start = self.data.len();
+		// the message length isn't known until the row is complete, so it's filled in afterwards
+		self.data.put_u8(b'D');
+		self.data.put_i32(0);
+		self.data.put_i16(self.column_types.len() as i16);
+
+		let mut writer = DataRowWriter::new(self);
+		let result = f(&mut writer).and_then(|_| writer.check_complete());
+
+		match result {
+			Ok(()) => {
+				let len = (self.data.len() - start - 1) as i32;
+				self.data[start + 1..start + 5].copy_from_slice(&len.to_be_bytes());
+				self.num_rows += 1;
+				Ok(())
+			}
+			Err(err) => {
+				self.data.truncate(start);
+				Err(err)
+			}
+		}
+	}
+
+	/// Removes up to the given number of rows from the start of the batch, returning them as a new batch
+	/// with the same columns. The rows aren't copied.
+	pub fn take_rows(&mut self, max_rows: usize) -> Self {
+		let mut len = 0;
+		let mut num_rows = 0;
+		while num_rows < max_rows && len < self.data.len() {
+			// each row is a complete message, whose length excludes its tag
+			let msg_len = (&self.data[len + 1..len + 5]).get_i32() as usize;
+			len += 1 + msg_len;
+			num_rows += 1;
+		}
+
+		self.num_rows -= num_rows;
+		Self {
+			format_codes: self.format_codes.clone(),
+			column_types: self.column_types.clone(),
+			settings: self.settings,
+			num_rows,
+			data: self.data.split_to(len),
+		}
 	}
 
 	/// Returns the number of rows currently written to this batch.
 	pub fn num_rows(&self) -> usize {
 		self.num_rows
 	}
+
+	/// Returns true if no rows have been written to this batch.
+	pub fn is_empty(&self) -> bool {
+		self.num_rows == 0
+	}
+
+	/// Returns the number of bytes the batch's rows occupy once encoded, including message headers.
+	pub fn encoded_len(&self) -> usize {
+		self.data.len()
+	}
+
+	/// Returns the number of bytes the batch can hold without reallocating.
+	pub fn capacity(&self) -> usize {
+		self.data.capacity()
+	}
+}
+
+fn pg_date_epoch() -> NaiveDate {
+	NaiveDate::from_ymd_opt(2000, 1, 1).expect("failed to create pg date epoch")
+}
+
+fn pg_timestamp_epoch() -> NaiveDateTime {
+	pg_date_epoch()
+		.and_hms_opt(0, 0, 0)
+		.expect("failed to create pg timestamp epoch")
+}
+
+fn pg_timestamp_micros(val: NaiveDateTime) -> Result<i64, ErrorResponse> {
+	val.signed_duration_since(pg_timestamp_epoch())
+		.num_microseconds()
+		.ok_or_else(|| ErrorResponse::error(SqlState::DatetimeFieldOverflow, "timestamp out of range"))
+}
+
+// the binary format represents infinite values with the extremes of the underlying integer
+fn pg_date_days(val: &MaybeInfinite<NaiveDate>) -> i32 {
+	match val {
+		MaybeInfinite::Value(date) => date.signed_duration_since(pg_date_epoch()).num_days() as i32,
+		MaybeInfinite::Infinity => i32::MAX,
+		MaybeInfinite::NegativeInfinity => i32::MIN,
+	}
+}
+
+fn pg_timestamp_value(val: &MaybeInfinite<NaiveDateTime>) -> Result<i64, ErrorResponse> {
+	match val {
+		MaybeInfinite::Value(timestamp) => pg_timestamp_micros(*timestamp),
+		MaybeInfinite::Infinity => Ok(i64::MAX),
+		MaybeInfinite::NegativeInfinity => Ok(i64::MIN),
+	}
+}
+
+// Returns true if a value encoded as `value_type` can be sent for a column or element of `expected_type`.
+fn is_compatible_type(expected_type: DataTypeOid, value_type: DataTypeOid, format_code: FormatCode) -> bool {
+	use DataTypeOid::*;
+
+	match (expected_type, value_type) {
+		// nothing to validate against
+		(Unspecified, _) | (Unknown(_), _) => true,
+		(expected, value) if expected == value => true,
+		// strings share a binary representation, and are sent as-is for any type in the text format
+		(Text | Varchar | Bpchar | Name, Text) => true,
+		(Json, Text) => true,
+		(_, Text) => format_code == FormatCode::Text,
+		(expected, value) => match (expected.element_type(), value.element_type()) {
+			(Some(expected), Some(value)) => is_compatible_type(expected, value, format_code),
+			_ => false,
+		},
+	}
+}
+
+fn type_mismatch(expected_type: DataTypeOid, value_type: DataTypeOid) -> ErrorResponse {
+	ErrorResponse::error(
+		SqlState::DatatypeMismatch,
+		format!(
+			"cannot write value of type {:?} for type {:?}",
+			value_type, expected_type
+		),
+	)
 }
 
 macro_rules! primitive_write {
-	($name: ident, $type: ident) => {
+	($name: ident, $type: ident, $oid: ident) => {
 		#[allow(missing_docs)]
-		pub fn $name(&mut self, val: $type) {
-			match self.parent.format_code {
-				FormatCode::Text => self.write_value(&val.to_string().into_bytes()),
-				FormatCode::Binary => self.write_value(&val.to_be_bytes()),
-			};
+		pub fn $name(&mut self, val: $type) -> Result<(), ErrorResponse> {
+			match self.format_code() {
+				FormatCode::Text => self.write_value(DataTypeOid::$oid, &val.to_string().into_bytes()),
+				FormatCode::Binary => self.write_value(DataTypeOid::$oid, &val.to_be_bytes()),
+			}
+		}
+	};
+}
+
+macro_rules! float_write {
+	($name: ident, $type: ident, $oid: ident, $precision: expr) => {
+		#[allow(missing_docs)]
+		pub fn $name(&mut self, val: $type) -> Result<(), ErrorResponse> {
+			match self.format_code() {
+				FormatCode::Text => {
+					let text = format_float(val, $precision, self.settings().extra_float_digits);
+					self.write_value(DataTypeOid::$oid, text.as_bytes())
+				}
+				FormatCode::Binary => self.write_value(DataTypeOid::$oid, &val.to_be_bytes()),
+			}
+		}
+	};
+}
+
+// Implements array writing for rows and records; arrays can't directly contain other arrays,
+// so array elements use ArrayWriter::write_array to add dimensions instead.
+macro_rules! array_write {
+	($type: ty) => {
+		impl $type {
+			/// Writes an array value, using the given element type.
+			///
+			/// Elements are written using the provided [ArrayWriter], and multidimensional arrays
+			/// can be built by nesting calls to [ArrayWriter::write_array].
+			pub fn write_array(
+				&mut self,
+				element_type: DataTypeOid,
+				f: impl FnOnce(&mut ArrayWriter) -> Result<(), ErrorResponse>,
+			) -> Result<(), ErrorResponse> {
+				let mut state = ArrayState::new(self.format_code(), *self.settings(), element_type);
+				let mut writer = ArrayWriter::new(&mut state, 0);
+				f(&mut writer)?;
+				writer.finish()?;
+
+				let array_type = element_type.array_type().unwrap_or(DataTypeOid::Unspecified);
+				self.write_value(array_type, &state.encode())
+			}
+		}
+	};
+}
+
+// Implements the typed value writers for a type with `format_code`, `settings` and `write_value` methods,
+// so that rows, arrays and records all share the same value encoding.
+macro_rules! value_writers {
+	($type: ty) => {
+		impl $type {
+			/// Writes a string value.
+			pub fn write_string(&mut self, val: &str) -> Result<(), ErrorResponse> {
+				self.write_value(DataTypeOid::Text, val.as_bytes())
+			}
+
+			/// Writes a byte array value.
+			/// The text format uses Postgres' hex encoding, e.g. `\x0a0b`.
+			pub fn write_bytea(&mut self, val: &[u8]) -> Result<(), ErrorResponse> {
+				match self.format_code() {
+					FormatCode::Text => {
+						let mut hex = String::with_capacity(2 + val.len() * 2);
+						hex.push_str("\\x");
+						for byte in val {
+							hex.push_str(&format!("{:02x}", byte));
+						}
+						self.write_value(DataTypeOid::Bytea, hex.as_bytes())
+					}
+					FormatCode::Binary => self.write_value(DataTypeOid::Bytea, val),
+				}
+			}
+
+			/// Writes a `json` value. The value must already be serialised JSON.
+			pub fn write_json(&mut self, val: &str) -> Result<(), ErrorResponse> {
+				self.write_value(DataTypeOid::Json, val.as_bytes())
+			}
+
+			/// Writes a `jsonb` value. The value must already be serialised JSON.
+			pub fn write_jsonb(&mut self, val: &str) -> Result<(), ErrorResponse> {
+				match self.format_code() {
+					FormatCode::Text => self.write_value(DataTypeOid::Jsonb, val.as_bytes()),
+					FormatCode::Binary => {
+						// binary jsonb is the text representation prefixed with a format version
+						let mut data = Vec::with_capacity(1 + val.len());
+						data.push(1);
+						data.extend_from_slice(val.as_bytes());
+						self.write_value(DataTypeOid::Jsonb, &data)
+					}
+				}
+			}
+
+			/// Writes a uuid value.
+			pub fn write_uuid(&mut self, val: &Uuid) -> Result<(), ErrorResponse> {
+				match self.format_code() {
+					FormatCode::Text => self.write_value(DataTypeOid::Uuid, val.hyphenated().to_string().as_bytes()),
+					FormatCode::Binary => self.write_value(DataTypeOid::Uuid, val.as_bytes()),
+				}
+			}
+
+			/// Writes a numeric value.
+			pub fn write_numeric(&mut self, val: &Numeric) -> Result<(), ErrorResponse> {
+				match self.format_code() {
+					FormatCode::Text => self.write_value(DataTypeOid::Numeric, val.to_string().as_bytes()),
+					FormatCode::Binary => {
+						let mut data = BytesMut::new();
+						val.encode_binary(&mut data);
+						self.write_value(DataTypeOid::Numeric, &data)
+					}
+				}
+			}
+
+			/// Writes an interval value.
+			pub fn write_interval(&mut self, val: &Interval) -> Result<(), ErrorResponse> {
+				match self.format_code() {
+					FormatCode::Text => {
+						let text = val.format_with_style(self.settings().interval_style);
+						self.write_value(DataTypeOid::Interval, text.as_bytes())
+					}
+					FormatCode::Binary => {
+						let mut data = BytesMut::with_capacity(16);
+						data.put_i64(val.microseconds);
+						data.put_i32(val.days);
+						data.put_i32(val.months);
+						self.write_value(DataTypeOid::Interval, &data)
+					}
+				}
+			}
+
+			/// Writes a bool value.
+			pub fn write_bool(&mut self, val: bool) -> Result<(), ErrorResponse> {
+				match self.format_code() {
+					FormatCode::Text => self.write_value(DataTypeOid::Bool, if val { b"t" } else { b"f" }),
+					FormatCode::Binary => self.write_value(DataTypeOid::Bool, &[val as u8]),
+				}
+			}
+
+			/// Writes a date value, which may be `infinity` or `-infinity`.
+			pub fn write_date(&mut self, val: impl Into<MaybeInfinite<NaiveDate>>) -> Result<(), ErrorResponse> {
+				let val = val.into();
+				match self.format_code() {
+					FormatCode::Binary => self.write_value(DataTypeOid::Date, &pg_date_days(&val).to_be_bytes()),
+					FormatCode::Text => {
+						let text = format_date(&val, self.settings());
+						self.write_value(DataTypeOid::Date, text.as_bytes())
+					}
+				}
+			}
+
+			/// Writes a timestamp value, which may be `infinity` or `-infinity`.
+			pub fn write_timestamp(
+				&mut self,
+				val: impl Into<MaybeInfinite<NaiveDateTime>>,
+			) -> Result<(), ErrorResponse> {
+				let val = val.into();
+				match self.format_code() {
+					FormatCode::Binary => {
+						let micros = pg_timestamp_value(&val)?;
+						self.write_value(DataTypeOid::Timestamp, &micros.to_be_bytes())
+					}
+					FormatCode::Text => {
+						let text = format_timestamp(&val, false, self.settings());
+						self.write_value(DataTypeOid::Timestamp, text.as_bytes())
+					}
+				}
+			}
+
+			/// Writes a time of day value.
+			pub fn write_time(&mut self, val: NaiveTime) -> Result<(), ErrorResponse> {
+				match self.format_code() {
+					FormatCode::Binary => {
+						let micros =
+							val.num_seconds_from_midnight() as i64 * 1_000_000 + (val.nanosecond() / 1000) as i64;
+						self.write_value(DataTypeOid::Time, &micros.to_be_bytes())
+					}
+					FormatCode::Text => {
+						let mut out = String::new();
+						write_time_fields(&mut out, &val);
+						self.write_value(DataTypeOid::Time, out.as_bytes())
+					}
+				}
+			}
+
+			/// Writes a timestamp with time zone value, which may be `infinity` or `-infinity`.
+			/// Text values are always written in UTC, matching the `TimeZone` reported to clients.
+			pub fn write_timestamptz(
+				&mut self,
+				val: impl Into<MaybeInfinite<DateTime<Utc>>>,
+			) -> Result<(), ErrorResponse> {
+				let val = match val.into() {
+					MaybeInfinite::Value(val) => MaybeInfinite::Value(val.naive_utc()),
+					MaybeInfinite::Infinity => MaybeInfinite::Infinity,
+					MaybeInfinite::NegativeInfinity => MaybeInfinite::NegativeInfinity,
+				};
+				match self.format_code() {
+					FormatCode::Binary => {
+						let micros = pg_timestamp_value(&val)?;
+						self.write_value(DataTypeOid::Timestamptz, &micros.to_be_bytes())
+					}
+					FormatCode::Text => {
+						let text = format_timestamp(&val, true, self.settings());
+						self.write_value(DataTypeOid::Timestamptz, text.as_bytes())
+					}
+				}
+			}
+
+			/// Writes a composite (record) value, with fields written using the provided [RecordWriter].
+			pub fn write_record(
+				&mut self,
+				f: impl FnOnce(&mut RecordWriter) -> Result<(), ErrorResponse>,
+			) -> Result<(), ErrorResponse> {
+				let mut writer = RecordWriter::new(self.format_code(), *self.settings());
+				f(&mut writer)?;
+				self.write_value(DataTypeOid::Record, &writer.encode())
+			}
+
+			primitive_write!(write_int2, i16, Int2);
+			primitive_write!(write_int4, i32, Int4);
+			primitive_write!(write_int8, i64, Int8);
+			float_write!(write_float4, f32, Float4, 6);
+			float_write!(write_float8, f64, Float8, 15);
 		}
 	};
 }
 
 /// Temporarily leased from a [DataRowBatch] to encode a single row.
+///
+/// Each value written fills the next column of the row.
 pub struct DataRowWriter<'a> {
 	current_col: usize,
 	parent: &'a mut DataRowBatch,
@@ -67,95 +1013,1661 @@ pub struct DataRowWriter<'a> {
 
 impl<'a> DataRowWriter<'a> {
 	fn new(parent: &'a mut DataRowBatch) -> Self {
-		parent.row.put_i16(parent.num_cols as i16);
 		Self { current_col: 0, parent }
 	}
 
-	fn write_value(&mut self, data: &[u8]) {
-		self.current_col += 1;
-		self.parent.row.put_i32(data.len() as i32);
-		self.parent.row.put_slice(data);
+	// writing beyond the last column is caught by write_value, so any format will do there
+	fn format_code(&self) -> FormatCode {
+		self.parent
+			.format_codes
+			.get(self.current_col)
+			.copied()
+			.unwrap_or(FormatCode::Text)
 	}
 
-	/// Writes a null value for the next column.
-	pub fn write_null(&mut self) {
-		self.current_col += 1;
-		self.parent.row.put_i32(-1);
+	fn settings(&self) -> &SessionSettings {
+		&self.parent.settings
 	}
 
-	/// Writes a string value for the next column.
-	pub fn write_string(&mut self, val: &str) {
-		self.write_value(val.as_bytes());
+	fn next_column(&mut self) -> Result<DataTypeOid, ErrorResponse> {
+		let column_type = *self.parent.column_types.get(self.current_col).ok_or_else(|| {
+			ErrorResponse::error(
+				SqlState::InternalError,
+				format!("row has more than {} columns", self.parent.column_types.len()),
+			)
+		})?;
+
+		self.current_col += 1;
+		Ok(column_type)
 	}
 
-	/// Writes a bool value for the next column.
-	pub fn write_bool(&mut self, val: bool) {
-		match self.parent.format_code {
-			FormatCode::Text => self.write_value(if val { "t" } else { "f" }.as_bytes()),
-			FormatCode::Binary => {
-				self.current_col += 1;
-				self.parent.row.put_u8(val as u8);
-			}
-		};
+	fn check_complete(&self) -> Result<(), ErrorResponse> {
+		if self.current_col != self.parent.column_types.len() {
+			return Err(ErrorResponse::error(
+				SqlState::InternalError,
+				format!(
+					"row has {} columns, expected {}",
+					self.current_col,
+					self.parent.column_types.len()
+				),
+			));
+		}
+
+		Ok(())
 	}
 
-	fn pg_date_epoch() -> NaiveDate {
-		NaiveDate::from_ymd_opt(2000, 1, 1).expect("failed to create pg date epoch")
+	fn write_value(&mut self, data_type: DataTypeOid, data: &[u8]) -> Result<(), ErrorResponse> {
+		// the format must be read before moving on to the next column
+		let format_code = self.format_code();
+		let column_type = self.next_column()?;
+		if !is_compatible_type(column_type, data_type, format_code) {
+			return Err(type_mismatch(column_type, data_type));
+		}
+
+		self.parent.data.put_i32(data.len() as i32);
+		self.parent.data.put_slice(data);
+		Ok(())
 	}
 
-	fn pg_timestamp_epoch() -> NaiveDateTime {
-		Self::pg_date_epoch()
-			.and_hms_opt(0, 0, 0)
-			.expect("failed to create pg timestamp epoch")
+	/// Writes a null value for the next column.
+	pub fn write_null(&mut self) -> Result<(), ErrorResponse> {
+		self.next_column()?;
+		self.parent.data.put_i32(-1);
+		Ok(())
 	}
+}
 
-	/// Writes a date value for the next column.
-	pub fn write_date(&mut self, val: NaiveDate) {
-		match self.parent.format_code {
-			FormatCode::Binary => self.write_int4(val.signed_duration_since(Self::pg_date_epoch()).num_days() as i32),
-			FormatCode::Text => self.write_string(&val.to_string()),
-		}
+value_writers!(DataRowWriter<'_>);
+array_write!(DataRowWriter<'_>);
+
+/// Encodes a Rust value as a column of a row, with a corresponding Postgres type.
+///
+/// This is the counterpart to [FromPgValue], and is used by `#[derive(PgRow)]` to write each field of a struct.
+pub trait ToPgValue {
+	/// Returns the Postgres type used for values of this type.
+	fn data_type() -> DataTypeOid;
+
+	/// Writes the value as the next column of the row.
+	fn write_pg_value(&self, row: &mut DataRowWriter) -> Result<(), ErrorResponse>;
+
+	/// Writes the value as the next column of the row, for a column declared with the given type
+	/// rather than [ToPgValue::data_type]. Types with more than one encoding (such as strings, which may be
+	/// sent as `json` or `jsonb`) use this to pick the right one.
+	fn write_pg_value_as(&self, data_type: DataTypeOid, row: &mut DataRowWriter) -> Result<(), ErrorResponse> {
+		let _ = data_type;
+		self.write_pg_value(row)
 	}
+}
 
-	/// Writes a timestamp value for the next column.
-	pub fn write_timestamp(&mut self, val: NaiveDateTime) {
-		match self.parent.format_code {
-			FormatCode::Binary => {
-				self.write_int8(
-					val.signed_duration_since(Self::pg_timestamp_epoch())
-						.num_microseconds()
-						.unwrap(),
-				);
+macro_rules! to_pg_value {
+	($type: ty, $oid: ident, |$val: ident, $row: ident| $write: expr) => {
+		impl ToPgValue for $type {
+			fn data_type() -> DataTypeOid {
+				DataTypeOid::$oid
+			}
+
+			fn write_pg_value(&self, $row: &mut DataRowWriter) -> Result<(), ErrorResponse> {
+				let $val = self;
+				$write
 			}
-			FormatCode::Text => self.write_string(&val.to_string()),
 		}
+	};
+}
+
+to_pg_value!(bool, Bool, |val, row| row.write_bool(*val));
+to_pg_value!(i16, Int2, |val, row| row.write_int2(*val));
+to_pg_value!(i32, Int4, |val, row| row.write_int4(*val));
+to_pg_value!(i64, Int8, |val, row| row.write_int8(*val));
+to_pg_value!(f32, Float4, |val, row| row.write_float4(*val));
+to_pg_value!(f64, Float8, |val, row| row.write_float8(*val));
+to_pg_value!(Vec<u8>, Bytea, |val, row| row.write_bytea(val));
+to_pg_value!(Uuid, Uuid, |val, row| row.write_uuid(val));
+to_pg_value!(Numeric, Numeric, |val, row| row.write_numeric(val));
+to_pg_value!(Interval, Interval, |val, row| row.write_interval(val));
+to_pg_value!(NaiveDate, Date, |val, row| row.write_date(*val));
+to_pg_value!(MaybeInfinite<NaiveDate>, Date, |val, row| row.write_date(*val));
+to_pg_value!(NaiveTime, Time, |val, row| row.write_time(*val));
+to_pg_value!(NaiveDateTime, Timestamp, |val, row| row.write_timestamp(*val));
+to_pg_value!(MaybeInfinite<NaiveDateTime>, Timestamp, |val, row| row
+	.write_timestamp(*val));
+to_pg_value!(DateTime<Utc>, Timestamptz, |val, row| row.write_timestamptz(*val));
+to_pg_value!(MaybeInfinite<DateTime<Utc>>, Timestamptz, |val, row| row
+	.write_timestamptz(*val));
+
+impl ToPgValue for str {
+	fn data_type() -> DataTypeOid {
+		DataTypeOid::Text
+	}
+
+	fn write_pg_value(&self, row: &mut DataRowWriter) -> Result<(), ErrorResponse> {
+		row.write_string(self)
 	}
 
-	primitive_write!(write_int2, i16);
-	primitive_write!(write_int4, i32);
-	primitive_write!(write_int8, i64);
-	primitive_write!(write_float4, f32);
-	primitive_write!(write_float8, f64);
+	fn write_pg_value_as(&self, data_type: DataTypeOid, row: &mut DataRowWriter) -> Result<(), ErrorResponse> {
+		match data_type {
+			DataTypeOid::Json => row.write_json(self),
+			DataTypeOid::Jsonb => row.write_jsonb(self),
+			_ => row.write_string(self),
+		}
+	}
 }
 
-impl Drop for DataRowWriter<'_> {
-	fn drop(&mut self) {
-		assert_eq!(
-			self.parent.num_cols, self.current_col,
-			"dropped a row writer with an invalid number of columns"
-		);
+impl ToPgValue for String {
+	fn data_type() -> DataTypeOid {
+		str::data_type()
+	}
 
-		self.parent.data.put_u8(b'D');
-		self.parent.data.put_i32((self.parent.row.len() + 4) as i32);
-		self.parent.data.extend(self.parent.row.split());
+	fn write_pg_value(&self, row: &mut DataRowWriter) -> Result<(), ErrorResponse> {
+		self.as_str().write_pg_value(row)
 	}
-}
+
+	fn write_pg_value_as(&self, data_type: DataTypeOid, row: &mut DataRowWriter) -> Result<(), ErrorResponse> {
+		self.as_str().write_pg_value_as(data_type, row)
+	}
+}
+
+impl<T: ToPgValue + ?Sized> ToPgValue for &T {
+	fn data_type() -> DataTypeOid {
+		T::data_type()
+	}
+
+	fn write_pg_value(&self, row: &mut DataRowWriter) -> Result<(), ErrorResponse> {
+		(**self).write_pg_value(row)
+	}
+
+	fn write_pg_value_as(&self, data_type: DataTypeOid, row: &mut DataRowWriter) -> Result<(), ErrorResponse> {
+		(**self).write_pg_value_as(data_type, row)
+	}
+}
+
+/// Writes `None` as a null value.
+impl<T: ToPgValue> ToPgValue for Option<T> {
+	fn data_type() -> DataTypeOid {
+		T::data_type()
+	}
+
+	fn write_pg_value(&self, row: &mut DataRowWriter) -> Result<(), ErrorResponse> {
+		match self {
+			Some(val) => val.write_pg_value(row),
+			None => row.write_null(),
+		}
+	}
+
+	fn write_pg_value_as(&self, data_type: DataTypeOid, row: &mut DataRowWriter) -> Result<(), ErrorResponse> {
+		match self {
+			Some(val) => val.write_pg_value_as(data_type, row),
+			None => row.write_null(),
+		}
+	}
+}
+
+/// A type which is written as a single row, with each field as a column.
+///
+/// Usually implemented with `#[derive(PgRow)]` from the `convergence-derive` crate, which keeps the
+/// field descriptions and the written values in sync.
+pub trait PgRow {
+	/// Returns the descriptions of the columns written by [PgRow::write_row], for use when preparing statements.
+	fn field_descriptions() -> Vec<FieldDescription>;
+
+	/// Writes each field as the next column of the row.
+	fn write_row(&self, row: &mut DataRowWriter) -> Result<(), ErrorResponse>;
+}
+
+struct ArrayState {
+	format_code: FormatCode,
+	settings: SessionSettings,
+	element_type: DataTypeOid,
+	// size of each dimension, filled in as the writers at each depth finish
+	dims: Vec<i32>,
+	// depth at which elements (rather than sub-arrays) were written
+	element_depth: Option<usize>,
+	has_null: bool,
+	data: BytesMut,
+}
+
+impl ArrayState {
+	fn new(format_code: FormatCode, settings: SessionSettings, element_type: DataTypeOid) -> Self {
+		Self {
+			format_code,
+			settings,
+			element_type,
+			dims: Vec::new(),
+			element_depth: None,
+			has_null: false,
+			data: BytesMut::new(),
+		}
+	}
+
+	fn encode(self) -> BytesMut {
+		let is_empty = self.dims.contains(&0);
+
+		match self.format_code {
+			FormatCode::Text if is_empty => BytesMut::from(&b"{}"[..]),
+			FormatCode::Text => self.data,
+			FormatCode::Binary => {
+				let dims = if is_empty { vec![] } else { self.dims };
+				let mut out = BytesMut::with_capacity(12 + dims.len() * 8 + self.data.len());
+				out.put_i32(dims.len() as i32);
+				out.put_i32(self.has_null as i32);
+				out.put_u32(self.element_type.into());
+				for dim in dims {
+					out.put_i32(dim);
+					out.put_i32(1); // lower bound
+				}
+				if !is_empty {
+					out.extend(self.data);
+				}
+				out
+			}
+		}
+	}
+}
+
+/// Temporarily leased to encode the elements of an array value.
+///
+/// Arrays must be rectangular: every sub-array at a given depth must have the same number of elements,
+/// and elements must all be written at the same depth.
+pub struct ArrayWriter<'a> {
+	state: &'a mut ArrayState,
+	depth: usize,
+	count: i32,
+}
+
+impl<'a> ArrayWriter<'a> {
+	fn new(state: &'a mut ArrayState, depth: usize) -> Self {
+		if state.format_code == FormatCode::Text {
+			state.data.put_u8(b'{');
+		}
+
+		Self { state, depth, count: 0 }
+	}
+
+	fn format_code(&self) -> FormatCode {
+		self.state.format_code
+	}
+
+	fn settings(&self) -> &SessionSettings {
+		&self.state.settings
+	}
+
+	fn start_item(&mut self, is_element: bool) -> Result<(), ErrorResponse> {
+		let valid_depth = match self.state.element_depth {
+			Some(depth) if is_element => depth == self.depth,
+			Some(depth) => depth > self.depth,
+			None if is_element => {
+				self.state.element_depth = Some(self.depth);
+				self.state.dims.len() <= self.depth
+			}
+			None => true,
+		};
+
+		if !valid_depth {
+			return Err(ErrorResponse::error(
+				SqlState::ArraySubscriptError,
+				"array elements must all be written at the same depth",
+			));
+		}
+
+		if self.state.format_code == FormatCode::Text && self.count > 0 {
+			self.state.data.put_u8(b',');
+		}
+		self.count += 1;
+		Ok(())
+	}
+
+	fn write_value(&mut self, data_type: DataTypeOid, data: &[u8]) -> Result<(), ErrorResponse> {
+		if !is_compatible_type(self.state.element_type, data_type, self.state.format_code) {
+			return Err(type_mismatch(self.state.element_type, data_type));
+		}
+
+		self.start_item(true)?;
+		match self.state.format_code {
+			FormatCode::Text => write_quoted(&mut self.state.data, data, needs_array_quotes, b'\\'),
+			FormatCode::Binary => {
+				self.state.data.put_i32(data.len() as i32);
+				self.state.data.put_slice(data);
+			}
+		}
+		Ok(())
+	}
+
+	/// Writes a null element.
+	pub fn write_null(&mut self) -> Result<(), ErrorResponse> {
+		self.start_item(true)?;
+		self.state.has_null = true;
+		match self.state.format_code {
+			FormatCode::Text => self.state.data.put_slice(b"NULL"),
+			FormatCode::Binary => self.state.data.put_i32(-1),
+		}
+		Ok(())
+	}
+
+	/// Writes a sub-array, adding a dimension to the array.
+	pub fn write_array(
+		&mut self,
+		f: impl FnOnce(&mut ArrayWriter) -> Result<(), ErrorResponse>,
+	) -> Result<(), ErrorResponse> {
+		self.start_item(false)?;
+		let mut writer = ArrayWriter::new(self.state, self.depth + 1);
+		f(&mut writer)?;
+		writer.finish()
+	}
+
+	fn finish(self) -> Result<(), ErrorResponse> {
+		if self.state.format_code == FormatCode::Text {
+			self.state.data.put_u8(b'}');
+		}
+
+		// inner writers finish first, so dimensions are filled in from the innermost outwards
+		if self.state.dims.len() <= self.depth {
+			self.state.dims.resize(self.depth + 1, -1);
+		}
+
+		let dim = &mut self.state.dims[self.depth];
+		if *dim == -1 {
+			*dim = self.count;
+		}
+
+		if *dim != self.count {
+			return Err(ErrorResponse::error(
+				SqlState::ArraySubscriptError,
+				"multidimensional arrays must have sub-arrays with matching dimensions",
+			));
+		}
+
+		Ok(())
+	}
+}
+
+value_writers!(ArrayWriter<'_>);
+
+/// Temporarily leased to encode the fields of a composite (record) value.
+pub struct RecordWriter {
+	format_code: FormatCode,
+	settings: SessionSettings,
+	num_fields: i32,
+	data: BytesMut,
+}
+
+impl RecordWriter {
+	fn new(format_code: FormatCode, settings: SessionSettings) -> Self {
+		let mut data = BytesMut::new();
+		if format_code == FormatCode::Text {
+			data.put_u8(b'(');
+		}
+
+		Self {
+			format_code,
+			settings,
+			num_fields: 0,
+			data,
+		}
+	}
+
+	fn format_code(&self) -> FormatCode {
+		self.format_code
+	}
+
+	fn settings(&self) -> &SessionSettings {
+		&self.settings
+	}
+
+	fn start_field(&mut self) {
+		if self.format_code == FormatCode::Text && self.num_fields > 0 {
+			self.data.put_u8(b',');
+		}
+		self.num_fields += 1;
+	}
+
+	fn write_value(&mut self, data_type: DataTypeOid, data: &[u8]) -> Result<(), ErrorResponse> {
+		self.start_field();
+		match self.format_code {
+			FormatCode::Text => write_quoted(&mut self.data, data, needs_record_quotes, b'"'),
+			FormatCode::Binary => {
+				self.data.put_u32(data_type.into());
+				self.data.put_i32(data.len() as i32);
+				self.data.put_slice(data);
+			}
+		}
+		Ok(())
+	}
+
+	/// Writes a null field, which is encoded as an unknown type in the binary format.
+	pub fn write_null(&mut self) -> Result<(), ErrorResponse> {
+		self.write_null_as(DataTypeOid::Unspecified)
+	}
+
+	/// Writes a null field with the given type.
+	pub fn write_null_as(&mut self, data_type: DataTypeOid) -> Result<(), ErrorResponse> {
+		self.start_field();
+		if self.format_code == FormatCode::Binary {
+			self.data.put_u32(data_type.into());
+			self.data.put_i32(-1);
+		}
+		Ok(())
+	}
+
+	fn encode(mut self) -> BytesMut {
+		match self.format_code {
+			FormatCode::Text => {
+				self.data.put_u8(b')');
+				self.data
+			}
+			FormatCode::Binary => {
+				let mut out = BytesMut::with_capacity(4 + self.data.len());
+				out.put_i32(self.num_fields);
+				out.extend(self.data);
+				out
+			}
+		}
+	}
+}
+
+value_writers!(RecordWriter);
+array_write!(RecordWriter);
+
+fn is_pg_space(byte: u8) -> bool {
+	matches!(byte, b' ' | b'\t' | b'\n' | b'\r' | 0x0b | 0x0c)
+}
+
+fn needs_array_quotes(data: &[u8]) -> bool {
+	data.is_empty()
+		|| data.eq_ignore_ascii_case(b"NULL")
+		|| data
+			.iter()
+			.any(|&b| matches!(b, b'"' | b'\\' | b'{' | b'}' | b',') || is_pg_space(b))
+}
+
+fn needs_record_quotes(data: &[u8]) -> bool {
+	data.is_empty()
+		|| data
+			.iter()
+			.any(|&b| matches!(b, b'"' | b'\\' | b'(' | b')' | b',') || is_pg_space(b))
+}
+
+// writes a value in an array or record literal, escaping quotes and backslashes with the given escape character
+fn write_quoted(dst: &mut BytesMut, data: &[u8], needs_quotes: fn(&[u8]) -> bool, escape: u8) {
+	if !needs_quotes(data) {
+		dst.put_slice(data);
+		return;
+	}
+
+	dst.put_u8(b'"');
+	for &byte in data {
+		if byte == b'"' || byte == b'\\' {
+			dst.put_u8(if escape == b'"' { byte } else { escape });
+		}
+		dst.put_u8(byte);
+	}
+	dst.put_u8(b'"');
+}
 
 impl Encoder<DataRowBatch> for ConnectionCodec {
 	type Error = ProtocolError;
 
-	fn encode(&mut self, item: DataRowBatch, dst: &mut BytesMut) -> Result<(), Self::Error> {
-		dst.extend(item.data);
+	fn encode(&mut self, mut item: DataRowBatch, dst: &mut BytesMut) -> Result<(), Self::Error> {
+		self.encode(&mut item, dst)
+	}
+}
+
+/// Encodes the batch's rows and clears it, so that it can be reused for further rows.
+impl Encoder<&mut DataRowBatch> for ConnectionCodec {
+	type Error = ProtocolError;
+
+	fn encode(&mut self, item: &mut DataRowBatch, dst: &mut BytesMut) -> Result<(), Self::Error> {
+		if dst.is_empty() {
+			// hand the encoded rows over as the output buffer, and keep its allocation for the next batch
+			std::mem::swap(dst, &mut item.data);
+		} else {
+			dst.extend_from_slice(&item.data);
+		}
+		item.clear();
 		Ok(())
 	}
 }
+
+/// Decodes a Rust value from a Postgres value in the text or binary format,
+/// such as a parameter supplied by a client.
+///
+/// Implementations exist for the Rust counterparts of each [DataTypeOid], as well as [Option] for nullable values
+/// and [Vec] for one-dimensional arrays. Values which can't be parsed produce an `invalid_text_representation` error.
+pub trait FromPgValue: Sized {
+	/// Returns true if values of the given type can be decoded as this type.
+	fn accepts(data_type: DataTypeOid) -> bool;
+
+	/// Decodes a value from its text representation.
+	fn from_text(data_type: DataTypeOid, text: &str) -> Result<Self, ErrorResponse>;
+
+	/// Decodes a value from its binary representation.
+	fn from_binary(data_type: DataTypeOid, data: &[u8]) -> Result<Self, ErrorResponse>;
+
+	/// Decodes a non-null value of the given type and format.
+	///
+	/// Values with an unspecified type are decoded as if they were of the type being decoded.
+	fn from_pg_value(data_type: DataTypeOid, format_code: FormatCode, data: &[u8]) -> Result<Self, ErrorResponse> {
+		let is_unspecified = matches!(data_type, DataTypeOid::Unspecified | DataTypeOid::Unknown(_));
+		if !is_unspecified && !Self::accepts(data_type) {
+			return Err(ErrorResponse::error(
+				SqlState::DatatypeMismatch,
+				format!(
+					"cannot decode value of type {:?} as {}",
+					data_type,
+					std::any::type_name::<Self>()
+				),
+			));
+		}
+
+		match format_code {
+			FormatCode::Text => {
+				let text = std::str::from_utf8(data).map_err(|_| {
+					ErrorResponse::error(
+						SqlState::CharacterNotInRepertoire,
+						"invalid byte sequence for encoding UTF8",
+					)
+				})?;
+				Self::from_text(data_type, text)
+			}
+			FormatCode::Binary => Self::from_binary(data_type, data),
+		}
+	}
+
+	/// Decodes a value of the given type and format which may be null.
+	/// Nulls are only accepted when decoding an [Option].
+	fn from_pg_nullable(
+		data_type: DataTypeOid,
+		format_code: FormatCode,
+		data: Option<&[u8]>,
+	) -> Result<Self, ErrorResponse> {
+		match data {
+			Some(data) => Self::from_pg_value(data_type, format_code, data),
+			None => Err(ErrorResponse::error(
+				SqlState::NullValueNotAllowed,
+				format!("unexpected null value of type {:?}", data_type),
+			)),
+		}
+	}
+}
+
+fn invalid_text(data_type: DataTypeOid, text: &str) -> ErrorResponse {
+	ErrorResponse::error(
+		SqlState::InvalidTextRepresentation,
+		format!("invalid input syntax for type {:?}: \"{}\"", data_type, text),
+	)
+}
+
+fn invalid_binary(data_type: DataTypeOid) -> ErrorResponse {
+	ErrorResponse::error(
+		SqlState::InvalidTextRepresentation,
+		format!("invalid binary representation for type {:?}", data_type),
+	)
+}
+
+fn out_of_range(data_type: DataTypeOid, value: impl Display) -> ErrorResponse {
+	ErrorResponse::error(
+		SqlState::NumericValueOutOfRange,
+		format!("value \"{}\" is out of range for type {:?}", value, data_type),
+	)
+}
+
+// reads a fixed number of bytes from the front of a binary value
+fn read_bytes<const N: usize>(data: &mut &[u8], data_type: DataTypeOid) -> Result<[u8; N], ErrorResponse> {
+	if data.len() < N {
+		return Err(invalid_binary(data_type));
+	}
+
+	let (bytes, rest) = data.split_at(N);
+	*data = rest;
+	Ok(bytes.try_into().expect("slice has the requested length"))
+}
+
+// requires the binary value to be fully consumed by the preceding reads
+fn read_end(data: &[u8], data_type: DataTypeOid) -> Result<(), ErrorResponse> {
+	if data.is_empty() {
+		Ok(())
+	} else {
+		Err(invalid_binary(data_type))
+	}
+}
+
+fn read_exact<const N: usize>(mut data: &[u8], data_type: DataTypeOid) -> Result<[u8; N], ErrorResponse> {
+	let bytes = read_bytes::<N>(&mut data, data_type)?;
+	read_end(data, data_type)?;
+	Ok(bytes)
+}
+
+// reads a binary integer of any width, so that narrower integer types can be decoded as wider ones
+fn read_binary_int(data_type: DataTypeOid, data: &[u8]) -> Result<i64, ErrorResponse> {
+	let size = data_type.size_bytes();
+	if size > 0 && size as usize != data.len() {
+		return Err(invalid_binary(data_type));
+	}
+
+	match data.len() {
+		2 => Ok(i16::from_be_bytes(read_exact(data, data_type)?) as i64),
+		4 => Ok(i32::from_be_bytes(read_exact(data, data_type)?) as i64),
+		8 => Ok(i64::from_be_bytes(read_exact(data, data_type)?)),
+		_ => Err(invalid_binary(data_type)),
+	}
+}
+
+macro_rules! int_from_pg {
+	($type: ty, $($oid: ident),+) => {
+		impl FromPgValue for $type {
+			fn accepts(data_type: DataTypeOid) -> bool {
+				matches!(data_type, $(DataTypeOid::$oid)|+)
+			}
+
+			fn from_text(data_type: DataTypeOid, text: &str) -> Result<Self, ErrorResponse> {
+				let value: i128 = text.trim().parse().map_err(|_| invalid_text(data_type, text))?;
+				<$type>::try_from(value).map_err(|_| out_of_range(data_type, text))
+			}
+
+			fn from_binary(data_type: DataTypeOid, data: &[u8]) -> Result<Self, ErrorResponse> {
+				let value = read_binary_int(data_type, data)?;
+				<$type>::try_from(value).map_err(|_| out_of_range(data_type, value))
+			}
+		}
+	};
+}
+
+int_from_pg!(i16, Int2);
+int_from_pg!(i32, Int2, Int4);
+int_from_pg!(i64, Int2, Int4, Int8);
+int_from_pg!(u32, Oid);
+
+impl FromPgValue for f32 {
+	fn accepts(data_type: DataTypeOid) -> bool {
+		data_type == DataTypeOid::Float4
+	}
+
+	fn from_text(data_type: DataTypeOid, text: &str) -> Result<Self, ErrorResponse> {
+		text.trim().parse().map_err(|_| invalid_text(data_type, text))
+	}
+
+	fn from_binary(data_type: DataTypeOid, data: &[u8]) -> Result<Self, ErrorResponse> {
+		Ok(f32::from_be_bytes(read_exact(data, data_type)?))
+	}
+}
+
+impl FromPgValue for f64 {
+	fn accepts(data_type: DataTypeOid) -> bool {
+		matches!(data_type, DataTypeOid::Float4 | DataTypeOid::Float8)
+	}
+
+	fn from_text(data_type: DataTypeOid, text: &str) -> Result<Self, ErrorResponse> {
+		text.trim().parse().map_err(|_| invalid_text(data_type, text))
+	}
+
+	fn from_binary(data_type: DataTypeOid, data: &[u8]) -> Result<Self, ErrorResponse> {
+		match (data_type, data.len()) {
+			(DataTypeOid::Float4, _) | (DataTypeOid::Unspecified | DataTypeOid::Unknown(_), 4) => {
+				Ok(f32::from_be_bytes(read_exact(data, data_type)?) as f64)
+			}
+			_ => Ok(f64::from_be_bytes(read_exact(data, data_type)?)),
+		}
+	}
+}
+
+impl FromPgValue for bool {
+	fn accepts(data_type: DataTypeOid) -> bool {
+		data_type == DataTypeOid::Bool
+	}
+
+	// accepts the same spellings as Postgres' boolin, including unique prefixes
+	fn from_text(data_type: DataTypeOid, text: &str) -> Result<Self, ErrorResponse> {
+		let value = text.trim().to_ascii_lowercase();
+		let matches = |word: &str, min_len: usize| value.len() >= min_len && word.starts_with(value.as_str());
+
+		if matches("true", 1) || matches("yes", 1) || matches("on", 2) || value == "1" {
+			Ok(true)
+		} else if matches("false", 1) || matches("no", 1) || matches("off", 2) || value == "0" {
+			Ok(false)
+		} else {
+			Err(invalid_text(data_type, text))
+		}
+	}
+
+	fn from_binary(data_type: DataTypeOid, data: &[u8]) -> Result<Self, ErrorResponse> {
+		Ok(read_exact::<1>(data, data_type)?[0] != 0)
+	}
+}
+
+impl FromPgValue for String {
+	fn accepts(data_type: DataTypeOid) -> bool {
+		use DataTypeOid::*;
+		matches!(data_type, Text | Varchar | Bpchar | Name | Json | Jsonb)
+	}
+
+	fn from_text(_: DataTypeOid, text: &str) -> Result<Self, ErrorResponse> {
+		Ok(text.to_owned())
+	}
+
+	fn from_binary(data_type: DataTypeOid, data: &[u8]) -> Result<Self, ErrorResponse> {
+		let data = match data_type {
+			// binary jsonb is the text representation prefixed with a format version
+			DataTypeOid::Jsonb => match data.split_first() {
+				Some((1, text)) => text,
+				_ => return Err(invalid_binary(data_type)),
+			},
+			_ => data,
+		};
+
+		String::from_utf8(data.to_vec()).map_err(|_| {
+			ErrorResponse::error(
+				SqlState::CharacterNotInRepertoire,
+				"invalid byte sequence for encoding UTF8",
+			)
+		})
+	}
+}
+
+impl FromPgValue for Vec<u8> {
+	fn accepts(data_type: DataTypeOid) -> bool {
+		data_type == DataTypeOid::Bytea
+	}
+
+	// accepts both the hex format (`\x0a0b`) and the legacy escape format (`\012\\`)
+	fn from_text(data_type: DataTypeOid, text: &str) -> Result<Self, ErrorResponse> {
+		if let Some(hex) = text.strip_prefix("\\x") {
+			let digits: Vec<u8> = hex.bytes().filter(|b| !is_pg_space(*b)).collect();
+			if !digits.len().is_multiple_of(2) {
+				return Err(invalid_text(data_type, text));
+			}
+
+			return digits
+				.chunks(2)
+				.map(|pair| {
+					std::str::from_utf8(pair)
+						.ok()
+						.and_then(|pair| u8::from_str_radix(pair, 16).ok())
+						.ok_or_else(|| invalid_text(data_type, text))
+				})
+				.collect();
+		}
+
+		let mut out = Vec::with_capacity(text.len());
+		let mut bytes = text.as_bytes();
+		while let Some((&byte, rest)) = bytes.split_first() {
+			if byte != b'\\' {
+				out.push(byte);
+				bytes = rest;
+			} else if rest.first() == Some(&b'\\') {
+				out.push(b'\\');
+				bytes = &rest[1..];
+			} else if rest.len() >= 3 && rest[..3].iter().all(|b| (b'0'..=b'7').contains(b)) && rest[0] <= b'3' {
+				out.push(rest[..3].iter().fold(0, |acc, b| acc * 8 + (b - b'0')));
+				bytes = &rest[3..];
+			} else {
+				return Err(invalid_text(data_type, text));
+			}
+		}
+		Ok(out)
+	}
+
+	fn from_binary(_: DataTypeOid, data: &[u8]) -> Result<Self, ErrorResponse> {
+		Ok(data.to_vec())
+	}
+}
+
+impl FromPgValue for Uuid {
+	fn accepts(data_type: DataTypeOid) -> bool {
+		data_type == DataTypeOid::Uuid
+	}
+
+	fn from_text(data_type: DataTypeOid, text: &str) -> Result<Self, ErrorResponse> {
+		Uuid::parse_str(text.trim()).map_err(|_| invalid_text(data_type, text))
+	}
+
+	fn from_binary(data_type: DataTypeOid, data: &[u8]) -> Result<Self, ErrorResponse> {
+		Ok(Uuid::from_bytes(read_exact(data, data_type)?))
+	}
+}
+
+impl FromPgValue for IpAddr {
+	fn accepts(data_type: DataTypeOid) -> bool {
+		data_type == DataTypeOid::Inet
+	}
+
+	// the network mask, if any, is discarded
+	fn from_text(data_type: DataTypeOid, text: &str) -> Result<Self, ErrorResponse> {
+		let (addr, bits) = match text.trim().split_once('/') {
+			Some((addr, bits)) => (addr, Some(bits)),
+			None => (text.trim(), None),
+		};
+		let addr: IpAddr = addr.parse().map_err(|_| invalid_text(data_type, text))?;
+		let max_bits = if addr.is_ipv4() { 32 } else { 128 };
+
+		match bits.map(str::parse::<u8>) {
+			None => Ok(addr),
+			Some(Ok(bits)) if bits <= max_bits => Ok(addr),
+			Some(_) => Err(invalid_text(data_type, text)),
+		}
+	}
+
+	fn from_binary(data_type: DataTypeOid, mut data: &[u8]) -> Result<Self, ErrorResponse> {
+		const PGSQL_AF_INET: u8 = 2;
+		const PGSQL_AF_INET6: u8 = 3;
+
+		let [family, _bits, _is_cidr, len] = read_bytes::<4>(&mut data, data_type)?;
+		let addr = match (family, len) {
+			(PGSQL_AF_INET, 4) => IpAddr::from(read_bytes::<4>(&mut data, data_type)?),
+			(PGSQL_AF_INET6, 16) => IpAddr::from(read_bytes::<16>(&mut data, data_type)?),
+			_ => return Err(invalid_binary(data_type)),
+		};
+		read_end(data, data_type)?;
+		Ok(addr)
+	}
+}
+
+impl std::str::FromStr for Numeric {
+	type Err = ();
+
+	/// Parses a decimal in Postgres' `numeric` input syntax, e.g. `-12.5`, `1.5e3`, `NaN` or `Infinity`.
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let s = s.trim();
+		match s.to_ascii_lowercase().as_str() {
+			"nan" => return Ok(Self::NaN),
+			"infinity" | "+infinity" | "inf" | "+inf" => return Ok(Self::Infinity),
+			"-infinity" | "-inf" => return Ok(Self::NegativeInfinity),
+			_ => (),
+		}
+
+		let (mantissa, exponent) = match s.find(['e', 'E']) {
+			Some(pos) => (&s[..pos], s[pos + 1..].parse::<i32>().map_err(|_| ())?),
+			None => (s, 0),
+		};
+		let (negative, mantissa) = match mantissa.as_bytes().first() {
+			Some(b'-') => (true, &mantissa[1..]),
+			Some(b'+') => (false, &mantissa[1..]),
+			_ => (false, mantissa),
+		};
+		let (int_part, frac_part) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+		let digits = format!("{}{}", int_part, frac_part);
+		if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+			return Err(());
+		}
+
+		let mut unscaled: i128 = digits.parse().map_err(|_| ())?;
+		let mut scale = frac_part.len() as i64 - exponent as i64;
+		if scale < 0 {
+			unscaled = 10i128
+				.checked_pow((-scale) as u32)
+				.and_then(|factor| unscaled.checked_mul(factor))
+				.ok_or(())?;
+			scale = 0;
+		}
+		let scale = u16::try_from(scale).map_err(|_| ())?;
+
+		Ok(Self::new(if negative { -unscaled } else { unscaled }, scale))
+	}
+}
+
+impl FromPgValue for Numeric {
+	fn accepts(data_type: DataTypeOid) -> bool {
+		data_type == DataTypeOid::Numeric
+	}
+
+	fn from_text(data_type: DataTypeOid, text: &str) -> Result<Self, ErrorResponse> {
+		text.parse().map_err(|_| invalid_text(data_type, text))
+	}
+
+	fn from_binary(data_type: DataTypeOid, mut data: &[u8]) -> Result<Self, ErrorResponse> {
+		let ndigits = i16::from_be_bytes(read_bytes(&mut data, data_type)?);
+		let weight = i16::from_be_bytes(read_bytes(&mut data, data_type)?) as i32;
+		let sign = u16::from_be_bytes(read_bytes(&mut data, data_type)?);
+		let scale = u16::from_be_bytes(read_bytes(&mut data, data_type)?);
+
+		let negative = match sign {
+			0x0000 => false,
+			0x4000 => true,
+			0xC000 => return Ok(Self::NaN),
+			0xD000 => return Ok(Self::Infinity),
+			0xF000 => return Ok(Self::NegativeInfinity),
+			_ => return Err(invalid_binary(data_type)),
+		};
+
+		// each base-10000 digit contributes digit * 10^(4 * power + scale) to the unscaled value,
+		// where digits below the scale are always zero
+		let mut unscaled: i128 = 0;
+		for i in 0..ndigits.max(0) as i32 {
+			let digit = i16::from_be_bytes(read_bytes(&mut data, data_type)?) as i128;
+			let exponent = 4 * (weight - i) + scale as i32;
+			let value = if exponent >= 0 {
+				10i128
+					.checked_pow(exponent as u32)
+					.and_then(|factor| digit.checked_mul(factor))
+			} else {
+				10i128.checked_pow((-exponent) as u32).map(|factor| digit / factor)
+			};
+			unscaled = value
+				.and_then(|value| unscaled.checked_add(value))
+				.ok_or_else(|| ErrorResponse::error(SqlState::NumericValueOutOfRange, "numeric value out of range"))?;
+		}
+		read_end(data, data_type)?;
+
+		Ok(Self::new(if negative { -unscaled } else { unscaled }, scale))
+	}
+}
+
+// parses an unsigned integer made up of only ASCII digits
+fn parse_digits<T: std::str::FromStr>(text: &str) -> Option<T> {
+	if text.is_empty() || !text.bytes().all(|b| b.is_ascii_digit()) {
+		return None;
+	}
+	text.parse().ok()
+}
+
+// why a date, time or interval couldn't be parsed
+#[derive(Clone, Copy, Debug)]
+enum DatetimeError {
+	Invalid,
+	OutOfRange,
+}
+
+fn datetime_error(err: DatetimeError, data_type: DataTypeOid, text: &str) -> ErrorResponse {
+	match err {
+		DatetimeError::Invalid => ErrorResponse::error(
+			SqlState::InvalidDatetimeFormat,
+			format!("invalid input syntax for type {:?}: \"{}\"", data_type, text),
+		),
+		DatetimeError::OutOfRange if data_type == DataTypeOid::Interval => ErrorResponse::error(
+			SqlState::IntervalFieldOverflow,
+			format!("interval field value out of range: \"{}\"", text),
+		),
+		DatetimeError::OutOfRange => ErrorResponse::error(
+			SqlState::DatetimeFieldOverflow,
+			format!("date/time field value out of range: \"{}\"", text),
+		),
+	}
+}
+
+// parses `HH:MM[:SS[.ffffff]]` into microseconds, rounding any further fractional digits
+fn parse_time_micros(text: &str) -> Result<i64, DatetimeError> {
+	let mut parts = text.split(':');
+	let field = |part: Option<&str>| part.and_then(parse_digits::<i64>).ok_or(DatetimeError::Invalid);
+	let hours = field(parts.next())?;
+	let minutes = field(parts.next())?;
+	let (seconds, micros) = match parts.next() {
+		Some(seconds) => {
+			let (seconds, frac) = seconds.split_once('.').unwrap_or((seconds, ""));
+			if !frac.bytes().all(|b| b.is_ascii_digit()) {
+				return Err(DatetimeError::Invalid);
+			}
+			let micros = match frac {
+				"" => 0,
+				frac => (format!("0.{}", frac)
+					.parse::<f64>()
+					.map_err(|_| DatetimeError::Invalid)?
+					* 1_000_000.0)
+					.round() as i64,
+			};
+			(field(Some(seconds))?, micros)
+		}
+		None => (0, 0),
+	};
+	if parts.next().is_some() {
+		return Err(DatetimeError::Invalid);
+	}
+	if minutes > 59 || seconds > 60 {
+		return Err(DatetimeError::OutOfRange);
+	}
+
+	hours
+		.checked_mul(60)
+		.and_then(|val| val.checked_add(minutes))
+		.and_then(|val| val.checked_mul(60))
+		.and_then(|val| val.checked_add(seconds))
+		.and_then(|val| val.checked_mul(1_000_000))
+		.and_then(|val| val.checked_add(micros))
+		.ok_or(DatetimeError::OutOfRange)
+}
+
+// parses the time of a `time` or `timestamp` value, which unlike an interval's can't be later than `24:00:00`
+fn parse_time_of_day_micros(text: &str) -> Result<i64, DatetimeError> {
+	match parse_time_micros(text)? {
+		micros if micros > 86_400_000_000 => Err(DatetimeError::OutOfRange),
+		micros => Ok(micros),
+	}
+}
+
+fn time_from_micros(micros: i64) -> Option<NaiveTime> {
+	if !(0..86_400_000_000).contains(&micros) {
+		return None;
+	}
+	NaiveTime::from_num_seconds_from_midnight_opt((micros / 1_000_000) as u32, (micros % 1_000_000) as u32 * 1000)
+}
+
+// parses a time zone offset such as `Z`, `+02`, `-05:30` or `+0530` into seconds east of UTC
+fn parse_offset(text: &str) -> Option<i32> {
+	if text.eq_ignore_ascii_case("z") || text.eq_ignore_ascii_case("utc") {
+		return Some(0);
+	}
+
+	let (sign, rest) = match text.as_bytes().first()? {
+		b'+' => (1, &text[1..]),
+		b'-' => (-1, &text[1..]),
+		_ => return None,
+	};
+	let fields: Vec<&str> = if rest.contains(':') {
+		rest.split(':').collect()
+	} else if rest.len() > 2 {
+		let (hours, minutes) = rest.split_at_checked(rest.len() - 2)?;
+		vec![hours, minutes]
+	} else {
+		vec![rest]
+	};
+	if fields.len() > 3 {
+		return None;
+	}
+
+	let mut seconds = 0;
+	for (i, field) in fields.iter().enumerate() {
+		seconds += parse_digits::<i32>(field)? * [3600, 60, 1][i];
+	}
+	Some(sign * seconds)
+}
+
+// splits a trailing time zone offset from a time, e.g. `04:05:06+02` or `04:05:06 UTC`
+fn split_offset(text: &str) -> (&str, Option<&str>) {
+	let text = text.trim_end();
+	if let Some(pos) = text.rfind(['+', '-']) {
+		return (text[..pos].trim_end(), Some(&text[pos..]));
+	}
+	for suffix in ["Z", "z", "UTC", "utc"] {
+		if let Some(time) = text.strip_suffix(suffix) {
+			return (time.trim_end(), Some(suffix));
+		}
+	}
+	(text, None)
+}
+
+// parses an ISO date `YYYY-MM-DD`, where BC years are converted to chrono's astronomical numbering
+fn parse_ymd(text: &str, bc: bool) -> Result<NaiveDate, DatetimeError> {
+	let mut parts = text.splitn(3, '-');
+	let year: i32 = parts.next().and_then(parse_digits).ok_or(DatetimeError::Invalid)?;
+	let month: u32 = parts.next().and_then(parse_digits).ok_or(DatetimeError::Invalid)?;
+	let day: u32 = parts.next().and_then(parse_digits).ok_or(DatetimeError::Invalid)?;
+	if year == 0 {
+		return Err(DatetimeError::OutOfRange);
+	}
+	NaiveDate::from_ymd_opt(if bc { 1 - year } else { year }, month, day).ok_or(DatetimeError::OutOfRange)
+}
+
+// handles the special inputs shared by dates and timestamps
+fn parse_special<T>(text: &str, epoch: T) -> Option<MaybeInfinite<T>> {
+	match text.to_ascii_lowercase().as_str() {
+		"infinity" | "+infinity" => Some(MaybeInfinite::Infinity),
+		"-infinity" => Some(MaybeInfinite::NegativeInfinity),
+		"epoch" => Some(MaybeInfinite::Value(epoch)),
+		_ => None,
+	}
+}
+
+fn strip_bc(text: &str) -> (&str, bool) {
+	let text = text.trim();
+	let pos = text.len().saturating_sub(2);
+	match text.get(pos..) {
+		Some(era) if era.eq_ignore_ascii_case("BC") => (text[..pos].trim_end(), true),
+		_ => (text.strip_suffix("AD").unwrap_or(text).trim_end(), false),
+	}
+}
+
+fn unix_epoch() -> NaiveDateTime {
+	NaiveDate::from_ymd_opt(1970, 1, 1)
+		.and_then(|date| date.and_hms_opt(0, 0, 0))
+		.expect("failed to create unix epoch")
+}
+
+// Parses an ISO timestamp, e.g. `2020-01-02 03:04:05.5+02`. When a time zone offset is given, the result is
+// converted to UTC if `apply_offset` is set and the offset is ignored otherwise, as Postgres does for `timestamp`.
+fn parse_timestamp(text: &str, apply_offset: bool) -> Result<MaybeInfinite<NaiveDateTime>, DatetimeError> {
+	if let Some(special) = parse_special(text.trim(), unix_epoch()) {
+		return Ok(special);
+	}
+
+	let (text, bc) = strip_bc(text);
+	let (date, time) = match text.find([' ', 'T']) {
+		Some(pos) => (&text[..pos], Some(text[pos + 1..].trim())),
+		None => (text, None),
+	};
+	let date = parse_ymd(date, bc)?;
+
+	let (micros, offset) = match time {
+		Some(time) => {
+			let (time, offset) = split_offset(time);
+			let offset = match offset {
+				Some(offset) => parse_offset(offset).ok_or(DatetimeError::Invalid)?,
+				None => 0,
+			};
+			(parse_time_of_day_micros(time)?, offset)
+		}
+		None => (0, 0),
+	};
+	let offset = if apply_offset { offset } else { 0 };
+
+	micros
+		.checked_sub(offset as i64 * 1_000_000)
+		.and_then(|micros| {
+			date.and_hms_opt(0, 0, 0)?
+				.checked_add_signed(chrono::Duration::microseconds(micros))
+		})
+		.map(MaybeInfinite::Value)
+		.ok_or(DatetimeError::OutOfRange)
+}
+
+fn finite<T>(val: MaybeInfinite<T>, data_type: DataTypeOid) -> Result<T, ErrorResponse> {
+	match val {
+		MaybeInfinite::Value(val) => Ok(val),
+		_ => Err(ErrorResponse::error(
+			SqlState::DatetimeFieldOverflow,
+			format!("infinite value of type {:?} is out of range", data_type),
+		)),
+	}
+}
+
+fn timestamp_from_micros(micros: i64) -> Result<MaybeInfinite<NaiveDateTime>, ErrorResponse> {
+	match micros {
+		i64::MAX => Ok(MaybeInfinite::Infinity),
+		i64::MIN => Ok(MaybeInfinite::NegativeInfinity),
+		micros => pg_timestamp_epoch()
+			.checked_add_signed(chrono::Duration::microseconds(micros))
+			.map(MaybeInfinite::Value)
+			.ok_or_else(|| ErrorResponse::error(SqlState::DatetimeFieldOverflow, "timestamp out of range")),
+	}
+}
+
+impl FromPgValue for MaybeInfinite<NaiveDate> {
+	fn accepts(data_type: DataTypeOid) -> bool {
+		data_type == DataTypeOid::Date
+	}
+
+	fn from_text(data_type: DataTypeOid, text: &str) -> Result<Self, ErrorResponse> {
+		if let Some(special) = parse_special(text.trim(), unix_epoch().date()) {
+			return Ok(special);
+		}
+
+		let (date, bc) = strip_bc(text);
+		parse_ymd(date, bc)
+			.map(MaybeInfinite::Value)
+			.map_err(|err| datetime_error(err, data_type, text))
+	}
+
+	fn from_binary(data_type: DataTypeOid, data: &[u8]) -> Result<Self, ErrorResponse> {
+		match i32::from_be_bytes(read_exact(data, data_type)?) {
+			i32::MAX => Ok(MaybeInfinite::Infinity),
+			i32::MIN => Ok(MaybeInfinite::NegativeInfinity),
+			days => pg_date_epoch()
+				.checked_add_signed(chrono::Duration::days(days as i64))
+				.map(MaybeInfinite::Value)
+				.ok_or_else(|| ErrorResponse::error(SqlState::DatetimeFieldOverflow, "date out of range")),
+		}
+	}
+}
+
+impl FromPgValue for MaybeInfinite<NaiveDateTime> {
+	fn accepts(data_type: DataTypeOid) -> bool {
+		data_type == DataTypeOid::Timestamp
+	}
+
+	fn from_text(data_type: DataTypeOid, text: &str) -> Result<Self, ErrorResponse> {
+		parse_timestamp(text, false).map_err(|err| datetime_error(err, data_type, text))
+	}
+
+	fn from_binary(data_type: DataTypeOid, data: &[u8]) -> Result<Self, ErrorResponse> {
+		timestamp_from_micros(i64::from_be_bytes(read_exact(data, data_type)?))
+	}
+}
+
+impl FromPgValue for MaybeInfinite<DateTime<Utc>> {
+	fn accepts(data_type: DataTypeOid) -> bool {
+		data_type == DataTypeOid::Timestamptz
+	}
+
+	// values without an offset are taken to be in UTC, matching the `TimeZone` reported to clients
+	fn from_text(data_type: DataTypeOid, text: &str) -> Result<Self, ErrorResponse> {
+		let val = parse_timestamp(text, true).map_err(|err| datetime_error(err, data_type, text))?;
+		Ok(val.map(|val| DateTime::from_naive_utc_and_offset(val, Utc)))
+	}
+
+	fn from_binary(data_type: DataTypeOid, data: &[u8]) -> Result<Self, ErrorResponse> {
+		let val = timestamp_from_micros(i64::from_be_bytes(read_exact(data, data_type)?))?;
+		Ok(val.map(|val| DateTime::from_naive_utc_and_offset(val, Utc)))
+	}
+}
+
+impl<T> MaybeInfinite<T> {
+	/// Converts a finite value using the given function, leaving infinite values as they are.
+	pub fn map<U>(self, f: impl FnOnce(T) -> U) -> MaybeInfinite<U> {
+		match self {
+			Self::Value(val) => MaybeInfinite::Value(f(val)),
+			Self::Infinity => MaybeInfinite::Infinity,
+			Self::NegativeInfinity => MaybeInfinite::NegativeInfinity,
+		}
+	}
+}
+
+// finite date and time types reject infinite values, which require decoding a MaybeInfinite instead
+macro_rules! finite_from_pg {
+	($type: ty) => {
+		impl FromPgValue for $type {
+			fn accepts(data_type: DataTypeOid) -> bool {
+				MaybeInfinite::<$type>::accepts(data_type)
+			}
+
+			fn from_text(data_type: DataTypeOid, text: &str) -> Result<Self, ErrorResponse> {
+				finite(MaybeInfinite::<$type>::from_text(data_type, text)?, data_type)
+			}
+
+			fn from_binary(data_type: DataTypeOid, data: &[u8]) -> Result<Self, ErrorResponse> {
+				finite(MaybeInfinite::<$type>::from_binary(data_type, data)?, data_type)
+			}
+		}
+	};
+}
+
+finite_from_pg!(NaiveDate);
+finite_from_pg!(NaiveDateTime);
+finite_from_pg!(DateTime<Utc>);
+
+impl FromPgValue for NaiveTime {
+	fn accepts(data_type: DataTypeOid) -> bool {
+		data_type == DataTypeOid::Time
+	}
+
+	fn from_text(data_type: DataTypeOid, text: &str) -> Result<Self, ErrorResponse> {
+		let micros = parse_time_of_day_micros(text.trim()).map_err(|err| datetime_error(err, data_type, text))?;
+		time_from_micros(micros).ok_or_else(|| datetime_error(DatetimeError::OutOfRange, data_type, text))
+	}
+
+	fn from_binary(data_type: DataTypeOid, data: &[u8]) -> Result<Self, ErrorResponse> {
+		time_from_micros(i64::from_be_bytes(read_exact(data, data_type)?)).ok_or_else(|| invalid_binary(data_type))
+	}
+}
+
+/// Decodes a `timetz` value as a time of day and its offset from UTC.
+impl FromPgValue for (NaiveTime, FixedOffset) {
+	fn accepts(data_type: DataTypeOid) -> bool {
+		data_type == DataTypeOid::Timetz
+	}
+
+	fn from_text(data_type: DataTypeOid, text: &str) -> Result<Self, ErrorResponse> {
+		let (time, offset) = split_offset(text.trim());
+		let micros = parse_time_of_day_micros(time).map_err(|err| datetime_error(err, data_type, text))?;
+		let time =
+			time_from_micros(micros).ok_or_else(|| datetime_error(DatetimeError::OutOfRange, data_type, text))?;
+		let offset = offset
+			.and_then(parse_offset)
+			.and_then(FixedOffset::east_opt)
+			.ok_or_else(|| datetime_error(DatetimeError::Invalid, data_type, text))?;
+
+		Ok((time, offset))
+	}
+
+	fn from_binary(data_type: DataTypeOid, mut data: &[u8]) -> Result<Self, ErrorResponse> {
+		let micros = i64::from_be_bytes(read_bytes(&mut data, data_type)?);
+		// postgres stores the zone as seconds west of UTC
+		let zone = i32::from_be_bytes(read_bytes(&mut data, data_type)?);
+		read_end(data, data_type)?;
+
+		let time = time_from_micros(micros);
+		let offset = zone.checked_neg().and_then(FixedOffset::east_opt);
+		time.zip(offset).ok_or_else(|| invalid_binary(data_type))
+	}
+}
+
+impl std::str::FromStr for Interval {
+	type Err = ();
+
+	/// Parses an interval written in any of the `IntervalStyle` output formats, e.g. `1 year 2 mons 03:04:05`,
+	/// `@ 1 hour ago` or `P1Y2M`. Each field carries its own sign, so a leading `-` in the `sql_standard`
+	/// format only applies to the first field.
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		parse_interval(s).map_err(|_| ())
+	}
+}
+
+fn parse_interval(s: &str) -> Result<Interval, DatetimeError> {
+	let s = s.trim();
+	let mut builder = IntervalBuilder::default();
+
+	if let Some(iso) = s.strip_prefix('P') {
+		builder.parse_iso_8601(iso)?;
+		return builder.finish(false);
+	}
+
+	let s = s.strip_prefix('@').unwrap_or(s);
+	let (s, ago) = match s.trim_end().strip_suffix("ago") {
+		Some(s) => (s, true),
+		None => (s, false),
+	};
+
+	let tokens: Vec<&str> = s.split_whitespace().collect();
+	if tokens.is_empty() {
+		return Err(DatetimeError::Invalid);
+	}
+
+	let mut i = 0;
+	while i < tokens.len() {
+		let token = tokens[i];
+		if token.contains(':') {
+			builder.add_time(token)?;
+		} else if let Some((years, months)) = split_year_month(token) {
+			// sql_standard year-month field, e.g. `1-2` or `-1-2`
+			let sign = if years.starts_with('-') { -1 } else { 1 };
+			let years: i64 = parse_digits(years.trim_start_matches(['+', '-'])).ok_or(DatetimeError::Invalid)?;
+			let months: i64 = parse_digits(months).ok_or(DatetimeError::Invalid)?;
+			let months = years
+				.checked_mul(12)
+				.and_then(|years| years.checked_add(months))
+				.ok_or(DatetimeError::OutOfRange)?;
+			builder.add_whole_months(sign * months)?;
+		} else {
+			let value: f64 = token.parse().map_err(|_| DatetimeError::Invalid)?;
+			match tokens.get(i + 1) {
+				Some(unit) if unit.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+					builder.add(value, unit)?;
+					i += 1;
+				}
+				// a bare number before a time is a number of days, otherwise it's a number of seconds
+				Some(_) => builder.add(value, "days")?,
+				None => builder.add(value, "seconds")?,
+			}
+		}
+		i += 1;
+	}
+
+	builder.finish(ago)
+}
+
+// splits a `Y-M` field, ignoring any leading sign
+fn split_year_month(token: &str) -> Option<(&str, &str)> {
+	let start = if token.starts_with(['+', '-']) { 1 } else { 0 };
+	let pos = token[start..].find('-')? + start;
+	Some((&token[..pos], &token[pos + 1..]))
+}
+
+// converts a whole number of units, which may be too large for any interval field
+fn whole_units(value: f64) -> Result<i64, DatetimeError> {
+	// i64::MAX isn't representable as an f64, so the upper bound is exclusive
+	if value >= i64::MIN as f64 && value < i64::MAX as f64 {
+		Ok(value as i64)
+	} else {
+		Err(DatetimeError::OutOfRange)
+	}
+}
+
+// accumulates interval fields, carrying fractional parts of larger units into smaller ones as Postgres does
+#[derive(Default)]
+struct IntervalBuilder {
+	months: i64,
+	days: i64,
+	micros: f64,
+}
+
+impl IntervalBuilder {
+	fn add(&mut self, value: f64, unit: &str) -> Result<(), DatetimeError> {
+		// Rust accepts `NaN` and `inf` as numbers, which Postgres doesn't
+		if !value.is_finite() {
+			return Err(DatetimeError::Invalid);
+		}
+
+		let unit = unit.to_ascii_lowercase();
+		let unit = unit.trim_end_matches(',');
+		match unit {
+			"millennium" | "millennia" | "millenniums" | "mil" | "mils" => self.add_months(value * 12_000.0)?,
+			"century" | "centuries" | "c" | "cent" => self.add_months(value * 1200.0)?,
+			"decade" | "decades" | "dec" | "decs" => self.add_months(value * 120.0)?,
+			"year" | "years" | "y" | "yr" | "yrs" => self.add_months(value * 12.0)?,
+			"month" | "months" | "mon" | "mons" => self.add_months(value)?,
+			"week" | "weeks" | "w" => self.add_days(value * 7.0)?,
+			"day" | "days" | "d" => self.add_days(value)?,
+			"hour" | "hours" | "h" | "hr" | "hrs" => self.micros += value * 3_600_000_000.0,
+			"minute" | "minutes" | "m" | "min" | "mins" => self.micros += value * 60_000_000.0,
+			"second" | "seconds" | "s" | "sec" | "secs" => self.micros += value * 1_000_000.0,
+			"millisecond" | "milliseconds" | "ms" | "msec" | "msecs" => self.micros += value * 1000.0,
+			"microsecond" | "microseconds" | "us" | "usec" | "usecs" => self.micros += value,
+			_ => return Err(DatetimeError::Invalid),
+		}
+		Ok(())
+	}
+
+	fn add_whole_months(&mut self, months: i64) -> Result<(), DatetimeError> {
+		self.months = self.months.checked_add(months).ok_or(DatetimeError::OutOfRange)?;
+		Ok(())
+	}
+
+	fn add_months(&mut self, months: f64) -> Result<(), DatetimeError> {
+		let whole = months.trunc();
+		self.add_whole_months(whole_units(whole)?)?;
+		self.add_days((months - whole) * 30.0)
+	}
+
+	fn add_days(&mut self, days: f64) -> Result<(), DatetimeError> {
+		let whole = days.trunc();
+		self.days = self
+			.days
+			.checked_add(whole_units(whole)?)
+			.ok_or(DatetimeError::OutOfRange)?;
+		self.micros += (days - whole) * 86_400_000_000.0;
+		Ok(())
+	}
+
+	// adds a `[-+]H:MM[:SS[.ffffff]]` time field
+	fn add_time(&mut self, text: &str) -> Result<(), DatetimeError> {
+		let (sign, time) = match text.as_bytes().first() {
+			Some(b'-') => (-1.0, &text[1..]),
+			Some(b'+') => (1.0, &text[1..]),
+			_ => (1.0, text),
+		};
+		self.micros += sign * parse_time_micros(time)? as f64;
+		Ok(())
+	}
+
+	fn parse_iso_8601(&mut self, text: &str) -> Result<(), DatetimeError> {
+		let (date, time) = match text.split_once('T') {
+			Some((date, time)) => (date, Some(time)),
+			None => (text, None),
+		};
+
+		for (part, units) in [(date, "YMWD"), (time.unwrap_or(""), "HMS")] {
+			let mut rest = part;
+			while !rest.is_empty() {
+				let pos = rest
+					.find(|c: char| c.is_ascii_alphabetic())
+					.ok_or(DatetimeError::Invalid)?;
+				let value: f64 = rest[..pos].parse().map_err(|_| DatetimeError::Invalid)?;
+				let designator = rest[pos..].chars().next().ok_or(DatetimeError::Invalid)?;
+				let unit = match (units, designator) {
+					("YMWD", 'Y') => "years",
+					("YMWD", 'M') => "months",
+					("YMWD", 'W') => "weeks",
+					("YMWD", 'D') => "days",
+					("HMS", 'H') => "hours",
+					("HMS", 'M') => "minutes",
+					("HMS", 'S') => "seconds",
+					_ => return Err(DatetimeError::Invalid),
+				};
+				self.add(value, unit)?;
+				rest = &rest[pos + 1..];
+			}
+		}
+
+		if date.is_empty() && time.is_none_or(str::is_empty) {
+			return Err(DatetimeError::Invalid);
+		}
+		Ok(())
+	}
+
+	// checks that every field fits in an interval, as Postgres rejects rather than truncates out of range values
+	fn finish(self, negate: bool) -> Result<Interval, DatetimeError> {
+		let sign = |val: i64| if negate { val.checked_neg() } else { Some(val) };
+		let months = sign(self.months).and_then(|months| i32::try_from(months).ok());
+		let days = sign(self.days).and_then(|days| i32::try_from(days).ok());
+		let micros = whole_units(self.micros.round()).ok().and_then(sign);
+
+		match (months, days, micros) {
+			(Some(months), Some(days), Some(micros)) => Ok(Interval::new(months, days, micros)),
+			_ => Err(DatetimeError::OutOfRange),
+		}
+	}
+}
+
+impl FromPgValue for Interval {
+	fn accepts(data_type: DataTypeOid) -> bool {
+		data_type == DataTypeOid::Interval
+	}
+
+	fn from_text(data_type: DataTypeOid, text: &str) -> Result<Self, ErrorResponse> {
+		parse_interval(text).map_err(|err| datetime_error(err, data_type, text))
+	}
+
+	fn from_binary(data_type: DataTypeOid, mut data: &[u8]) -> Result<Self, ErrorResponse> {
+		let microseconds = i64::from_be_bytes(read_bytes(&mut data, data_type)?);
+		let days = i32::from_be_bytes(read_bytes(&mut data, data_type)?);
+		let months = i32::from_be_bytes(read_bytes(&mut data, data_type)?);
+		read_end(data, data_type)?;
+		Ok(Self::new(months, days, microseconds))
+	}
+}
+
+impl<T: FromPgValue> FromPgValue for Option<T> {
+	fn accepts(data_type: DataTypeOid) -> bool {
+		T::accepts(data_type)
+	}
+
+	fn from_text(data_type: DataTypeOid, text: &str) -> Result<Self, ErrorResponse> {
+		T::from_text(data_type, text).map(Some)
+	}
+
+	fn from_binary(data_type: DataTypeOid, data: &[u8]) -> Result<Self, ErrorResponse> {
+		T::from_binary(data_type, data).map(Some)
+	}
+
+	fn from_pg_nullable(
+		data_type: DataTypeOid,
+		format_code: FormatCode,
+		data: Option<&[u8]>,
+	) -> Result<Self, ErrorResponse> {
+		match data {
+			Some(data) => T::from_pg_value(data_type, format_code, data).map(Some),
+			None => Ok(None),
+		}
+	}
+}
+
+fn multidimensional_array() -> ErrorResponse {
+	ErrorResponse::error(
+		SqlState::FeatureNotSupported,
+		"decoding multidimensional arrays is not supported",
+	)
+}
+
+// splits the elements of a one-dimensional text array, returning None for unquoted NULLs
+fn parse_text_array(data_type: DataTypeOid, text: &str) -> Result<Vec<Option<String>>, ErrorResponse> {
+	let inner = text
+		.trim()
+		.strip_prefix('{')
+		.and_then(|inner| inner.strip_suffix('}'))
+		.ok_or_else(|| invalid_text(data_type, text))?;
+
+	let mut elements = Vec::new();
+	if inner.trim().is_empty() {
+		return Ok(elements);
+	}
+
+	let mut chars = inner.chars().peekable();
+	loop {
+		while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+		let element = match chars.peek() {
+			Some('{') => return Err(multidimensional_array()),
+			Some('"') => {
+				chars.next();
+				let mut element = String::new();
+				loop {
+					match chars.next().ok_or_else(|| invalid_text(data_type, text))? {
+						'"' => break,
+						'\\' => element.push(chars.next().ok_or_else(|| invalid_text(data_type, text))?),
+						c => element.push(c),
+					}
+				}
+				while chars.next_if(|c| c.is_whitespace()).is_some() {}
+				Some(element)
+			}
+			_ => {
+				let mut element = String::new();
+				while let Some(c) = chars.next_if(|&c| c != ',') {
+					match c {
+						'"' | '{' | '}' => return Err(invalid_text(data_type, text)),
+						'\\' => element.push(chars.next().ok_or_else(|| invalid_text(data_type, text))?),
+						c => element.push(c),
+					}
+				}
+				let element = element.trim_end();
+				if element.is_empty() {
+					return Err(invalid_text(data_type, text));
+				}
+				(!element.eq_ignore_ascii_case("NULL")).then(|| element.to_owned())
+			}
+		};
+		elements.push(element);
+
+		match chars.next() {
+			Some(',') => continue,
+			None => return Ok(elements),
+			Some(_) => return Err(invalid_text(data_type, text)),
+		}
+	}
+}
+
+/// Decodes a one-dimensional array. Arrays containing nulls must be decoded as a `Vec<Option<T>>`.
+impl<T: FromPgValue> FromPgValue for Vec<T> {
+	fn accepts(data_type: DataTypeOid) -> bool {
+		data_type.element_type().is_some_and(T::accepts)
+	}
+
+	fn from_text(data_type: DataTypeOid, text: &str) -> Result<Self, ErrorResponse> {
+		let element_type = data_type.element_type().unwrap_or(DataTypeOid::Unspecified);
+		parse_text_array(data_type, text)?
+			.iter()
+			.map(|element| T::from_pg_nullable(element_type, FormatCode::Text, element.as_deref().map(str::as_bytes)))
+			.collect()
+	}
+
+	fn from_binary(data_type: DataTypeOid, mut data: &[u8]) -> Result<Self, ErrorResponse> {
+		let ndim = i32::from_be_bytes(read_bytes(&mut data, data_type)?);
+		let _has_null = i32::from_be_bytes(read_bytes(&mut data, data_type)?);
+		let element_type = DataTypeOid::from(u32::from_be_bytes(read_bytes(&mut data, data_type)?));
+
+		let len = match ndim {
+			0 => 0,
+			1 => {
+				let len = i32::from_be_bytes(read_bytes(&mut data, data_type)?);
+				let _lower_bound = i32::from_be_bytes(read_bytes(&mut data, data_type)?);
+				usize::try_from(len).map_err(|_| invalid_binary(data_type))?
+			}
+			n if n > 1 => return Err(multidimensional_array()),
+			_ => return Err(invalid_binary(data_type)),
+		};
+
+		let mut elements = Vec::with_capacity(len.min(data.len() / 4));
+		for _ in 0..len {
+			let element = match i32::from_be_bytes(read_bytes(&mut data, data_type)?) {
+				-1 => None,
+				element_len => {
+					let element_len = usize::try_from(element_len).map_err(|_| invalid_binary(data_type))?;
+					if data.len() < element_len {
+						return Err(invalid_binary(data_type));
+					}
+					let (element, rest) = data.split_at(element_len);
+					data = rest;
+					Some(element)
+				}
+			};
+			elements.push(T::from_pg_nullable(element_type, FormatCode::Binary, element)?);
+		}
+		read_end(data, data_type)?;
+
+		Ok(elements)
+	}
+}
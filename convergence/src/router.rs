@@ -0,0 +1,360 @@
+//! Contains [RouterEngine], which serves several engines from a single endpoint.
+
+use crate::engine::{Engine, Parameters, Portal, PreparedStatement, SessionEndReason, SessionInfo};
+use crate::protocol::{DataTypeOid, ErrorResponse, FieldDescription, FunctionCall, ParameterStatus, SqlState};
+use crate::protocol_ext::DataRowBatch;
+use async_trait::async_trait;
+use bytes::Bytes;
+use sqlparser::ast::{visit_relations_mut, Ident, Statement};
+use std::any::Any;
+use std::ops::ControlFlow;
+
+type AnyStatement = Box<dyn Any + Send + Sync>;
+
+/// A statement prepared by one of a [RouterEngine]'s engines.
+pub struct RoutedStatement {
+	engine: usize,
+	statement: AnyStatement,
+	fields: Vec<FieldDescription>,
+	parameter_types: Vec<DataTypeOid>,
+}
+
+impl PreparedStatement for RoutedStatement {
+	fn fields(&self) -> &[FieldDescription] {
+		&self.fields
+	}
+
+	fn parameter_types(&self) -> &[DataTypeOid] {
+		&self.parameter_types
+	}
+}
+
+/// A portal created by one of a [RouterEngine]'s engines, whichever type of portal that engine uses.
+pub struct RoutedPortal(Box<dyn Portal>);
+
+#[async_trait]
+impl Portal for RoutedPortal {
+	async fn fetch(&mut self, batch: &mut DataRowBatch) -> Result<(), ErrorResponse> {
+		self.0.fetch(batch).await
+	}
+
+	async fn fetch_rows(&mut self, batch: &mut DataRowBatch, max_rows: usize) -> Result<bool, ErrorResponse> {
+		self.0.fetch_rows(batch, max_rows).await
+	}
+}
+
+// an engine with its prepared statement and portal types erased, so that engines of different types can be routed to
+#[async_trait]
+trait AnyEngine: Send + Sync {
+	fn parse(&self, text: &str) -> Result<Vec<Statement>, ErrorResponse>;
+
+	fn parameter_statuses(&self) -> Vec<ParameterStatus>;
+
+	async fn prepare(&mut self, engine: usize, stmt: &Statement) -> Result<RoutedStatement, ErrorResponse>;
+
+	async fn prepare_raw(&mut self, engine: usize, text: &str) -> Result<Option<RoutedStatement>, ErrorResponse>;
+
+	async fn create_portal(
+		&mut self,
+		statement: &AnyStatement,
+		parameters: &Parameters,
+	) -> Result<RoutedPortal, ErrorResponse>;
+
+	async fn on_session_start(&mut self, info: &SessionInfo) -> Result<(), ErrorResponse>;
+
+	async fn on_session_end(&mut self, reason: &SessionEndReason);
+
+	async fn reset(&mut self) -> Result<(), ErrorResponse>;
+
+	async fn call_function(&mut self, call: &FunctionCall) -> Result<Option<Bytes>, ErrorResponse>;
+}
+
+fn erase<S: PreparedStatement + 'static>(engine: usize, statement: S) -> RoutedStatement {
+	RoutedStatement {
+		engine,
+		fields: statement.fields().to_vec(),
+		parameter_types: statement.parameter_types().to_vec(),
+		statement: Box::new(statement),
+	}
+}
+
+#[async_trait]
+impl<E> AnyEngine for E
+where
+	E: Engine,
+	E::PreparedStatementType: 'static,
+	E::PortalType: 'static,
+{
+	fn parse(&self, text: &str) -> Result<Vec<Statement>, ErrorResponse> {
+		Engine::parse(self, text)
+	}
+
+	fn parameter_statuses(&self) -> Vec<ParameterStatus> {
+		Engine::parameter_statuses(self)
+	}
+
+	async fn prepare(&mut self, engine: usize, stmt: &Statement) -> Result<RoutedStatement, ErrorResponse> {
+		let statement = Engine::prepare(self, stmt).await?;
+		Ok(erase(engine, statement))
+	}
+
+	async fn prepare_raw(&mut self, engine: usize, text: &str) -> Result<Option<RoutedStatement>, ErrorResponse> {
+		let statement = Engine::prepare_raw(self, text).await?;
+		Ok(statement.map(|statement| erase(engine, statement)))
+	}
+
+	async fn create_portal(
+		&mut self,
+		statement: &AnyStatement,
+		parameters: &Parameters,
+	) -> Result<RoutedPortal, ErrorResponse> {
+		// statements are always bound by the engine which prepared them
+		let statement = statement
+			.downcast_ref::<E::PreparedStatementType>()
+			.ok_or_else(|| ErrorResponse::error(SqlState::InternalError, "statement prepared by another engine"))?;
+		let portal = Engine::create_portal(self, statement, parameters).await?;
+		Ok(RoutedPortal(Box::new(portal)))
+	}
+
+	async fn on_session_start(&mut self, info: &SessionInfo) -> Result<(), ErrorResponse> {
+		Engine::on_session_start(self, info).await
+	}
+
+	async fn on_session_end(&mut self, reason: &SessionEndReason) {
+		Engine::on_session_end(self, reason).await
+	}
+
+	async fn reset(&mut self) -> Result<(), ErrorResponse> {
+		Engine::reset(self).await
+	}
+
+	async fn call_function(&mut self, call: &FunctionCall) -> Result<Option<Bytes>, ErrorResponse> {
+		Engine::call_function(self, call).await
+	}
+}
+
+/// Returned when configuring a [RouterEngine] with an engine name which hasn't been added.
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+#[error("no engine named \"{0}\" has been added")]
+pub struct UnknownEngineError(pub String);
+
+type Predicate = Box<dyn Fn(&Statement) -> bool + Send + Sync>;
+
+// unquoted identifiers are case-insensitive, as in Postgres
+fn ident_name(ident: &Ident) -> String {
+	match ident.quote_style {
+		Some(_) => ident.value.clone(),
+		None => ident.value.to_lowercase(),
+	}
+}
+
+/// An engine which owns several named engines, possibly of different types, and routes each statement to one of them.
+///
+/// Statements are routed to the first of these which applies:
+/// - the engine given by the first predicate added with [RouterEngine::with_predicate] which matches the statement
+/// - the engine named by the schema of the statement's table references, e.g. `sales` for `sales.orders`,
+///   which is removed from the statement before the engine prepares it
+/// - the engine named by the session's `database` startup parameter
+/// - the default engine, if one was set with [RouterEngine::with_default_engine]
+///
+/// Sessions connecting to a database which isn't the name of an engine are rejected unless there's a default engine.
+/// Queries are parsed with the session's engine's parser, see [Engine::parse], so statements routed to other engines
+/// must also be understood by that parser. Unparsed queries and fast-path function calls go to the session's engine,
+/// as there's nothing to route them by.
+#[derive(Default)]
+pub struct RouterEngine {
+	engines: Vec<(String, Box<dyn AnyEngine>)>,
+	predicates: Vec<(Predicate, usize)>,
+	default_engine: Option<usize>,
+	// the engine named by the session's database, if any
+	session_engine: Option<usize>,
+	// the number of engines which have accepted the session, which are the only ones told when it ends
+	started_engines: usize,
+}
+
+impl RouterEngine {
+	/// Creates a router without any engines.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Adds an engine, whose name is matched against databases and schemas.
+	/// Names are matched case-sensitively, so should be lowercase unless clients quote them.
+	pub fn with_engine<E>(mut self, name: impl Into<String>, engine: E) -> Self
+	where
+		E: Engine,
+		E::PreparedStatementType: 'static,
+		E::PortalType: 'static,
+	{
+		self.engines.push((name.into(), Box::new(engine)));
+		self
+	}
+
+	/// Sets the engine used when a statement can't be routed by any other means, which must already have been added.
+	pub fn with_default_engine(mut self, name: &str) -> Result<Self, UnknownEngineError> {
+		self.default_engine = Some(self.added_engine(name)?);
+		Ok(self)
+	}
+
+	/// Routes statements matching the predicate to the named engine, which must already have been added.
+	pub fn with_predicate(
+		mut self,
+		name: &str,
+		predicate: impl Fn(&Statement) -> bool + Send + Sync + 'static,
+	) -> Result<Self, UnknownEngineError> {
+		let engine = self.added_engine(name)?;
+		self.predicates.push((Box::new(predicate), engine));
+		Ok(self)
+	}
+
+	fn engine_index(&self, name: &str) -> Option<usize> {
+		self.engines.iter().position(|(engine_name, _)| engine_name == name)
+	}
+
+	fn added_engine(&self, name: &str) -> Result<usize, UnknownEngineError> {
+		self.engine_index(name)
+			.ok_or_else(|| UnknownEngineError(name.to_owned()))
+	}
+
+	fn session_engine(&self) -> Result<usize, ErrorResponse> {
+		self.session_engine.or(self.default_engine).ok_or_else(|| {
+			ErrorResponse::error(
+				SqlState::InvalidCatalogName,
+				"no engine has been selected for the session",
+			)
+		})
+	}
+
+	// returns the engine named by the schema of the statement's table references, if any,
+	// removing the schema from the statement
+	fn schema_route(&self, statement: &mut Statement) -> Result<Option<usize>, ErrorResponse> {
+		let mut route = None;
+		let result = visit_relations_mut(statement, |table| {
+			if table.0.len() < 2 {
+				return ControlFlow::Continue(());
+			}
+
+			let engine = match self.engine_index(&ident_name(&table.0[0])) {
+				Some(engine) => engine,
+				None => return ControlFlow::Continue(()),
+			};
+
+			if route.is_some_and(|route| route != engine) {
+				return ControlFlow::Break(());
+			}
+
+			route = Some(engine);
+			table.0.remove(0);
+			ControlFlow::Continue(())
+		});
+
+		match result {
+			ControlFlow::Continue(()) => Ok(route),
+			ControlFlow::Break(()) => Err(ErrorResponse::error(
+				SqlState::FeatureNotSupported,
+				"statements can't reference tables from multiple engines",
+			)),
+		}
+	}
+}
+
+#[async_trait]
+impl Engine for RouterEngine {
+	type PreparedStatementType = RoutedStatement;
+	type PortalType = RoutedPortal;
+
+	fn parse(&self, text: &str) -> Result<Vec<Statement>, ErrorResponse> {
+		let engine = self.session_engine()?;
+		self.engines[engine].1.parse(text)
+	}
+
+	fn parameter_statuses(&self) -> Vec<ParameterStatus> {
+		match self.session_engine() {
+			Ok(engine) => self.engines[engine].1.parameter_statuses(),
+			Err(_) => Vec::new(),
+		}
+	}
+
+	async fn prepare(&mut self, stmt: &Statement) -> Result<Self::PreparedStatementType, ErrorResponse> {
+		let mut stmt = stmt.clone();
+		let predicate_route = self
+			.predicates
+			.iter()
+			.find(|(predicate, _)| predicate(&stmt))
+			.map(|(_, engine)| *engine);
+
+		let engine = match predicate_route {
+			Some(engine) => engine,
+			None => match self.schema_route(&mut stmt)? {
+				Some(engine) => engine,
+				None => self.session_engine()?,
+			},
+		};
+
+		self.engines[engine].1.prepare(engine, &stmt).await
+	}
+
+	async fn create_portal(
+		&mut self,
+		statement: &Self::PreparedStatementType,
+		parameters: &Parameters,
+	) -> Result<Self::PortalType, ErrorResponse> {
+		self.engines[statement.engine]
+			.1
+			.create_portal(&statement.statement, parameters)
+			.await
+	}
+
+	async fn prepare_raw(&mut self, text: &str) -> Result<Option<Self::PreparedStatementType>, ErrorResponse> {
+		let engine = self.session_engine()?;
+		self.engines[engine].1.prepare_raw(engine, text).await
+	}
+
+	async fn on_session_start(&mut self, info: &SessionInfo) -> Result<(), ErrorResponse> {
+		self.session_engine = self.engine_index(info.database());
+		if self.session_engine.is_none() && self.default_engine.is_none() {
+			return Err(ErrorResponse::fatal(
+				SqlState::InvalidCatalogName,
+				format!("database \"{}\" does not exist", info.database()),
+			));
+		}
+
+		for index in 0..self.engines.len() {
+			if let Err(err) = self.engines[index].1.on_session_start(info).await {
+				// the engines which already accepted the session still need to know that it's over
+				let reason = SessionEndReason::Error(err.clone());
+				for (_, engine) in &mut self.engines[..self.started_engines] {
+					engine.on_session_end(&reason).await;
+				}
+				self.started_engines = 0;
+				return Err(err);
+			}
+			self.started_engines += 1;
+		}
+		Ok(())
+	}
+
+	async fn on_session_end(&mut self, reason: &SessionEndReason) {
+		for (_, engine) in &mut self.engines[..self.started_engines] {
+			engine.on_session_end(reason).await;
+		}
+		self.started_engines = 0;
+	}
+
+	// every engine is reset even if one fails, so that none of them keep state from the previous session
+	async fn reset(&mut self) -> Result<(), ErrorResponse> {
+		let mut result = Ok(());
+		for (_, engine) in &mut self.engines {
+			let engine_result = engine.reset().await;
+			if result.is_ok() {
+				result = engine_result;
+			}
+		}
+		result
+	}
+
+	async fn call_function(&mut self, call: &FunctionCall) -> Result<Option<Bytes>, ErrorResponse> {
+		let engine = self.session_engine()?;
+		self.engines[engine].1.call_function(call).await
+	}
+}
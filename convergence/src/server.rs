@@ -1,7 +1,7 @@
 //! Contains utility types and functions for starting and running servers.
 
 use crate::connection::Connection;
-use crate::engine::Engine;
+use crate::engine::{Engine, EngineExt, ReadOnlyLayer};
 use std::pin::Pin;
 use std::sync::Arc;
 use tokio::net::TcpListener;
@@ -11,6 +11,8 @@ use tokio::net::TcpListener;
 pub struct BindOptions {
 	addr: String,
 	port: u16,
+	max_message_size: Option<usize>,
+	read_only: bool,
 }
 
 impl BindOptions {
@@ -20,6 +22,8 @@ impl BindOptions {
 		Self {
 			addr: "127.0.0.1".to_owned(),
 			port: 5432,
+			max_message_size: None,
+			read_only: false,
 		}
 	}
 
@@ -39,17 +43,53 @@ impl BindOptions {
 	pub fn use_all_interfaces(self) -> Self {
 		self.with_addr("0.0.0.0")
 	}
+
+	/// Sets the largest message clients may send, rather than the default of just under 1 GiB.
+	/// Connections sending larger messages are closed with an error.
+	pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+		self.max_message_size = Some(max_message_size);
+		self
+	}
+
+	/// Sets whether connections reject statements which modify data or schema,
+	/// by wrapping each connection's engine in a [ReadOnlyLayer].
+	pub fn with_read_only(mut self, read_only: bool) -> Self {
+		self.read_only = read_only;
+		self
+	}
+
+	// applies the options which are set per connection
+	fn configure<E: Engine>(&self, mut conn: Connection<E>) -> Connection<E> {
+		if let Some(max_message_size) = self.max_message_size {
+			conn = conn.with_max_message_size(max_message_size);
+		}
+		conn
+	}
 }
 
 type EngineFunc<E> = Arc<dyn Fn() -> Pin<Box<dyn futures::Future<Output = E> + Send>> + Send + Sync>;
 
-async fn run_with_listener<E: Engine>(listener: TcpListener, engine_func: EngineFunc<E>) -> std::io::Result<()> {
+async fn run_with_listener<E: Engine>(
+	listener: TcpListener,
+	engine_func: EngineFunc<E>,
+	bind: Arc<BindOptions>,
+) -> std::io::Result<()> {
 	loop {
 		let (stream, _) = listener.accept().await?;
-		let engine_func = engine_func.clone();
+		// responses are already batched until the client needs them, so waiting to coalesce writes
+		// only adds latency, as with Postgres itself. This is best effort, as for any other socket option.
+		let _ = stream.set_nodelay(true);
+		let (engine_func, bind) = (engine_func.clone(), bind.clone());
 		tokio::spawn(async move {
-			let mut conn = Connection::new(engine_func().await);
-			conn.run(stream).await.unwrap();
+			let engine = engine_func().await;
+			// fatal errors have already been reported to the client by the time the connection ends
+			let _ = if bind.read_only {
+				bind.configure(Connection::new(engine.with_layer(&ReadOnlyLayer)))
+					.run(stream)
+					.await
+			} else {
+				bind.configure(Connection::new(engine)).run(stream).await
+			};
 		});
 	}
 }
@@ -58,8 +98,8 @@ async fn run_with_listener<E: Engine>(listener: TcpListener, engine_func: Engine
 ///
 /// Does not return unless the server terminates entirely.
 pub async fn run<E: Engine>(bind: BindOptions, engine_func: EngineFunc<E>) -> std::io::Result<()> {
-	let listener = TcpListener::bind((bind.addr, bind.port)).await?;
-	run_with_listener(listener, engine_func).await
+	let listener = TcpListener::bind((bind.addr.as_str(), bind.port)).await?;
+	run_with_listener(listener, engine_func, Arc::new(bind)).await
 }
 
 /// Starts a server using a function responsible for producing engine instances and set of bind options.
@@ -69,10 +109,10 @@ pub async fn run<E: Engine>(bind: BindOptions, engine_func: EngineFunc<E>) -> st
 ///
 /// Useful for creating test harnesses binding to port 0 to select a random port.
 pub async fn run_background<E: Engine>(bind: BindOptions, engine_func: EngineFunc<E>) -> std::io::Result<u16> {
-	let listener = TcpListener::bind((bind.addr, bind.port)).await?;
+	let listener = TcpListener::bind((bind.addr.as_str(), bind.port)).await?;
 	let port = listener.local_addr()?.port();
 
-	tokio::spawn(async move { run_with_listener(listener, engine_func).await });
+	tokio::spawn(async move { run_with_listener(listener, engine_func, Arc::new(bind)).await });
 
 	Ok(port)
 }
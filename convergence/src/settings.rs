@@ -0,0 +1,237 @@
+//! Contains [SessionSettings], the per-connection settings that control how values are formatted as text.
+
+use crate::protocol::{ErrorResponse, ParameterStatus, SqlState};
+
+/// The output format for dates and timestamps, the first component of Postgres' `DateStyle` setting.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DateStyle {
+	/// `1997-12-17 07:37:16`
+	Iso,
+	/// `12/17/1997 07:37:16`
+	Sql,
+	/// `Wed Dec 17 07:37:16 1997`
+	Postgres,
+	/// `17.12.1997 07:37:16`
+	German,
+}
+
+impl DateStyle {
+	fn name(&self) -> &'static str {
+		match self {
+			Self::Iso => "ISO",
+			Self::Sql => "SQL",
+			Self::Postgres => "Postgres",
+			Self::German => "German",
+		}
+	}
+}
+
+/// The ordering of day, month and year fields, the second component of Postgres' `DateStyle` setting.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DateOrder {
+	/// Day, month, year.
+	Dmy,
+	/// Month, day, year.
+	Mdy,
+	/// Year, month, day.
+	Ymd,
+}
+
+impl DateOrder {
+	fn name(&self) -> &'static str {
+		match self {
+			Self::Dmy => "DMY",
+			Self::Mdy => "MDY",
+			Self::Ymd => "YMD",
+		}
+	}
+}
+
+/// The output format for intervals, as set by Postgres' `IntervalStyle` setting.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IntervalStyle {
+	/// `1 year 2 mons 3 days 04:05:06`
+	Postgres,
+	/// `@ 1 year 2 mons 3 days 4 hours 5 mins 6 secs`
+	PostgresVerbose,
+	/// `1-2 3 4:05:06`
+	SqlStandard,
+	/// `P1Y2M3DT4H5M6S`
+	Iso8601,
+}
+
+impl IntervalStyle {
+	fn name(&self) -> &'static str {
+		match self {
+			Self::Postgres => "postgres",
+			Self::PostgresVerbose => "postgres_verbose",
+			Self::SqlStandard => "sql_standard",
+			Self::Iso8601 => "iso_8601",
+		}
+	}
+}
+
+/// Session settings which affect the text representation of values.
+///
+/// Each connection starts with Postgres' defaults, which may be changed by startup parameters or `SET` statements.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SessionSettings {
+	/// The date and timestamp output format.
+	pub date_style: DateStyle,
+	/// The ordering of date fields, for formats which aren't always year first.
+	pub date_order: DateOrder,
+	/// The interval output format.
+	pub interval_style: IntervalStyle,
+	/// Adjusts the number of digits used for floating point values.
+	/// Positive values use the shortest representation that round-trips exactly.
+	pub extra_float_digits: i32,
+}
+
+impl Default for SessionSettings {
+	fn default() -> Self {
+		Self {
+			date_style: DateStyle::Iso,
+			date_order: DateOrder::Mdy,
+			interval_style: IntervalStyle::Postgres,
+			extra_float_digits: 1,
+		}
+	}
+}
+
+const DATE_STYLE: &str = "DateStyle";
+const INTERVAL_STYLE: &str = "IntervalStyle";
+const EXTRA_FLOAT_DIGITS: &str = "extra_float_digits";
+
+fn invalid_value(name: &str, value: &str) -> ErrorResponse {
+	ErrorResponse::error(
+		SqlState::InvalidParameterValue,
+		format!("invalid value for parameter \"{}\": \"{}\"", name, value),
+	)
+}
+
+impl SessionSettings {
+	/// Returns true if the named setting is handled by [SessionSettings::set]. Names are case-insensitive.
+	pub fn is_setting(name: &str) -> bool {
+		Self::canonical_name(name).is_some()
+	}
+
+	fn canonical_name(name: &str) -> Option<&'static str> {
+		[DATE_STYLE, INTERVAL_STYLE, EXTRA_FLOAT_DIGITS]
+			.iter()
+			.find(|setting| setting.eq_ignore_ascii_case(name))
+			.copied()
+	}
+
+	/// Updates a setting from its textual value, as given in a startup parameter or `SET` statement.
+	/// A value of `DEFAULT` restores the setting's default.
+	pub fn set(&mut self, name: &str, value: &str) -> Result<(), ErrorResponse> {
+		let name = Self::canonical_name(name).ok_or_else(|| {
+			ErrorResponse::error(
+				SqlState::UndefinedObject,
+				format!("unrecognized configuration parameter \"{}\"", name),
+			)
+		})?;
+
+		let is_default = value.trim().eq_ignore_ascii_case("default");
+		let defaults = Self::default();
+
+		match name {
+			DATE_STYLE if is_default => {
+				self.date_style = defaults.date_style;
+				self.date_order = defaults.date_order;
+			}
+			DATE_STYLE => {
+				let (date_style, date_order) =
+					self.parse_date_style(value).ok_or_else(|| invalid_value(name, value))?;
+				self.date_style = date_style;
+				self.date_order = date_order;
+			}
+			INTERVAL_STYLE if is_default => self.interval_style = defaults.interval_style,
+			INTERVAL_STYLE => {
+				self.interval_style = match value.trim().to_ascii_lowercase().as_str() {
+					"postgres" => IntervalStyle::Postgres,
+					"postgres_verbose" => IntervalStyle::PostgresVerbose,
+					"sql_standard" => IntervalStyle::SqlStandard,
+					"iso_8601" => IntervalStyle::Iso8601,
+					_ => return Err(invalid_value(name, value)),
+				}
+			}
+			EXTRA_FLOAT_DIGITS if is_default => self.extra_float_digits = defaults.extra_float_digits,
+			_ => {
+				self.extra_float_digits = value
+					.trim()
+					.parse()
+					.ok()
+					.filter(|digits| (-15..=3).contains(digits))
+					.ok_or_else(|| invalid_value(name, value))?
+			}
+		}
+
+		Ok(())
+	}
+
+	// follows check_datestyle: either component may be omitted, in which case it keeps its current value,
+	// except that choosing the German style without an order implies DMY
+	fn parse_date_style(&self, value: &str) -> Option<(DateStyle, DateOrder)> {
+		let mut date_style = None;
+		let mut date_order = None;
+
+		for token in value
+			.split(|c: char| c == ',' || c.is_whitespace())
+			.filter(|t| !t.is_empty())
+		{
+			let (style, order) = match token.to_ascii_uppercase().as_str() {
+				"ISO" => (Some(DateStyle::Iso), None),
+				"SQL" => (Some(DateStyle::Sql), None),
+				"POSTGRES" => (Some(DateStyle::Postgres), None),
+				"GERMAN" => (Some(DateStyle::German), None),
+				"YMD" => (None, Some(DateOrder::Ymd)),
+				"DMY" | "EURO" | "EUROPEAN" => (None, Some(DateOrder::Dmy)),
+				"MDY" | "US" | "NONEURO" | "NONEUROPEAN" => (None, Some(DateOrder::Mdy)),
+				_ => return None,
+			};
+
+			// conflicting components are rejected, but repeating the same one is fine
+			if style.is_some() && date_style.is_some() && style != date_style {
+				return None;
+			}
+			if order.is_some() && date_order.is_some() && order != date_order {
+				return None;
+			}
+			date_style = style.or(date_style);
+			date_order = order.or(date_order);
+		}
+
+		if date_style == Some(DateStyle::German) && date_order.is_none() {
+			date_order = Some(DateOrder::Dmy);
+		}
+
+		Some((
+			date_style.unwrap_or(self.date_style),
+			date_order.unwrap_or(self.date_order),
+		))
+	}
+
+	/// Returns the value of `DateStyle` as reported to clients, e.g. `ISO, MDY`.
+	pub fn date_style_value(&self) -> String {
+		format!("{}, {}", self.date_style.name(), self.date_order.name())
+	}
+
+	/// Returns the [ParameterStatus] message reporting the named setting's value, if clients are notified of
+	/// changes to it.
+	pub fn parameter_status(&self, name: &str) -> Option<ParameterStatus> {
+		match Self::canonical_name(name)? {
+			DATE_STYLE => Some(ParameterStatus::new(DATE_STYLE, self.date_style_value())),
+			INTERVAL_STYLE => Some(ParameterStatus::new(INTERVAL_STYLE, self.interval_style.name())),
+			_ => None,
+		}
+	}
+
+	/// Returns the [ParameterStatus] messages for all reported settings, as sent during startup.
+	pub fn parameter_statuses(&self) -> Vec<ParameterStatus> {
+		[DATE_STYLE, INTERVAL_STYLE]
+			.iter()
+			.filter_map(|name| self.parameter_status(name))
+			.collect()
+	}
+}
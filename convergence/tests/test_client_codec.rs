@@ -0,0 +1,415 @@
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use convergence::engine::{Engine, Parameters, Portal};
+use convergence::protocol::*;
+use convergence::protocol_ext::DataRowBatch;
+use convergence::server::{self, BindOptions};
+use futures::{SinkExt, StreamExt};
+use sqlparser::ast::Statement;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio_util::codec::{Decoder, Encoder, Framed};
+
+struct ReturnSingleScalarPortal;
+
+#[async_trait]
+impl Portal for ReturnSingleScalarPortal {
+	async fn fetch(&mut self, batch: &mut DataRowBatch) -> Result<(), ErrorResponse> {
+		batch.write_row(|row| row.write_int4(1))
+	}
+}
+
+struct ReturnSingleScalarEngine;
+
+#[async_trait]
+impl Engine for ReturnSingleScalarEngine {
+	type PreparedStatementType = Vec<FieldDescription>;
+	type PortalType = ReturnSingleScalarPortal;
+
+	async fn prepare(&mut self, _: &Statement) -> Result<Vec<FieldDescription>, ErrorResponse> {
+		Ok(vec![FieldDescription {
+			name: "test".to_owned(),
+			data_type: DataTypeOid::Int4,
+		}])
+	}
+
+	async fn create_portal(
+		&mut self,
+		_: &Self::PreparedStatementType,
+		_: &Parameters,
+	) -> Result<Self::PortalType, ErrorResponse> {
+		Ok(ReturnSingleScalarPortal)
+	}
+}
+
+fn startup() -> ClientMessage {
+	let mut parameters = HashMap::new();
+	parameters.insert("user".to_owned(), "test".to_owned());
+	parameters.insert("DateStyle".to_owned(), "German".to_owned());
+
+	ClientMessage::Startup(Startup {
+		requested_protocol_version: (3, 0),
+		parameters,
+	})
+}
+
+// encodes each message with the client codec and checks the server decodes it unchanged
+fn assert_client_round_trip(messages: Vec<ClientMessage>) {
+	let mut client = ClientCodec::new();
+	let mut server = ConnectionCodec::new();
+	let mut buf = BytesMut::new();
+
+	for message in messages {
+		client.encode(message.clone(), &mut buf).unwrap();
+		assert_eq!(server.decode(&mut buf).unwrap(), Some(message));
+		assert!(buf.is_empty());
+	}
+}
+
+fn assert_server_round_trip(messages: Vec<ServerMessage>) {
+	let mut client = ClientCodec::new();
+	let mut server = ConnectionCodec::new();
+	let mut buf = BytesMut::new();
+
+	for message in messages {
+		server.encode(message.clone(), &mut buf).unwrap();
+		assert_eq!(client.decode(&mut buf).unwrap(), Some(message));
+		assert!(buf.is_empty());
+	}
+}
+
+#[test]
+fn client_messages() {
+	assert_client_round_trip(vec![
+		startup(),
+		ClientMessage::Parse(Parse {
+			prepared_statement_name: "stmt".to_owned(),
+			query: "select $1".to_owned(),
+			parameter_types: vec![DataTypeOid::Int4],
+		}),
+		ClientMessage::Describe(Describe::PreparedStatement("stmt".to_owned())),
+		ClientMessage::Bind(Bind {
+			portal: "portal".to_owned(),
+			prepared_statement_name: "stmt".to_owned(),
+			parameter_format: BindFormat::PerColumn(vec![FormatCode::Binary, FormatCode::Text]),
+			parameters: vec![Some(Bytes::from_static(&[0, 0, 0, 1])), None],
+			result_format: BindFormat::All(FormatCode::Binary),
+		}),
+		ClientMessage::Describe(Describe::Portal("portal".to_owned())),
+		ClientMessage::Execute(Execute {
+			portal: "portal".to_owned(),
+			max_rows: Some(10),
+		}),
+		ClientMessage::Flush,
+		ClientMessage::Close(Close::Portal("portal".to_owned())),
+		ClientMessage::Close(Close::PreparedStatement("stmt".to_owned())),
+		ClientMessage::Sync,
+		ClientMessage::Query("select 1".to_owned()),
+		ClientMessage::FunctionCall(FunctionCall {
+			function_oid: 764,
+			argument_format: BindFormat::All(FormatCode::Binary),
+			arguments: vec![Some(Bytes::from_static(&[0, 0, 0, 1])), None],
+			result_format: FormatCode::Text,
+		}),
+		ClientMessage::Terminate,
+	]);
+
+	assert_client_round_trip(vec![ClientMessage::SSLRequest]);
+	assert_client_round_trip(vec![ClientMessage::GSSENCRequest]);
+	assert_client_round_trip(vec![ClientMessage::CancelRequest(CancelRequest {
+		process_id: 1,
+		secret_key: vec![2; 32],
+	})]);
+}
+
+#[test]
+fn server_messages() {
+	assert_server_round_trip(vec![
+		ServerMessage::AuthenticationOk,
+		ServerMessage::ParameterStatus(ParameterStatus::new("TimeZone", "UTC")),
+		ServerMessage::ReadyForQuery(TransactionStatus::Idle),
+		ServerMessage::ReadyForQuery(TransactionStatus::InTransaction),
+		ServerMessage::ReadyForQuery(TransactionStatus::Failed),
+		ServerMessage::ParseComplete,
+		ServerMessage::ParameterDescription(ParameterDescription {
+			parameter_types: vec![DataTypeOid::Int4, DataTypeOid::Unknown(12345)],
+		}),
+		ServerMessage::RowDescription(
+			RowDescription::with_bind_format(
+				vec![
+					FieldDescription {
+						name: "a".to_owned(),
+						data_type: DataTypeOid::Int4,
+					},
+					FieldDescription {
+						name: "b".to_owned(),
+						data_type: DataTypeOid::Text,
+					},
+				],
+				&BindFormat::PerColumn(vec![FormatCode::Binary, FormatCode::Text]),
+			)
+			.unwrap(),
+		),
+		ServerMessage::BindComplete,
+		ServerMessage::DataRow(DataRow {
+			values: vec![Some(Bytes::from_static(&[0, 0, 0, 1])), None, Some(Bytes::new())],
+		}),
+		ServerMessage::CommandComplete(CommandComplete {
+			command_tag: "SELECT 1".to_owned(),
+		}),
+		ServerMessage::NoData,
+		ServerMessage::CloseComplete,
+		ServerMessage::FunctionCallResponse(FunctionCallResponse {
+			result: Some(Bytes::from_static(b"2")),
+		}),
+		ServerMessage::FunctionCallResponse(FunctionCallResponse { result: None }),
+		ServerMessage::EmptyQueryResponse,
+		ServerMessage::ErrorResponse(ErrorResponse::fatal(SqlState::QueryCanceled, "canceled")),
+		ServerMessage::ErrorResponse(
+			ErrorResponse::error(SqlState::SyntaxError, "syntax error")
+				.with_field(b'S', "FEHLER")
+				.with_field(b'D', "detail")
+				.with_field(b'H', "hint")
+				.with_field(b'P', "8"),
+		),
+		ServerMessage::NegotiateProtocolVersion(NegotiateProtocolVersion {
+			newest_minor_version: 2,
+			unrecognized_options: vec!["_pq_.a".to_owned(), "_pq_.b".to_owned()],
+		}),
+		ServerMessage::BackendKeyData(BackendKeyData {
+			process_id: 42,
+			secret_key: vec![7; 32],
+		}),
+		ServerMessage::AuthenticationRequest(AuthenticationRequest::KerberosV5),
+		ServerMessage::AuthenticationRequest(AuthenticationRequest::CleartextPassword),
+		ServerMessage::AuthenticationRequest(AuthenticationRequest::Md5Password { salt: [1, 2, 3, 4] }),
+		ServerMessage::AuthenticationRequest(AuthenticationRequest::Gss),
+		ServerMessage::AuthenticationRequest(AuthenticationRequest::GssContinue(Bytes::from_static(b"token"))),
+		ServerMessage::AuthenticationRequest(AuthenticationRequest::Sspi),
+		ServerMessage::AuthenticationRequest(AuthenticationRequest::Sasl {
+			mechanisms: vec!["SCRAM-SHA-256-PLUS".to_owned(), "SCRAM-SHA-256".to_owned()],
+		}),
+		ServerMessage::AuthenticationRequest(AuthenticationRequest::SaslContinue(Bytes::from_static(b"r=abc"))),
+		ServerMessage::AuthenticationRequest(AuthenticationRequest::SaslFinal(Bytes::from_static(b"v=xyz"))),
+		ServerMessage::NoticeResponse(NoticeResponse {
+			sql_state: SqlState::Warning,
+			severity: "WARNING".to_owned(),
+			message: "hello".to_owned(),
+			fields: vec![(b'D', "detail".to_owned())],
+		}),
+		ServerMessage::NotificationResponse(NotificationResponse {
+			process_id: 42,
+			channel: "events".to_owned(),
+			payload: "{}".to_owned(),
+		}),
+		ServerMessage::PortalSuspended,
+		ServerMessage::Other {
+			tag: b'?',
+			body: Bytes::from_static(b"unknown"),
+		},
+	]);
+}
+
+#[test]
+fn legacy_notice_response() {
+	// servers before 9.6 only send the localised severity
+	let mut buf = BytesMut::from(&b"N\0\0\0\x15SWARNING\0Mhello\0\0"[..]);
+	assert_eq!(
+		ClientCodec::new().decode(&mut buf).unwrap(),
+		Some(ServerMessage::NoticeResponse(NoticeResponse {
+			sql_state: SqlState::InternalError,
+			severity: "WARNING".to_owned(),
+			message: "hello".to_owned(),
+			fields: vec![],
+		}))
+	);
+}
+
+#[test]
+fn ssl_response() {
+	let mut client = ClientCodec::new();
+	let mut buf = BytesMut::new();
+	client.encode(ClientMessage::SSLRequest, &mut buf).unwrap();
+
+	let mut buf = BytesMut::from(&b"N"[..]);
+	assert_eq!(
+		client.decode(&mut buf).unwrap(),
+		Some(ServerMessage::SSLResponse(false))
+	);
+
+	client
+		.encode(ClientMessage::GSSENCRequest, &mut BytesMut::new())
+		.unwrap();
+	let mut buf = BytesMut::from(&b"G"[..]);
+	assert_eq!(
+		client.decode(&mut buf).unwrap(),
+		Some(ServerMessage::GSSENCResponse(true))
+	);
+
+	// only valid replies to the request are accepted
+	client
+		.encode(ClientMessage::GSSENCRequest, &mut BytesMut::new())
+		.unwrap();
+	let mut buf = BytesMut::from(&b"S"[..]);
+	assert!(client.decode(&mut buf).is_err());
+}
+
+#[test]
+fn partial_messages() {
+	let mut server = ConnectionCodec::new();
+	let mut encoded = BytesMut::new();
+	server
+		.encode(
+			CommandComplete {
+				command_tag: "SELECT 1".to_owned(),
+			},
+			&mut encoded,
+		)
+		.unwrap();
+
+	let mut client = ClientCodec::new();
+	let mut buf = BytesMut::new();
+	for &byte in &encoded[..encoded.len() - 1] {
+		buf.extend_from_slice(&[byte]);
+		assert_eq!(client.decode(&mut buf).unwrap(), None);
+	}
+
+	buf.extend_from_slice(&encoded[encoded.len() - 1..]);
+	assert!(matches!(
+		client.decode(&mut buf).unwrap(),
+		Some(ServerMessage::CommandComplete(_))
+	));
+}
+
+#[test]
+fn data_row_batch() {
+	let mut batch = DataRowBatch::new(FormatCode::Text, 2);
+	batch
+		.write_row(|row| {
+			row.write_int4(1)?;
+			row.write_null()
+		})
+		.unwrap();
+
+	let mut buf = BytesMut::new();
+	ConnectionCodec::new().encode(batch, &mut buf).unwrap();
+	assert_eq!(
+		ClientCodec::new().decode(&mut buf).unwrap(),
+		Some(ServerMessage::DataRow(DataRow {
+			values: vec![Some(Bytes::from_static(b"1")), None],
+		}))
+	);
+}
+
+#[test]
+fn malformed_server_messages() {
+	let mut client = ClientCodec::new();
+
+	// length too short to include itself
+	let mut buf = BytesMut::from(&[b'C', 0, 0, 0, 3][..]);
+	assert!(client.decode(&mut buf).is_err());
+
+	// unterminated command tag
+	let mut buf = BytesMut::from(&[b'C', 0, 0, 0, 6, b'S', b'E'][..]);
+	assert!(client.decode(&mut buf).is_err());
+
+	// column value longer than the message
+	let mut buf = BytesMut::from(&[b'D', 0, 0, 0, 11, 0, 1, 0, 0, 0, 9, 1][..]);
+	assert!(client.decode(&mut buf).is_err());
+
+	// unknown transaction status
+	let mut buf = BytesMut::from(&[b'Z', 0, 0, 0, 5, b'X'][..]);
+	assert!(client.decode(&mut buf).is_err());
+
+	// unknown authentication request
+	let mut buf = BytesMut::from(&[b'R', 0, 0, 0, 8, 0, 0, 0, 99][..]);
+	assert!(client.decode(&mut buf).is_err());
+
+	// truncated MD5 salt
+	let mut buf = BytesMut::from(&[b'R', 0, 0, 0, 10, 0, 0, 0, 5, 1, 2][..]);
+	assert!(client.decode(&mut buf).is_err());
+}
+
+#[tokio::test]
+async fn simple_query_session() {
+	let port = server::run_background(
+		BindOptions::new().with_port(0),
+		Arc::new(|| Box::pin(async { ReturnSingleScalarEngine })),
+	)
+	.await
+	.unwrap();
+
+	let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+	let mut framed = Framed::new(stream, ClientCodec::new());
+
+	framed.send(ClientMessage::SSLRequest).await.unwrap();
+	assert_eq!(framed.next().await.unwrap().unwrap(), ServerMessage::SSLResponse(false));
+
+	framed.send(startup()).await.unwrap();
+	assert_eq!(framed.next().await.unwrap().unwrap(), ServerMessage::AuthenticationOk);
+
+	let mut statuses = HashMap::new();
+	loop {
+		match framed.next().await.unwrap().unwrap() {
+			ServerMessage::ParameterStatus(status) => {
+				statuses.insert(status.name().to_owned(), status.value().to_owned());
+			}
+			ServerMessage::BackendKeyData(key_data) => assert_eq!(key_data.secret_key.len(), 4),
+			ServerMessage::ReadyForQuery(_) => break,
+			other => panic!("unexpected message: {:?}", other),
+		}
+	}
+	assert_eq!(statuses["DateStyle"], "German, DMY");
+
+	framed.send(ClientMessage::Query("select 1".to_owned())).await.unwrap();
+
+	let mut messages = Vec::new();
+	loop {
+		match framed.next().await.unwrap().unwrap() {
+			ServerMessage::ReadyForQuery(_) => break,
+			other => messages.push(other),
+		}
+	}
+
+	assert_eq!(
+		messages,
+		vec![
+			ServerMessage::RowDescription(RowDescription::with_format(
+				vec![FieldDescription {
+					name: "test".to_owned(),
+					data_type: DataTypeOid::Int4,
+				}],
+				FormatCode::Text
+			)),
+			ServerMessage::DataRow(DataRow {
+				values: vec![Some(Bytes::from_static(b"1"))],
+			}),
+			ServerMessage::CommandComplete(CommandComplete {
+				command_tag: "SELECT 1".to_owned(),
+			}),
+		]
+	);
+
+	// engines don't provide any functions by default
+	framed
+		.send(ClientMessage::FunctionCall(FunctionCall {
+			function_oid: 764,
+			argument_format: BindFormat::All(FormatCode::Text),
+			arguments: vec![],
+			result_format: FormatCode::Text,
+		}))
+		.await
+		.unwrap();
+	match framed.next().await.unwrap().unwrap() {
+		ServerMessage::ErrorResponse(err) => assert_eq!(err.sql_state, SqlState::UndefinedFunction),
+		other => panic!("unexpected message: {:?}", other),
+	}
+	assert_eq!(
+		framed.next().await.unwrap().unwrap(),
+		ServerMessage::ReadyForQuery(TransactionStatus::Idle)
+	);
+
+	framed.send(ClientMessage::Terminate).await.unwrap();
+	assert!(framed.next().await.is_none());
+}
@@ -1,20 +1,35 @@
 use async_trait::async_trait;
-use convergence::engine::{Engine, Portal};
-use convergence::protocol::{DataTypeOid, ErrorResponse, FieldDescription, SqlState};
-use convergence::protocol_ext::DataRowBatch;
+use bytes::Bytes;
+use convergence::engine::{
+	Engine, EngineExt, Parameters, Portal, PreparedStatement, ReadOnlyLayer, SessionEndReason, SessionInfo,
+};
+use convergence::plan_cache::PlanCache;
+use convergence::protocol::{
+	BackendKeyData, Bind, BindFormat, CancelRequest, ClientCodec, ClientMessage, Close, DataTypeOid, ErrorResponse,
+	Execute, FieldDescription, FormatCode, FunctionCall, FunctionCallResponse, NegotiateProtocolVersion,
+	ParameterStatus, Parse, ServerMessage, SqlState, Startup,
+};
+use convergence::protocol_ext::{DataRowBatch, FromPgValue};
 use convergence::server::{self, BindOptions};
+use futures::future::try_join_all;
+use futures::{SinkExt, StreamExt};
 use sqlparser::ast::{Expr, SelectItem, SetExpr, Statement};
-use std::sync::Arc;
-use tokio_postgres::{connect, NoTls, SimpleQueryMessage};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio_postgres::error::Severity;
+use tokio_postgres::tls::NoTlsStream;
+use tokio_postgres::types::Type;
+use tokio_postgres::{connect, Client, Connection, NoTls, SimpleQueryMessage, Socket};
+use tokio_util::codec::Framed;
 
 struct ReturnSingleScalarPortal;
 
 #[async_trait]
 impl Portal for ReturnSingleScalarPortal {
 	async fn fetch(&mut self, batch: &mut DataRowBatch) -> Result<(), ErrorResponse> {
-		let mut row = batch.create_row();
-		row.write_int4(1);
-		Ok(())
+		batch.write_row(|row| row.write_int4(1))
 	}
 }
 
@@ -22,6 +37,7 @@ struct ReturnSingleScalarEngine;
 
 #[async_trait]
 impl Engine for ReturnSingleScalarEngine {
+	type PreparedStatementType = Vec<FieldDescription>;
 	type PortalType = ReturnSingleScalarPortal;
 
 	async fn prepare(&mut self, statement: &Statement) -> Result<Vec<FieldDescription>, ErrorResponse> {
@@ -45,12 +61,79 @@ impl Engine for ReturnSingleScalarEngine {
 		}])
 	}
 
-	async fn create_portal(&mut self, _: &Statement) -> Result<Self::PortalType, ErrorResponse> {
+	async fn create_portal(
+		&mut self,
+		_: &Self::PreparedStatementType,
+		_: &Parameters,
+	) -> Result<Self::PortalType, ErrorResponse> {
 		Ok(ReturnSingleScalarPortal)
 	}
+
+	// commands starting with '!' aren't SQL, but return a single row like any other query
+	async fn prepare_raw(&mut self, text: &str) -> Result<Option<Vec<FieldDescription>>, ErrorResponse> {
+		Ok(text.starts_with('!').then(|| {
+			vec![FieldDescription {
+				name: "test".to_owned(),
+				data_type: DataTypeOid::Int4,
+			}]
+		}))
+	}
+
+	// function 1 sums its int4 arguments, treating nulls as zero
+	async fn call_function(&mut self, call: &FunctionCall) -> Result<Option<Bytes>, ErrorResponse> {
+		if call.function_oid != 1 {
+			return Err(ErrorResponse::error(SqlState::UndefinedFunction, "no such function"));
+		}
+
+		let mut sum = 0;
+		for (arg, format_code) in call.arguments.iter().zip(call.argument_formats()?) {
+			sum += Option::<i32>::from_pg_nullable(DataTypeOid::Int4, format_code, arg.as_deref())?.unwrap_or(0);
+		}
+
+		Ok(Some(match call.result_format {
+			FormatCode::Text => Bytes::from(sum.to_string()),
+			FormatCode::Binary => Bytes::copy_from_slice(&sum.to_be_bytes()),
+		}))
+	}
+}
+
+struct ReturnRowsPortal;
+
+#[async_trait]
+impl Portal for ReturnRowsPortal {
+	async fn fetch(&mut self, batch: &mut DataRowBatch) -> Result<(), ErrorResponse> {
+		for i in 1..=10 {
+			batch.write_row(|row| row.write_int4(i))?;
+		}
+		Ok(())
+	}
+}
+
+// returns the numbers 1 to 10 for any query
+struct ReturnRowsEngine;
+
+#[async_trait]
+impl Engine for ReturnRowsEngine {
+	type PreparedStatementType = Vec<FieldDescription>;
+	type PortalType = ReturnRowsPortal;
+
+	async fn prepare(&mut self, _: &Statement) -> Result<Vec<FieldDescription>, ErrorResponse> {
+		Ok(vec![FieldDescription {
+			name: "n".to_owned(),
+			data_type: DataTypeOid::Int4,
+		}])
+	}
+
+	async fn create_portal(
+		&mut self,
+		_: &Self::PreparedStatementType,
+		_: &Parameters,
+	) -> Result<Self::PortalType, ErrorResponse> {
+		Ok(ReturnRowsPortal)
+	}
 }
 
-async fn setup() -> tokio_postgres::Client {
+async fn setup_with_conn() -> (Client, Connection<Socket, NoTlsStream>) {
 	let port = server::run_background(
 		BindOptions::new().with_port(0),
 		Arc::new(|| Box::pin(async { ReturnSingleScalarEngine })),
@@ -58,9 +141,13 @@ async fn setup() -> tokio_postgres::Client {
 	.await
 	.unwrap();
 
-	let (client, conn) = connect(&format!("postgres://localhost:{}/test", port), NoTls)
+	connect(&format!("postgres://localhost:{}/test", port), NoTls)
 		.await
-		.expect("failed to init client");
+		.expect("failed to init client")
+}
+
+async fn setup() -> Client {
+	let (client, conn) = setup_with_conn().await;
 
 	tokio::spawn(async move { conn.await.unwrap() });
 
@@ -107,6 +194,43 @@ async fn error_handling() {
 	assert_eq!(err.code().unwrap().code(), SqlState::DataException.code());
 }
 
+#[tokio::test]
+async fn error_keeps_connection_open() {
+	let client = setup().await;
+	client
+		.simple_query("select test_error from blah")
+		.await
+		.expect_err("expected error in query");
+
+	let messages = client.simple_query("select 1").await.unwrap();
+	assert_eq!(messages.len(), 3);
+	assert!(!client.is_closed());
+}
+
+#[tokio::test]
+async fn fatal_error_closes_connection() {
+	let (client, conn) = setup_with_conn().await;
+	let conn_task = tokio::spawn(conn);
+
+	let err = client
+		.query_one("select test_fatal from blah", &[])
+		.await
+		.expect_err("expected error in query");
+
+	let db_err = err.as_db_error().expect("expected db error");
+	assert_eq!(db_err.code().code(), SqlState::DataException.code());
+	assert_eq!(db_err.severity(), "FATAL");
+	assert_eq!(db_err.parsed_severity(), Some(Severity::Fatal));
+
+	// the server should hang up, ending the client's connection task
+	let _ = conn_task.await.unwrap();
+	assert!(client.is_closed());
+	client
+		.query_one("select 1", &[])
+		.await
+		.expect_err("expected closed connection");
+}
+
 #[tokio::test]
 async fn set_variable_noop() {
 	let client = setup().await;
@@ -116,6 +240,46 @@ async fn set_variable_noop() {
 		.expect("failed to set var");
 }
 
+#[tokio::test]
+async fn set_session_setting() {
+	let client = setup().await;
+	client
+		.simple_query("SET DateStyle = 'SQL, DMY'")
+		.await
+		.expect("failed to set DateStyle");
+	client
+		.simple_query("SET extra_float_digits TO 0")
+		.await
+		.expect("failed to set extra_float_digits");
+
+	let err = client
+		.simple_query("SET IntervalStyle = bogus")
+		.await
+		.expect_err("expected invalid value error");
+	assert_eq!(
+		err.code(),
+		Some(&tokio_postgres::error::SqlState::INVALID_PARAMETER_VALUE)
+	);
+
+	// the connection remains usable afterwards
+	client.simple_query("select 1").await.unwrap();
+}
+
+#[tokio::test]
+async fn unparsed_queries() {
+	let client = setup().await;
+
+	let messages = client.simple_query("!status").await.unwrap();
+	assert!(matches!(&messages[1], SimpleQueryMessage::Row(row) if row.get(0) == Some("1")));
+
+	let row = client.query_one("!status", &[]).await.unwrap();
+	assert_eq!(row.get::<_, i32>(0), 1);
+
+	// text the engine doesn't accept is still a syntax error
+	let err = client.simple_query("status").await.unwrap_err();
+	assert_eq!(err.code().unwrap().code(), SqlState::SyntaxError.code());
+}
+
 #[tokio::test]
 async fn empty_simple_query() {
 	let client = setup().await;
@@ -127,3 +291,1041 @@ async fn empty_extended_query() {
 	let client = setup().await;
 	client.query("", &[]).await.unwrap();
 }
+
+#[tokio::test]
+async fn oversized_message_closes_connection() {
+	let port = server::run_background(
+		BindOptions::new().with_port(0).with_max_message_size(1024),
+		Arc::new(|| Box::pin(async { ReturnSingleScalarEngine })),
+	)
+	.await
+	.unwrap();
+
+	let (client, conn) = connect(&format!("postgres://localhost:{}/test", port), NoTls)
+		.await
+		.expect("failed to init client");
+	let conn_task = tokio::spawn(conn);
+
+	client.simple_query("select 1").await.unwrap();
+
+	let query = format!("select 1 -- {}", "x".repeat(2048));
+	let err = client.simple_query(&query).await.expect_err("expected error in query");
+	let db_err = err.as_db_error().expect("expected db error");
+	assert_eq!(db_err.code().code(), SqlState::ProtocolViolation.code());
+	assert_eq!(db_err.severity(), "FATAL");
+
+	let _ = conn_task.await.unwrap();
+	assert!(client.is_closed());
+}
+
+// sends a startup message with the given protocol version and parameters,
+// returning the server's responses up to the first ReadyForQuery or until it hangs up
+async fn connect_raw() -> Framed<TcpStream, ClientCodec> {
+	let port = server::run_background(
+		BindOptions::new().with_port(0),
+		Arc::new(|| Box::pin(async { ReturnSingleScalarEngine })),
+	)
+	.await
+	.unwrap();
+
+	let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+	Framed::new(stream, ClientCodec::new())
+}
+
+fn startup_message(version: (i16, i16), parameters: &[(&str, &str)]) -> ClientMessage {
+	ClientMessage::Startup(Startup {
+		requested_protocol_version: version,
+		parameters: parameters
+			.iter()
+			.map(|&(name, value)| (name.to_owned(), value.to_owned()))
+			.collect(),
+	})
+}
+
+// returns the server's responses up to the first ReadyForQuery or until it hangs up
+async fn read_until_ready(framed: &mut Framed<TcpStream, ClientCodec>) -> Vec<ServerMessage> {
+	let mut messages = Vec::new();
+	while let Some(message) = framed.next().await {
+		match message.unwrap() {
+			ServerMessage::ReadyForQuery(_) => break,
+			other => messages.push(other),
+		}
+	}
+	messages
+}
+
+async fn raw_startup(version: (i16, i16), parameters: &[(&str, &str)]) -> Vec<ServerMessage> {
+	let mut framed = connect_raw().await;
+	framed.send(startup_message(version, parameters)).await.unwrap();
+	read_until_ready(&mut framed).await
+}
+
+fn single_error(messages: &[ServerMessage]) -> &ErrorResponse {
+	match messages {
+		[ServerMessage::ErrorResponse(err)] => err,
+		other => panic!("unexpected messages: {:?}", other),
+	}
+}
+
+fn key_data(messages: &[ServerMessage]) -> &BackendKeyData {
+	messages
+		.iter()
+		.find_map(|message| match message {
+			ServerMessage::BackendKeyData(key_data) => Some(key_data),
+			_ => None,
+		})
+		.expect("expected key data")
+}
+
+#[tokio::test]
+async fn protocol_3_0() {
+	let messages = raw_startup((3, 0), &[("user", "test")]).await;
+	assert_eq!(messages[0], ServerMessage::AuthenticationOk);
+	assert!(!messages
+		.iter()
+		.any(|message| matches!(message, ServerMessage::NegotiateProtocolVersion(_))));
+
+	let key_data = key_data(&messages);
+	assert!(key_data.process_id > 0);
+	assert_eq!(key_data.secret_key.len(), 4);
+}
+
+#[tokio::test]
+async fn protocol_3_2() {
+	let messages = raw_startup((3, 2), &[("user", "test")]).await;
+	assert_eq!(messages[0], ServerMessage::AuthenticationOk);
+	assert_eq!(key_data(&messages).secret_key.len(), 32);
+
+	// keys are random
+	let other_messages = raw_startup((3, 2), &[("user", "test")]).await;
+	assert_ne!(key_data(&messages), key_data(&other_messages));
+}
+
+#[tokio::test]
+async fn protocol_negotiation() {
+	let messages = raw_startup((3, 9), &[("user", "test")]).await;
+	assert_eq!(
+		messages[0],
+		ServerMessage::NegotiateProtocolVersion(NegotiateProtocolVersion {
+			newest_minor_version: 2,
+			unrecognized_options: vec![],
+		})
+	);
+	assert_eq!(messages[1], ServerMessage::AuthenticationOk);
+	assert_eq!(key_data(&messages).secret_key.len(), 32);
+
+	// unknown protocol options are reported even when the version is supported
+	let messages = raw_startup((3, 0), &[("user", "test"), ("_pq_.b", "1"), ("_pq_.a", "1")]).await;
+	assert_eq!(
+		messages[0],
+		ServerMessage::NegotiateProtocolVersion(NegotiateProtocolVersion {
+			newest_minor_version: 0,
+			unrecognized_options: vec!["_pq_.a".to_owned(), "_pq_.b".to_owned()],
+		})
+	);
+	assert_eq!(key_data(&messages).secret_key.len(), 4);
+}
+
+#[tokio::test]
+async fn unsupported_protocol_major_version() {
+	let messages = raw_startup((2, 0), &[("user", "test")]).await;
+	let err = single_error(&messages);
+	assert_eq!(err.sql_state, SqlState::FeatureNotSupported);
+	assert!(err.severity.is_fatal());
+	assert_eq!(
+		err.message,
+		"unsupported frontend protocol 2.0: server supports 3.0 to 3.2"
+	);
+
+	// unknown special request codes are rejected in the same way
+	let messages = raw_startup((1234, 5681), &[("user", "test")]).await;
+	assert_eq!(single_error(&messages).sql_state, SqlState::FeatureNotSupported);
+}
+
+#[tokio::test]
+async fn encryption_requests_are_declined() {
+	let mut framed = connect_raw().await;
+
+	framed.send(ClientMessage::GSSENCRequest).await.unwrap();
+	assert_eq!(
+		framed.next().await.unwrap().unwrap(),
+		ServerMessage::GSSENCResponse(false)
+	);
+
+	framed.send(ClientMessage::SSLRequest).await.unwrap();
+	assert_eq!(framed.next().await.unwrap().unwrap(), ServerMessage::SSLResponse(false));
+
+	framed.send(startup_message((3, 0), &[("user", "test")])).await.unwrap();
+	let messages = read_until_ready(&mut framed).await;
+	assert_eq!(messages[0], ServerMessage::AuthenticationOk);
+}
+
+#[tokio::test]
+async fn cancel_request_closes_connection() {
+	let mut framed = connect_raw().await;
+	framed
+		.send(ClientMessage::CancelRequest(CancelRequest {
+			process_id: 1,
+			secret_key: vec![0; 4],
+		}))
+		.await
+		.unwrap();
+	assert!(framed.next().await.is_none());
+}
+
+#[tokio::test]
+async fn malformed_startup_packets() {
+	let messages = raw_startup((3, 0), &[("database", "test")]).await;
+	let err = single_error(&messages);
+	assert_eq!(err.sql_state, SqlState::InvalidAuthorizationSpecification);
+	assert!(err.severity.is_fatal());
+	assert_eq!(err.message, "no PostgreSQL user name specified in startup packet");
+
+	// a parameter list without its terminator
+	let mut framed = connect_raw().await;
+	framed
+		.get_mut()
+		.write_all(&[0, 0, 0, 13, 0, 3, 0, 0, b'u', b's', b'e', b'r', 0])
+		.await
+		.unwrap();
+	let messages = read_until_ready(&mut framed).await;
+	let err = single_error(&messages);
+	assert_eq!(err.sql_state, SqlState::ProtocolViolation);
+	assert!(err.severity.is_fatal());
+	assert_eq!(
+		err.message,
+		"invalid startup packet layout: expected terminator as last byte"
+	);
+}
+
+#[tokio::test]
+async fn pipelined_queries() {
+	let client = setup().await;
+
+	// tokio-postgres pipelines concurrent queries on one connection, each ending in its own Sync
+	let queries = (0..20).map(|_| client.query_one("select 1", &[]));
+	let rows = try_join_all(queries).await.unwrap();
+	assert!(rows.iter().all(|row| row.get::<_, i32>(0) == 1));
+
+	// an error only affects its own query
+	let (ok, err, other_ok) = tokio::join!(
+		client.query_one("select 1", &[]),
+		client.query_one("select test_error from blah", &[]),
+		client.query_one("select 1", &[]),
+	);
+	assert_eq!(ok.unwrap().get::<_, i32>(0), 1);
+	assert_eq!(err.unwrap_err().code().unwrap().code(), SqlState::DataException.code());
+	assert_eq!(other_ok.unwrap().get::<_, i32>(0), 1);
+
+	// dropping a prepared statement closes it on the server
+	let statement = client.prepare("select 1").await.unwrap();
+	client.query_one(&statement, &[]).await.unwrap();
+	drop(statement);
+	client.query_one("select 1", &[]).await.unwrap();
+}
+
+fn parse_message(name: &str, query: &str) -> ClientMessage {
+	ClientMessage::Parse(Parse {
+		prepared_statement_name: name.to_owned(),
+		query: query.to_owned(),
+		parameter_types: vec![],
+	})
+}
+
+fn bind_message(name: &str) -> ClientMessage {
+	ClientMessage::Bind(Bind {
+		portal: "".to_owned(),
+		prepared_statement_name: name.to_owned(),
+		parameter_format: BindFormat::All(FormatCode::Text),
+		parameters: vec![],
+		result_format: BindFormat::All(FormatCode::Text),
+	})
+}
+
+fn execute_message() -> ClientMessage {
+	ClientMessage::Execute(Execute {
+		portal: "".to_owned(),
+		max_rows: None,
+	})
+}
+
+async fn started_raw() -> Framed<TcpStream, ClientCodec> {
+	let mut framed = connect_raw().await;
+	framed.send(startup_message((3, 0), &[("user", "test")])).await.unwrap();
+	read_until_ready(&mut framed).await;
+	framed
+}
+
+#[tokio::test]
+async fn pipeline_error_skips_until_sync() {
+	let mut framed = started_raw().await;
+
+	for message in [
+		parse_message("", "select test_error from blah"),
+		bind_message(""),
+		execute_message(),
+		ClientMessage::Sync,
+		parse_message("", "select 1"),
+		bind_message(""),
+		execute_message(),
+		ClientMessage::Sync,
+	] {
+		framed.feed(message).await.unwrap();
+	}
+	framed.flush().await.unwrap();
+
+	// the failed parse's bind and execute are skipped, with a single ReadyForQuery for the whole batch
+	let messages = read_until_ready(&mut framed).await;
+	assert_eq!(single_error(&messages).sql_state, SqlState::DataException);
+
+	let messages = read_until_ready(&mut framed).await;
+	assert_eq!(messages[0], ServerMessage::ParseComplete);
+	assert_eq!(messages[1], ServerMessage::BindComplete);
+	assert!(matches!(messages[2], ServerMessage::DataRow(_)));
+	assert!(matches!(messages[3], ServerMessage::CommandComplete(_)));
+}
+
+#[tokio::test]
+async fn flush_and_close() {
+	let mut framed = started_raw().await;
+
+	// responses are only sent once the client asks for them
+	framed.send(parse_message("stmt", "select 1")).await.unwrap();
+	framed.send(ClientMessage::Flush).await.unwrap();
+	assert_eq!(framed.next().await.unwrap().unwrap(), ServerMessage::ParseComplete);
+
+	// closing a missing statement isn't an error
+	for message in [
+		ClientMessage::Close(Close::PreparedStatement("stmt".to_owned())),
+		ClientMessage::Close(Close::PreparedStatement("missing".to_owned())),
+		ClientMessage::Close(Close::Portal("missing".to_owned())),
+		ClientMessage::Sync,
+	] {
+		framed.feed(message).await.unwrap();
+	}
+	framed.flush().await.unwrap();
+	assert_eq!(
+		read_until_ready(&mut framed).await,
+		vec![ServerMessage::CloseComplete; 3]
+	);
+
+	// but the closed statement can no longer be used
+	framed.feed(bind_message("stmt")).await.unwrap();
+	framed.send(ClientMessage::Sync).await.unwrap();
+	let messages = read_until_ready(&mut framed).await;
+	assert_eq!(single_error(&messages).sql_state, SqlState::InvalidSQLStatementName);
+}
+
+async fn setup_rows() -> Client {
+	let port = server::run_background(
+		BindOptions::new().with_port(0),
+		Arc::new(|| Box::pin(async { ReturnRowsEngine })),
+	)
+	.await
+	.unwrap();
+
+	let (client, conn) = connect(&format!("postgres://localhost:{}/test", port), NoTls)
+		.await
+		.expect("failed to init client");
+	tokio::spawn(async move { conn.await.unwrap() });
+
+	client
+}
+
+// runs a simple query, returning the first column of each row and the command's row count
+async fn simple_rows(client: &Client, query: &str) -> (Vec<String>, u64) {
+	let mut rows = Vec::new();
+	let mut count = None;
+	for message in client.simple_query(query).await.unwrap() {
+		match message {
+			SimpleQueryMessage::Row(row) => rows.push(row.get(0).unwrap().to_owned()),
+			SimpleQueryMessage::CommandComplete(rows) => count = Some(rows),
+			_ => (),
+		}
+	}
+	(rows, count.expect("expected command complete"))
+}
+
+#[tokio::test]
+async fn cursor_fetch_and_move() {
+	let client = setup_rows().await;
+
+	assert_eq!(simple_rows(&client, "DECLARE c CURSOR FOR select n").await, (vec![], 0));
+	assert_eq!(
+		simple_rows(&client, "FETCH 3 FROM c").await,
+		(vec!["1".to_owned(), "2".to_owned(), "3".to_owned()], 3)
+	);
+	assert_eq!(simple_rows(&client, "MOVE 2 IN c").await, (vec![], 2));
+	assert_eq!(
+		simple_rows(&client, "FETCH NEXT FROM C").await,
+		(vec!["6".to_owned()], 1)
+	);
+	assert_eq!(simple_rows(&client, "FETCH ALL FROM c").await.1, 4);
+
+	// an exhausted cursor returns no more rows
+	assert_eq!(simple_rows(&client, "FETCH FORWARD 5 FROM c").await, (vec![], 0));
+
+	simple_rows(&client, "CLOSE c").await;
+	let err = client.simple_query("FETCH 1 FROM c").await.unwrap_err();
+	assert_eq!(err.code().unwrap().code(), SqlState::InvalidCursorName.code());
+}
+
+#[tokio::test]
+async fn cursor_errors() {
+	let client = setup_rows().await;
+	client
+		.batch_execute("DECLARE c CURSOR WITH HOLD FOR select n")
+		.await
+		.unwrap();
+	client.batch_execute("DECLARE d CURSOR FOR select n").await.unwrap();
+
+	let err = client.simple_query("DECLARE c CURSOR FOR select n").await.unwrap_err();
+	assert_eq!(err.code().unwrap().code(), SqlState::DuplicateCursor.code());
+
+	let err = client.simple_query("FETCH BACKWARD 1 FROM c").await.unwrap_err();
+	assert_eq!(
+		err.code().unwrap().code(),
+		SqlState::ObjectNotInPrerequisiteState.code()
+	);
+
+	let err = client
+		.simple_query("DECLARE s SCROLL CURSOR FOR select n")
+		.await
+		.unwrap_err();
+	assert_eq!(err.code().unwrap().code(), SqlState::FeatureNotSupported.code());
+
+	// the cursors are unaffected by the errors
+	assert_eq!(simple_rows(&client, "FETCH 1 FROM c").await, (vec!["1".to_owned()], 1));
+	assert_eq!(simple_rows(&client, "FETCH 1 FROM d").await, (vec!["1".to_owned()], 1));
+
+	client.batch_execute("CLOSE ALL").await.unwrap();
+	for name in ["c", "d"] {
+		let err = client.simple_query(&format!("CLOSE {}", name)).await.unwrap_err();
+		assert_eq!(err.code().unwrap().code(), SqlState::InvalidCursorName.code());
+	}
+}
+
+#[tokio::test]
+async fn cursor_extended_query() {
+	let client = setup_rows().await;
+	client
+		.execute("DECLARE b BINARY CURSOR FOR select n", &[])
+		.await
+		.unwrap();
+
+	let rows = client.query("FETCH 2 FROM b", &[]).await.unwrap();
+	let values: Vec<i32> = rows.iter().map(|row| row.get(0)).collect();
+	assert_eq!(values, vec![1, 2]);
+	assert_eq!(client.execute("MOVE ALL FROM b", &[]).await.unwrap(), 8);
+
+	// tokio-postgres requests binary results, so text cursors can't be fetched with the extended protocol
+	client.execute("DECLARE t CURSOR FOR select n", &[]).await.unwrap();
+	let err = client.query("FETCH 2 FROM t", &[]).await.unwrap_err();
+	assert_eq!(err.code().unwrap().code(), SqlState::FeatureNotSupported.code());
+}
+
+// returns the numbers from 1 up to its limit, producing only as many rows as it's asked for
+struct IncrementalPortal {
+	next: i32,
+	limit: i32,
+	produced: Arc<Mutex<i32>>,
+}
+
+#[async_trait]
+impl Portal for IncrementalPortal {
+	async fn fetch(&mut self, batch: &mut DataRowBatch) -> Result<(), ErrorResponse> {
+		while self.fetch_rows(batch, usize::MAX).await? {}
+		Ok(())
+	}
+
+	async fn fetch_rows(&mut self, batch: &mut DataRowBatch, max_rows: usize) -> Result<bool, ErrorResponse> {
+		for _ in 0..max_rows {
+			if self.next > self.limit {
+				return Ok(false);
+			}
+			batch.write_row(|row| row.write_int4(self.next))?;
+			self.next += 1;
+			*self.produced.lock().unwrap() += 1;
+		}
+		Ok(self.next <= self.limit)
+	}
+}
+
+struct IncrementalEngine {
+	produced: Arc<Mutex<i32>>,
+}
+
+#[async_trait]
+impl Engine for IncrementalEngine {
+	type PreparedStatementType = Vec<FieldDescription>;
+	type PortalType = IncrementalPortal;
+
+	async fn prepare(&mut self, _: &Statement) -> Result<Vec<FieldDescription>, ErrorResponse> {
+		Ok(vec![FieldDescription {
+			name: "n".to_owned(),
+			data_type: DataTypeOid::Int4,
+		}])
+	}
+
+	async fn create_portal(
+		&mut self,
+		_: &Self::PreparedStatementType,
+		_: &Parameters,
+	) -> Result<Self::PortalType, ErrorResponse> {
+		Ok(IncrementalPortal {
+			next: 1,
+			limit: 1000,
+			produced: self.produced.clone(),
+		})
+	}
+}
+
+#[tokio::test]
+async fn cursor_fetches_rows_incrementally() {
+	let produced = Arc::new(Mutex::new(0));
+	let engine_produced = produced.clone();
+	let port = server::run_background(
+		BindOptions::new().with_port(0),
+		Arc::new(move || {
+			let produced = engine_produced.clone();
+			Box::pin(async move { IncrementalEngine { produced } })
+		}),
+	)
+	.await
+	.unwrap();
+	let (client, conn) = connect(&format!("postgres://localhost:{}/test", port), NoTls)
+		.await
+		.expect("failed to init client");
+	tokio::spawn(conn);
+
+	// only the rows which are returned are fetched from the engine
+	simple_rows(&client, "DECLARE c CURSOR FOR select n").await;
+	assert_eq!(*produced.lock().unwrap(), 0);
+	assert_eq!(
+		simple_rows(&client, "FETCH 2 FROM c").await,
+		(vec!["1".to_owned(), "2".to_owned()], 2)
+	);
+	assert_eq!(*produced.lock().unwrap(), 2);
+	assert_eq!(simple_rows(&client, "MOVE 3 IN c").await, (vec![], 3));
+	assert_eq!(*produced.lock().unwrap(), 5);
+
+	let (rows, count) = simple_rows(&client, "FETCH ALL FROM c").await;
+	assert_eq!((rows.first().map(String::as_str), count), (Some("6"), 995));
+	assert_eq!(simple_rows(&client, "FETCH 1 FROM c").await, (vec![], 0));
+	assert_eq!(*produced.lock().unwrap(), 1000);
+
+	// WITH HOLD cursors still fetch every row up front
+	simple_rows(&client, "DECLARE h CURSOR WITH HOLD FOR select n").await;
+	assert_eq!(*produced.lock().unwrap(), 2000);
+}
+
+fn function_call_message(function_oid: u32, arguments: Vec<Option<&'static [u8]>>) -> ClientMessage {
+	ClientMessage::FunctionCall(FunctionCall {
+		function_oid,
+		argument_format: BindFormat::PerColumn(vec![FormatCode::Binary, FormatCode::Text, FormatCode::Text]),
+		arguments: arguments.into_iter().map(|arg| arg.map(Bytes::from_static)).collect(),
+		result_format: FormatCode::Text,
+	})
+}
+
+#[tokio::test]
+async fn function_call() {
+	let mut framed = started_raw().await;
+
+	framed
+		.send(function_call_message(1, vec![Some(&[0, 0, 0, 2]), Some(b"3"), None]))
+		.await
+		.unwrap();
+	assert_eq!(
+		read_until_ready(&mut framed).await,
+		vec![ServerMessage::FunctionCallResponse(FunctionCallResponse {
+			result: Some(Bytes::from_static(b"5")),
+		})]
+	);
+
+	// errors end the call, as for a simple query
+	framed
+		.send(function_call_message(2, vec![None, None, None]))
+		.await
+		.unwrap();
+	let messages = read_until_ready(&mut framed).await;
+	assert_eq!(single_error(&messages).sql_state, SqlState::UndefinedFunction);
+
+	framed.send(function_call_message(1, vec![Some(b"1")])).await.unwrap();
+	let messages = read_until_ready(&mut framed).await;
+	assert_eq!(single_error(&messages).sql_state, SqlState::ProtocolViolation);
+}
+
+// records the session lifecycle events and statements it sees, rejecting sessions for the user "rejected"
+struct LifecycleEngine {
+	events: Arc<Mutex<Vec<String>>>,
+	plan_cache: Option<Arc<PlanCache<Vec<FieldDescription>>>>,
+}
+
+#[async_trait]
+impl Engine for LifecycleEngine {
+	type PreparedStatementType = Vec<FieldDescription>;
+	type PortalType = ReturnSingleScalarPortal;
+
+	async fn prepare(&mut self, statement: &Statement) -> Result<Vec<FieldDescription>, ErrorResponse> {
+		self.events.lock().unwrap().push(format!("prepare {}", statement));
+		Ok(vec![FieldDescription {
+			name: "test".to_owned(),
+			data_type: DataTypeOid::Int4,
+		}])
+	}
+
+	async fn create_portal(
+		&mut self,
+		_: &Self::PreparedStatementType,
+		parameters: &Parameters,
+	) -> Result<Self::PortalType, ErrorResponse> {
+		let event = format!("bind {:?}", parameters.values);
+		self.events.lock().unwrap().push(event);
+		Ok(ReturnSingleScalarPortal)
+	}
+
+	fn plan_cache(&self) -> Option<Arc<PlanCache<Self::PreparedStatementType>>> {
+		self.plan_cache.clone()
+	}
+
+	async fn on_session_start(&mut self, info: &SessionInfo) -> Result<(), ErrorResponse> {
+		if info.user() == "rejected" {
+			return Err(ErrorResponse::error(
+				SqlState::InvalidAuthorizationSpecification,
+				"session rejected",
+			));
+		}
+
+		let event = format!("start {} {}", info.user(), info.database());
+		self.events.lock().unwrap().push(event);
+		Ok(())
+	}
+
+	async fn on_session_end(&mut self, reason: &SessionEndReason) {
+		self.events.lock().unwrap().push(format!("end {:?}", reason));
+	}
+
+	async fn reset(&mut self) -> Result<(), ErrorResponse> {
+		self.events.lock().unwrap().push("reset".to_owned());
+		Ok(())
+	}
+}
+
+async fn setup_lifecycle() -> (u16, Arc<Mutex<Vec<String>>>) {
+	setup_lifecycle_with_cache(None).await
+}
+
+async fn setup_lifecycle_with_cache(
+	plan_cache: Option<Arc<PlanCache<Vec<FieldDescription>>>>,
+) -> (u16, Arc<Mutex<Vec<String>>>) {
+	let events = Arc::new(Mutex::new(Vec::new()));
+	let engine_events = events.clone();
+	let port = server::run_background(
+		BindOptions::new().with_port(0),
+		Arc::new(move || {
+			let events = engine_events.clone();
+			let plan_cache = plan_cache.clone();
+			Box::pin(async move { LifecycleEngine { events, plan_cache } })
+		}),
+	)
+	.await
+	.unwrap();
+
+	(port, events)
+}
+
+// waits for the server to finish ending the session, returning the recorded events
+async fn session_events(events: &Mutex<Vec<String>>) -> Vec<String> {
+	for _ in 0..100 {
+		if events.lock().unwrap().iter().any(|event| event.starts_with("end")) {
+			break;
+		}
+		tokio::time::sleep(Duration::from_millis(10)).await;
+	}
+	events.lock().unwrap().clone()
+}
+
+#[tokio::test]
+async fn session_lifecycle() {
+	let (port, events) = setup_lifecycle().await;
+
+	let (client, conn) = connect(&format!("postgres://test@localhost:{}/db", port), NoTls)
+		.await
+		.expect("failed to init client");
+	let conn_task = tokio::spawn(conn);
+
+	client.simple_query("DISCARD ALL").await.unwrap();
+	client.simple_query("reset all;").await.unwrap();
+	drop(client);
+	conn_task.await.unwrap().unwrap();
+
+	assert_eq!(
+		session_events(&events).await,
+		vec!["start test db", "reset", "reset", "end Terminated"]
+	);
+}
+
+#[tokio::test]
+async fn session_end_on_disconnect() {
+	let (port, events) = setup_lifecycle().await;
+
+	let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+	let mut framed = Framed::new(stream, ClientCodec::new());
+	framed.send(startup_message((3, 0), &[("user", "test")])).await.unwrap();
+	read_until_ready(&mut framed).await;
+	drop(framed);
+
+	// the database defaults to the user name
+	assert_eq!(
+		session_events(&events).await,
+		vec!["start test test", "end Disconnected"]
+	);
+}
+
+#[tokio::test]
+async fn session_rejected() {
+	let (port, events) = setup_lifecycle().await;
+
+	let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+	let mut framed = Framed::new(stream, ClientCodec::new());
+	framed
+		.send(startup_message((3, 0), &[("user", "rejected")]))
+		.await
+		.unwrap();
+
+	let messages = read_until_ready(&mut framed).await;
+	let err = single_error(&messages);
+	assert_eq!(err.sql_state, SqlState::InvalidAuthorizationSpecification);
+	assert!(err.severity.is_fatal());
+
+	// sessions the engine didn't accept never end
+	assert!(events.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn discard_and_reset_all() {
+	let mut framed = started_raw().await;
+
+	framed.feed(parse_message("stmt", "select 1")).await.unwrap();
+	framed.send(ClientMessage::Sync).await.unwrap();
+	read_until_ready(&mut framed).await;
+
+	// RESET ALL restores the settings and reports the ones that changed
+	framed
+		.send(ClientMessage::Query("SET DateStyle = 'SQL, DMY'".to_owned()))
+		.await
+		.unwrap();
+	read_until_ready(&mut framed).await;
+	framed.send(ClientMessage::Query("RESET ALL".to_owned())).await.unwrap();
+	let messages = read_until_ready(&mut framed).await;
+	assert_eq!(
+		messages[0],
+		ServerMessage::ParameterStatus(ParameterStatus::new("DateStyle", "ISO, MDY"))
+	);
+	assert_eq!(messages.len(), 2);
+
+	// RESET ALL keeps prepared statements, but DISCARD ALL drops them
+	framed.feed(bind_message("stmt")).await.unwrap();
+	framed.send(ClientMessage::Sync).await.unwrap();
+	assert_eq!(read_until_ready(&mut framed).await, vec![ServerMessage::BindComplete]);
+
+	framed
+		.send(ClientMessage::Query("DISCARD ALL".to_owned()))
+		.await
+		.unwrap();
+	let messages = read_until_ready(&mut framed).await;
+	assert_eq!(messages.len(), 1);
+
+	framed.feed(bind_message("stmt")).await.unwrap();
+	framed.send(ClientMessage::Sync).await.unwrap();
+	let messages = read_until_ready(&mut framed).await;
+	assert_eq!(single_error(&messages).sql_state, SqlState::InvalidSQLStatementName);
+}
+
+#[tokio::test]
+async fn statements_are_prepared_once() {
+	let (port, events) = setup_lifecycle().await;
+
+	let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+	let mut framed = Framed::new(stream, ClientCodec::new());
+	framed.send(startup_message((3, 0), &[("user", "test")])).await.unwrap();
+	read_until_ready(&mut framed).await;
+
+	framed.send(ClientMessage::Query("select 1".to_owned())).await.unwrap();
+	read_until_ready(&mut framed).await;
+
+	// binding a prepared statement only binds its parameters
+	let bind_with_value = |parameter_format, value: &'static [u8]| {
+		ClientMessage::Bind(Bind {
+			portal: "".to_owned(),
+			prepared_statement_name: "stmt".to_owned(),
+			parameter_format,
+			parameters: vec![Some(Bytes::from_static(value))],
+			result_format: BindFormat::All(FormatCode::Text),
+		})
+	};
+	for message in [
+		parse_message("stmt", "select $1"),
+		bind_with_value(BindFormat::All(FormatCode::Text), b"1"),
+		execute_message(),
+		bind_with_value(BindFormat::All(FormatCode::Text), b"2"),
+		execute_message(),
+		ClientMessage::Sync,
+	] {
+		framed.feed(message).await.unwrap();
+	}
+	framed.flush().await.unwrap();
+	read_until_ready(&mut framed).await;
+
+	assert_eq!(
+		*events.lock().unwrap(),
+		vec![
+			"start test test",
+			"prepare SELECT 1",
+			"bind []",
+			"prepare SELECT $1",
+			"bind [Some(b\"1\")]",
+			"bind [Some(b\"2\")]",
+		]
+	);
+
+	// each parameter needs a format
+	framed
+		.feed(bind_with_value(BindFormat::PerColumn(vec![FormatCode::Text; 2]), b"3"))
+		.await
+		.unwrap();
+	framed.send(ClientMessage::Sync).await.unwrap();
+	let messages = read_until_ready(&mut framed).await;
+	assert_eq!(single_error(&messages).sql_state, SqlState::ProtocolViolation);
+}
+
+struct TypedStatement {
+	fields: Vec<FieldDescription>,
+	parameter_types: Vec<DataTypeOid>,
+}
+
+impl PreparedStatement for TypedStatement {
+	fn fields(&self) -> &[FieldDescription] {
+		&self.fields
+	}
+
+	fn parameter_types(&self) -> &[DataTypeOid] {
+		&self.parameter_types
+	}
+}
+
+// returns its single int4 parameter, which it infers from the statement
+struct EchoParameterEngine;
+
+struct EchoParameterPortal(Option<Bytes>);
+
+#[async_trait]
+impl Portal for EchoParameterPortal {
+	async fn fetch(&mut self, batch: &mut DataRowBatch) -> Result<(), ErrorResponse> {
+		let value = Option::<i32>::from_pg_nullable(DataTypeOid::Int4, FormatCode::Binary, self.0.as_deref())?;
+		batch.write_row(|row| row.write_int4(value.unwrap_or(0)))
+	}
+}
+
+#[async_trait]
+impl Engine for EchoParameterEngine {
+	type PreparedStatementType = TypedStatement;
+	type PortalType = EchoParameterPortal;
+
+	async fn prepare(&mut self, statement: &Statement) -> Result<TypedStatement, ErrorResponse> {
+		Ok(TypedStatement {
+			fields: vec![FieldDescription {
+				name: "value".to_owned(),
+				data_type: DataTypeOid::Int4,
+			}],
+			parameter_types: match statement.to_string().contains("$1") {
+				true => vec![DataTypeOid::Int4],
+				false => vec![],
+			},
+		})
+	}
+
+	async fn create_portal(
+		&mut self,
+		_: &Self::PreparedStatementType,
+		parameters: &Parameters,
+	) -> Result<Self::PortalType, ErrorResponse> {
+		Ok(EchoParameterPortal(parameters.values.first().cloned().flatten()))
+	}
+}
+
+#[tokio::test]
+async fn parameters_are_described() {
+	let port = server::run_background(
+		BindOptions::new().with_port(0),
+		Arc::new(|| Box::pin(async { EchoParameterEngine })),
+	)
+	.await
+	.unwrap();
+	let (client, conn) = connect(&format!("postgres://localhost:{}/test", port), NoTls)
+		.await
+		.expect("failed to init client");
+	tokio::spawn(conn);
+
+	// clients rely on the description to know how many parameters to send and how to encode them
+	let statement = client.prepare("select $1").await.unwrap();
+	assert_eq!(statement.params(), &[Type::INT4]);
+	let rows = client.query(&statement, &[&7i32]).await.unwrap();
+	assert_eq!(rows[0].get::<_, i32>(0), 7);
+
+	// types given by the client take precedence
+	let statement = client
+		.prepare_typed("select $1, $2", &[Type::INT8, Type::TEXT])
+		.await
+		.unwrap();
+	assert_eq!(statement.params(), &[Type::INT8, Type::TEXT]);
+}
+
+async fn lifecycle_client(port: u16) -> Client {
+	let (client, conn) = connect(&format!("postgres://test@localhost:{}/db", port), NoTls)
+		.await
+		.expect("failed to init client");
+	tokio::spawn(conn);
+	client
+}
+
+fn prepared_statements(events: &Mutex<Vec<String>>) -> Vec<String> {
+	events
+		.lock()
+		.unwrap()
+		.iter()
+		.filter(|event| event.starts_with("prepare"))
+		.cloned()
+		.collect()
+}
+
+#[tokio::test]
+async fn plan_cache_is_shared_between_connections() {
+	let plan_cache = Arc::new(PlanCache::new(10));
+	let (port, events) = setup_lifecycle_with_cache(Some(plan_cache.clone())).await;
+
+	for query in ["select 1", "select  1;", "SELECT 1", "set DateStyle = 'ISO'"] {
+		let client = lifecycle_client(port).await;
+		client.simple_query(query).await.unwrap();
+		client.prepare("select 1").await.unwrap();
+	}
+
+	// queries which only differ in whitespace are prepared once, while the connection's own statements aren't cached
+	assert_eq!(
+		prepared_statements(&events),
+		vec!["prepare SELECT 1", "prepare SELECT 1"]
+	);
+	assert_eq!(plan_cache.len(), 2);
+
+	// invalidated statements are prepared again
+	plan_cache.invalidate(|key, _| key.query() == "select 1");
+	assert_eq!(plan_cache.len(), 1);
+	lifecycle_client(port).await.simple_query("select 1").await.unwrap();
+	assert_eq!(prepared_statements(&events).len(), 3);
+}
+
+async fn setup_read_only() -> (Client, u16) {
+	let port = server::run_background(
+		BindOptions::new().with_port(0).with_read_only(true),
+		Arc::new(|| Box::pin(async { ReturnSingleScalarEngine })),
+	)
+	.await
+	.unwrap();
+
+	let (client, conn) = connect(&format!("postgres://localhost:{}/test", port), NoTls)
+		.await
+		.expect("failed to init client");
+	tokio::spawn(conn);
+
+	(client, port)
+}
+
+#[tokio::test]
+async fn read_only_mode() {
+	let (client, port) = setup_read_only().await;
+
+	for query in ["insert into t values (1)", "drop table t", "create table t (a int)"] {
+		let err = client.simple_query(query).await.expect_err("expected read-only error");
+		assert_eq!(
+			err.code(),
+			Some(&tokio_postgres::error::SqlState::READ_ONLY_SQL_TRANSACTION)
+		);
+	}
+
+	let err = client
+		.prepare("delete from t")
+		.await
+		.expect_err("expected read-only error");
+	assert_eq!(
+		err.code(),
+		Some(&tokio_postgres::error::SqlState::READ_ONLY_SQL_TRANSACTION)
+	);
+
+	// unparsed queries can't be checked
+	let err = client.simple_query("!cmd").await.expect_err("expected read-only error");
+	assert_eq!(
+		err.code(),
+		Some(&tokio_postgres::error::SqlState::READ_ONLY_SQL_TRANSACTION)
+	);
+
+	// reads, session settings and cursors are still allowed
+	client.simple_query("select 1").await.unwrap();
+	client.simple_query("set DateStyle = 'ISO'").await.unwrap();
+	client.simple_query("declare c cursor for select 1").await.unwrap();
+	client.simple_query("fetch 1 from c").await.unwrap();
+
+	let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+	let mut framed = Framed::new(stream, ClientCodec::new());
+	framed.send(startup_message((3, 0), &[("user", "test")])).await.unwrap();
+	let messages = read_until_ready(&mut framed).await;
+	for param in ["default_transaction_read_only", "transaction_read_only"] {
+		assert!(messages.contains(&ServerMessage::ParameterStatus(ParameterStatus::new(param, "on"))));
+	}
+
+	// nor can fast-path function calls
+	framed
+		.send(function_call_message(1, vec![Some(&[0, 0, 0, 2]), Some(b"3"), None]))
+		.await
+		.unwrap();
+	let messages = read_until_ready(&mut framed).await;
+	assert_eq!(single_error(&messages).sql_state, SqlState::ReadOnlySqlTransaction);
+}
+
+#[tokio::test]
+async fn read_only_layer_ignores_shared_plan_cache() {
+	let plan_cache = Arc::new(PlanCache::new(10));
+
+	// an engine without the layer caches a write, which an engine with it sharing the same cache must still reject
+	let (port, _) = setup_lifecycle_with_cache(Some(plan_cache.clone())).await;
+	let client = lifecycle_client(port).await;
+	client.simple_query("insert into t values (1)").await.unwrap();
+	assert_eq!(plan_cache.len(), 1);
+
+	let read_only_port = server::run_background(
+		BindOptions::new().with_port(0),
+		Arc::new(move || {
+			let plan_cache = Some(plan_cache.clone());
+			Box::pin(async move {
+				LifecycleEngine {
+					events: Default::default(),
+					plan_cache,
+				}
+				.with_layer(&ReadOnlyLayer)
+			})
+		}),
+	)
+	.await
+	.unwrap();
+	let client = lifecycle_client(read_only_port).await;
+
+	let err = client
+		.simple_query("insert into t values (1)")
+		.await
+		.expect_err("expected read-only error");
+	assert_eq!(
+		err.code(),
+		Some(&tokio_postgres::error::SqlState::READ_ONLY_SQL_TRANSACTION)
+	);
+	client.simple_query("select 1").await.unwrap();
+}
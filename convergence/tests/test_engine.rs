@@ -0,0 +1,277 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use convergence::engine::{
+	is_read_only, Engine, EngineExt, LoggingLayer, Parameters, Portal, ReadOnlyLayer, RewriteLayer,
+};
+use convergence::protocol::{
+	BindFormat, DataTypeOid, ErrorResponse, FieldDescription, FormatCode, FunctionCall, ParameterStatus,
+	RowDescription, SqlState,
+};
+use convergence::protocol_ext::DataRowBatch;
+use sqlparser::ast::{Expr, SelectItem, SetExpr, Statement, Value};
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::Parser;
+use std::sync::{Arc, Mutex};
+
+struct ReturnSingleScalarPortal;
+
+#[async_trait]
+impl Portal for ReturnSingleScalarPortal {
+	async fn fetch(&mut self, batch: &mut DataRowBatch) -> Result<(), ErrorResponse> {
+		batch.write_row(|row| row.write_int4(1))
+	}
+}
+
+// records the statements it prepares
+struct RecordingEngine {
+	prepared: Arc<Mutex<Vec<String>>>,
+}
+
+#[async_trait]
+impl Engine for RecordingEngine {
+	type PreparedStatementType = Vec<FieldDescription>;
+	type PortalType = ReturnSingleScalarPortal;
+
+	async fn prepare(&mut self, statement: &Statement) -> Result<Vec<FieldDescription>, ErrorResponse> {
+		self.prepared.lock().unwrap().push(statement.to_string());
+		Ok(vec![FieldDescription {
+			name: "test".to_owned(),
+			data_type: DataTypeOid::Int4,
+		}])
+	}
+
+	async fn create_portal(
+		&mut self,
+		_: &Self::PreparedStatementType,
+		_: &Parameters,
+	) -> Result<Self::PortalType, ErrorResponse> {
+		Ok(ReturnSingleScalarPortal)
+	}
+
+	async fn prepare_raw(&mut self, text: &str) -> Result<Option<Vec<FieldDescription>>, ErrorResponse> {
+		self.prepared.lock().unwrap().push(text.to_owned());
+		Ok(Some(vec![]))
+	}
+
+	async fn call_function(&mut self, _: &FunctionCall) -> Result<Option<Bytes>, ErrorResponse> {
+		Ok(None)
+	}
+}
+
+fn function_call() -> FunctionCall {
+	FunctionCall {
+		function_oid: 1,
+		argument_format: BindFormat::All(FormatCode::Text),
+		arguments: vec![],
+		result_format: FormatCode::Text,
+	}
+}
+
+fn recording_engine() -> (RecordingEngine, Arc<Mutex<Vec<String>>>) {
+	let prepared = Arc::new(Mutex::new(Vec::new()));
+	(
+		RecordingEngine {
+			prepared: prepared.clone(),
+		},
+		prepared,
+	)
+}
+
+fn parse(query: &str) -> Statement {
+	Parser::parse_sql(&PostgreSqlDialect {}, query).unwrap().remove(0)
+}
+
+// rewrites every integer literal to 2
+fn rewrite_literals(statement: &mut Statement) -> Result<(), ErrorResponse> {
+	match statement {
+		Statement::Query(query) => {
+			if let SetExpr::Select(select) = &mut *query.body {
+				for item in &mut select.projection {
+					if let SelectItem::UnnamedExpr(Expr::Value(Value::Number(value, _))) = item {
+						*value = "2".to_owned();
+					}
+				}
+			}
+			Ok(())
+		}
+		_ => Err(ErrorResponse::error(
+			SqlState::InsufficientPrivilege,
+			"only queries are allowed",
+		)),
+	}
+}
+
+#[test]
+fn read_only_statements() {
+	for query in [
+		"select * from t",
+		"with a as (select 1) select * from a union select 2",
+		"explain insert into t values (1)",
+		"show DateStyle",
+		"set DateStyle = 'ISO'",
+		"declare c cursor for select 1",
+		"fetch 1 from c",
+		"start transaction read only",
+		"commit",
+	] {
+		assert!(is_read_only(&parse(query)), "{}", query);
+	}
+
+	for query in [
+		"insert into t values (1)",
+		"update t set a = 1",
+		"delete from t",
+		"create table t (a int)",
+		"drop table t",
+		"truncate t",
+		"select * into t2 from t",
+		"select * from t for update",
+		"with a as (insert into t values (1) returning *) select * from a",
+		"explain analyze delete from t",
+		"declare c cursor for select * from t for update",
+		"grant select on t to u",
+	] {
+		assert!(!is_read_only(&parse(query)), "{}", query);
+	}
+}
+
+#[tokio::test]
+async fn read_only_layer() {
+	let (engine, prepared) = recording_engine();
+	let mut engine = engine.with_layer(&ReadOnlyLayer);
+	assert!(engine
+		.parameter_statuses()
+		.contains(&ParameterStatus::new("transaction_read_only", "on")));
+
+	engine.prepare(&parse("select 1")).await.unwrap();
+
+	let err = engine
+		.prepare(&parse("insert into t values (1)"))
+		.await
+		.expect_err("expected read-only error");
+	assert_eq!(err.sql_state, SqlState::ReadOnlySqlTransaction);
+	assert_eq!(err.message, "cannot execute INSERT in a read-only transaction");
+
+	// errors name the command, rather than the statement's first keyword
+	for (query, command) in [
+		(
+			"with a as (insert into t values (1) returning *) select * from a",
+			"INSERT",
+		),
+		("create table t (a int)", "CREATE TABLE"),
+		("create materialized view v as select 1", "CREATE MATERIALIZED VIEW"),
+		("drop index i", "DROP INDEX"),
+		("select * into t2 from t", "SELECT INTO"),
+		("select * from t for update", "SELECT FOR UPDATE"),
+		("explain analyze delete from t", "DELETE"),
+		("truncate t", "TRUNCATE TABLE"),
+		("grant select on t to u", "GRANT"),
+	] {
+		let err = engine
+			.prepare(&parse(query))
+			.await
+			.expect_err("expected read-only error");
+		assert_eq!(
+			err.message,
+			format!("cannot execute {} in a read-only transaction", command),
+			"{}",
+			query
+		);
+	}
+
+	assert_eq!(*prepared.lock().unwrap(), vec!["SELECT 1"]);
+
+	// fast-path functions may write data, so they're rejected without reaching the engine
+	let err = engine
+		.call_function(&function_call())
+		.await
+		.expect_err("expected read-only error");
+	assert_eq!(err.sql_state, SqlState::ReadOnlySqlTransaction);
+}
+
+#[tokio::test]
+async fn rewrite_layer() {
+	let (engine, prepared) = recording_engine();
+	let mut engine = engine.with_layer(&RewriteLayer::new(rewrite_literals));
+
+	engine.prepare(&parse("select 1")).await.unwrap();
+
+	let err = engine
+		.prepare(&parse("drop table t"))
+		.await
+		.expect_err("expected rewriter error");
+	assert_eq!(err.sql_state, SqlState::InsufficientPrivilege);
+
+	assert_eq!(*prepared.lock().unwrap(), vec!["SELECT 2"]);
+
+	// unparsed queries and fast-path functions would bypass the rewriter, so they're rejected by default
+	let err = engine.prepare_raw("\\dt").await.expect_err("expected rewriter error");
+	assert_eq!(err.sql_state, SqlState::FeatureNotSupported);
+	let err = engine
+		.call_function(&function_call())
+		.await
+		.expect_err("expected rewriter error");
+	assert_eq!(err.sql_state, SqlState::FeatureNotSupported);
+
+	let (engine, prepared) = recording_engine();
+	let mut engine = engine.with_layer(&RewriteLayer::new(rewrite_literals).with_pass_through());
+	assert!(engine.prepare_raw("\\dt").await.unwrap().is_some());
+	assert_eq!(engine.call_function(&function_call()).await.unwrap(), None);
+	assert_eq!(*prepared.lock().unwrap(), vec!["\\dt"]);
+}
+
+struct TestLogger {
+	messages: Mutex<Vec<String>>,
+}
+
+// captures the engine's log messages, ignoring those from dependencies such as sqlparser
+impl log::Log for TestLogger {
+	fn enabled(&self, metadata: &log::Metadata) -> bool {
+		metadata.target().starts_with("convergence::")
+	}
+
+	fn log(&self, record: &log::Record) {
+		if self.enabled(record.metadata()) {
+			self.messages.lock().unwrap().push(record.args().to_string());
+		}
+	}
+
+	fn flush(&self) {}
+}
+
+static LOGGER: TestLogger = TestLogger {
+	messages: Mutex::new(Vec::new()),
+};
+
+#[tokio::test]
+async fn layers_compose() {
+	log::set_logger(&LOGGER).unwrap();
+	log::set_max_level(log::LevelFilter::Debug);
+
+	// the read-only check sees the rewritten statement, and the logging layer sees every statement first
+	let (engine, prepared) = recording_engine();
+	let layers = ((ReadOnlyLayer, RewriteLayer::new(rewrite_literals)), LoggingLayer);
+	let mut engine = engine.with_layer(&layers);
+
+	let statement = engine.prepare(&parse("select 1")).await.unwrap();
+	let mut portal = engine.create_portal(&statement, &Parameters::default()).await.unwrap();
+
+	let row_desc = RowDescription::with_format(statement, FormatCode::Text);
+	let mut batch = DataRowBatch::from_row_desc(&row_desc);
+	portal.fetch(&mut batch).await.unwrap();
+	assert_eq!(batch.num_rows(), 1);
+
+	engine.prepare(&parse("delete from t")).await.unwrap_err();
+	assert_eq!(*prepared.lock().unwrap(), vec!["SELECT 2"]);
+
+	let messages = LOGGER.messages.lock().unwrap();
+	assert!(messages[0].starts_with("prepared statement in "));
+	assert!(messages[0].ends_with(": SELECT 1"));
+	assert_eq!(messages[1], "creating portal with 0 parameters");
+	assert!(messages[2].starts_with("fetched 1 rows in "));
+	assert_eq!(
+		messages[3],
+		"failed to prepare statement: DELETE FROM t: only queries are allowed"
+	);
+	assert_eq!(messages.len(), 4);
+}
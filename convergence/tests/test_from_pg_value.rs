@@ -0,0 +1,484 @@
+use bytes::BytesMut;
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use convergence::protocol::{DataTypeOid, FormatCode, SqlState};
+use convergence::protocol_ext::{DataRowBatch, FromPgValue, Interval, MaybeInfinite, Numeric};
+use convergence::settings::{IntervalStyle, SessionSettings};
+use std::net::IpAddr;
+use tokio_postgres::types::{ToSql, Type};
+use uuid::Uuid;
+
+fn text<T: FromPgValue>(data_type: DataTypeOid, text: &str) -> T {
+	T::from_pg_value(data_type, FormatCode::Text, text.as_bytes()).unwrap()
+}
+
+fn text_err<T: FromPgValue + std::fmt::Debug>(data_type: DataTypeOid, text: &str) -> SqlState {
+	T::from_pg_value(data_type, FormatCode::Text, text.as_bytes())
+		.unwrap_err()
+		.sql_state
+}
+
+fn binary<T: FromPgValue>(data_type: DataTypeOid, data: &[u8]) -> T {
+	T::from_pg_value(data_type, FormatCode::Binary, data).unwrap()
+}
+
+// encodes a value in the binary format using tokio-postgres
+fn to_binary(ty: Type, val: impl ToSql) -> Vec<u8> {
+	let mut buf = BytesMut::new();
+	val.to_sql(&ty, &mut buf).unwrap();
+	buf.to_vec()
+}
+
+fn timestamp(h: u32, m: u32, s: u32, us: u32) -> NaiveDateTime {
+	NaiveDate::from_ymd_opt(2020, 1, 2)
+		.unwrap()
+		.and_hms_micro_opt(h, m, s, us)
+		.unwrap()
+}
+
+#[test]
+fn integers() {
+	assert_eq!(text::<i32>(DataTypeOid::Int4, " 42 "), 42);
+	assert_eq!(text::<i64>(DataTypeOid::Int8, "-9223372036854775808"), i64::MIN);
+	assert_eq!(text::<u32>(DataTypeOid::Oid, "4294967295"), u32::MAX);
+	assert_eq!(
+		text_err::<i32>(DataTypeOid::Int4, "4x"),
+		SqlState::InvalidTextRepresentation
+	);
+	assert_eq!(
+		text_err::<i16>(DataTypeOid::Int2, "40000"),
+		SqlState::NumericValueOutOfRange
+	);
+
+	assert_eq!(binary::<i16>(DataTypeOid::Int2, &to_binary(Type::INT2, -3i16)), -3);
+	assert_eq!(binary::<i64>(DataTypeOid::Int2, &to_binary(Type::INT2, -3i16)), -3);
+	assert_eq!(binary::<i64>(DataTypeOid::Int4, &to_binary(Type::INT4, 7i32)), 7);
+	assert_eq!(
+		i32::from_pg_value(DataTypeOid::Int4, FormatCode::Binary, &[0, 1])
+			.unwrap_err()
+			.sql_state,
+		SqlState::InvalidTextRepresentation
+	);
+	assert_eq!(
+		i32::from_pg_value(DataTypeOid::Int8, FormatCode::Binary, &to_binary(Type::INT8, 1i64))
+			.unwrap_err()
+			.sql_state,
+		SqlState::DatatypeMismatch
+	);
+}
+
+#[test]
+fn unspecified_types() {
+	assert_eq!(text::<i32>(DataTypeOid::Unspecified, "5"), 5);
+	assert_eq!(text::<String>(DataTypeOid::Unknown(705), "hello"), "hello");
+	assert_eq!(text::<Vec<i32>>(DataTypeOid::Unspecified, "{1,2}"), vec![1, 2]);
+}
+
+#[test]
+fn floats_and_bools() {
+	assert!(text::<f64>(DataTypeOid::Float8, "NaN").is_nan());
+	assert_eq!(text::<f64>(DataTypeOid::Float8, "Infinity"), f64::INFINITY);
+	assert_eq!(text::<f64>(DataTypeOid::Float8, "-Infinity"), f64::NEG_INFINITY);
+	assert_eq!(text::<f64>(DataTypeOid::Float8, "1e+100"), 1e100);
+	assert_eq!(text::<f32>(DataTypeOid::Float4, "0.1"), 0.1);
+	assert_eq!(
+		binary::<f64>(DataTypeOid::Float4, &to_binary(Type::FLOAT4, 1.5f32)),
+		1.5
+	);
+	assert_eq!(
+		binary::<f64>(DataTypeOid::Float8, &to_binary(Type::FLOAT8, -0.25f64)),
+		-0.25
+	);
+	assert_eq!(
+		text_err::<f64>(DataTypeOid::Float8, "one"),
+		SqlState::InvalidTextRepresentation
+	);
+
+	for (input, expected) in [("t", true), ("TRUE", true), ("yes", true), ("on", true), ("1", true)] {
+		assert_eq!(text::<bool>(DataTypeOid::Bool, input), expected);
+	}
+	for (input, expected) in [
+		("f", false),
+		("fal", false),
+		("no", false),
+		("off", false),
+		("0", false),
+	] {
+		assert_eq!(text::<bool>(DataTypeOid::Bool, input), expected);
+	}
+	assert_eq!(
+		text_err::<bool>(DataTypeOid::Bool, "o"),
+		SqlState::InvalidTextRepresentation
+	);
+	assert!(binary::<bool>(DataTypeOid::Bool, &to_binary(Type::BOOL, true)));
+}
+
+#[test]
+fn strings_and_bytes() {
+	assert_eq!(text::<String>(DataTypeOid::Varchar, "hello"), "hello");
+	assert_eq!(binary::<String>(DataTypeOid::Jsonb, b"\x01{\"a\":1}"), "{\"a\":1}");
+	assert_eq!(
+		String::from_pg_value(DataTypeOid::Text, FormatCode::Binary, &[0xff])
+			.unwrap_err()
+			.sql_state,
+		SqlState::CharacterNotInRepertoire
+	);
+
+	assert_eq!(text::<Vec<u8>>(DataTypeOid::Bytea, "\\x0a0B"), vec![10, 11]);
+	assert_eq!(text::<Vec<u8>>(DataTypeOid::Bytea, "a\\\\b\\001"), b"a\\b\x01".to_vec());
+	assert_eq!(
+		text_err::<Vec<u8>>(DataTypeOid::Bytea, "\\x0"),
+		SqlState::InvalidTextRepresentation
+	);
+	assert_eq!(binary::<Vec<u8>>(DataTypeOid::Bytea, &[1, 2, 3]), vec![1, 2, 3]);
+}
+
+#[test]
+fn uuids_and_inets() {
+	let uuid = Uuid::parse_str("a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11").unwrap();
+	assert_eq!(
+		text::<Uuid>(DataTypeOid::Uuid, "A0EEBC999C0B4EF8BB6D6BB9BD380A11"),
+		uuid
+	);
+	assert_eq!(binary::<Uuid>(DataTypeOid::Uuid, &to_binary(Type::UUID, uuid)), uuid);
+
+	let addr: IpAddr = "192.168.0.1".parse().unwrap();
+	assert_eq!(text::<IpAddr>(DataTypeOid::Inet, "192.168.0.1/24"), addr);
+	assert_eq!(
+		text_err::<IpAddr>(DataTypeOid::Inet, "192.168.0.1/33"),
+		SqlState::InvalidTextRepresentation
+	);
+	assert_eq!(binary::<IpAddr>(DataTypeOid::Inet, &to_binary(Type::INET, addr)), addr);
+
+	let addr: IpAddr = "::1".parse().unwrap();
+	assert_eq!(binary::<IpAddr>(DataTypeOid::Inet, &to_binary(Type::INET, addr)), addr);
+}
+
+#[test]
+fn numerics() {
+	assert_eq!(text::<Numeric>(DataTypeOid::Numeric, "-12.50"), Numeric::new(-1250, 2));
+	assert_eq!(text::<Numeric>(DataTypeOid::Numeric, "1.5e3"), Numeric::new(1500, 0));
+	assert_eq!(text::<Numeric>(DataTypeOid::Numeric, "25e-3"), Numeric::new(25, 3));
+	assert_eq!(text::<Numeric>(DataTypeOid::Numeric, "NaN"), Numeric::NaN);
+	assert_eq!(
+		text::<Numeric>(DataTypeOid::Numeric, "-Infinity"),
+		Numeric::NegativeInfinity
+	);
+	assert_eq!(
+		text_err::<Numeric>(DataTypeOid::Numeric, "1.2.3"),
+		SqlState::InvalidTextRepresentation
+	);
+
+	// binary values written by DataRowWriter decode to the same value
+	for val in [
+		Numeric::new(0, 0),
+		Numeric::new(123456789, 4),
+		Numeric::new(-5, 3),
+		Numeric::new(100000000, 0),
+		Numeric::new(15, 1),
+		Numeric::Infinity,
+	] {
+		let mut batch = DataRowBatch::new(FormatCode::Binary, 1);
+		batch.write_row(|row| row.write_numeric(&val)).unwrap();
+		let mut buf = BytesMut::new();
+		tokio_util::codec::Encoder::encode(&mut convergence::protocol::ConnectionCodec::new(), batch, &mut buf)
+			.unwrap();
+
+		// skip the message header, column count and value length
+		assert_eq!(binary::<Numeric>(DataTypeOid::Numeric, &buf[11..]), val);
+	}
+}
+
+#[test]
+fn dates_and_timestamps() {
+	let date = NaiveDate::from_ymd_opt(2020, 1, 2).unwrap();
+	assert_eq!(text::<NaiveDate>(DataTypeOid::Date, "2020-01-02"), date);
+	assert_eq!(
+		text::<NaiveDate>(DataTypeOid::Date, "0044-03-15 BC"),
+		NaiveDate::from_ymd_opt(-43, 3, 15).unwrap()
+	);
+	assert_eq!(
+		text::<MaybeInfinite<NaiveDate>>(DataTypeOid::Date, "infinity"),
+		MaybeInfinite::Infinity
+	);
+	assert_eq!(
+		text_err::<NaiveDate>(DataTypeOid::Date, "-infinity"),
+		SqlState::DatetimeFieldOverflow
+	);
+	assert_eq!(
+		text_err::<NaiveDate>(DataTypeOid::Date, "2020-02-30"),
+		SqlState::DatetimeFieldOverflow
+	);
+	assert_eq!(
+		text_err::<NaiveDate>(DataTypeOid::Date, "2020-01-0x"),
+		SqlState::InvalidDatetimeFormat
+	);
+	// multi-byte characters where an era or offset is expected are rejected rather than split
+	assert_eq!(
+		text_err::<NaiveDate>(DataTypeOid::Date, "€"),
+		SqlState::InvalidDatetimeFormat
+	);
+	assert_eq!(
+		binary::<NaiveDate>(DataTypeOid::Date, &to_binary(Type::DATE, date)),
+		date
+	);
+	assert_eq!(
+		binary::<MaybeInfinite<NaiveDate>>(DataTypeOid::Date, &i32::MIN.to_be_bytes()),
+		MaybeInfinite::NegativeInfinity
+	);
+
+	let val = timestamp(3, 4, 5, 500_000);
+	assert_eq!(
+		text::<NaiveDateTime>(DataTypeOid::Timestamp, "2020-01-02 03:04:05.5"),
+		val
+	);
+	assert_eq!(
+		text::<NaiveDateTime>(DataTypeOid::Timestamp, "2020-01-02T03:04:05.5+02"),
+		val
+	);
+	assert_eq!(
+		text::<NaiveDateTime>(DataTypeOid::Timestamp, "2020-01-02"),
+		timestamp(0, 0, 0, 0)
+	);
+	assert_eq!(
+		binary::<NaiveDateTime>(DataTypeOid::Timestamp, &to_binary(Type::TIMESTAMP, val)),
+		val
+	);
+	assert_eq!(
+		text::<MaybeInfinite<NaiveDateTime>>(DataTypeOid::Timestamp, "-infinity"),
+		MaybeInfinite::NegativeInfinity
+	);
+	// the time of day can be at most 24:00:00, which is midnight of the next day
+	assert_eq!(
+		text::<NaiveDateTime>(DataTypeOid::Timestamp, "2020-01-01 24:00:00"),
+		timestamp(0, 0, 0, 0)
+	);
+	assert_eq!(
+		text_err::<NaiveDateTime>(DataTypeOid::Timestamp, "2020-01-01 25:00:00"),
+		SqlState::DatetimeFieldOverflow
+	);
+	assert_eq!(
+		text_err::<NaiveDateTime>(DataTypeOid::Timestamp, "2020-01-01 24:00:00.5"),
+		SqlState::DatetimeFieldOverflow
+	);
+
+	let utc = Utc.from_utc_datetime(&val);
+	assert_eq!(
+		text::<DateTime<Utc>>(DataTypeOid::Timestamptz, "2020-01-02 05:04:05.5+02"),
+		utc
+	);
+	assert_eq!(
+		text::<DateTime<Utc>>(DataTypeOid::Timestamptz, "2020-01-02 01:34:05.5-01:30"),
+		utc
+	);
+	assert_eq!(
+		text::<DateTime<Utc>>(DataTypeOid::Timestamptz, "2020-01-02 03:04:05.5"),
+		utc
+	);
+	assert_eq!(
+		text_err::<DateTime<Utc>>(DataTypeOid::Timestamptz, "2020-01-01 04:05+€"),
+		SqlState::InvalidDatetimeFormat
+	);
+	assert_eq!(
+		binary::<DateTime<Utc>>(DataTypeOid::Timestamptz, &to_binary(Type::TIMESTAMPTZ, utc)),
+		utc
+	);
+}
+
+#[test]
+fn times() {
+	let time = NaiveTime::from_hms_micro_opt(4, 5, 6, 789_000).unwrap();
+	assert_eq!(text::<NaiveTime>(DataTypeOid::Time, "04:05:06.789"), time);
+	assert_eq!(
+		text::<NaiveTime>(DataTypeOid::Time, "04:05"),
+		NaiveTime::from_hms_opt(4, 5, 0).unwrap()
+	);
+	assert_eq!(
+		text_err::<NaiveTime>(DataTypeOid::Time, "04:65"),
+		SqlState::DatetimeFieldOverflow
+	);
+	assert_eq!(
+		text_err::<NaiveTime>(DataTypeOid::Time, "9999999999999999:00"),
+		SqlState::DatetimeFieldOverflow
+	);
+	assert_eq!(
+		text_err::<NaiveTime>(DataTypeOid::Time, "25:00"),
+		SqlState::DatetimeFieldOverflow
+	);
+	assert_eq!(
+		binary::<NaiveTime>(DataTypeOid::Time, &to_binary(Type::TIME, time)),
+		time
+	);
+
+	let offset = FixedOffset::east_opt(5 * 3600 + 1800).unwrap();
+	assert_eq!(
+		text::<(NaiveTime, FixedOffset)>(DataTypeOid::Timetz, "04:05:06.789+05:30"),
+		(time, offset)
+	);
+
+	let mut data = 14_706_789_000i64.to_be_bytes().to_vec();
+	data.extend((-(5 * 3600 + 1800i32)).to_be_bytes());
+	assert_eq!(
+		binary::<(NaiveTime, FixedOffset)>(DataTypeOid::Timetz, &data),
+		(time, offset)
+	);
+}
+
+#[test]
+fn intervals() {
+	let micros = |h: i64, m: i64, s: i64, us: i64| ((h * 60 + m) * 60 + s) * 1_000_000 + us;
+	let intervals = [
+		Interval::default(),
+		Interval::new(14, 3, micros(4, 5, 6, 789_000)),
+		Interval::new(-14, -3, -micros(4, 5, 6, 789_000)),
+		Interval::new(0, 0, -micros(0, 1, 30, 0)),
+		Interval::new(-12, 2, 0),
+		Interval::new(0, 1, -micros(0, 0, 1, 0)),
+	];
+
+	// every output style can be read back
+	for style in [
+		IntervalStyle::Postgres,
+		IntervalStyle::PostgresVerbose,
+		IntervalStyle::SqlStandard,
+		IntervalStyle::Iso8601,
+	] {
+		for val in intervals {
+			let formatted = val.format_with_style(style);
+			assert_eq!(
+				text::<Interval>(DataTypeOid::Interval, &formatted),
+				val,
+				"{}",
+				formatted
+			);
+		}
+	}
+
+	assert_eq!(
+		text::<Interval>(DataTypeOid::Interval, "1.5 days 2 weeks"),
+		Interval::new(0, 15, micros(12, 0, 0, 0))
+	);
+	assert_eq!(
+		text::<Interval>(DataTypeOid::Interval, "1 mon -2 hours"),
+		Interval::new(1, 0, -micros(2, 0, 0, 0))
+	);
+	// unlike times of day, interval times aren't limited to 24 hours
+	assert_eq!(
+		text::<Interval>(DataTypeOid::Interval, "25:00:00"),
+		Interval::new(0, 0, micros(25, 0, 0, 0))
+	);
+	assert_eq!(
+		text_err::<Interval>(DataTypeOid::Interval, "1 fortnight"),
+		SqlState::InvalidDatetimeFormat
+	);
+	assert_eq!(
+		text_err::<Interval>(DataTypeOid::Interval, ""),
+		SqlState::InvalidDatetimeFormat
+	);
+
+	// fields which don't fit are rejected rather than wrapped or truncated
+	for overflowing in [
+		"3000000000 months",
+		"-3000000000 days",
+		"1000000000000000000 years",
+		"9999999999999999:00",
+		"P1000000000000000000Y",
+		"100000000000000000000 hours",
+		"-2147483648 mons ago",
+	] {
+		assert_eq!(
+			text_err::<Interval>(DataTypeOid::Interval, overflowing),
+			SqlState::IntervalFieldOverflow,
+			"{}",
+			overflowing
+		);
+	}
+	assert_eq!(
+		text::<Interval>(DataTypeOid::Interval, "-2147483648 mons"),
+		Interval::new(i32::MIN, 0, 0)
+	);
+
+	let mut data = 1_500_000i64.to_be_bytes().to_vec();
+	data.extend(3i32.to_be_bytes());
+	data.extend(14i32.to_be_bytes());
+	assert_eq!(
+		binary::<Interval>(DataTypeOid::Interval, &data),
+		Interval::new(14, 3, 1_500_000)
+	);
+
+	// sanity check that the settings default is the style used above
+	assert_eq!(SessionSettings::default().interval_style, IntervalStyle::Postgres);
+}
+
+#[test]
+fn nulls() {
+	assert_eq!(
+		Option::<i32>::from_pg_nullable(DataTypeOid::Int4, FormatCode::Text, None).unwrap(),
+		None
+	);
+	assert_eq!(
+		Option::<i32>::from_pg_nullable(DataTypeOid::Int4, FormatCode::Text, Some(b"3")).unwrap(),
+		Some(3)
+	);
+	assert_eq!(
+		i32::from_pg_nullable(DataTypeOid::Int4, FormatCode::Text, None)
+			.unwrap_err()
+			.sql_state,
+		SqlState::NullValueNotAllowed
+	);
+}
+
+#[test]
+fn arrays() {
+	assert_eq!(
+		text::<Vec<Option<i32>>>(DataTypeOid::Int4Array, "{1, 2 ,NULL}"),
+		vec![Some(1), Some(2), None]
+	);
+	assert_eq!(text::<Vec<i32>>(DataTypeOid::Int4Array, "{}"), Vec::<i32>::new());
+	assert_eq!(
+		text::<Vec<Option<String>>>(DataTypeOid::TextArray, r#"{"a,b","c\"d",NULL,"NULL",e\,f}"#),
+		vec![
+			Some("a,b".to_owned()),
+			Some("c\"d".to_owned()),
+			None,
+			Some("NULL".to_owned()),
+			Some("e,f".to_owned())
+		]
+	);
+	assert_eq!(
+		text_err::<Vec<i32>>(DataTypeOid::Int4Array, "{1,NULL}"),
+		SqlState::NullValueNotAllowed
+	);
+	assert_eq!(
+		text_err::<Vec<i32>>(DataTypeOid::Int4Array, "{{1},{2}}"),
+		SqlState::FeatureNotSupported
+	);
+	assert_eq!(
+		text_err::<Vec<i32>>(DataTypeOid::Int4Array, "{1,}"),
+		SqlState::InvalidTextRepresentation
+	);
+	assert_eq!(
+		text_err::<Vec<i32>>(DataTypeOid::Int4Array, "1,2"),
+		SqlState::InvalidTextRepresentation
+	);
+
+	let ints = vec![1i32, -2, 3];
+	assert_eq!(
+		binary::<Vec<i32>>(DataTypeOid::Int4Array, &to_binary(Type::INT4_ARRAY, &ints)),
+		ints
+	);
+	let strings = vec![Some("a".to_owned()), None];
+	assert_eq!(
+		binary::<Vec<Option<String>>>(DataTypeOid::TextArray, &to_binary(Type::TEXT_ARRAY, &strings)),
+		strings
+	);
+	assert_eq!(
+		binary::<Vec<i64>>(DataTypeOid::Int8Array, &to_binary(Type::INT8_ARRAY, Vec::<i64>::new())),
+		Vec::<i64>::new()
+	);
+	assert_eq!(
+		Vec::<i32>::from_pg_value(DataTypeOid::Int4, FormatCode::Text, b"{1}")
+			.unwrap_err()
+			.sql_state,
+		SqlState::DatatypeMismatch
+	);
+}
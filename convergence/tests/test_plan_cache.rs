@@ -0,0 +1,81 @@
+use convergence::plan_cache::{PlanCache, PlanCacheKey};
+use convergence::protocol::DataTypeOid;
+use std::sync::Arc;
+
+fn key(query: &str) -> PlanCacheKey {
+	PlanCacheKey::new(query, &[])
+}
+
+#[test]
+fn key_normalisation() {
+	assert_eq!(key("  select\n\t1 ;; ").query(), "select 1");
+	assert_eq!(key("select $1,  $2").query(), "select $1, $2");
+
+	// literals, quoted identifiers and comments are left untouched
+	assert_eq!(key("select  'a  b',  \"c  d\"").query(), "select 'a  b', \"c  d\"");
+	assert_eq!(key("select 1 --  a\n  ,  2").query(), "select 1 --  a\n , 2");
+	assert_eq!(
+		key("select /* a /*  b */  c */  1").query(),
+		"select /* a /*  b */  c */ 1"
+	);
+	assert_ne!(key("select 1 -- a\n, 2"), key("select 1 -- a , 2"));
+
+	// strings which can't be delimited reliably prevent normalisation
+	assert_eq!(key("select  $$a  b$$").query(), "select  $$a  b$$");
+	assert_eq!(key("select  E'a\\'  b'").query(), "select  E'a\\'  b'");
+
+	// parameter types are part of the key
+	assert_ne!(key("select $1"), PlanCacheKey::new("select $1", &[DataTypeOid::Int4]));
+}
+
+#[test]
+fn least_recently_used_eviction() {
+	let cache = PlanCache::new(2);
+	cache.insert(key("select 1"), Arc::new(1));
+	cache.insert(key("select 2"), Arc::new(2));
+
+	// using the first statement makes the second the least recently used
+	assert_eq!(cache.get(&key("select 1")).as_deref(), Some(&1));
+	cache.insert(key("select 3"), Arc::new(3));
+
+	assert_eq!(cache.len(), 2);
+	assert_eq!(cache.get(&key("select 2")), None);
+	assert_eq!(cache.get(&key("select 1")).as_deref(), Some(&1));
+	assert_eq!(cache.get(&key("select 3")).as_deref(), Some(&3));
+
+	// replacing a statement doesn't evict another, and marks it as recently used
+	cache.insert(key("select 3"), Arc::new(4));
+	assert_eq!(cache.len(), 2);
+	assert_eq!(cache.get(&key("select 3")).as_deref(), Some(&4));
+
+	cache.insert(key("select 1"), Arc::new(5));
+	cache.insert(key("select 6"), Arc::new(6));
+	assert_eq!(cache.get(&key("select 3")), None);
+	assert_eq!(cache.get(&key("select 1")).as_deref(), Some(&5));
+
+	// invalidated statements free their space without evicting others
+	cache.invalidate(|key, _| key.query() == "select 6");
+	cache.insert(key("select 7"), Arc::new(7));
+	assert_eq!(cache.len(), 2);
+	assert_eq!(cache.get(&key("select 1")).as_deref(), Some(&5));
+	assert_eq!(cache.get(&key("select 7")).as_deref(), Some(&7));
+}
+
+#[test]
+fn invalidation() {
+	let cache = PlanCache::new(10);
+	cache.insert(key("select * from a"), Arc::new(1));
+	cache.insert(key("select * from b"), Arc::new(2));
+
+	cache.invalidate(|key, _| key.query().ends_with("from a"));
+	assert_eq!(cache.get(&key("select * from a")), None);
+	assert_eq!(cache.len(), 1);
+
+	cache.clear();
+	assert!(cache.is_empty());
+
+	// a cache without any capacity never holds statements
+	let cache = PlanCache::new(0);
+	cache.insert(key("select 1"), Arc::new(1));
+	assert!(cache.is_empty());
+}
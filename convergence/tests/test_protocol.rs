@@ -0,0 +1,329 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use convergence::protocol::*;
+use std::collections::HashMap;
+use tokio_util::codec::{Decoder, Encoder};
+
+#[test]
+fn sql_state_codes_round_trip() {
+	for state in &[
+		SqlState::SuccessfulCompletion,
+		SqlState::UndefinedTable,
+		SqlState::UndefinedColumn,
+		SqlState::DivisionByZero,
+		SqlState::UniqueViolation,
+		SqlState::InsufficientPrivilege,
+		SqlState::InvalidSQLStatementName,
+	] {
+		assert_eq!(&SqlState::from_code(state.code()), state);
+	}
+
+	assert_eq!(SqlState::UndefinedTable.code(), "42P01");
+	assert_eq!(SqlState::UniqueViolation.code(), "23505");
+}
+
+#[test]
+fn sql_state_custom_codes() {
+	let state = SqlState::from_code("ZZ123");
+	assert_eq!(state, SqlState::Custom("ZZ123".to_owned()));
+	assert_eq!(state.code(), "ZZ123");
+	assert_eq!(state.class(), "ZZ");
+}
+
+#[test]
+fn sql_state_classes() {
+	assert!(SqlState::UniqueViolation.is_integrity_violation());
+	assert!(SqlState::ForeignKeyViolation.is_integrity_violation());
+	assert!(!SqlState::DivisionByZero.is_integrity_violation());
+	assert!(SqlState::DivisionByZero.is_data_exception());
+	assert!(SqlState::UndefinedTable.is_syntax_error_or_access_rule_violation());
+	assert!(SqlState::SerializationFailure.is_transaction_rollback());
+	assert!(SqlState::ProtocolViolation.is_connection_exception());
+	assert!(SqlState::QueryCanceled.is_operator_intervention());
+	assert_eq!(SqlState::InsufficientPrivilege.class(), "42");
+}
+
+#[test]
+fn data_type_oids_round_trip() {
+	for &oid in &[16u32, 20, 1700, 1043, 2950, 3802, 1184, 1186, 869, 1007, 1009, 2951] {
+		assert_eq!(u32::from(DataTypeOid::from(oid)), oid);
+	}
+
+	assert_eq!(DataTypeOid::from(1700), DataTypeOid::Numeric);
+	assert_eq!(DataTypeOid::from(99999), DataTypeOid::Unknown(99999));
+}
+
+#[test]
+fn data_type_sizes() {
+	assert_eq!(DataTypeOid::Uuid.size_bytes(), 16);
+	assert_eq!(DataTypeOid::Interval.size_bytes(), 16);
+	assert_eq!(DataTypeOid::Timetz.size_bytes(), 12);
+	assert_eq!(DataTypeOid::Name.size_bytes(), 64);
+	assert_eq!(DataTypeOid::Numeric.size_bytes(), -1);
+	assert_eq!(DataTypeOid::Int4Array.size_bytes(), -1);
+	assert_eq!(DataTypeOid::Unknown(99999).size_bytes(), -1);
+}
+
+#[test]
+fn data_type_arrays() {
+	assert_eq!(DataTypeOid::Int4.array_type(), Some(DataTypeOid::Int4Array));
+	assert_eq!(DataTypeOid::TextArray.element_type(), Some(DataTypeOid::Text));
+	assert_eq!(DataTypeOid::Text.element_type(), None);
+	assert_eq!(DataTypeOid::Unspecified.array_type(), None);
+}
+
+#[test]
+fn row_description_formats() {
+	let fields = vec![
+		FieldDescription {
+			name: "a".to_owned(),
+			data_type: DataTypeOid::Int4,
+		},
+		FieldDescription {
+			name: "b".to_owned(),
+			data_type: DataTypeOid::Text,
+		},
+	];
+
+	let desc = RowDescription::with_bind_format(fields.clone(), &BindFormat::All(FormatCode::Binary)).unwrap();
+	assert_eq!(desc.format_codes(), &[FormatCode::Binary, FormatCode::Binary]);
+
+	let err = RowDescription::with_bind_format(fields, &BindFormat::PerColumn(vec![FormatCode::Text])).unwrap_err();
+	assert_eq!(err.sql_state, SqlState::ProtocolViolation);
+}
+
+fn encode_client_messages(messages: Vec<ClientMessage>) -> BytesMut {
+	let mut codec = ClientCodec::new();
+	let mut buf = BytesMut::new();
+	for message in messages {
+		codec.encode(message, &mut buf).unwrap();
+	}
+	buf
+}
+
+// returns a codec which has already received a startup message
+fn started_codec() -> ConnectionCodec {
+	let mut codec = ConnectionCodec::new();
+	let mut buf = encode_client_messages(vec![ClientMessage::Startup(Startup {
+		requested_protocol_version: (3, 0),
+		parameters: HashMap::new(),
+	})]);
+	assert!(matches!(codec.decode(&mut buf), Ok(Some(ClientMessage::Startup(_)))));
+	codec
+}
+
+fn decode_raw(codec: &mut ConnectionCodec, tag: u8, body: &[u8]) -> Result<Option<ClientMessage>, ProtocolError> {
+	let mut buf = BytesMut::new();
+	buf.put_u8(tag);
+	buf.put_i32(body.len() as i32 + 4);
+	buf.put_slice(body);
+	codec.decode(&mut buf)
+}
+
+fn sample_messages() -> Vec<ClientMessage> {
+	vec![
+		ClientMessage::Parse(Parse {
+			prepared_statement_name: "s".to_owned(),
+			query: "select $1".to_owned(),
+			parameter_types: vec![DataTypeOid::Int4],
+		}),
+		ClientMessage::Bind(Bind {
+			portal: "p".to_owned(),
+			prepared_statement_name: "s".to_owned(),
+			parameter_format: BindFormat::All(FormatCode::Binary),
+			parameters: vec![Some(Bytes::from_static(&[0, 0, 0, 1])), None],
+			result_format: BindFormat::PerColumn(vec![FormatCode::Text, FormatCode::Binary]),
+		}),
+		ClientMessage::Describe(Describe::Portal("p".to_owned())),
+		ClientMessage::Execute(Execute {
+			portal: "p".to_owned(),
+			max_rows: None,
+		}),
+		ClientMessage::Sync,
+		ClientMessage::Query("select 1".to_owned()),
+	]
+}
+
+#[test]
+fn decode_partial_messages() {
+	let encoded = encode_client_messages(sample_messages());
+
+	// every prefix decodes cleanly, with the incomplete message left for later
+	for len in 0..encoded.len() {
+		let mut codec = started_codec();
+		let mut buf = BytesMut::from(&encoded[..len]);
+		let mut num_messages = 0;
+		while codec.decode(&mut buf).unwrap().is_some() {
+			num_messages += 1;
+		}
+		assert!(num_messages < sample_messages().len());
+	}
+
+	let mut codec = started_codec();
+	let mut buf = encoded;
+	for message in sample_messages() {
+		assert_eq!(codec.decode(&mut buf).unwrap(), Some(message));
+	}
+	assert!(buf.is_empty());
+}
+
+#[test]
+fn decode_corrupted_messages() {
+	let encoded = encode_client_messages(sample_messages());
+
+	// corrupting any byte may produce an error, but never a panic
+	for pos in 0..encoded.len() {
+		for &byte in &[0u8, 0x80, 0xff] {
+			let mut codec = started_codec();
+			let mut buf = encoded.clone();
+			buf[pos] = byte;
+			while let Ok(Some(_)) = codec.decode(&mut buf) {}
+		}
+	}
+}
+
+#[test]
+fn decode_truncated_fields() {
+	let mut codec = started_codec();
+
+	// missing the parameter count
+	let err = decode_raw(&mut codec, b'P', b"s\0select 1\0").unwrap_err();
+	assert!(matches!(err, ProtocolError::UnexpectedEndOfMessage));
+
+	// two parameter types announced, one present
+	let err = decode_raw(&mut codec, b'P', b"s\0select 1\0\0\x02\0\0\0\x17").unwrap_err();
+	assert!(matches!(err, ProtocolError::UnexpectedEndOfMessage));
+
+	// parameter value longer than the message
+	let err = decode_raw(&mut codec, b'B', b"\0\0\0\0\0\x01\0\0\0\x10ab\0\0").unwrap_err();
+	assert!(matches!(err, ProtocolError::UnexpectedEndOfMessage));
+
+	// unterminated portal name
+	let err = decode_raw(&mut codec, b'E', b"portal").unwrap_err();
+	assert!(matches!(err, ProtocolError::UnexpectedEndOfMessage));
+
+	// missing describe target
+	let err = decode_raw(&mut codec, b'D', b"").unwrap_err();
+	assert!(matches!(err, ProtocolError::UnexpectedEndOfMessage));
+}
+
+#[test]
+fn decode_invalid_fields() {
+	let mut codec = started_codec();
+
+	// negative parameter count
+	let err = decode_raw(&mut codec, b'P', b"s\0select 1\0\xff\xff").unwrap_err();
+	assert!(matches!(err, ProtocolError::ParserError));
+
+	// parameter lengths below -1
+	let err = decode_raw(&mut codec, b'B', b"\0\0\0\0\0\x01\xff\xff\xff\xfe\0\0").unwrap_err();
+	assert!(matches!(err, ProtocolError::ParserError));
+
+	// unknown format code
+	let err = decode_raw(&mut codec, b'B', b"\0\0\0\0\0\0\0\x01\0\x02").unwrap_err();
+	assert!(matches!(err, ProtocolError::InvalidFormatCode(2)));
+
+	// trailing data after the message's fields
+	let err = decode_raw(&mut codec, b'S', b"x").unwrap_err();
+	assert!(matches!(err, ProtocolError::ParserError));
+
+	let err = decode_raw(&mut codec, b'Z', b"").unwrap_err();
+	assert!(matches!(err, ProtocolError::InvalidMessageType(b'Z')));
+}
+
+#[test]
+fn decode_invalid_lengths() {
+	let mut codec = started_codec();
+	let mut buf = BytesMut::from(&[b'Q', 0xff, 0xff, 0xff, 0xf0][..]);
+	assert!(matches!(
+		codec.decode(&mut buf).unwrap_err(),
+		ProtocolError::InvalidMessageLength(-16)
+	));
+
+	let mut buf = BytesMut::from(&[b'Q', 0, 0, 0, 3][..]);
+	assert!(matches!(
+		codec.decode(&mut buf).unwrap_err(),
+		ProtocolError::InvalidMessageLength(3)
+	));
+
+	// startup packets have a smaller header and limit
+	let mut codec = ConnectionCodec::new();
+	let mut buf = BytesMut::from(&[0, 0, 0, 4, 0, 3, 0, 0][..]);
+	assert!(matches!(
+		codec.decode(&mut buf).unwrap_err(),
+		ProtocolError::InvalidMessageLength(4)
+	));
+
+	let mut buf = BytesMut::from(&[0, 1, 0, 0, 0, 3, 0, 0][..]);
+	assert!(matches!(
+		codec.decode(&mut buf).unwrap_err(),
+		ProtocolError::MessageTooLarge(65536)
+	));
+
+	// unterminated parameter list
+	let mut buf = BytesMut::from(&[0, 0, 0, 13, 0, 3, 0, 0, b'u', b's', b'e', b'r', 0][..]);
+	assert!(matches!(
+		codec.decode(&mut buf).unwrap_err(),
+		ProtocolError::InvalidStartupPacketLayout
+	));
+
+	// data after the terminator
+	let mut buf = BytesMut::from(&[0, 0, 0, 10, 0, 3, 0, 0, 0, b'x'][..]);
+	assert!(matches!(
+		codec.decode(&mut buf).unwrap_err(),
+		ProtocolError::InvalidStartupPacketLayout
+	));
+}
+
+#[test]
+fn decode_startup_requests() {
+	let mut codec = ConnectionCodec::new();
+	let mut buf = BytesMut::from(&[0, 0, 0, 8, 0x04, 0xd2, 0x16, 0x30, 0, 0, 0, 8, 0x04, 0xd2, 0x16, 0x2f][..]);
+	assert_eq!(codec.decode(&mut buf).unwrap(), Some(ClientMessage::GSSENCRequest));
+	assert_eq!(codec.decode(&mut buf).unwrap(), Some(ClientMessage::SSLRequest));
+
+	// cancellation keys are 4 bytes before protocol 3.2, and up to 256 bytes after
+	for &key_len in &[4, 32, 256] {
+		let cancel = ClientMessage::CancelRequest(CancelRequest {
+			process_id: 123,
+			secret_key: vec![1; key_len],
+		});
+		let mut buf = encode_client_messages(vec![cancel.clone()]);
+		assert_eq!(ConnectionCodec::new().decode(&mut buf).unwrap(), Some(cancel));
+	}
+
+	for &key_len in &[0, 3, 257] {
+		let mut buf = encode_client_messages(vec![ClientMessage::CancelRequest(CancelRequest {
+			process_id: 123,
+			secret_key: vec![1; key_len],
+		})]);
+		assert!(matches!(
+			ConnectionCodec::new().decode(&mut buf).unwrap_err(),
+			ProtocolError::InvalidMessageLength(_)
+		));
+	}
+
+	// encryption requests don't have a body
+	let mut buf = BytesMut::from(&[0, 0, 0, 9, 0x04, 0xd2, 0x16, 0x2f, 0][..]);
+	assert!(ConnectionCodec::new().decode(&mut buf).is_err());
+}
+
+#[test]
+fn decode_max_message_size() {
+	let mut codec = started_codec().with_max_message_size(64);
+	let mut buf = encode_client_messages(vec![ClientMessage::Query("x".repeat(59))]);
+	assert!(codec.decode(&mut buf).unwrap().is_some());
+
+	// rejected as soon as the header arrives
+	let mut buf = encode_client_messages(vec![ClientMessage::Query("x".repeat(60))]);
+	buf.truncate(5);
+	assert!(matches!(
+		codec.decode(&mut buf).unwrap_err(),
+		ProtocolError::MessageTooLarge(65)
+	));
+
+	// messages under the default limit only reserve space as their data arrives
+	let mut codec = started_codec();
+	let mut buf = BytesMut::from(&[b'Q', 0x3f, 0xff, 0xff, 0xff][..]);
+	assert!(codec.decode(&mut buf).unwrap().is_none());
+	assert!(buf.capacity() <= 2 << 20);
+}
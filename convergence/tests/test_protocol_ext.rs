@@ -0,0 +1,717 @@
+use bytes::{Buf, BytesMut};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike, Utc};
+use convergence::protocol::{ConnectionCodec, DataTypeOid, ErrorResponse, FormatCode, SqlState};
+use convergence::protocol_ext::{DataRowBatch, DataRowWriter, Interval, MaybeInfinite, Numeric};
+use convergence::settings::SessionSettings;
+use tokio_postgres::types::{FromSql, Type};
+use tokio_util::codec::Encoder;
+use uuid::Uuid;
+
+type WriteResult = Result<(), ErrorResponse>;
+
+fn batch_bytes(batch: DataRowBatch) -> BytesMut {
+	let mut buf = BytesMut::new();
+	ConnectionCodec::new().encode(batch, &mut buf).unwrap();
+	buf
+}
+
+// encodes a single-column row and returns the raw column value
+fn encode_value(format_code: FormatCode, write: impl FnOnce(&mut DataRowWriter) -> WriteResult) -> Vec<u8> {
+	encode_value_with(SessionSettings::default(), format_code, write)
+}
+
+fn encode_value_with(
+	settings: SessionSettings,
+	format_code: FormatCode,
+	write: impl FnOnce(&mut DataRowWriter) -> WriteResult,
+) -> Vec<u8> {
+	let mut batch = DataRowBatch::new(format_code, 1).with_settings(settings);
+	batch.write_row(write).unwrap();
+
+	let mut buf = batch_bytes(batch);
+	assert_eq!(buf.get_u8(), b'D');
+	assert_eq!(buf.get_i32() as usize, buf.len() + 4);
+	assert_eq!(buf.get_i16(), 1);
+	let len = buf.get_i32() as usize;
+	assert_eq!(buf.len(), len);
+	buf.to_vec()
+}
+
+fn encode_text(write: impl FnOnce(&mut DataRowWriter) -> WriteResult) -> String {
+	String::from_utf8(encode_value(FormatCode::Text, write)).unwrap()
+}
+
+fn encode_text_with(settings: &str, write: impl FnOnce(&mut DataRowWriter) -> WriteResult) -> String {
+	let mut session = SessionSettings::default();
+	for setting in settings.split(';') {
+		let (name, value) = setting.split_once('=').unwrap();
+		session.set(name, value).unwrap();
+	}
+
+	String::from_utf8(encode_value_with(session, FormatCode::Text, write)).unwrap()
+}
+
+fn encode_binary(write: impl FnOnce(&mut DataRowWriter) -> WriteResult) -> Vec<u8> {
+	encode_value(FormatCode::Binary, write)
+}
+
+fn decode_binary<T: for<'a> FromSql<'a>>(ty: Type, write: impl FnOnce(&mut DataRowWriter) -> WriteResult) -> T {
+	T::from_sql(&ty, &encode_binary(write)).unwrap()
+}
+
+// encodes a value in binary, decodes it with tokio-postgres and checks it matches the original value
+fn assert_round_trip<T>(ty: Type, val: T, write: impl FnOnce(&mut DataRowWriter, T) -> WriteResult)
+where
+	T: for<'a> FromSql<'a> + PartialEq + std::fmt::Debug + Clone,
+{
+	assert_eq!(decode_binary::<T>(ty, |row| write(row, val.clone())), val);
+}
+
+#[test]
+fn primitive_round_trips() {
+	assert_round_trip(Type::BOOL, true, |row, val| row.write_bool(val));
+	assert_round_trip(Type::BOOL, false, |row, val| row.write_bool(val));
+	assert_round_trip(Type::INT2, -12i16, |row, val| row.write_int2(val));
+	assert_round_trip(Type::INT4, i32::MAX, |row, val| row.write_int4(val));
+	assert_round_trip(Type::INT8, i64::MIN, |row, val| row.write_int8(val));
+	assert_round_trip(Type::FLOAT4, 1.5f32, |row, val| row.write_float4(val));
+	assert_round_trip(Type::FLOAT8, -0.25f64, |row, val| row.write_float8(val));
+	assert_round_trip(Type::TEXT, "hello".to_owned(), |row, val| row.write_string(&val));
+	assert_round_trip(
+		Type::DATE,
+		NaiveDate::from_ymd_opt(1999, 12, 31).unwrap(),
+		|row, val| row.write_date(val),
+	);
+	assert_round_trip(
+		Type::TIMESTAMP,
+		NaiveDate::from_ymd_opt(2021, 6, 7)
+			.unwrap()
+			.and_hms_micro_opt(8, 9, 10, 11)
+			.unwrap(),
+		|row, val: NaiveDateTime| row.write_timestamp(val),
+	);
+}
+
+#[test]
+fn bool_binary_has_length_prefix() {
+	let mut batch = DataRowBatch::new(FormatCode::Binary, 2);
+	batch
+		.write_row(|row| {
+			row.write_bool(true)?;
+			row.write_int2(7)
+		})
+		.unwrap();
+
+	assert_eq!(
+		&batch_bytes(batch)[..],
+		&[b'D', 0, 0, 0, 17, 0, 2, 0, 0, 0, 1, 1, 0, 0, 0, 2, 0, 7][..]
+	);
+}
+
+#[test]
+fn column_count_mismatch() {
+	let mut batch = DataRowBatch::new(FormatCode::Text, 2);
+
+	let err = batch.write_row(|row| row.write_int4(1)).unwrap_err();
+	assert_eq!(err.sql_state, SqlState::InternalError);
+
+	let err = batch
+		.write_row(|row| {
+			row.write_int4(1)?;
+			row.write_int4(2)?;
+			row.write_int4(3)
+		})
+		.unwrap_err();
+	assert_eq!(err.sql_state, SqlState::InternalError);
+
+	// failed rows are discarded entirely
+	assert_eq!(batch.num_rows(), 0);
+	batch
+		.write_row(|row| {
+			row.write_int4(1)?;
+			row.write_null()
+		})
+		.unwrap();
+	assert_eq!(batch.num_rows(), 1);
+	assert_eq!(
+		&batch_bytes(batch)[..],
+		&[b'D', 0, 0, 0, 15, 0, 2, 0, 0, 0, 1, b'1', 255, 255, 255, 255][..]
+	);
+}
+
+#[test]
+fn batch_reuse() {
+	let mut batch = DataRowBatch::new(FormatCode::Binary, 1).with_capacity(64);
+	assert!(batch.is_empty());
+	assert!(batch.capacity() >= 64);
+
+	batch.write_row(|row| row.write_int4(1)).unwrap();
+	batch.write_row(|row| row.write_int4(2)).unwrap();
+	assert_eq!(batch.num_rows(), 2);
+	assert_eq!(batch.encoded_len(), 2 * 15);
+
+	// encoding by reference hands over the rows and leaves the batch empty
+	let mut codec = ConnectionCodec::new();
+	let mut buf = BytesMut::new();
+	codec.encode(&mut batch, &mut buf).unwrap();
+	assert_eq!(buf.len(), 2 * 15);
+	assert!(batch.is_empty());
+	assert_eq!(batch.encoded_len(), 0);
+
+	// appended to whatever was already buffered
+	batch.write_row(|row| row.write_int4(3)).unwrap();
+	codec.encode(&mut batch, &mut buf).unwrap();
+	assert_eq!(&buf[30..], &[b'D', 0, 0, 0, 14, 0, 1, 0, 0, 0, 4, 0, 0, 0, 3][..]);
+
+	batch.write_row(|row| row.write_int4(4)).unwrap();
+	batch.clear();
+	assert_eq!(batch.num_rows(), 0);
+	assert_eq!(batch.encoded_len(), 0);
+}
+
+#[test]
+fn take_rows() {
+	let mut batch = DataRowBatch::new(FormatCode::Binary, 1);
+	for i in 0..5 {
+		batch.write_row(|row| row.write_int4(i)).unwrap();
+	}
+
+	let first = batch.take_rows(2);
+	assert_eq!(first.num_rows(), 2);
+	assert_eq!(batch.num_rows(), 3);
+	assert_eq!(
+		&batch_bytes(first)[15..],
+		&[b'D', 0, 0, 0, 14, 0, 1, 0, 0, 0, 4, 0, 0, 0, 1][..]
+	);
+
+	// taking more rows than remain takes everything
+	let rest = batch.take_rows(10);
+	assert_eq!(rest.num_rows(), 3);
+	assert_eq!(rest.encoded_len(), 3 * 15);
+	assert!(batch.is_empty());
+	assert!(batch.take_rows(1).is_empty());
+}
+
+#[test]
+fn column_type_mismatch() {
+	let mut batch = DataRowBatch::with_column_types(FormatCode::Binary, vec![DataTypeOid::Int4, DataTypeOid::Varchar]);
+
+	let err = batch
+		.write_row(|row| {
+			row.write_int8(1)?;
+			row.write_string("a")
+		})
+		.unwrap_err();
+	assert_eq!(err.sql_state, SqlState::DatatypeMismatch);
+
+	// strings can't be sent for other types in the binary format
+	let err = batch
+		.write_row(|row| {
+			row.write_string("1")?;
+			row.write_string("a")
+		})
+		.unwrap_err();
+	assert_eq!(err.sql_state, SqlState::DatatypeMismatch);
+
+	batch
+		.write_row(|row| {
+			row.write_int4(1)?;
+			row.write_string("a")
+		})
+		.unwrap();
+	assert_eq!(batch.num_rows(), 1);
+
+	// ...but are accepted for any type in the text format
+	let mut batch = DataRowBatch::with_column_types(FormatCode::Text, vec![DataTypeOid::Numeric]);
+	batch.write_row(|row| row.write_string("1.50")).unwrap();
+}
+
+#[test]
+fn mixed_column_formats() {
+	// each value is checked against the format of its own column, not the next one
+	let mut batch = DataRowBatch::with_column_formats(
+		vec![FormatCode::Text, FormatCode::Binary],
+		vec![DataTypeOid::Int4, DataTypeOid::Int4],
+	);
+	batch
+		.write_row(|row| {
+			row.write_string("1")?;
+			row.write_int4(2)
+		})
+		.unwrap();
+
+	let err = batch
+		.write_row(|row| {
+			row.write_string("1")?;
+			row.write_string("hello")
+		})
+		.unwrap_err();
+	assert_eq!(err.sql_state, SqlState::DatatypeMismatch);
+	assert_eq!(batch.num_rows(), 1);
+}
+
+#[test]
+fn numeric_text() {
+	assert_eq!(encode_text(|row| row.write_numeric(&Numeric::new(12345, 2))), "123.45");
+	assert_eq!(encode_text(|row| row.write_numeric(&Numeric::new(-5, 3))), "-0.005");
+	assert_eq!(encode_text(|row| row.write_numeric(&Numeric::new(42, 0))), "42");
+	assert_eq!(encode_text(|row| row.write_numeric(&Numeric::NaN)), "NaN");
+	assert_eq!(
+		encode_text(|row| row.write_numeric(&Numeric::NegativeInfinity)),
+		"-Infinity"
+	);
+}
+
+#[test]
+fn numeric_binary() {
+	// ndigits, weight, sign, dscale, base-10000 digits
+	let encoded = |val: Numeric| -> Vec<i16> {
+		encode_binary(|row| row.write_numeric(&val))
+			.chunks(2)
+			.map(|c| i16::from_be_bytes([c[0], c[1]]))
+			.collect()
+	};
+
+	assert_eq!(encoded(Numeric::new(12345, 2)), vec![2, 0, 0, 2, 123, 4500]);
+	assert_eq!(encoded(Numeric::new(-1, 4)), vec![1, -1, 0x4000, 4, 1]);
+	assert_eq!(encoded(Numeric::new(12345678, 0)), vec![2, 1, 0, 0, 1234, 5678]);
+	assert_eq!(encoded(Numeric::new(10000, 0)), vec![1, 1, 0, 0, 1]);
+	assert_eq!(encoded(Numeric::new(0, 2)), vec![0, 0, 0, 2]);
+	assert_eq!(encoded(Numeric::NaN), vec![0, 0, 0xC000u16 as i16, 0]);
+	assert_eq!(encoded(Numeric::Infinity), vec![0, 0, 0xD000u16 as i16, 0]);
+}
+
+#[test]
+fn uuid_values() {
+	let val = Uuid::parse_str("a1a2a3a4-b1b2-c1c2-d1d2-d3d4d5d6d7d8").unwrap();
+	assert_eq!(
+		encode_text(|row| row.write_uuid(&val)),
+		"a1a2a3a4-b1b2-c1c2-d1d2-d3d4d5d6d7d8"
+	);
+	assert_eq!(decode_binary::<Uuid>(Type::UUID, |row| row.write_uuid(&val)), val);
+}
+
+#[test]
+fn bytea_values() {
+	assert_eq!(encode_text(|row| row.write_bytea(&[0x0a, 0x0b, 0xff])), "\\x0a0bff");
+	assert_eq!(encode_text(|row| row.write_bytea(&[])), "\\x");
+	assert_eq!(
+		decode_binary::<Vec<u8>>(Type::BYTEA, |row| row.write_bytea(&[0x0a, 0x0b, 0xff])),
+		vec![0x0a, 0x0b, 0xff]
+	);
+}
+
+#[test]
+fn json_values() {
+	let json = r#"{"a":[1,2]}"#;
+	let expected: serde_json::Value = serde_json::from_str(json).unwrap();
+
+	assert_eq!(encode_text(|row| row.write_json(json)), json);
+	assert_eq!(encode_text(|row| row.write_jsonb(json)), json);
+	assert_eq!(
+		decode_binary::<serde_json::Value>(Type::JSON, |row| row.write_json(json)),
+		expected
+	);
+	assert_eq!(
+		decode_binary::<serde_json::Value>(Type::JSONB, |row| row.write_jsonb(json)),
+		expected
+	);
+}
+
+#[test]
+fn interval_text() {
+	let micros = |h: i64, m: i64, s: i64, us: i64| ((h * 60 + m) * 60 + s) * 1_000_000 + us;
+
+	assert_eq!(encode_text(|row| row.write_interval(&Interval::default())), "00:00:00");
+	assert_eq!(
+		encode_text(|row| row.write_interval(&Interval::new(14, 3, micros(4, 5, 6, 789_000)))),
+		"1 year 2 mons 3 days 04:05:06.789"
+	);
+	assert_eq!(
+		encode_text(|row| row.write_interval(&Interval::new(-12, 2, 0))),
+		"-1 years +2 days"
+	);
+	assert_eq!(
+		encode_text(|row| row.write_interval(&Interval::new(0, -1, micros(1, 0, 0, 0)))),
+		"-1 days +01:00:00"
+	);
+	assert_eq!(
+		encode_text(|row| row.write_interval(&Interval::new(0, 0, -micros(0, 1, 30, 0)))),
+		"-00:01:30"
+	);
+}
+
+#[test]
+fn interval_binary() {
+	let mut data = BytesMut::from(&encode_binary(|row| row.write_interval(&Interval::new(14, 3, 1_500_000)))[..]);
+	assert_eq!(data.get_i64(), 1_500_000);
+	assert_eq!(data.get_i32(), 3);
+	assert_eq!(data.get_i32(), 14);
+}
+
+#[test]
+fn time_values() {
+	let val = NaiveTime::from_hms_micro_opt(4, 5, 6, 789_000).unwrap();
+	assert_eq!(encode_text(|row| row.write_time(val)), "04:05:06.789");
+	assert_eq!(
+		encode_text(|row| row.write_time(NaiveTime::from_hms_opt(23, 0, 0).unwrap())),
+		"23:00:00"
+	);
+	assert_eq!(decode_binary::<NaiveTime>(Type::TIME, |row| row.write_time(val)), val);
+}
+
+#[test]
+fn timestamptz_values() {
+	let val: DateTime<Utc> = Utc.from_utc_datetime(
+		&NaiveDate::from_ymd_opt(2020, 1, 2)
+			.unwrap()
+			.and_hms_micro_opt(3, 4, 5, 500_000)
+			.unwrap(),
+	);
+
+	assert_eq!(
+		encode_text(|row| row.write_timestamptz(val)),
+		"2020-01-02 03:04:05.5+00"
+	);
+	assert_eq!(
+		decode_binary::<DateTime<Utc>>(Type::TIMESTAMPTZ, |row| row.write_timestamptz(val)),
+		val
+	);
+}
+
+#[test]
+fn array_text() {
+	assert_eq!(
+		encode_text(|row| row.write_array(DataTypeOid::Int4, |arr| {
+			arr.write_int4(1)?;
+			arr.write_int4(2)?;
+			arr.write_null()
+		})),
+		"{1,2,NULL}"
+	);
+
+	assert_eq!(
+		encode_text(|row| row.write_array(DataTypeOid::Text, |arr| {
+			for val in &["a", "b c", "", "null", "x\"y", "back\\slash", "{}"] {
+				arr.write_string(val)?;
+			}
+			Ok(())
+		})),
+		r#"{a,"b c","","null","x\"y","back\\slash","{}"}"#
+	);
+
+	assert_eq!(encode_text(|row| row.write_array(DataTypeOid::Int4, |_| Ok(()))), "{}");
+}
+
+#[test]
+fn array_binary() {
+	assert_eq!(
+		decode_binary::<Vec<Option<i32>>>(Type::INT4_ARRAY, |row| row.write_array(DataTypeOid::Int4, |arr| {
+			arr.write_int4(1)?;
+			arr.write_int4(2)?;
+			arr.write_null()
+		})),
+		vec![Some(1), Some(2), None]
+	);
+
+	assert_eq!(
+		decode_binary::<Vec<String>>(Type::TEXT_ARRAY, |row| row.write_array(DataTypeOid::Text, |arr| {
+			arr.write_string("a")?;
+			arr.write_string("b c")
+		})),
+		vec!["a".to_owned(), "b c".to_owned()]
+	);
+
+	assert_eq!(
+		decode_binary::<Vec<i32>>(Type::INT4_ARRAY, |row| row.write_array(DataTypeOid::Int4, |_| Ok(()))),
+		Vec::<i32>::new()
+	);
+}
+
+#[test]
+fn multidimensional_arrays() {
+	let write = |row: &mut DataRowWriter| {
+		row.write_array(DataTypeOid::Int4, |arr| {
+			for outer in 0..3 {
+				arr.write_array(|sub| {
+					sub.write_int4(outer * 2 + 1)?;
+					sub.write_int4(outer * 2 + 2)
+				})?;
+			}
+			Ok(())
+		})
+	};
+
+	assert_eq!(encode_text(write), "{{1,2},{3,4},{5,6}}");
+
+	let mut data = BytesMut::from(&encode_binary(write)[..]);
+	assert_eq!(data.get_i32(), 2); // dimensions
+	assert_eq!(data.get_i32(), 0); // has nulls
+	assert_eq!(data.get_u32(), 23); // element oid
+	assert_eq!((data.get_i32(), data.get_i32()), (3, 1));
+	assert_eq!((data.get_i32(), data.get_i32()), (2, 1));
+	for expected in 1..=6 {
+		assert_eq!(data.get_i32(), 4);
+		assert_eq!(data.get_i32(), expected);
+	}
+	assert!(data.is_empty());
+}
+
+#[test]
+fn invalid_arrays() {
+	let mut batch = DataRowBatch::new(FormatCode::Text, 1);
+
+	let ragged = batch.write_row(|row| {
+		row.write_array(DataTypeOid::Int4, |arr| {
+			arr.write_array(|sub| sub.write_int4(1))?;
+			arr.write_array(|sub| {
+				sub.write_int4(2)?;
+				sub.write_int4(3)
+			})
+		})
+	});
+	assert_eq!(ragged.unwrap_err().sql_state, SqlState::ArraySubscriptError);
+
+	let mixed_depths = batch.write_row(|row| {
+		row.write_array(DataTypeOid::Int4, |arr| {
+			arr.write_int4(1)?;
+			arr.write_array(|sub| sub.write_int4(2))
+		})
+	});
+	assert_eq!(mixed_depths.unwrap_err().sql_state, SqlState::ArraySubscriptError);
+
+	let wrong_type = batch.write_row(|row| row.write_array(DataTypeOid::Int4, |arr| arr.write_int8(1)));
+	assert_eq!(wrong_type.unwrap_err().sql_state, SqlState::DatatypeMismatch);
+
+	assert_eq!(batch.num_rows(), 0);
+}
+
+#[test]
+fn record_text() {
+	assert_eq!(
+		encode_text(|row| row.write_record(|rec| {
+			rec.write_int4(1)?;
+			rec.write_string("a b")?;
+			rec.write_null()?;
+			rec.write_string("x\"y")?;
+			rec.write_string("")
+		})),
+		r#"(1,"a b",,"x""y","")"#
+	);
+
+	assert_eq!(
+		encode_text(|row| row.write_array(DataTypeOid::Record, |arr| {
+			arr.write_record(|rec| {
+				rec.write_int4(1)?;
+				rec.write_string("a")
+			})?;
+			arr.write_record(|rec| {
+				rec.write_int4(2)?;
+				rec.write_array(DataTypeOid::Int4, |arr| arr.write_int4(3))
+			})
+		})),
+		r#"{"(1,a)","(2,{3})"}"#
+	);
+}
+
+#[test]
+fn record_binary() {
+	let mut data = BytesMut::from(
+		&encode_binary(|row| {
+			row.write_record(|rec| {
+				rec.write_int4(7)?;
+				rec.write_null_as(DataTypeOid::Text)
+			})
+		})[..],
+	);
+
+	assert_eq!(data.get_i32(), 2); // fields
+	assert_eq!(data.get_u32(), 23);
+	assert_eq!(data.get_i32(), 4);
+	assert_eq!(data.get_i32(), 7);
+	assert_eq!(data.get_u32(), 25);
+	assert_eq!(data.get_i32(), -1);
+	assert!(data.is_empty());
+}
+
+#[test]
+fn float_text() {
+	let float8 = |val: f64| encode_text(|row| row.write_float8(val));
+	let float4 = |val: f32| encode_text(|row| row.write_float4(val));
+
+	assert_eq!(float8(f64::NAN), "NaN");
+	assert_eq!(float8(f64::INFINITY), "Infinity");
+	assert_eq!(float8(f64::NEG_INFINITY), "-Infinity");
+	assert_eq!(float8(-0.0), "-0");
+	assert_eq!(float8(0.1), "0.1");
+	assert_eq!(float8(1.0 / 3.0), "0.3333333333333333");
+	assert_eq!(float8(1e14), "100000000000000");
+	assert_eq!(float8(1e15), "1e+15");
+	assert_eq!(float8(1e100), "1e+100");
+	assert_eq!(float8(-1.2345678901234568e17), "-1.2345678901234568e+17");
+	assert_eq!(float8(0.0001), "0.0001");
+	assert_eq!(float8(0.00001), "1e-05");
+	assert_eq!(float4(0.1), "0.1");
+	assert_eq!(float4(123456.0), "123456");
+	assert_eq!(float4(1e6), "1e+06");
+	assert_eq!(float4(f32::NEG_INFINITY), "-Infinity");
+}
+
+#[test]
+fn float_text_extra_digits() {
+	let float8 = |digits: &str, val: f64| {
+		encode_text_with(&format!("extra_float_digits={}", digits), |row| row.write_float8(val))
+	};
+
+	assert_eq!(float8("0", 1.0 / 3.0), "0.333333333333333");
+	assert_eq!(float8("0", 0.1), "0.1");
+	assert_eq!(float8("0", 123456789012345.0), "123456789012345");
+	assert_eq!(float8("0", 1e15), "1e+15");
+	assert_eq!(float8("-15", 123.0), "1e+02");
+	assert_eq!(float8("3", 0.1), "0.1");
+	assert_eq!(
+		encode_text_with("extra_float_digits=0", |row| row.write_float4(1.0 / 3.0)),
+		"0.333333"
+	);
+}
+
+#[test]
+fn date_text() {
+	let date = NaiveDate::from_ymd_opt(2020, 1, 2).unwrap();
+	let date_text =
+		|date_style: &str| encode_text_with(&format!("DateStyle={}", date_style), |row| row.write_date(date));
+
+	assert_eq!(encode_text(|row| row.write_date(date)), "2020-01-02");
+	assert_eq!(date_text("ISO, DMY"), "2020-01-02");
+	assert_eq!(date_text("SQL, MDY"), "01/02/2020");
+	assert_eq!(date_text("SQL, DMY"), "02/01/2020");
+	assert_eq!(date_text("German"), "02.01.2020");
+	assert_eq!(date_text("Postgres, MDY"), "01-02-2020");
+	assert_eq!(date_text("Postgres, DMY"), "02-01-2020");
+
+	let bc = NaiveDate::from_ymd_opt(-43, 3, 15).unwrap();
+	assert_eq!(encode_text(|row| row.write_date(bc)), "0044-03-15 BC");
+	assert_eq!(encode_text(|row| row.write_date(MaybeInfinite::Infinity)), "infinity");
+	assert_eq!(
+		encode_text(|row| row.write_date(MaybeInfinite::NegativeInfinity)),
+		"-infinity"
+	);
+}
+
+#[test]
+fn timestamp_text() {
+	let val = NaiveDate::from_ymd_opt(2020, 1, 2)
+		.unwrap()
+		.and_hms_micro_opt(3, 4, 5, 500_000)
+		.unwrap();
+	let timestamp_text =
+		|date_style: &str| encode_text_with(&format!("DateStyle={}", date_style), |row| row.write_timestamp(val));
+	let timestamptz_text = |date_style: &str| {
+		encode_text_with(&format!("DateStyle={}", date_style), |row| {
+			row.write_timestamptz(Utc.from_utc_datetime(&val))
+		})
+	};
+
+	assert_eq!(encode_text(|row| row.write_timestamp(val)), "2020-01-02 03:04:05.5");
+	assert_eq!(
+		encode_text(|row| row.write_timestamp(val.with_nanosecond(0).unwrap())),
+		"2020-01-02 03:04:05"
+	);
+	assert_eq!(timestamp_text("SQL, MDY"), "01/02/2020 03:04:05.5");
+	assert_eq!(timestamp_text("German"), "02.01.2020 03:04:05.5");
+	assert_eq!(timestamp_text("Postgres, MDY"), "Thu Jan 02 03:04:05.5 2020");
+	assert_eq!(timestamp_text("Postgres, DMY"), "Thu 02 Jan 03:04:05.5 2020");
+	assert_eq!(timestamptz_text("ISO"), "2020-01-02 03:04:05.5+00");
+	assert_eq!(timestamptz_text("SQL"), "01/02/2020 03:04:05.5 UTC");
+	assert_eq!(timestamptz_text("Postgres"), "Thu Jan 02 03:04:05.5 2020 UTC");
+
+	let bc = NaiveDate::from_ymd_opt(0, 6, 1).unwrap().and_hms_opt(12, 0, 0).unwrap();
+	assert_eq!(encode_text(|row| row.write_timestamp(bc)), "0001-06-01 12:00:00 BC");
+	assert_eq!(
+		encode_text(|row| row.write_timestamp(MaybeInfinite::Infinity)),
+		"infinity"
+	);
+	assert_eq!(
+		encode_text(|row| row.write_timestamptz(MaybeInfinite::NegativeInfinity)),
+		"-infinity"
+	);
+}
+
+#[test]
+fn infinite_binary() {
+	assert_eq!(
+		encode_binary(|row| row.write_date(MaybeInfinite::Infinity)),
+		i32::MAX.to_be_bytes()
+	);
+	assert_eq!(
+		encode_binary(|row| row.write_timestamp(MaybeInfinite::NegativeInfinity)),
+		i64::MIN.to_be_bytes()
+	);
+	assert_eq!(
+		encode_binary(|row| row.write_timestamptz(MaybeInfinite::Infinity)),
+		i64::MAX.to_be_bytes()
+	);
+}
+
+#[test]
+fn interval_styles() {
+	let micros = |h: i64, m: i64, s: i64, us: i64| ((h * 60 + m) * 60 + s) * 1_000_000 + us;
+	let interval_text = |style: &str, val: Interval| {
+		encode_text_with(&format!("IntervalStyle={}", style), |row| row.write_interval(&val))
+	};
+	let mixed = Interval::new(14, 3, micros(4, 5, 6, 789_000));
+	let negative = Interval::new(-14, -3, -micros(4, 5, 6, 789_000));
+
+	assert_eq!(
+		interval_text("postgres_verbose", mixed),
+		"@ 1 year 2 mons 3 days 4 hours 5 mins 6.789 secs"
+	);
+	assert_eq!(
+		interval_text("postgres_verbose", negative),
+		"@ 1 year 2 mons 3 days 4 hours 5 mins 6.789 secs ago"
+	);
+	assert_eq!(
+		interval_text("postgres_verbose", Interval::new(0, 1, -micros(0, 0, 1, 0))),
+		"@ 1 day -1 sec"
+	);
+	assert_eq!(interval_text("postgres_verbose", Interval::default()), "@ 0");
+
+	assert_eq!(interval_text("sql_standard", mixed), "+1-2 +3 +4:05:06.789");
+	assert_eq!(interval_text("sql_standard", negative), "-1-2 -3 -4:05:06.789");
+	assert_eq!(interval_text("sql_standard", Interval::new(14, 0, 0)), "1-2");
+	assert_eq!(
+		interval_text("sql_standard", Interval::new(0, 3, micros(4, 5, 6, 0))),
+		"3 4:05:06"
+	);
+	assert_eq!(
+		interval_text("sql_standard", Interval::new(0, 0, -micros(0, 1, 30, 0))),
+		"-0:01:30"
+	);
+	assert_eq!(interval_text("sql_standard", Interval::default()), "0");
+
+	assert_eq!(interval_text("iso_8601", mixed), "P1Y2M3DT4H5M6.789S");
+	assert_eq!(interval_text("iso_8601", negative), "P-1Y-2M-3DT-4H-5M-6.789S");
+	assert_eq!(interval_text("iso_8601", Interval::new(0, 1, 0)), "P1D");
+	assert_eq!(interval_text("iso_8601", Interval::default()), "PT0S");
+}
+
+#[test]
+fn array_elements_use_session_settings() {
+	assert_eq!(
+		encode_text_with("DateStyle=SQL, DMY", |row| {
+			row.write_array(DataTypeOid::Float8, |arr| {
+				arr.write_float8(f64::INFINITY)?;
+				arr.write_float8(1e100)
+			})
+		}),
+		"{Infinity,1e+100}"
+	);
+	assert_eq!(
+		encode_text_with("DateStyle=SQL, DMY", |row| {
+			row.write_array(DataTypeOid::Date, |arr| {
+				arr.write_date(NaiveDate::from_ymd_opt(2020, 1, 2).unwrap())
+			})
+		}),
+		"{02/01/2020}"
+	);
+}
@@ -0,0 +1,268 @@
+use async_trait::async_trait;
+use convergence::engine::{Engine, Parameters, Portal, PreparedStatement};
+use convergence::protocol::{self, DataTypeOid, ErrorResponse, FieldDescription};
+use convergence::protocol_ext::DataRowBatch;
+use convergence::router::{RouterEngine, UnknownEngineError};
+use convergence::server::{self, BindOptions};
+use sqlparser::ast::Statement;
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::Parser;
+use std::sync::{Arc, Mutex};
+use tokio_postgres::error::SqlState;
+use tokio_postgres::{connect, Client, NoTls, SimpleQueryMessage};
+
+struct ValuePortal(i32);
+
+#[async_trait]
+impl Portal for ValuePortal {
+	async fn fetch(&mut self, batch: &mut DataRowBatch) -> Result<(), ErrorResponse> {
+		let value = self.0;
+		batch.write_row(|row| row.write_int4(value))
+	}
+}
+
+fn fields() -> Vec<FieldDescription> {
+	vec![FieldDescription {
+		name: "value".to_owned(),
+		data_type: DataTypeOid::Int4,
+	}]
+}
+
+// returns 1 for every query, recording the statements it prepares
+struct FirstEngine {
+	prepared: Arc<Mutex<Vec<String>>>,
+}
+
+#[async_trait]
+impl Engine for FirstEngine {
+	type PreparedStatementType = Vec<FieldDescription>;
+	type PortalType = ValuePortal;
+
+	async fn prepare(&mut self, statement: &Statement) -> Result<Vec<FieldDescription>, ErrorResponse> {
+		self.prepared.lock().unwrap().push(format!("first: {}", statement));
+		Ok(fields())
+	}
+
+	async fn create_portal(
+		&mut self,
+		_: &Self::PreparedStatementType,
+		_: &Parameters,
+	) -> Result<Self::PortalType, ErrorResponse> {
+		Ok(ValuePortal(1))
+	}
+
+	async fn reset(&mut self) -> Result<(), ErrorResponse> {
+		self.prepared.lock().unwrap().push("first: reset".to_owned());
+		Err(ErrorResponse::error(protocol::SqlState::InternalError, "reset failed"))
+	}
+}
+
+struct SecondStatement {
+	fields: Vec<FieldDescription>,
+	value: i32,
+}
+
+impl PreparedStatement for SecondStatement {
+	fn fields(&self) -> &[FieldDescription] {
+		&self.fields
+	}
+}
+
+// returns 2 for every query using its own type of prepared statement and parser,
+// recording the statements it prepares
+struct SecondEngine {
+	prepared: Arc<Mutex<Vec<String>>>,
+}
+
+#[async_trait]
+impl Engine for SecondEngine {
+	type PreparedStatementType = SecondStatement;
+	type PortalType = ValuePortal;
+
+	// also understands `ping`, as a stand-in for custom syntax
+	fn parse(&self, text: &str) -> Result<Vec<Statement>, ErrorResponse> {
+		let text = if text.trim() == "ping" { "select 1" } else { text };
+		Parser::parse_sql(&PostgreSqlDialect {}, text)
+			.map_err(|err| ErrorResponse::error(protocol::SqlState::SyntaxError, err.to_string()))
+	}
+
+	async fn prepare(&mut self, statement: &Statement) -> Result<SecondStatement, ErrorResponse> {
+		self.prepared.lock().unwrap().push(format!("second: {}", statement));
+		Ok(SecondStatement {
+			fields: fields(),
+			value: 2,
+		})
+	}
+
+	async fn create_portal(
+		&mut self,
+		statement: &Self::PreparedStatementType,
+		_: &Parameters,
+	) -> Result<Self::PortalType, ErrorResponse> {
+		Ok(ValuePortal(statement.value))
+	}
+
+	async fn reset(&mut self) -> Result<(), ErrorResponse> {
+		self.prepared.lock().unwrap().push("second: reset".to_owned());
+		Ok(())
+	}
+}
+
+async fn setup(default_engine: Option<&'static str>) -> (u16, Arc<Mutex<Vec<String>>>) {
+	let prepared = Arc::new(Mutex::new(Vec::new()));
+	let engine_prepared = prepared.clone();
+	let port = server::run_background(
+		BindOptions::new().with_port(0),
+		Arc::new(move || {
+			let prepared = engine_prepared.clone();
+			Box::pin(async move {
+				let router = RouterEngine::new()
+					.with_engine(
+						"first",
+						FirstEngine {
+							prepared: prepared.clone(),
+						},
+					)
+					.with_engine("second", SecondEngine { prepared })
+					.with_predicate("second", |statement| statement.to_string().contains("logs"))
+					.unwrap();
+				match default_engine {
+					Some(name) => router.with_default_engine(name).unwrap(),
+					None => router,
+				}
+			})
+		}),
+	)
+	.await
+	.unwrap();
+
+	(port, prepared)
+}
+
+async fn client(port: u16, database: &str) -> Result<Client, tokio_postgres::Error> {
+	let (client, conn) = connect(&format!("postgres://test@localhost:{}/{}", port, database), NoTls).await?;
+	tokio::spawn(conn);
+	Ok(client)
+}
+
+async fn query_value(client: &Client, query: &str) -> Result<String, tokio_postgres::Error> {
+	for message in client.simple_query(query).await? {
+		if let SimpleQueryMessage::Row(row) = message {
+			return Ok(row.get(0).unwrap().to_owned());
+		}
+	}
+	panic!("expected a row");
+}
+
+#[tokio::test]
+async fn route_by_database() {
+	let (port, _) = setup(None).await;
+
+	let first = client(port, "first").await.unwrap();
+	assert_eq!(query_value(&first, "select 1").await.unwrap(), "1");
+
+	let second = client(port, "second").await.unwrap();
+	assert_eq!(query_value(&second, "select 1").await.unwrap(), "2");
+
+	// extended queries are bound by the engine which prepared them
+	let statement = second.prepare("select 1").await.unwrap();
+	let row = second.query_one(&statement, &[]).await.unwrap();
+	assert_eq!(row.get::<_, i32>(0), 2);
+
+	let err = client(port, "missing").await.expect_err("expected unknown database");
+	assert_eq!(err.code(), Some(&SqlState::INVALID_CATALOG_NAME));
+}
+
+#[tokio::test]
+async fn route_by_default_engine() {
+	let (port, _) = setup(Some("second")).await;
+
+	let client = client(port, "missing").await.unwrap();
+	assert_eq!(query_value(&client, "select 1").await.unwrap(), "2");
+}
+
+#[tokio::test]
+async fn route_by_schema() {
+	let (port, prepared) = setup(None).await;
+	let client = client(port, "first").await.unwrap();
+
+	// the schema is removed before the engine sees the statement
+	assert_eq!(
+		query_value(&client, "select * from second.t join u on true")
+			.await
+			.unwrap(),
+		"2"
+	);
+	assert_eq!(query_value(&client, "select * from \"first\".t").await.unwrap(), "1");
+	assert_eq!(query_value(&client, "select * from other.t").await.unwrap(), "1");
+	assert_eq!(
+		*prepared.lock().unwrap(),
+		vec![
+			"second: SELECT * FROM t JOIN u ON true",
+			"first: SELECT * FROM t",
+			"first: SELECT * FROM other.t",
+		]
+	);
+
+	let err = query_value(&client, "select * from first.t join second.u on true")
+		.await
+		.expect_err("expected cross-engine error");
+	assert_eq!(err.code(), Some(&SqlState::FEATURE_NOT_SUPPORTED));
+}
+
+#[tokio::test]
+async fn route_by_predicate() {
+	let (port, _) = setup(None).await;
+	let client = client(port, "first").await.unwrap();
+
+	// predicates take precedence over schemas
+	assert_eq!(query_value(&client, "select * from logs").await.unwrap(), "2");
+	assert_eq!(query_value(&client, "select * from first.logs").await.unwrap(), "2");
+}
+
+#[test]
+fn unknown_engine_names() {
+	let router = RouterEngine::new().with_engine(
+		"first",
+		FirstEngine {
+			prepared: Arc::default(),
+		},
+	);
+	let err = router.with_default_engine("missing").err().unwrap();
+	assert_eq!(err, UnknownEngineError("missing".to_owned()));
+
+	let router = RouterEngine::new().with_engine(
+		"first",
+		FirstEngine {
+			prepared: Arc::default(),
+		},
+	);
+	assert!(router.with_predicate("missing", |_| true).is_err());
+}
+
+#[tokio::test]
+async fn parse_with_session_engine() {
+	let (port, _) = setup(None).await;
+
+	// only the second engine's parser understands `ping`
+	let second = client(port, "second").await.unwrap();
+	assert_eq!(query_value(&second, "ping").await.unwrap(), "2");
+
+	let first = client(port, "first").await.unwrap();
+	let err = query_value(&first, "ping").await.expect_err("expected syntax error");
+	assert_eq!(err.code(), Some(&SqlState::SYNTAX_ERROR));
+}
+
+#[tokio::test]
+async fn reset_every_engine() {
+	let (port, prepared) = setup(None).await;
+	let client = client(port, "first").await.unwrap();
+
+	// the first engine's error is reported, but the second engine is still reset
+	let err = client
+		.batch_execute("discard all")
+		.await
+		.expect_err("expected reset error");
+	assert_eq!(err.code(), Some(&SqlState::INTERNAL_ERROR));
+	assert_eq!(*prepared.lock().unwrap(), vec!["first: reset", "second: reset"]);
+}
@@ -0,0 +1,67 @@
+use convergence::protocol::SqlState;
+use convergence::settings::{DateOrder, DateStyle, IntervalStyle, SessionSettings};
+
+#[test]
+fn defaults() {
+	let settings = SessionSettings::default();
+	assert_eq!(settings.date_style_value(), "ISO, MDY");
+	assert_eq!(settings.interval_style, IntervalStyle::Postgres);
+	assert_eq!(settings.extra_float_digits, 1);
+	assert_eq!(settings.parameter_statuses().len(), 2);
+}
+
+#[test]
+fn date_style() {
+	let mut settings = SessionSettings::default();
+
+	settings.set("datestyle", "SQL, DMY").unwrap();
+	assert_eq!(
+		(settings.date_style, settings.date_order),
+		(DateStyle::Sql, DateOrder::Dmy)
+	);
+
+	// omitted components keep their current value
+	settings.set("DateStyle", "Postgres").unwrap();
+	assert_eq!(settings.date_style_value(), "Postgres, DMY");
+	settings.set("DateStyle", "US").unwrap();
+	assert_eq!(settings.date_style_value(), "Postgres, MDY");
+
+	// German implies a day-first order unless one is given
+	settings.set("DateStyle", "German").unwrap();
+	assert_eq!(settings.date_style_value(), "German, DMY");
+	settings.set("DateStyle", "German, YMD").unwrap();
+	assert_eq!(settings.date_style_value(), "German, YMD");
+
+	settings.set("DateStyle", "DEFAULT").unwrap();
+	assert_eq!(settings.date_style_value(), "ISO, MDY");
+
+	for invalid in ["ISO, SQL", "DMY, MDY", "ISO, bogus"] {
+		let err = settings.set("DateStyle", invalid).unwrap_err();
+		assert_eq!(err.sql_state, SqlState::InvalidParameterValue);
+	}
+	assert_eq!(settings.date_style_value(), "ISO, MDY");
+}
+
+#[test]
+fn interval_style_and_float_digits() {
+	let mut settings = SessionSettings::default();
+
+	settings.set("intervalstyle", "ISO_8601").unwrap();
+	assert_eq!(settings.interval_style, IntervalStyle::Iso8601);
+	assert!(settings.set("IntervalStyle", "iso").is_err());
+
+	settings.set("extra_float_digits", "-15").unwrap();
+	assert_eq!(settings.extra_float_digits, -15);
+	assert!(settings.set("extra_float_digits", "4").is_err());
+	assert!(settings.set("extra_float_digits", "one").is_err());
+	assert_eq!(settings.extra_float_digits, -15);
+}
+
+#[test]
+fn unknown_setting() {
+	assert!(!SessionSettings::is_setting("work_mem"));
+	assert_eq!(
+		SessionSettings::default().set("work_mem", "4MB").unwrap_err().sql_state,
+		SqlState::UndefinedObject
+	);
+}